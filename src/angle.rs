@@ -335,6 +335,15 @@ where
         Angle(rad.0)
     }
 }
+impl<S> From<Angle<S>> for ArcMin<S>
+where
+    S: BaseFloat,
+{
+    fn from(angle: Angle<S>) -> Self {
+        let rad: Rad<S> = angle.into();
+        rad.into()
+    }
+}
 // Convert from ArcSec<S>
 impl<S> From<ArcSec<S>> for Angle<S>
 where
@@ -345,6 +354,15 @@ where
         Angle(rad.0)
     }
 }
+impl<S> From<Angle<S>> for ArcSec<S>
+where
+    S: BaseFloat,
+{
+    fn from(angle: Angle<S>) -> Self {
+        let rad: Rad<S> = angle.into();
+        rad.into()
+    }
+}
 /*
 impl<S> PartialEq<S> for Angle<S>
 where
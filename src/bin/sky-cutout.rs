@@ -0,0 +1,86 @@
+// Reprojected cutouts of a local HiPS, written as PNG or FITS files with their WCS
+//
+// sky-cutout HIPS_DIR --center RA DEC --fov DEG [--projection TAN|SIN|ARC|MOL]
+//     [--rotation DEG] [--size WIDTHxHEIGHT] --output FILE
+use wgpu_sky_rendering::fits;
+use wgpu_sky_rendering::hips::HipsReader;
+use wgpu_sky_rendering::wcs::{Wcs, WcsProjection};
+
+use std::path::Path;
+
+const USAGE: &str = "usage: sky-cutout HIPS_DIR --center RA DEC --fov DEG [--projection TAN|SIN|ARC|MOL] \
+                     [--rotation DEG] [--size WIDTHxHEIGHT] --output FILE";
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = cutout(&args) {
+        log::error!("{}", e);
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+}
+
+fn cutout(args: &[String]) -> Result<(), String> {
+    let mut hips = None;
+    let mut center = None;
+    let mut fov = None;
+    let mut projection = WcsProjection::Tan;
+    let mut rotation = 0.0;
+    let mut size = (512, 512);
+    let mut output = None;
+
+    let mut args = args.iter();
+    let float = |value: &str| value.parse::<f64>().map_err(|e| format!("{}: {}", value, e));
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--center" => {
+                let ra = float(value(&mut args, arg)?)?;
+                let dec = float(value(&mut args, arg)?)?;
+                center = Some((ra, dec));
+            }
+            "--fov" => fov = Some(float(value(&mut args, arg)?)?),
+            "--rotation" => rotation = float(value(&mut args, arg)?)?,
+            "--projection" => {
+                let code = value(&mut args, arg)?;
+                projection = WcsProjection::parse(&code.to_uppercase())
+                    .ok_or_else(|| format!("unsupported projection {}", code))?;
+            }
+            "--size" => {
+                let text = value(&mut args, arg)?;
+                size = text
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .filter(|&(w, h)| w > 0 && h > 0)
+                    .ok_or_else(|| format!("invalid size {}", text))?;
+            }
+            "--output" => output = Some(value(&mut args, arg)?),
+            _ if hips.is_none() => hips = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    let hips = hips.ok_or("missing HiPS directory")?;
+    let center = center.ok_or("missing --center")?;
+    let fov = fov.ok_or("missing --fov")?;
+    let output = Path::new(output.ok_or("missing --output")?);
+
+    let wcs = Wcs::cutout(projection, center, fov, rotation, size).map_err(|e| e.to_string())?;
+    let mut reader = HipsReader::open(hips).map_err(|e| format!("{}: {}", hips, e))?;
+    let image = reader.cutout(&wcs, size).map_err(|e| e.to_string())?;
+
+    let is_fits = output
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ["fits", "fit"].contains(&ext.to_ascii_lowercase().as_str()));
+    let result = if is_fits {
+        std::fs::write(output, fits::write_rgb_image(&image, &wcs.cards())).map_err(|e| e.to_string())
+    } else {
+        image.save(output).map_err(|e| e.to_string())
+    };
+    result.map_err(|e| format!("cannot write {}: {}", output.display(), e))
+}
+
+/// Value following an option
+fn value<'a>(args: &mut std::slice::Iter<'a, String>, option: &str) -> Result<&'a String, String> {
+    args.next().ok_or_else(|| format!("missing value after {}", option))
+}
@@ -0,0 +1,72 @@
+// HiPS generated from FITS images with a WCS, readable by the viewer and sky-cutout
+//
+// sky-hipsgen OUTPUT_DIR IMAGE.fits... [--order N] [--tile-width W] [--title TITLE]
+use wgpu_sky_rendering::healpix;
+use wgpu_sky_rendering::hipsgen;
+use wgpu_sky_rendering::images::ImageLayer;
+
+use std::path::Path;
+
+const USAGE: &str = "usage: sky-hipsgen OUTPUT_DIR IMAGE.fits... [--order N] [--tile-width W] [--title TITLE]";
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = hipsgen(&args) {
+        log::error!("{}", e);
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+}
+
+fn hipsgen(args: &[String]) -> Result<(), String> {
+    let mut order = None;
+    let mut tile_width = 512;
+    let mut title = None;
+    let mut paths = vec![];
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--order" => {
+                let text = value(&mut args, arg)?;
+                order = Some(text.parse::<u8>().map_err(|e| format!("{}: {}", text, e))?);
+            }
+            "--tile-width" => {
+                let text = value(&mut args, arg)?;
+                tile_width = text.parse::<u32>().map_err(|e| format!("{}: {}", text, e))?;
+                if !tile_width.is_power_of_two() || tile_width > 1 << healpix::MAX_DEPTH {
+                    return Err(format!("the tile width {} is not a power of two up to 2^{}", text, healpix::MAX_DEPTH));
+                }
+            }
+            "--title" => title = Some(value(&mut args, arg)?.clone()),
+            _ => paths.push(arg),
+        }
+    }
+    let (dir, paths) = paths.split_first().ok_or("missing output directory")?;
+    if paths.is_empty() {
+        return Err("missing FITS images".to_string());
+    }
+    let dir = Path::new(dir);
+
+    let images = paths
+        .iter()
+        .map(|path| ImageLayer::from_file(path).map_err(|e| format!("{}: {}", path, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let order = order.unwrap_or_else(|| hipsgen::default_order(&images, tile_width));
+    let title = title.unwrap_or_else(|| {
+        dir.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "HiPS".to_string())
+    });
+    log::info!("{} images into the tiles of the order {} of {}", images.len(), order, dir.display());
+
+    let moc = hipsgen::generate(&images, dir, order, tile_width, &title).map_err(|e| e.to_string())?;
+    log::info!("{:.6}% of the sky covered", 100.0 * moc.sky_fraction());
+    Ok(())
+}
+
+/// Value following an option
+fn value<'a>(args: &mut std::slice::Iter<'a, String>, option: &str) -> Result<&'a String, String> {
+    args.next().ok_or_else(|| format!("missing value after {}", option))
+}
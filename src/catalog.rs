@@ -0,0 +1,542 @@
+use crate::angle::{Angle, ArcDeg};
+use crate::error::Error;
+use crate::frame::Epoch;
+use crate::math::{self, Vec3};
+
+use std::path::Path;
+
+// Astronomical unit expressed in km.yr/s
+const AU_KM_YR_PER_S: f64 = 4.740_470_463_533_348;
+const MAS_TO_RAD: f64 = std::f64::consts::PI / (180.0 * 3_600_000.0);
+
+/// A table of sources as read from a file, all the values kept as strings
+#[derive(Clone, Debug, Default)]
+pub struct Table {
+    pub names: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// Index of the first column whose name matches one of the candidates (case insensitive)
+    pub fn column_index(&self, candidates: &[&str]) -> Option<usize> {
+        candidates.iter().find_map(|candidate| {
+            self.names
+                .iter()
+                .position(|name| name.trim().eq_ignore_ascii_case(candidate))
+        })
+    }
+
+    /// Values of a column parsed as floats, NaN standing for the empty or invalid ones
+    pub fn column_f64(&self, idx: usize) -> Vec<f64> {
+        self.rows
+            .iter()
+            .map(|row| {
+                row.get(idx)
+                    .and_then(|v| v.trim().parse::<f64>().ok())
+                    .unwrap_or(f64::NAN)
+            })
+            .collect()
+    }
+
+    /// Read a CSV or TSV table
+    ///
+    /// The first line that is neither empty nor a `#` comment gives the column names.
+    /// The delimiter is a tab if that line contains one, a comma otherwise.
+    pub fn from_csv(text: &str) -> Result<Self, Error> {
+        let mut lines = text
+            .lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'));
+
+        let header = lines
+            .next()
+            .ok_or_else(|| Error::Parse("empty table".to_string()))?;
+        let delimiter = if header.contains('\t') { '\t' } else { ',' };
+        let names = split_csv_line(header, delimiter);
+
+        let rows = lines
+            .enumerate()
+            .map(|(i, line)| {
+                let row = split_csv_line(line, delimiter);
+                if row.len() == names.len() {
+                    Ok(row)
+                } else {
+                    Err(Error::Parse(format!(
+                        "row {} has {} fields instead of {}",
+                        i + 1,
+                        row.len(),
+                        names.len()
+                    )))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self { names, rows })
+    }
+
+    /// Read the first table of a VOTable document
+    ///
+    /// Only the TABLEDATA serialization is supported.
+    pub fn from_votable(text: &str) -> Result<Self, Error> {
+        let mut names = vec![];
+        let mut rows = vec![];
+        let mut row: Option<Vec<String>> = None;
+
+        let mut rest = text;
+        while let Some(start) = rest.find('<') {
+            let end = rest[start..]
+                .find('>')
+                .map(|end| start + end)
+                .ok_or_else(|| Error::Parse("unterminated tag".to_string()))?;
+            let tag = &rest[(start + 1)..end];
+            rest = &rest[(end + 1)..];
+
+            let name = tag
+                .trim_start_matches('/')
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or("");
+            // Namespace prefixes are ignored
+            let name = name.rsplit(':').next().unwrap_or(name);
+            let closing = tag.starts_with('/');
+
+            match (name, closing) {
+                ("FIELD", false) if rows.is_empty() && row.is_none() => {
+                    let field_name = xml_attribute(tag, "name")
+                        .ok_or_else(|| Error::Parse("FIELD without a name".to_string()))?;
+                    names.push(field_name);
+                }
+                ("BINARY" | "BINARY2" | "FITS", false) => {
+                    return Err(Error::Parse(
+                        "only the TABLEDATA serialization is supported".to_string(),
+                    ));
+                }
+                ("TR", false) => row = Some(vec![]),
+                ("TR", true) => {
+                    let row = row
+                        .take()
+                        .ok_or_else(|| Error::Parse("unexpected </TR>".to_string()))?;
+                    if row.len() != names.len() {
+                        return Err(Error::Parse(format!(
+                            "row {} has {} cells instead of {}",
+                            rows.len() + 1,
+                            row.len(),
+                            names.len()
+                        )));
+                    }
+                    rows.push(row);
+                }
+                ("TD", false) => {
+                    let cell = if tag.ends_with('/') {
+                        String::new()
+                    } else {
+                        let end = rest
+                            .find("</")
+                            .ok_or_else(|| Error::Parse("unterminated TD".to_string()))?;
+                        let cell = xml_unescape(rest[..end].trim());
+                        rest = &rest[end..];
+                        cell
+                    };
+                    row.as_mut()
+                        .ok_or_else(|| Error::Parse("TD outside of a TR".to_string()))?
+                        .push(cell);
+                }
+                // Only the first table is read
+                ("TABLE", true) => break,
+                _ => (),
+            }
+        }
+
+        if names.is_empty() {
+            Err(Error::Parse("no FIELD found".to_string()))
+        } else {
+            Ok(Self { names, rows })
+        }
+    }
+
+    /// Read a table from a file, VOTable documents being recognized by their content
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)?;
+        if text.trim_start().starts_with('<') {
+            Self::from_votable(&text)
+        } else {
+            Self::from_csv(&text)
+        }
+    }
+}
+
+// Split a line on a delimiter, fields may be quoted with double quotes
+fn split_csv_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => {
+                fields.push(field.trim().to_string());
+                field.clear();
+            }
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+
+    fields
+}
+
+fn xml_attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+    while let Some(idx) = rest.find(name) {
+        let before = rest[..idx].chars().last();
+        let after = rest[(idx + name.len())..].trim_start();
+        rest = &rest[(idx + name.len())..];
+
+        if before.is_some_and(|c| c.is_whitespace()) && after.starts_with('=') {
+            let value = after[1..].trim_start();
+            let quote = value.chars().next()?;
+            let value = &value[1..];
+            let end = value.find(quote)?;
+            return Some(xml_unescape(&value[..end]));
+        }
+    }
+    None
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+const RA_NAMES: &[&str] = &["ra", "ra_icrs", "raj2000", "_raj2000", "ra_deg", "radeg"];
+const DEC_NAMES: &[&str] = &["dec", "de", "dec_icrs", "de_icrs", "dej2000", "_dej2000", "dec_deg", "dedeg"];
+const PM_RA_NAMES: &[&str] = &["pmra", "pm_ra", "pmra_cosdec", "pmracosdec"];
+const PM_DEC_NAMES: &[&str] = &["pmdec", "pmde", "pm_dec"];
+const PARALLAX_NAMES: &[&str] = &["parallax", "plx"];
+const RV_NAMES: &[&str] = &["radial_velocity", "rv", "rvel", "hrv"];
+const EPOCH_NAMES: &[&str] = &["ref_epoch", "epoch", "epoch_ra", "epoch_pos", "epoch_mjd", "epoch_jd"];
+const MAG_NAMES: &[&str] = &["mag", "vmag", "gmag", "phot_g_mean_mag", "rmag", "jmag"];
+const COLOR_NAMES: &[&str] = &["color", "colour", "b-v", "b_v", "bp_rp", "bp-rp"];
+const SIZE_NAMES: &[&str] = &["size", "radius"];
+
+/// Space motion of a set of sources, stored column-wise
+///
+/// Missing values (NaN) are considered as null motions, and the parallaxes that are not
+/// positive as unknown distances, i.e. without radial motion.
+#[derive(Clone, Debug)]
+pub struct SpaceMotion {
+    /// Proper motion in right ascension, multiplied by cos(dec), in mas/yr
+    pub pm_ra_cosdec: Vec<f64>,
+    /// Proper motion in declination in mas/yr
+    pub pm_dec: Vec<f64>,
+    /// Parallax in mas
+    pub parallax: Vec<f64>,
+    /// Radial velocity in km/s
+    pub radial_velocity: Vec<f64>,
+    /// Epoch of the positions, as Julian years
+    pub epoch: Vec<f64>,
+}
+
+impl SpaceMotion {
+    /// Propagate the positions to a given epoch
+    ///
+    /// This is the rigorous propagation of a uniform space motion (Lindegren et al., 2018,
+    /// Gaia DR2 documentation section 4.1.7). The proper motions, parallaxes, radial velocities
+    /// and epochs are updated in place, as well as the positions.
+    ///
+    /// # Arguments
+    ///
+    /// * `ra` - Right ascensions in degrees
+    /// * `dec` - Declinations in degrees
+    /// * `to` - The epoch to propagate the positions to
+    pub fn propagate(&mut self, ra: &mut [f64], dec: &mut [f64], to: &Epoch) {
+        let to = to.julian_year();
+        let sources = ra
+            .iter_mut()
+            .zip(dec.iter_mut())
+            .zip(self.pm_ra_cosdec.iter_mut())
+            .zip(self.pm_dec.iter_mut())
+            .zip(self.parallax.iter_mut())
+            .zip(self.radial_velocity.iter_mut())
+            .zip(self.epoch.iter_mut());
+
+        for ((((((ra, dec), pm_ra), pm_dec), plx), rv), epoch) in sources {
+            let tau = if epoch.is_nan() { 0.0 } else { to - *epoch };
+            if tau == 0.0 {
+                continue;
+            }
+            let or_zero = |v: f64| if v.is_nan() { 0.0 } else { v };
+
+            let (s_ra, c_ra) = ra.to_radians().sin_cos();
+            let (s_dec, c_dec) = dec.to_radians().sin_cos();
+            // Normal triad at the source position
+            let p = [-s_ra, c_ra, 0.0];
+            let q = [-s_dec * c_ra, -s_dec * s_ra, c_dec];
+            let r = [c_dec * c_ra, c_dec * s_ra, s_dec];
+
+            let plx_mas = if *plx > 0.0 { *plx } else { 0.0 };
+            // Proper motions in rad/yr
+            let mu_a = or_zero(*pm_ra) * MAS_TO_RAD;
+            let mu_d = or_zero(*pm_dec) * MAS_TO_RAD;
+            let mu_r = or_zero(*rv) * plx_mas / AU_KM_YR_PER_S * MAS_TO_RAD;
+            let mu = [
+                p[0] * mu_a + q[0] * mu_d,
+                p[1] * mu_a + q[1] * mu_d,
+                p[2] * mu_a + q[2] * mu_d,
+            ];
+            let mu2 = mu_a * mu_a + mu_d * mu_d;
+
+            let f = 1.0 / (1.0 + 2.0 * mu_r * tau + (mu2 + mu_r * mu_r) * tau * tau).sqrt();
+            let u: [f64; 3] = std::array::from_fn(|i| (r[i] * (1.0 + mu_r * tau) + mu[i] * tau) * f);
+            let mu_new: [f64; 3] =
+                std::array::from_fn(|i| (mu[i] * (1.0 + mu_r * tau) - r[i] * mu2 * tau) * f * f * f);
+            let mu_r_new = (mu_r + (mu2 + mu_r * mu_r) * tau) * f * f;
+
+            let ra_new = u[1].atan2(u[0]).rem_euclid(2.0 * std::f64::consts::PI);
+            let dec_new = u[2].atan2((u[0] * u[0] + u[1] * u[1]).sqrt());
+
+            let (s_ra, c_ra) = ra_new.sin_cos();
+            let s_dec = dec_new.sin();
+            let p = [-s_ra, c_ra, 0.0];
+            let q = [-s_dec * c_ra, -s_dec * s_ra, dec_new.cos()];
+
+            *ra = ra_new.to_degrees();
+            *dec = dec_new.to_degrees();
+            if !pm_ra.is_nan() {
+                *pm_ra = (p[0] * mu_new[0] + p[1] * mu_new[1]) / MAS_TO_RAD;
+            }
+            if !pm_dec.is_nan() {
+                *pm_dec = (q[0] * mu_new[0] + q[1] * mu_new[1] + q[2] * mu_new[2]) / MAS_TO_RAD;
+            }
+            if !plx.is_nan() {
+                *plx *= f;
+            }
+            if !rv.is_nan() && plx_mas != 0.0 {
+                *rv = mu_r_new / MAS_TO_RAD * AU_KM_YR_PER_S / *plx;
+            }
+            *epoch = to;
+        }
+    }
+}
+
+/// A set of sources with their positions
+pub struct Catalog {
+    pub table: Table,
+    /// Right ascensions in degrees
+    pub ra: Vec<f64>,
+    /// Declinations in degrees
+    pub dec: Vec<f64>,
+    /// Space motions of the sources, if the table has an epoch column
+    pub motion: Option<SpaceMotion>,
+    /// Magnitudes, if the table has a magnitude column
+    pub mag: Option<Vec<f64>>,
+    /// Color indices, if the table has a color column
+    pub color: Option<Vec<f64>>,
+    /// Sizes, if the table has a size column
+    pub size: Option<Vec<f64>>,
+}
+
+impl Catalog {
+    /// Build a catalog from a table, the positions being propagated to the given epoch
+    /// when the table provides an epoch column.
+    pub fn from_table(table: Table, epoch: &Epoch) -> Result<Self, Error> {
+        let ra_idx = table
+            .column_index(RA_NAMES)
+            .ok_or(Error::MissingColumn("right ascension"))?;
+        let dec_idx = table
+            .column_index(DEC_NAMES)
+            .ok_or(Error::MissingColumn("declination"))?;
+
+        let mut ra = table.column_f64(ra_idx);
+        let mut dec = table.column_f64(dec_idx);
+        let optional_column = |names: &[&str]| table.column_index(names).map(|idx| table.column_f64(idx));
+        let mag = optional_column(MAG_NAMES);
+        let color = optional_column(COLOR_NAMES);
+        let size = optional_column(SIZE_NAMES);
+
+        let motion = table.column_index(EPOCH_NAMES).map(|epoch_idx| {
+            let column = |names: &[&str]| {
+                table
+                    .column_index(names)
+                    .map(|idx| table.column_f64(idx))
+                    .unwrap_or_else(|| vec![f64::NAN; table.rows.len()])
+            };
+            // The unit of the epochs is given by the name of their column, Julian years
+            // by default
+            let name = table.names[epoch_idx].to_lowercase();
+            let epoch = table
+                .column_f64(epoch_idx)
+                .into_iter()
+                .map(|e| {
+                    let year = if name.contains("mjd") {
+                        Epoch(e + 2_400_000.5).julian_year()
+                    } else if name.contains("jd") {
+                        Epoch(e).julian_year()
+                    } else {
+                        e
+                    };
+                    // Epochs in another unit are left unknown rather than read as years
+                    if (1000.0..=3000.0).contains(&year) {
+                        year
+                    } else {
+                        f64::NAN
+                    }
+                })
+                .collect();
+
+            SpaceMotion {
+                pm_ra_cosdec: column(PM_RA_NAMES),
+                pm_dec: column(PM_DEC_NAMES),
+                parallax: column(PARALLAX_NAMES),
+                radial_velocity: column(RV_NAMES),
+                epoch,
+            }
+        });
+
+        let mut catalog = Self {
+            table,
+            ra: vec![],
+            dec: vec![],
+            motion,
+            mag,
+            color,
+            size,
+        };
+        if let Some(motion) = catalog.motion.as_mut() {
+            motion.propagate(&mut ra, &mut dec, epoch);
+        }
+        catalog.ra = ra;
+        catalog.dec = dec;
+
+        Ok(catalog)
+    }
+
+    /// Load a catalog from a CSV, TSV or VOTable file
+    pub fn from_file<P: AsRef<Path>>(path: P, epoch: &Epoch) -> Result<Self, Error> {
+        Self::from_table(Table::from_file(path)?, epoch)
+    }
+
+    /// Move the sources to another epoch. Does nothing for catalogs without space motion.
+    pub fn propagate(&mut self, epoch: &Epoch) {
+        if let Some(motion) = self.motion.as_mut() {
+            motion.propagate(&mut self.ra, &mut self.dec, epoch);
+        }
+    }
+
+    /// Unit vectors of the sources in the ICRS frame
+    pub fn positions(&self) -> Vec<Vec3<f64>> {
+        self.ra
+            .iter()
+            .zip(&self.dec)
+            .map(|(&ra, &dec)| math::radec_to_xyz(Angle::new(ArcDeg(ra)), Angle::new(ArcDeg(dec))))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ra.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ra.is_empty()
+    }
+}
+
+mod tests {
+    #[test]
+    fn space_motion() {
+        use super::*;
+        // SOFA iauStarpm test case, from MJD 50083 to MJD 53736
+        let dec0 = -1.093_989_828_f64;
+        let mut ra = [0.016_867_56_f64.to_degrees()];
+        let mut dec = [dec0.to_degrees()];
+        let mut motion = SpaceMotion {
+            pm_ra_cosdec: vec![-1.783_235_16e-5 * dec0.cos() / MAS_TO_RAD],
+            pm_dec: vec![2.336_024_047e-6 / MAS_TO_RAD],
+            parallax: vec![747.23],
+            radial_velocity: vec![-21.6],
+            epoch: vec![Epoch(2_400_000.5 + 50_083.0).julian_year()],
+        };
+        motion.propagate(&mut ra, &mut dec, &Epoch(2_400_000.5 + 53_736.0));
+
+        // SOFA also accounts for the light-time, that is below 1 mas here
+        assert!((ra[0].to_radians() - 0.016_689_190_694_142_56).abs() < 5e-9);
+        assert!((dec[0].to_radians() - -1.093_966_454_217_128).abs() < 5e-9);
+        let pm_ra = motion.pm_ra_cosdec[0] * MAS_TO_RAD / dec[0].to_radians().cos();
+        assert!((pm_ra - -0.178_366_268_215_317_6e-4).abs() < 1e-10);
+        assert!((motion.pm_dec[0] * MAS_TO_RAD - 0.233_809_291_598_399e-5).abs() < 1e-10);
+        assert!((motion.parallax[0] - 747.353_383_531_772).abs() < 1e-3);
+        assert!((motion.radial_velocity[0] - -21.599_051_704_764_17).abs() < 1e-3);
+
+        // Going back to the original epoch
+        motion.propagate(&mut ra, &mut dec, &Epoch(2_400_000.5 + 50_083.0));
+        assert!((dec[0].to_radians() - dec0).abs() < 1e-12);
+        assert!((motion.radial_velocity[0] - -21.6).abs() < 1e-9);
+
+        // A negative parallax gives no radial motion
+        let (mut ra, mut dec) = ([10.0], [20.0]);
+        let mut motion = SpaceMotion {
+            pm_ra_cosdec: vec![f64::NAN],
+            pm_dec: vec![f64::NAN],
+            parallax: vec![-0.5],
+            radial_velocity: vec![1e5],
+            epoch: vec![2000.0],
+        };
+        motion.propagate(&mut ra, &mut dec, &Epoch(2_451_545.0 + 365.25e4));
+        assert_eq!((ra[0], dec[0]), (10.0, 20.0));
+        assert_eq!(motion.radial_velocity[0], 1e5);
+
+        // The epochs are read in the unit of their column, the implausible years being unknown.
+        // The sources move by 1 arcsec/yr in declination.
+        let dec_at_j2000 = |column: &str, epoch: &str| {
+            let table = Table {
+                names: vec!["ra".into(), "dec".into(), "pmdec".into(), column.into()],
+                rows: vec![vec!["1".into(), "2".into(), "1000".into(), epoch.into()]],
+            };
+            let catalog = Catalog::from_table(table, &Epoch::J2000).unwrap();
+            (catalog.dec[0] - 2.0) * 3600.0
+        };
+        assert!((dec_at_j2000("epoch", "2016.0") - -16.0).abs() < 1e-6);
+        assert!((dec_at_j2000("epoch_mjd", "58849.0") - -20.0).abs() < 1e-2);
+        assert!((dec_at_j2000("epoch_jd", "2458849.5") - -20.0).abs() < 1e-2);
+        assert_eq!(dec_at_j2000("epoch", "58849.0"), 0.0);
+    }
+
+    #[test]
+    fn parse() {
+        use super::*;
+        let csv = "# comment\nname,ra,dec\n\"Alpha, A\",10.5,-20\n\"Say \"\"hi\"\"\",11,21\n";
+        let table = Table::from_csv(csv).unwrap();
+        assert_eq!(table.names, vec!["name", "ra", "dec"]);
+        assert_eq!(table.rows[0], vec!["Alpha, A", "10.5", "-20"]);
+        assert_eq!(table.rows[1][0], "Say \"hi\"");
+        assert!(matches!(Table::from_csv("ra\tdec\n1\n"), Err(Error::Parse(_))));
+
+        let votable = r#"<?xml version="1.0"?>
+<VOTABLE version="1.4" xmlns="http://www.ivoa.net/xml/VOTable/v1.3">
+  <RESOURCE><TABLE>
+    <FIELD name="RAJ2000" datatype="double" unit="deg"/>
+    <FIELD ID="dec" name="DEJ2000" datatype="double" unit="deg"/>
+    <FIELD name="Vmag" datatype="float"><DESCRIPTION>V &amp; mag</DESCRIPTION></FIELD>
+    <DATA><TABLEDATA>
+      <TR><TD>83.63</TD><TD>22.01</TD><TD>8.4</TD></TR>
+      <TR><TD>10.68</TD><TD>41.27</TD><TD/></TR>
+    </TABLEDATA></DATA>
+  </TABLE></RESOURCE>
+</VOTABLE>"#;
+        let table = Table::from_votable(votable).unwrap();
+        assert_eq!(table.names, vec!["RAJ2000", "DEJ2000", "Vmag"]);
+        assert_eq!(table.rows[1], vec!["10.68", "41.27", ""]);
+        let catalog = Catalog::from_table(table, &Epoch::J2000).unwrap();
+        assert_eq!(catalog.dec, vec![22.01, 41.27]);
+        assert!(catalog.mag.unwrap()[1].is_nan());
+    }
+}
@@ -0,0 +1,349 @@
+// Colormaps and intensity transfer functions of the scalar data, applied in the
+// fragment shaders through a uniform so that they change without re-uploading the data.
+use crate::cuts::AutoCuts;
+
+// Number of colors of the lookup tables sent to the shaders
+pub const LUT_SIZE: usize = 256;
+// Number of bins of the cumulative histogram used by the equalization
+pub const CDF_SIZE: usize = 256;
+// Values sampled at most to compute the histogram
+const MAX_HISTOGRAM_SAMPLES: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Colormap {
+    Grayscale,
+    Viridis,
+    Magma,
+    Cubehelix,
+    Planck,
+}
+
+impl Colormap {
+    pub const ALL: [Colormap; 5] = [
+        Colormap::Grayscale,
+        Colormap::Viridis,
+        Colormap::Magma,
+        Colormap::Cubehelix,
+        Colormap::Planck,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Colormap::Grayscale => "grayscale",
+            Colormap::Viridis => "viridis",
+            Colormap::Magma => "magma",
+            Colormap::Cubehelix => "cubehelix",
+            Colormap::Planck => "planck",
+        }
+    }
+
+    /// Colormap following this one, cycling back to the first
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&c| c == self).unwrap_or(0);
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+
+    /// sRGB color of a normalized value in `[0, 1]`
+    pub fn color(self, t: f64) -> [f64; 3] {
+        let t = t.clamp(0.0, 1.0);
+        let rgb = match self {
+            Colormap::Grayscale => [t, t, t],
+            // Polynomial fits of the matplotlib colormaps
+            Colormap::Viridis => polynomial(
+                &[
+                    [0.277_727, 0.005_407, 0.334_100],
+                    [0.105_093, 1.404_614, 1.384_590],
+                    [-0.330_862, 0.214_848, 0.095_095],
+                    [-4.634_230, -5.799_101, -19.332_441],
+                    [6.228_270, 14.179_933, 56.690_553],
+                    [4.776_385, -13.745_145, -65.353_033],
+                    [-5.435_456, 4.645_853, 26.312_435],
+                ],
+                t,
+            ),
+            Colormap::Magma => polynomial(
+                &[
+                    [-0.002_136, -0.000_750, -0.005_386],
+                    [0.251_661, 0.677_523, 2.494_027],
+                    [8.353_717, -3.577_720, 0.314_468],
+                    [-27.668_733, 14.264_731, -13.649_213],
+                    [52.176_140, -27.943_606, 12.944_169],
+                    [-50.768_525, 29.046_583, 4.234_153],
+                    [18.655_705, -11.489_774, -5.601_962],
+                ],
+                t,
+            ),
+            Colormap::Cubehelix => {
+                // Green (2011) with a start of 0.5, -1.5 rotations and a hue of 1
+                let angle = 2.0 * std::f64::consts::PI * (0.5 / 3.0 + 1.0 - 1.5 * t);
+                let amp = t * (1.0 - t) / 2.0;
+                let (s, c) = angle.sin_cos();
+                [
+                    t + amp * (-0.148_61 * c + 1.782_77 * s),
+                    t + amp * (-0.292_27 * c - 0.906_49 * s),
+                    t + amp * (1.972_94 * c),
+                ]
+            }
+            Colormap::Planck => {
+                // Blue to red colormap of the Planck maps
+                const COLORS: [[f64; 3]; 7] = [
+                    [0.0, 0.0, 255.0],
+                    [0.0, 112.0, 255.0],
+                    [0.0, 221.0, 255.0],
+                    [255.0, 237.0, 217.0],
+                    [255.0, 180.0, 0.0],
+                    [255.0, 75.0, 0.0],
+                    [100.0, 0.0, 0.0],
+                ];
+                let x = t * (COLORS.len() - 1) as f64;
+                let k = (x as usize).min(COLORS.len() - 2);
+                let f = x - k as f64;
+                let (a, b) = (COLORS[k], COLORS[k + 1]);
+                [0, 1, 2].map(|c| (a[c] + (b[c] - a[c]) * f) / 255.0)
+            }
+        };
+        rgb.map(|c| c.clamp(0.0, 1.0))
+    }
+
+    /// Colors sampled regularly over `[0, 1]`, in linear RGB for the sRGB render targets
+    pub fn lut(self) -> [[f32; 4]; LUT_SIZE] {
+        let mut lut = [[0.0; 4]; LUT_SIZE];
+        for (i, color) in lut.iter_mut().enumerate() {
+            let [r, g, b] = self.color(i as f64 / (LUT_SIZE - 1) as f64);
+            *color = [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), 1.0];
+        }
+        lut
+    }
+}
+
+fn polynomial(coeffs: &[[f64; 3]], t: f64) -> [f64; 3] {
+    [0, 1, 2].map(|c| coeffs.iter().rev().fold(0.0, |acc, coeff| acc * t + coeff[c]))
+}
+
+fn srgb_to_linear(c: f64) -> f32 {
+    let c = if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    };
+    c as f32
+}
+
+/// Transfer function applied to the values normalized by the cuts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stretch {
+    Linear,
+    Log,
+    Sqrt,
+    Asinh,
+    /// Histogram equalization
+    Histogram,
+}
+
+impl Stretch {
+    pub const ALL: [Stretch; 5] = [
+        Stretch::Linear,
+        Stretch::Log,
+        Stretch::Sqrt,
+        Stretch::Asinh,
+        Stretch::Histogram,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stretch::Linear => "linear",
+            Stretch::Log => "log",
+            Stretch::Sqrt => "sqrt",
+            Stretch::Asinh => "asinh",
+            Stretch::Histogram => "histogram equalization",
+        }
+    }
+
+    /// Stretch following this one, cycling back to the first
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&s| s == self).unwrap_or(0);
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+
+    /// Index of the stretch in the shaders
+    pub fn index(self) -> usize {
+        Self::ALL.iter().position(|&s| s == self).unwrap()
+    }
+
+    /// Same as the shaders, mapping `[0, 1]` onto itself
+    ///
+    /// # Arguments
+    ///
+    /// * `cdf` - Cumulative histogram of the normalized values, for the equalization
+    pub fn apply(self, t: f64, cdf: &[f32; CDF_SIZE]) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Stretch::Linear => t,
+            Stretch::Log => (1000.0 * t).ln_1p() / 1000_f64.ln_1p(),
+            Stretch::Sqrt => t.sqrt(),
+            Stretch::Asinh => (10.0 * t).asinh() / 10_f64.asinh(),
+            Stretch::Histogram => {
+                // The bin k ends at (k + 1) / CDF_SIZE
+                let x = t * CDF_SIZE as f64 - 1.0;
+                if x < 0.0 {
+                    cdf[0] as f64 * t * CDF_SIZE as f64
+                } else {
+                    let k = (x as usize).min(CDF_SIZE - 2);
+                    let f = (x - k as f64).min(1.0);
+                    cdf[k] as f64 + (cdf[k + 1] - cdf[k]) as f64 * f
+                }
+            }
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ColormapUniform {
+    // x: value at the bottom of the colormap, y: value at its top,
+    // z: index of the stretch, w: 1 if the scalar values are drawn
+    params: [f32; 4],
+    lut: [[f32; 4]; LUT_SIZE],
+    cdf: [[f32; 4]; CDF_SIZE / 4],
+}
+
+impl ColormapUniform {
+    /// Uniform of the layers without scalar values
+    pub fn disabled() -> Self {
+        bytemuck::Zeroable::zeroed()
+    }
+}
+
+/// How the scalar values of a layer are turned into colors
+#[derive(Clone, Debug)]
+pub struct ColorScale {
+    pub colormap: Colormap,
+    pub stretch: Stretch,
+    pub auto_cuts: AutoCuts,
+    cuts: (f32, f32),
+    cdf: [f32; CDF_SIZE],
+}
+
+impl ColorScale {
+    /// Linear viridis scale between the extreme values
+    pub fn new(values: &[f32]) -> Self {
+        let (min, max) = values
+            .iter()
+            .filter(|v| v.is_finite())
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &v| (min.min(v), max.max(v)));
+        let cuts = if min <= max { (min, max) } else { (0.0, 1.0) };
+        let mut scale = Self {
+            colormap: Colormap::Viridis,
+            stretch: Stretch::Linear,
+            auto_cuts: AutoCuts::Manual,
+            cuts,
+            cdf: [0.0; CDF_SIZE],
+        };
+        scale.set_cuts(cuts, values);
+        scale
+    }
+
+    /// Values drawn at the bottom and at the top of the colormap
+    pub fn cuts(&self) -> (f32, f32) {
+        self.cuts
+    }
+
+    /// Cumulative histogram of the values between the cuts, normalized by them
+    pub fn cdf(&self) -> &[f32; CDF_SIZE] {
+        &self.cdf
+    }
+
+    /// Change the cuts, updating the histogram of the values between them
+    pub fn set_cuts(&mut self, cuts: (f32, f32), values: &[f32]) {
+        self.cuts = cuts;
+
+        let (low, high) = (cuts.0.min(cuts.1), cuts.0.max(cuts.1));
+        let mut counts = [0_u64; CDF_SIZE];
+        let step = values.len().div_ceil(MAX_HISTOGRAM_SAMPLES).max(1);
+        for &v in values.iter().step_by(step).filter(|&&v| v >= low && v <= high) {
+            if let Some(t) = self.normalize(v) {
+                counts[((t * CDF_SIZE as f64) as usize).min(CDF_SIZE - 1)] += 1;
+            }
+        }
+        let total = counts.iter().sum::<u64>().max(1) as f64;
+        let mut sum = 0;
+        for (cdf, count) in self.cdf.iter_mut().zip(counts) {
+            sum += count;
+            *cdf = (sum as f64 / total) as f32;
+        }
+    }
+
+    /// Value normalized by the cuts in `[0, 1]`, None for the blank ones
+    fn normalize(&self, value: f32) -> Option<f64> {
+        if !value.is_finite() {
+            return None;
+        }
+        let (min, max) = (self.cuts.0 as f64, self.cuts.1 as f64);
+        let range = if (max - min).abs() < 1e-30 { 1e-30 } else { max - min };
+        Some(((value as f64 - min) / range).clamp(0.0, 1.0))
+    }
+
+    /// Value normalized by the cuts then stretched, None for the blank ones
+    pub fn stretched(&self, value: f32) -> Option<f64> {
+        Some(self.stretch.apply(self.normalize(value)?, &self.cdf))
+    }
+
+    /// sRGB color of a value, as drawn by the shaders, None for the blank ones
+    pub fn color(&self, value: f32) -> Option<[u8; 3]> {
+        let t = self.stretched(value)?;
+        Some(self.colormap.color(t).map(|c| (c * 255.0).round() as u8))
+    }
+
+    pub fn uniform(&self) -> ColormapUniform {
+        let mut cdf = [[0.0; 4]; CDF_SIZE / 4];
+        for (k, &v) in self.cdf.iter().enumerate() {
+            cdf[k / 4][k % 4] = v;
+        }
+        ColormapUniform {
+            params: [self.cuts.0, self.cuts.1, self.stretch.index() as f32, 1.0],
+            lut: self.colormap.lut(),
+            cdf,
+        }
+    }
+}
+
+mod tests {
+    #[test]
+    fn colormap() {
+        use super::*;
+
+        // The colormaps go from dark to light, except the diverging one
+        let luminance = |[r, g, b]: [f64; 3]| 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        for colormap in Colormap::ALL.iter().filter(|&&c| c != Colormap::Planck) {
+            assert!(luminance(colormap.color(0.0)) < 0.1, "{}", colormap.name());
+            assert!(luminance(colormap.color(1.0)) > 0.8, "{}", colormap.name());
+        }
+        assert_eq!(Colormap::Planck.next(), Colormap::Grayscale);
+
+        let values: Vec<f32> = (0..1000).map(|v| (v as f32 / 100.0).powi(2)).collect();
+        let mut scale = ColorScale::new(&values);
+        let max = values[999];
+        assert_eq!(scale.cuts(), (0.0, max));
+        assert_eq!(scale.color(f32::NAN), None);
+        for stretch in Stretch::ALL {
+            scale.stretch = stretch;
+            let bottom = scale.colormap.color(0.0).map(|c| (c * 255.0).round() as u8);
+            assert_eq!(scale.color(-1.0), Some(bottom));
+            assert_eq!(scale.color(0.0), Some(bottom));
+            assert_eq!(scale.color(200.0), scale.color(max));
+        }
+
+        // The equalization spreads the values evenly: the quarter of them lies below 2.5^2
+        let cdf = &scale.cdf;
+        let t = Stretch::Histogram.apply(6.25 / max as f64, cdf);
+        assert!((t - 0.25).abs() < 0.01, "{}", t);
+        assert!(Stretch::Histogram.apply(1.0, cdf) > 0.999);
+        for stretch in Stretch::ALL {
+            assert!(stretch.apply(0.0, cdf).abs() < 1e-9 && (stretch.apply(1.0, cdf) - 1.0).abs() < 1e-3);
+        }
+
+        // Narrower cuts update the histogram
+        scale.set_cuts((0.0, 25.0), &values);
+        assert!((Stretch::Histogram.apply(0.25, &scale.cdf) - 0.5).abs() < 0.01);
+    }
+}
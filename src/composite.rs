@@ -0,0 +1,430 @@
+use crate::colormap::CDF_SIZE;
+use crate::error::Error;
+use crate::glsl;
+use crate::healpix_map::HealpixMap;
+use crate::layers::BlendMode;
+use crate::math::Vec3;
+use crate::texture::Texture;
+use crate::vertex::Vertex;
+
+use std::path::Path;
+
+/// How the values of the three bands are turned into a color
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompositeStretch {
+    /// Each band through its own cuts and stretch
+    Independent,
+    /// Asinh stretch of the mean of the bands normalized by their cuts, scaling the three
+    /// bands alike so that the colors are preserved (Lupton et al., 2004)
+    ///
+    /// `q` softens the stretch, which tends to a linear one when it goes to 0.
+    Lupton { q: f32 },
+}
+
+impl CompositeStretch {
+    pub const ALL: [CompositeStretch; 2] = [CompositeStretch::Independent, CompositeStretch::Lupton { q: 8.0 }];
+
+    pub fn name(self) -> String {
+        match self {
+            CompositeStretch::Independent => "independent stretches".to_string(),
+            CompositeStretch::Lupton { q } => format!("Lupton asinh Q={}", q),
+        }
+    }
+
+    /// Stretch following this one, cycling back to the first
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&s| s == self).unwrap_or(0);
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+}
+
+/// Lupton et al. (2004) color preserving asinh stretch
+///
+/// Returns the color, saturated by scaling the three components alike.
+///
+/// # Arguments
+///
+/// * `t` - The red, green and blue values normalized by their cuts, non negative
+/// * `q` - Softening parameter, strictly positive
+pub fn lupton(t: [f64; 3], q: f64) -> [f64; 3] {
+    let intensity = (t[0] + t[1] + t[2]) / 3.0;
+    if intensity <= 0.0 {
+        return [0.0; 3];
+    }
+    // A mean intensity of 1 is mapped to 1
+    let f = (q * intensity).asinh() / q.asinh() / intensity;
+    let rgb = t.map(|c| c * f);
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    if max > 1.0 {
+        rgb.map(|c| c / max)
+    } else {
+        rgb
+    }
+}
+
+/// False color composite of three full sky HEALPix maps, e.g. three bands of a survey
+pub struct CompositeLayer {
+    pub name: String,
+    /// The red, green and blue bands, each with its own cuts, stretch and automatic cuts
+    pub bands: [HealpixMap; 3],
+    pub stretch: CompositeStretch,
+    pub opacity: f32,
+    pub visible: bool,
+    pub blend: BlendMode,
+}
+
+impl CompositeLayer {
+    pub fn new(bands: [HealpixMap; 3]) -> Self {
+        let name = format!("{} / {} / {}", bands[0].name, bands[1].name, bands[2].name);
+        Self {
+            name,
+            bands,
+            stretch: CompositeStretch::Independent,
+            opacity: 1.0,
+            visible: true,
+            blend: BlendMode::Alpha,
+        }
+    }
+
+    /// Read the red, green and blue bands from HEALPix FITS maps
+    pub fn from_files<P: AsRef<Path>>(paths: &[P; 3]) -> Result<Self, Error> {
+        let [red, green, blue] = paths;
+        Ok(Self::new([
+            HealpixMap::from_file(red)?,
+            HealpixMap::from_file(green)?,
+            HealpixMap::from_file(blue)?,
+        ]))
+    }
+
+    /// sRGB color of the red, green and blue values, as drawn by the shader
+    ///
+    /// The blank values count as the bottom of their band, None if the three are blank.
+    pub fn color(&self, values: [f32; 3]) -> Option<[f64; 3]> {
+        if values.iter().all(|v| !v.is_finite()) {
+            return None;
+        }
+        let color = match self.stretch {
+            CompositeStretch::Independent => {
+                let [r, g, b] = &self.bands;
+                [
+                    r.scale.stretched(values[0]).unwrap_or(0.0),
+                    g.scale.stretched(values[1]).unwrap_or(0.0),
+                    b.scale.stretched(values[2]).unwrap_or(0.0),
+                ]
+            }
+            CompositeStretch::Lupton { q } => {
+                let mut t = [0.0; 3];
+                for ((t, band), &v) in t.iter_mut().zip(&self.bands).zip(&values) {
+                    let (min, max) = band.scale.cuts();
+                    let range = if (max - min).abs() < 1e-30 { 1e-30 } else { max as f64 - min as f64 };
+                    if v.is_finite() {
+                        *t = ((v as f64 - min as f64) / range).max(0.0);
+                    }
+                }
+                lupton(t, q as f64)
+            }
+        };
+        Some(color)
+    }
+
+    /// Values of the bands in the cell containing a position given in the ICRS frame
+    pub fn values(&self, pos_icrs: &Vec3<f64>) -> [f32; 3] {
+        let [r, g, b] = &self.bands;
+        [r.value(pos_icrs), g.value(pos_icrs), b.value(pos_icrs)]
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CompositeUniform {
+    // Per band, x: value at the bottom, y: value at the top, z: index of the stretch
+    bands: [[f32; 4]; 3],
+    // x: 1 for the Lupton stretch, y: its softening parameter, z: opacity
+    params: [f32; 4],
+    cdf: [[[f32; 4]; CDF_SIZE / 4]; 3],
+}
+
+impl CompositeUniform {
+    fn new(layer: &CompositeLayer) -> Self {
+        let mut bands = [[0.0; 4]; 3];
+        let mut cdf = [[[0.0; 4]; CDF_SIZE / 4]; 3];
+        for (k, band) in layer.bands.iter().enumerate() {
+            let (min, max) = band.scale.cuts();
+            bands[k] = [min, max, band.scale.stretch.index() as f32, 0.0];
+            for (i, &v) in band.scale.cdf().iter().enumerate() {
+                cdf[k][i / 4][i % 4] = v;
+            }
+        }
+        let (lupton, q) = match layer.stretch {
+            CompositeStretch::Independent => (0.0, 0.0),
+            CompositeStretch::Lupton { q } => (1.0, q),
+        };
+
+        Self {
+            bands,
+            params: [lupton, q, layer.opacity, 0.0],
+            cdf,
+        }
+    }
+}
+
+struct GpuComposite {
+    // Keeps the tiles alive while the bind group uses them
+    _texture: Texture,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    visible: bool,
+    blend: BlendMode,
+}
+
+/// Draws the composites over the sky mesh, combining their bands in the fragment shader
+pub struct CompositeRenderer {
+    // One pipeline per blend mode
+    pipelines: Vec<wgpu::RenderPipeline>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    composites: Vec<GpuComposite>,
+}
+
+impl CompositeRenderer {
+    /// # Arguments
+    ///
+    /// * `sky_bind_group_layout` - Layout of the bind group of the sky, whose view uniforms are reused
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, sky_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<CompositeUniform>() as wgpu::BufferAddress,
+                        ),
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("composites_bind_group_layout"),
+        });
+
+        let vs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("allsky vert shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: include_str!("shaders/allsky.vert").into(),
+                stage: naga::ShaderStage::Vertex,
+                defines: Default::default(),
+            },
+        });
+        let fs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("composite frag shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: glsl::with_shared(include_str!("shaders/composite.frag"), &[glsl::COMMON, glsl::COLORMAP]).into(),
+                stage: naga::ShaderStage::Fragment,
+                defines: Default::default(),
+            },
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Composites Pipeline Layout"),
+            bind_group_layouts: &[sky_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |mode: &BlendMode| device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Composites Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vs_shader,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                buffers: &[Vertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_shader,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(mode.blend_state()),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
+        let pipelines = BlendMode::ALL.iter().map(create_pipeline).collect();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            pipelines,
+            bind_group_layout,
+            sampler,
+            composites: vec![],
+        }
+    }
+
+    /// Upload the bands of the layers as the tiles of their base cells, the 12 tiles of the
+    /// red band being followed by the green then the blue ones
+    ///
+    /// This must be called whenever layers are added or removed, not at every frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_tile_size` - Width of the tiles of the deepest bands
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, layers: &[CompositeLayer], max_tile_size: u32) {
+        self.composites = layers
+            .iter()
+            .map(|layer| {
+                // The shallower bands are repeated to the size of the deepest one
+                let depth = layer.bands.iter().map(|band| band.depth()).max().unwrap_or(0);
+                let size = max_tile_size.min(1 << depth);
+                let values: Vec<f32> = layer.bands.iter().flat_map(|band| band.tiles(size).concat()).collect();
+                let texture = Texture::from_scalar_values(device, queue, Some(&values), (size, size, 36), &layer.name);
+
+                let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("composite uniform"),
+                    size: std::mem::size_of::<CompositeUniform>() as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                queue.write_buffer(&uniform_buffer, 0, bytemuck::bytes_of(&CompositeUniform::new(layer)));
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&texture.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: uniform_buffer.as_entire_binding(),
+                        },
+                    ],
+                    label: Some("composites_bind_group"),
+                });
+
+                GpuComposite {
+                    _texture: texture,
+                    uniform_buffer,
+                    bind_group,
+                    visible: layer.visible,
+                    blend: layer.blend,
+                }
+            })
+            .collect();
+    }
+
+    /// Update the cuts, stretches, opacity, visibility and blend mode of the layers for the next frame
+    pub fn prepare(&mut self, queue: &wgpu::Queue, layers: &[CompositeLayer]) {
+        for (gpu, layer) in self.composites.iter_mut().zip(layers) {
+            queue.write_buffer(&gpu.uniform_buffer, 0, bytemuck::bytes_of(&CompositeUniform::new(layer)));
+            gpu.visible = layer.visible;
+            gpu.blend = layer.blend;
+        }
+    }
+
+    /// Draw a composite over the sky mesh
+    ///
+    /// The sky bind group, vertex and index buffers must be set in the render pass.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - Index of the layer in the list given to `upload`
+    pub fn draw_layer<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, idx: usize, num_indices: u32) {
+        if let Some(composite) = self.composites.get(idx).filter(|composite| composite.visible) {
+            render_pass.set_pipeline(&self.pipelines[composite.blend.index()]);
+            render_pass.set_bind_group(1, &composite.bind_group, &[]);
+            render_pass.draw_indexed(0..num_indices, 0, 0..1);
+        }
+    }
+}
+
+mod tests {
+    #[test]
+    fn composite() {
+        use super::*;
+
+        // The mean intensity is stretched, the ratios between the bands are kept
+        let rgb = lupton([0.1, 0.05, 0.0], 8.0);
+        let f = (8.0_f64 * 0.05).asinh() / 8.0_f64.asinh() / 0.05;
+        assert!((rgb[0] - 0.1 * f).abs() < 1e-12 && (rgb[1] - 0.05 * f).abs() < 1e-12 && rgb[2] == 0.0);
+        assert!(rgb[0] > 0.1);
+        // Saturated colors keep their hue
+        let rgb = lupton([4.0, 2.0, 1.0], 8.0);
+        assert_eq!(rgb[0], 1.0);
+        assert!((rgb[1] - 0.5).abs() < 1e-12 && (rgb[2] - 0.25).abs() < 1e-12);
+        assert_eq!(lupton([0.0; 3], 8.0), [0.0; 3]);
+
+        // Bands of different orders, null in the base cell 0 and the blue one being blank around the south pole
+        let band = |name: &str, depth: u8, value: f32| {
+            let mut values = vec![value; crate::healpix::n_cells(depth) as usize];
+            let n = values.len() / 12;
+            for v in &mut values[..n] {
+                *v = 0.0;
+            }
+            HealpixMap::new(name.to_string(), depth, values).unwrap()
+        };
+        let mut blue = band("b", 0, 2.0);
+        let mut values = blue.values().to_vec();
+        for v in &mut values[8..] {
+            *v = f32::NAN;
+        }
+        blue = HealpixMap::new(blue.name.clone(), 0, values).unwrap();
+        let mut layer = CompositeLayer::new([band("r", 1, 4.0), band("g", 0, 1.0), blue]);
+        assert_eq!(layer.name, "r / g / b");
+
+        // The north pole falls in the base cell 0, the south one in the cells 8 to 11
+        let north = layer.values(&Vec3::new(0.0, 1.0, 0.0));
+        let south = layer.values(&Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(south[..2], [4.0, 1.0]);
+        assert!(south[2].is_nan());
+        assert_eq!(layer.color(south), Some([1.0, 1.0, 0.0]));
+        assert_eq!(layer.color([f32::NAN; 3]), None);
+
+        layer.stretch = layer.stretch.next();
+        assert_eq!(layer.stretch, CompositeStretch::Lupton { q: 8.0 });
+        assert_eq!(layer.color(north), Some([0.0; 3]));
+        let [r, g, b] = layer.color([2.0, 0.25, 1.0]).unwrap();
+        assert!((r - 2.0 * g).abs() < 1e-9 && (r - b).abs() < 1e-9);
+        assert!(r > 0.5);
+    }
+}
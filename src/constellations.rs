@@ -0,0 +1,274 @@
+use crate::angle::{Angle, ArcDeg};
+use crate::error::Error;
+use crate::frame::Epoch;
+use crate::math::{self, Mat3, Vec2, Vec3};
+use crate::overlay::View;
+use crate::projection::Projection;
+use crate::stars::StarCatalog;
+
+use cgmath::{InnerSpace, Matrix};
+
+// Angular step in degrees used to sample the lines before projecting them
+const SAMPLE_STEP_DEG: f64 = 2.0;
+
+/// IAU abbreviations and names of the 88 constellations
+pub const NAMES: [(&str, &str); 88] = [
+    ("And", "Andromeda"), ("Ant", "Antlia"), ("Aps", "Apus"), ("Aqr", "Aquarius"),
+    ("Aql", "Aquila"), ("Ara", "Ara"), ("Ari", "Aries"), ("Aur", "Auriga"),
+    ("Boo", "Bootes"), ("Cae", "Caelum"), ("Cam", "Camelopardalis"), ("Cnc", "Cancer"),
+    ("CVn", "Canes Venatici"), ("CMa", "Canis Major"), ("CMi", "Canis Minor"), ("Cap", "Capricornus"),
+    ("Car", "Carina"), ("Cas", "Cassiopeia"), ("Cen", "Centaurus"), ("Cep", "Cepheus"),
+    ("Cet", "Cetus"), ("Cha", "Chamaeleon"), ("Cir", "Circinus"), ("Col", "Columba"),
+    ("Com", "Coma Berenices"), ("CrA", "Corona Australis"), ("CrB", "Corona Borealis"), ("Crv", "Corvus"),
+    ("Crt", "Crater"), ("Cru", "Crux"), ("Cyg", "Cygnus"), ("Del", "Delphinus"),
+    ("Dor", "Dorado"), ("Dra", "Draco"), ("Equ", "Equuleus"), ("Eri", "Eridanus"),
+    ("For", "Fornax"), ("Gem", "Gemini"), ("Gru", "Grus"), ("Her", "Hercules"),
+    ("Hor", "Horologium"), ("Hya", "Hydra"), ("Hyi", "Hydrus"), ("Ind", "Indus"),
+    ("Lac", "Lacerta"), ("Leo", "Leo"), ("LMi", "Leo Minor"), ("Lep", "Lepus"),
+    ("Lib", "Libra"), ("Lup", "Lupus"), ("Lyn", "Lynx"), ("Lyr", "Lyra"),
+    ("Men", "Mensa"), ("Mic", "Microscopium"), ("Mon", "Monoceros"), ("Mus", "Musca"),
+    ("Nor", "Norma"), ("Oct", "Octans"), ("Oph", "Ophiuchus"), ("Ori", "Orion"),
+    ("Pav", "Pavo"), ("Peg", "Pegasus"), ("Per", "Perseus"), ("Phe", "Phoenix"),
+    ("Pic", "Pictor"), ("Psc", "Pisces"), ("PsA", "Piscis Austrinus"), ("Pup", "Puppis"),
+    ("Pyx", "Pyxis"), ("Ret", "Reticulum"), ("Sge", "Sagitta"), ("Sgr", "Sagittarius"),
+    ("Sco", "Scorpius"), ("Scl", "Sculptor"), ("Sct", "Scutum"), ("Ser", "Serpens"),
+    ("Sex", "Sextans"), ("Tau", "Taurus"), ("Tel", "Telescopium"), ("Tri", "Triangulum"),
+    ("TrA", "Triangulum Australe"), ("Tuc", "Tucana"), ("UMa", "Ursa Major"), ("UMi", "Ursa Minor"),
+    ("Vel", "Vela"), ("Vir", "Virgo"), ("Vol", "Volans"), ("Vul", "Vulpecula"),
+];
+
+/// Name of a constellation from its IAU abbreviation, whatever its case
+pub fn name(abbr: &str) -> Option<&'static str> {
+    NAMES
+        .iter()
+        .find(|(a, _)| a.eq_ignore_ascii_case(abbr))
+        .map(|&(_, name)| name)
+}
+
+/// Stick figure of a constellation, as pairs of star catalog numbers
+#[derive(Clone, Debug, PartialEq)]
+pub struct Figure {
+    pub abbr: String,
+    pub pairs: Vec<(u64, u64)>,
+}
+
+/// Boundary of a constellation
+#[derive(Clone, Debug, PartialEq)]
+pub struct Boundary {
+    pub abbr: String,
+    /// Vertices of the polygon as B1875 right ascensions and declinations in degrees
+    pub vertices: Vec<(f64, f64)>,
+}
+
+/// Constellation figures, boundaries and names
+#[derive(Default)]
+pub struct Constellations {
+    pub figures: Vec<Figure>,
+    pub boundaries: Vec<Boundary>,
+    // Segments of the figures in the ICRS frame, once their stars are known
+    segments: Vec<(String, Vec3<f64>, Vec3<f64>)>,
+}
+
+impl Constellations {
+    /// Read the stick figures
+    ///
+    /// Each line gives the abbreviation of a constellation followed by pairs of
+    /// star catalog numbers. The pairs may be preceded by their count, as in the
+    /// constellationship.fab files of Stellarium.
+    pub fn parse_figures(text: &str) -> Result<Vec<Figure>, Error> {
+        let mut figures: Vec<Figure> = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = || Error::Parse(format!("line {}: invalid figure", i + 1));
+
+            let mut tokens = line.split_whitespace();
+            let abbr = tokens.next().ok_or_else(err)?.to_string();
+            let mut ids = tokens
+                .map(|token| token.parse::<u64>().map_err(|_| err()))
+                .collect::<Result<Vec<_>, _>>()?;
+            if ids.len() % 2 == 1 {
+                if ids[0] as usize != ids.len() / 2 {
+                    return Err(err());
+                }
+                ids.remove(0);
+            }
+            let pairs = ids.chunks(2).map(|pair| (pair[0], pair[1]));
+
+            // A constellation may span several lines
+            match figures.iter_mut().find(|figure| figure.abbr == abbr) {
+                Some(figure) => figure.pairs.extend(pairs),
+                None => figures.push(Figure {
+                    abbr,
+                    pairs: pairs.collect(),
+                }),
+            }
+        }
+        Ok(figures)
+    }
+
+    /// Read the boundaries
+    ///
+    /// Each line gives a vertex as its B1875 right ascension in hours, its
+    /// declination in degrees and the abbreviation of its constellation, the
+    /// consecutive vertices of a constellation making its polygon (e.g. the
+    /// bound_18.dat file of the VizieR VI/49 catalog).
+    pub fn parse_boundaries(text: &str) -> Result<Vec<Boundary>, Error> {
+        let mut boundaries: Vec<Boundary> = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = || Error::Parse(format!("line {}: invalid boundary vertex", i + 1));
+
+            let mut tokens = line.split_whitespace();
+            let ra_hours: f64 = tokens.next().and_then(|t| t.parse().ok()).ok_or_else(err)?;
+            let dec: f64 = tokens.next().and_then(|t| t.parse().ok()).ok_or_else(err)?;
+            let abbr = tokens.next().ok_or_else(err)?;
+            let vertex = (15.0 * ra_hours, dec);
+
+            match boundaries.last_mut() {
+                Some(boundary) if boundary.abbr.eq_ignore_ascii_case(abbr) => boundary.vertices.push(vertex),
+                _ => boundaries.push(Boundary {
+                    abbr: abbr.to_string(),
+                    vertices: vec![vertex],
+                }),
+            }
+        }
+        Ok(boundaries)
+    }
+
+    /// Find the stars of the figures in a star catalog
+    ///
+    /// The pairs whose stars are not in the catalog are not drawn.
+    pub fn resolve_figures(&mut self, stars: &StarCatalog) {
+        let positions = stars.positions_by_id();
+        self.segments = self
+            .figures
+            .iter()
+            .flat_map(|figure| {
+                let positions = &positions;
+                figure.pairs.iter().filter_map(move |(a, b)| {
+                    Some((figure.abbr.clone(), *positions.get(a)?, *positions.get(b)?))
+                })
+            })
+            .collect();
+    }
+
+    /// Project the figures into clip space polylines
+    pub fn figure_lines<P: Projection<f64>>(&self, view: &View) -> Vec<Vec<Vec2<f64>>> {
+        self.segments
+            .iter()
+            .flat_map(|(_, a, b)| {
+                let angle = a.angle(*b).0;
+                let num_samples = (angle.to_degrees() / SAMPLE_STEP_DEG).ceil().max(1.0) as usize;
+                // Great circle arc between the two stars
+                view.trace::<P>(|t| slerp(a, b, angle, t), num_samples, false)
+            })
+            .collect()
+    }
+
+    /// Project the boundaries into clip space polylines
+    pub fn boundary_lines<P: Projection<f64>>(&self, view: &View) -> Vec<Vec<Vec2<f64>>> {
+        let to_icrs = b1875_to_icrs();
+        let pos = |ra: f64, dec: f64| to_icrs * math::radec_to_xyz(Angle::new(ArcDeg(ra)), Angle::new(ArcDeg(dec)));
+
+        self.boundaries
+            .iter()
+            .flat_map(|boundary| {
+                let vertices = &boundary.vertices;
+                (0..vertices.len()).map(move |i| (vertices[i], vertices[(i + 1) % vertices.len()]))
+            })
+            .flat_map(|((ra0, dec0), (ra1, dec1))| {
+                // The edges follow the B1875 meridians and parallels
+                let d_ra = (ra1 - ra0 + 180.0).rem_euclid(360.0) - 180.0;
+                let extent = d_ra.abs().max((dec1 - dec0).abs());
+                let num_samples = (extent / SAMPLE_STEP_DEG).ceil().max(1.0) as usize;
+                view.trace::<P>(|t| pos(ra0 + d_ra * t, dec0 + (dec1 - dec0) * t), num_samples, false)
+            })
+            .collect()
+    }
+
+    /// Names of the constellations and the ICRS positions where they are written
+    ///
+    /// A name is placed at the center of the stars of the figure, or at the
+    /// center of the boundary without figure.
+    pub fn labels(&self) -> Vec<(&'static str, Vec3<f64>)> {
+        let to_icrs = b1875_to_icrs();
+        NAMES
+            .iter()
+            .filter_map(|&(abbr, name)| {
+                let mut sum = Vec3::new(0.0, 0.0, 0.0);
+                for (_, a, b) in self.segments.iter().filter(|(s, _, _)| s.eq_ignore_ascii_case(abbr)) {
+                    sum += a + b;
+                }
+                if sum == Vec3::new(0.0, 0.0, 0.0) {
+                    for boundary in self.boundaries.iter().filter(|b| b.abbr.eq_ignore_ascii_case(abbr)) {
+                        for &(ra, dec) in &boundary.vertices {
+                            sum += to_icrs * math::radec_to_xyz(Angle::new(ArcDeg(ra)), Angle::new(ArcDeg(dec)));
+                        }
+                    }
+                }
+                (sum.magnitude2() > 0.0).then(|| (name, sum.normalize()))
+            })
+            .collect()
+    }
+}
+
+// Point of the great circle arc from a to b
+fn slerp(a: &Vec3<f64>, b: &Vec3<f64>, angle: f64, t: f64) -> Vec3<f64> {
+    if angle < 1e-9 {
+        return *a;
+    }
+    (a * ((1.0 - t) * angle).sin() + b * (t * angle).sin()) / angle.sin()
+}
+
+/// Rotation from the B1875 mean equator and equinox, in which the boundaries are defined, to the ICRS
+pub fn b1875_to_icrs() -> Mat3<f64> {
+    Epoch::from_besselian_year(1875.0).precession_matrix().transpose()
+}
+
+mod tests {
+    #[test]
+    fn constellations() {
+        use super::*;
+        use crate::stars::Star;
+
+        let figures = Constellations::parse_figures("# Orion belt\nOri 2 26727 26311 26311 25930\nOri 24436 27366\n").unwrap();
+        assert_eq!(figures.len(), 1);
+        assert_eq!(figures[0].pairs, vec![(26727, 26311), (26311, 25930), (24436, 27366)]);
+        assert!(Constellations::parse_figures("Ori 3 1 2").is_err());
+
+        let boundaries = Constellations::parse_boundaries(" 5.0 10.0 ORI\n 6.0 10.0 ORI\n 6.0 -10.0 ORI\n 0.0 88.0 UMI\n").unwrap();
+        assert_eq!(boundaries.len(), 2);
+        assert_eq!(boundaries[0].vertices, vec![(75.0, 10.0), (90.0, 10.0), (90.0, -10.0)]);
+
+        let star = |id: u64, ra: f64, dec: f64| Star { id: Some(id), ra, dec, mag: 2.0, b_v: 0.0 };
+        let stars = StarCatalog::new(vec![star(26727, 85.19, -1.94), star(26311, 84.05, -1.20), star(25930, 83.00, -0.30)]);
+        let mut constellations = Constellations {
+            figures,
+            boundaries,
+            ..Default::default()
+        };
+        constellations.resolve_figures(&stars);
+        // The pair with unknown stars is dropped
+        assert_eq!(constellations.segments.len(), 2);
+
+        // Orion is named at the center of its belt, Ursa Minor at its boundary
+        let labels = constellations.labels();
+        assert_eq!(labels.len(), 2);
+        let (ra, dec) = math::xyz_to_radec(&labels[0].1);
+        assert_eq!(labels[0].0, "Orion");
+        assert!((ArcDeg::from(ra).0 - 84.07).abs() < 0.1 && (ArcDeg::from(dec).0 + 1.16).abs() < 0.1);
+        assert_eq!(labels[1].0, "Ursa Minor");
+
+        // The precession over 125 years moves the pole of B1875 by about 0.7 degree
+        let pole = b1875_to_icrs() * Vec3::new(0.0, 1.0, 0.0);
+        let shift = ArcDeg::from(Angle(pole.angle(Vec3::new(0.0, 1.0, 0.0)).0)).0;
+        assert!((shift - 0.696).abs() < 0.01);
+        assert_eq!(name("cma"), Some("Canis Major"));
+    }
+}
@@ -0,0 +1,156 @@
+use crate::angle::{Angle, ArcDeg};
+use crate::frame;
+use crate::healpix;
+use crate::math::{self, Vec3};
+use crate::sky_source;
+
+use std::fmt;
+
+/// Everything known about the sky position under the mouse cursor
+#[derive(Clone, Debug, PartialEq)]
+pub struct CursorInfo {
+    /// ICRS right ascension in degrees
+    pub ra: f64,
+    /// ICRS declination in degrees
+    pub dec: f64,
+    /// Galactic longitude in degrees
+    pub l: f64,
+    /// Galactic latitude in degrees
+    pub b: f64,
+    /// HEALPix order of the tiles
+    pub order: u8,
+    /// Number of the HEALPix cell (i.e. the tile) containing the position
+    pub cell: u64,
+    /// RGBA value of the tile or panorama pixel containing the position
+    pub pixel: Option<[u8; 4]>,
+    /// Value of the HEALPix map at the position
+    pub value: Option<f32>,
+    /// Planetary body the position is on, its RA and Dec being its planetocentric
+    /// longitude and latitude
+    pub body: Option<String>,
+}
+
+impl CursorInfo {
+    /// Describe a position of the sky
+    ///
+    /// # Arguments
+    ///
+    /// * `pos_icrs` - Unit vector in the ICRS frame
+    /// * `order` - HEALPix order of the tiles
+    /// * `tiles` - The tiles of that order, indexed by their cell number
+    pub fn new(pos_icrs: &Vec3<f64>, order: u8, tiles: &[image::RgbaImage]) -> Self {
+        let (ra, dec) = math::xyz_to_radec(pos_icrs);
+        let ra = Angle(ra.to_radians().rem_euclid(2.0 * std::f64::consts::PI));
+        let (l, b) = frame::galactic_coordinates(ra, dec);
+
+        // The repo (x, y, z) frame is the usual (Y, Z, X) one
+        let cell = healpix::hash_with_dxdy(order, pos_icrs.z, pos_icrs.x, pos_icrs.y).0;
+        // Same lookup as the fragment shader
+        let pixel = sky_source::tile_pixel(tiles, order, pos_icrs);
+
+        Self {
+            ra: ArcDeg::from(ra).0,
+            dec: ArcDeg::from(dec).0,
+            l: ArcDeg::from(l).0,
+            b: ArcDeg::from(b).0,
+            order,
+            cell,
+            pixel,
+            value: None,
+            body: None,
+        }
+    }
+
+    /// Same info with the pixel of a panorama drawn at the position instead of the tiles
+    pub fn with_pixel(self, pixel: Option<[u8; 4]>) -> Self {
+        Self { pixel, ..self }
+    }
+
+    /// Same info for a position on a planetary body
+    pub fn with_body(self, body: &str) -> Self {
+        Self {
+            body: Some(body.to_string()),
+            ..self
+        }
+    }
+
+    /// Same info with the value of the HEALPix map drawn at the position
+    pub fn with_value(self, value: f32) -> Self {
+        Self {
+            value: Some(value),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for CursorInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.body {
+            Some(body) => write!(
+                f,
+                "{} lon {:.6}°E lat {:+.6}° | HEALPix order {} cell {}",
+                body, self.ra, self.dec, self.order, self.cell
+            )?,
+            None => write!(
+                f,
+                "RA {} Dec {} ({:.6}, {:+.6}) | l {:.4} b {:+.4} | HEALPix order {} cell {}",
+                format_hms(self.ra),
+                format_dms(self.dec),
+                self.ra,
+                self.dec,
+                self.l,
+                self.b,
+                self.order,
+                self.cell
+            )?,
+        }
+        if let Some([r, g, b, a]) = self.pixel {
+            write!(f, " | RGBA ({}, {}, {}, {})", r, g, b, a)?;
+        }
+        if let Some(value) = self.value {
+            write!(f, " | value {}", value)?;
+        }
+        Ok(())
+    }
+}
+
+// Split a positive value into units, sixtieths and 1/(60*60*10^decimals)
+pub(crate) fn sexagesimal(value: f64, decimals: usize) -> (u64, u64, f64) {
+    let scale = 10_u64.pow(decimals as u32);
+    let total = (value * 3600.0 * scale as f64).round() as u64;
+    let (units, rest) = (total / (3600 * scale), total % (3600 * scale));
+    let (minutes, seconds) = (rest / (60 * scale), rest % (60 * scale));
+
+    (units, minutes, seconds as f64 / scale as f64)
+}
+
+/// Format a right ascension given in degrees as hours, minutes and seconds
+pub fn format_hms(ra: f64) -> String {
+    let (h, m, s) = sexagesimal(ra.rem_euclid(360.0) / 15.0, 2);
+    format!("{:02}h{:02}m{:05.2}s", h % 24, m, s)
+}
+
+/// Format a declination given in degrees as signed degrees, arcminutes and arcseconds
+pub fn format_dms(dec: f64) -> String {
+    let sign = if dec < 0.0 { '-' } else { '+' };
+    let (d, m, s) = sexagesimal(dec.abs(), 1);
+    format!("{}{:02}°{:02}'{:04.1}\"", sign, d, m, s)
+}
+
+mod tests {
+    #[test]
+    fn sexagesimal() {
+        use super::*;
+        assert_eq!(format_hms(0.0), "00h00m00.00s");
+        assert_eq!(format_hms(83.633_08), "05h34m31.94s");
+        // Rounding must carry over to the minutes and hours
+        assert_eq!(format_hms(359.999_999_9), "00h00m00.00s");
+        assert_eq!(format_dms(22.014_47), "+22°00'52.1\"");
+        assert_eq!(format_dms(-0.5), "-00°30'00.0\"");
+        assert_eq!(format_dms(-29.999_999_9), "-30°00'00.0\"");
+
+        // The planetocentric longitudes increase from z to x, as the right ascensions
+        let info = CursorInfo::new(&Vec3::new(1.0, 0.0, 0.0), 0, &[]).with_body("mars");
+        assert!(info.to_string().starts_with("mars lon 90.000000°E lat +0.000000° | HEALPix order 0"));
+    }
+}
@@ -0,0 +1,301 @@
+// Automatic cut levels from the histograms of the values in the HEALPix cells of the sky,
+// merged over the cells in view.
+use std::collections::HashMap;
+
+// HEALPix order of the cells the histograms are gathered in
+const STATS_DEPTH: u8 = 3;
+// The bins hold the same number of values over the whole layer, so that the
+// histograms are the most precise where most of the values are
+const NUM_BINS: usize = 1024;
+// Values of a layer read at most to build its histograms
+const MAX_VALUES: usize = 1 << 22;
+// Number of sorted samples drawn from the histograms for the zscale algorithm
+const ZSCALE_SAMPLES: usize = 1000;
+
+/// How the cuts of a layer are chosen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutoCuts {
+    /// Set by hand
+    Manual,
+    /// Extreme values in view
+    MinMax,
+    /// Percentiles of the values in view
+    Percentile { low: f32, high: f32 },
+    /// IRAF zscale of the values in view
+    ZScale,
+}
+
+impl AutoCuts {
+    pub const ALL: [AutoCuts; 4] = [
+        AutoCuts::Manual,
+        AutoCuts::MinMax,
+        AutoCuts::Percentile { low: 0.5, high: 99.5 },
+        AutoCuts::ZScale,
+    ];
+
+    pub fn name(self) -> String {
+        match self {
+            AutoCuts::Manual => "manual cuts".to_string(),
+            AutoCuts::MinMax => "min/max".to_string(),
+            AutoCuts::Percentile { low, high } => format!("{}/{} percentiles", low, high),
+            AutoCuts::ZScale => "zscale".to_string(),
+        }
+    }
+
+    /// Mode following this one, cycling back to the first
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&c| c == self).unwrap_or(0);
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Clone)]
+struct CellStats {
+    min: f32,
+    max: f32,
+    counts: Vec<u32>,
+}
+
+/// Histograms of the values of a layer in the HEALPix cells of the sky
+#[derive(Clone)]
+pub struct SkyStats {
+    depth: u8,
+    // NUM_BINS + 1 increasing bin edges
+    edges: Vec<f32>,
+    cells: HashMap<u64, CellStats>,
+}
+
+impl SkyStats {
+    /// HEALPix order of the cells given to `cuts`
+    pub const DEPTH: u8 = STATS_DEPTH;
+
+    /// # Arguments
+    ///
+    /// * `depth` - HEALPix order of the cells of the values, at most `SkyStats::DEPTH`
+    /// * `values` - The cell containing each value, and the value; iterated twice
+    pub fn new<I>(depth: u8, values: I) -> Self
+    where
+        I: Iterator<Item = (u64, f32)> + Clone,
+    {
+        let finite = values.filter(|(_, v)| v.is_finite());
+
+        // Bin edges from the quantiles of the whole layer
+        let mut sorted: Vec<f32> = finite.clone().map(|(_, v)| v).collect();
+        sorted.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        let edges: Vec<f32> = if sorted.is_empty() {
+            vec![]
+        } else {
+            (0..=NUM_BINS)
+                .map(|k| sorted[(k * (sorted.len() - 1)) / NUM_BINS])
+                .collect()
+        };
+
+        let mut cells: HashMap<u64, CellStats> = HashMap::new();
+        for (cell, v) in finite {
+            let stats = cells.entry(cell).or_insert_with(|| CellStats {
+                min: v,
+                max: v,
+                counts: vec![0; NUM_BINS],
+            });
+            stats.min = stats.min.min(v);
+            stats.max = stats.max.max(v);
+            // Last edge below or at the value
+            let bin = edges.partition_point(|&e| e <= v).clamp(1, NUM_BINS) - 1;
+            stats.counts[bin] += 1;
+        }
+
+        Self {
+            depth: depth.min(STATS_DEPTH),
+            edges,
+            cells,
+        }
+    }
+
+    /// Statistics of the values of a full sky HEALPix map given in the NESTED scheme
+    pub fn from_nested(depth: u8, values: &[f32]) -> Self {
+        let stats_depth = depth.min(STATS_DEPTH);
+        let shift = 2 * (depth - stats_depth);
+        let step = Self::sampling_step(values.len());
+        Self::new(
+            stats_depth,
+            values
+                .iter()
+                .enumerate()
+                .step_by(step)
+                .map(move |(hash, &v)| ((hash as u64) >> shift, v)),
+        )
+    }
+
+    /// Step between the values of a layer to read, given their total number
+    pub fn sampling_step(num_values: usize) -> usize {
+        num_values.div_ceil(MAX_VALUES).max(1)
+    }
+
+    /// Cuts of the values in the given cells, None for the manual mode or if the
+    /// layer has no value there
+    ///
+    /// # Arguments
+    ///
+    /// * `cells` - Cells of the order `SkyStats::DEPTH`
+    pub fn cuts(&self, mode: AutoCuts, cells: &[u64]) -> Option<(f32, f32)> {
+        let shift = 2 * (STATS_DEPTH - self.depth);
+        let mut counts = vec![0_u64; NUM_BINS];
+        let (mut min, mut max) = (f32::INFINITY, f32::NEG_INFINITY);
+        let mut last = None;
+        for &cell in cells {
+            let cell = cell >> shift;
+            // The cells of a lower order come several times in a row
+            if last == Some(cell) {
+                continue;
+            }
+            last = Some(cell);
+            if let Some(stats) = self.cells.get(&cell) {
+                min = min.min(stats.min);
+                max = max.max(stats.max);
+                for (sum, &c) in counts.iter_mut().zip(&stats.counts) {
+                    *sum += c as u64;
+                }
+            }
+        }
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return None;
+        }
+
+        // Value below which lies the fraction q of the values
+        let quantile = |q: f64| {
+            let target = q.clamp(0.0, 1.0) * total as f64;
+            let mut sum = 0.0;
+            for (bin, &c) in counts.iter().enumerate() {
+                if c > 0 && sum + c as f64 >= target {
+                    let f = (target - sum) / c as f64;
+                    let (a, b) = (self.edges[bin] as f64, self.edges[bin + 1] as f64);
+                    return ((a + (b - a) * f) as f32).clamp(min, max);
+                }
+                sum += c as f64;
+            }
+            max
+        };
+
+        match mode {
+            AutoCuts::Manual => None,
+            AutoCuts::MinMax => Some((min, max)),
+            AutoCuts::Percentile { low, high } => Some((
+                quantile(low as f64 / 100.0),
+                quantile(high as f64 / 100.0),
+            )),
+            AutoCuts::ZScale => {
+                let samples: Vec<f32> = (0..ZSCALE_SAMPLES)
+                    .map(|k| quantile((k as f64 + 0.5) / ZSCALE_SAMPLES as f64))
+                    .collect();
+                Some(zscale(&samples))
+            }
+        }
+    }
+}
+
+/// IRAF zscale cuts of sorted samples, with a contrast of 0.25
+pub fn zscale(samples: &[f32]) -> (f32, f32) {
+    const CONTRAST: f64 = 0.25;
+    const MAX_REJECT: f64 = 0.5;
+    const MIN_PIXELS: usize = 5;
+    const KREJ: f64 = 2.5;
+    const MAX_ITERATIONS: usize = 5;
+
+    let npix = samples.len();
+    if npix == 0 {
+        return (0.0, 1.0);
+    }
+    let (vmin, vmax) = (samples[0] as f64, samples[npix - 1] as f64);
+    let min_pixels = MIN_PIXELS.max((npix as f64 * MAX_REJECT) as usize);
+    let ngrow = 1.max((npix as f64 * 0.01) as usize);
+
+    // Fit a line to the samples against their rank, rejecting the outliers
+    let mut bad = vec![false; npix];
+    let mut num_good = npix;
+    let mut last_num_good = npix + 1;
+    let mut fit = None;
+    for _ in 0..MAX_ITERATIONS {
+        if num_good >= last_num_good || num_good < min_pixels {
+            break;
+        }
+        let good = || (0..npix).filter(|&i| !bad[i]);
+        let n = num_good as f64;
+        let (sx, sy) = good().fold((0.0, 0.0), |(sx, sy), i| (sx + i as f64, sy + samples[i] as f64));
+        let (mx, my) = (sx / n, sy / n);
+        let (sxy, sxx) = good().fold((0.0, 0.0), |(sxy, sxx), i| {
+            let dx = i as f64 - mx;
+            (sxy + dx * (samples[i] as f64 - my), sxx + dx * dx)
+        });
+        let slope = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+        let intercept = my - slope * mx;
+        fit = Some((slope, intercept));
+
+        let flat = |i: usize| samples[i] as f64 - (intercept + slope * i as f64);
+        let var = good().map(|i| flat(i).powi(2)).sum::<f64>() / n;
+        let threshold = KREJ * var.sqrt();
+        let rejected: Vec<bool> = (0..npix).map(|i| bad[i] || flat(i).abs() > threshold).collect();
+        // Grow the rejected regions over their neighbours
+        for (i, bad) in bad.iter_mut().enumerate() {
+            let lo = i.saturating_sub(ngrow / 2);
+            let hi = (i + (ngrow - 1) / 2 + 1).min(npix);
+            *bad = rejected[lo..hi].iter().any(|&r| r);
+        }
+        last_num_good = num_good;
+        num_good = bad.iter().filter(|&&b| !b).count();
+    }
+
+    match fit {
+        Some((slope, _)) if num_good >= min_pixels => {
+            let slope = slope / CONTRAST;
+            let center = (npix - 1) / 2;
+            let median = if npix % 2 == 1 {
+                samples[npix / 2] as f64
+            } else {
+                0.5 * (samples[npix / 2 - 1] as f64 + samples[npix / 2] as f64)
+            };
+            (
+                vmin.max(median - (center as f64 - 1.0) * slope) as f32,
+                vmax.min(median + (npix - center) as f64 * slope) as f32,
+            )
+        }
+        _ => (vmin as f32, vmax as f32),
+    }
+}
+
+mod tests {
+    #[test]
+    fn cuts() {
+        use super::*;
+
+        // A flat background of values in [100, 110[ with a few bright pixels in the cell 1
+        let values: Vec<(u64, f32)> = (0..10_000)
+            .map(|k| {
+                let cell = (k % 2) as u64;
+                let v = if k % 1000 == 501 { 1e5 } else { 100.0 + (k % 1000) as f32 / 100.0 };
+                (cell, v)
+            })
+            .collect();
+        let stats = SkyStats::new(SkyStats::DEPTH, values.iter().copied());
+
+        assert_eq!(stats.cuts(AutoCuts::Manual, &[0, 1]), None);
+        assert_eq!(stats.cuts(AutoCuts::MinMax, &[0]), Some((100.0, 100.0 + 998.0 / 100.0)));
+        assert_eq!(stats.cuts(AutoCuts::MinMax, &[0, 1]), Some((100.0, 1e5)));
+        assert_eq!(stats.cuts(AutoCuts::MinMax, &[2]), None);
+
+        // The outliers are left out of the percentiles and of the zscale cuts
+        let (low, high) = stats.cuts(AutoCuts::ALL[2], &[0, 1]).unwrap();
+        assert!((low - 100.05).abs() < 0.05 && (high - 109.95).abs() < 0.05, "{} {}", low, high);
+        // For a uniform distribution, zscale spans 4 times the range of the values around their median
+        let (low, high) = stats.cuts(AutoCuts::ZScale, &[0, 1]).unwrap();
+        assert!((low - 100.0).abs() < 0.1 && (high - 125.0).abs() < 0.5, "{} {}", low, high);
+
+        // Exact zscale of a straight line: the slope is divided by the contrast
+        let line: Vec<f32> = (0..101).map(|i| i as f32).collect();
+        assert_eq!(zscale(&line), (0.0, 100.0));
+        let line: Vec<f32> = (0..101).map(|i| 1000.0 + 0.01 * i as f32).collect();
+        let (low, high) = zscale(&line);
+        assert!((low - 1000.0).abs() < 1e-3 && (high - 1001.0).abs() < 1e-3);
+        assert_eq!(AutoCuts::ZScale.next(), AutoCuts::Manual);
+    }
+}
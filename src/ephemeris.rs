@@ -0,0 +1,414 @@
+use crate::angle::Angle;
+use crate::frame::{self, Epoch};
+use crate::math::{self, Vec3};
+
+use cgmath::InnerSpace;
+
+// Speed of light in au/day
+const C_AU_PER_DAY: f64 = 173.144_632_7;
+const KM_PER_AU: f64 = 149_597_870.7;
+// Mean obliquity of the ecliptic at J2000.0 in degrees
+const OBLIQUITY_J2000: f64 = 23.439_291_1;
+
+/// Solar-system bodies whose positions are computed by the low-precision ephemeris
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Body {
+    Sun,
+    Moon,
+    Mercury,
+    Venus,
+    Mars,
+    Jupiter,
+    Saturn,
+    Uranus,
+    Neptune,
+}
+
+// Keplerian elements and their rates per Julian century (Standish, 1992).
+// Valid from 1800 AD to 2050 AD, given in the J2000 ecliptic frame:
+// semi-major axis (au), eccentricity, inclination (deg), mean longitude (deg),
+// longitude of the perihelion (deg), longitude of the ascending node (deg)
+struct OrbitalElements {
+    elements: [f64; 6],
+    rates: [f64; 6],
+}
+
+const MERCURY: OrbitalElements = OrbitalElements {
+    elements: [0.387_099_27, 0.205_635_93, 7.004_979_02, 252.250_323_50, 77.457_796_28, 48.330_765_93],
+    rates: [0.000_000_37, 0.000_019_06, -0.005_947_49, 149_472.674_111_75, 0.160_476_89, -0.125_340_81],
+};
+const VENUS: OrbitalElements = OrbitalElements {
+    elements: [0.723_335_66, 0.006_776_72, 3.394_676_05, 181.979_099_50, 131.602_467_18, 76.679_842_55],
+    rates: [0.000_003_90, -0.000_041_07, -0.000_788_90, 58_517.815_387_29, 0.002_683_29, -0.277_694_18],
+};
+const EARTH_MOON_BARYCENTER: OrbitalElements = OrbitalElements {
+    elements: [1.000_002_61, 0.016_711_23, -0.000_015_31, 100.464_571_66, 102.937_681_93, 0.0],
+    rates: [0.000_005_62, -0.000_043_92, -0.012_946_68, 35_999.372_449_81, 0.323_273_64, 0.0],
+};
+const MARS: OrbitalElements = OrbitalElements {
+    elements: [1.523_710_34, 0.093_394_10, 1.849_691_42, -4.553_432_05, -23.943_629_59, 49.559_538_91],
+    rates: [0.000_018_47, 0.000_078_82, -0.008_131_31, 19_140.302_684_99, 0.444_410_88, -0.292_573_43],
+};
+const JUPITER: OrbitalElements = OrbitalElements {
+    elements: [5.202_887_00, 0.048_386_24, 1.304_396_95, 34.396_440_51, 14.728_479_83, 100.473_909_09],
+    rates: [-0.000_116_07, -0.000_132_53, -0.001_837_14, 3_034.746_127_75, 0.212_526_68, 0.204_691_06],
+};
+const SATURN: OrbitalElements = OrbitalElements {
+    elements: [9.536_675_94, 0.053_861_79, 2.485_991_87, 49.954_244_23, 92.598_878_31, 113.662_424_48],
+    rates: [-0.001_250_60, -0.000_509_91, 0.001_936_09, 1_222.493_622_01, -0.418_972_16, -0.288_677_94],
+};
+const URANUS: OrbitalElements = OrbitalElements {
+    elements: [19.189_164_64, 0.047_257_44, 0.772_637_83, 313.238_104_51, 170.954_276_30, 74.016_925_03],
+    rates: [-0.001_961_76, -0.000_043_97, -0.002_429_39, 428.482_027_85, 0.408_052_81, 0.042_405_89],
+};
+const NEPTUNE: OrbitalElements = OrbitalElements {
+    elements: [30.069_922_76, 0.008_590_48, 1.770_043_47, -55.120_029_69, 44.964_762_27, 131.784_225_74],
+    rates: [0.000_262_91, 0.000_051_05, 0.000_353_72, 218.459_453_25, -0.322_414_64, -0.005_086_64],
+};
+
+impl OrbitalElements {
+    /// Heliocentric position in au, in the J2000 ecliptic frame
+    fn heliocentric_position(&self, t: f64) -> Vec3<f64> {
+        let el: Vec<f64> = self
+            .elements
+            .iter()
+            .zip(self.rates.iter())
+            .map(|(e, r)| e + r * t)
+            .collect();
+        let (a, e) = (el[0], el[1]);
+        let (i, l, varpi, node) = (
+            el[2].to_radians(),
+            el[3].to_radians(),
+            el[4].to_radians(),
+            el[5].to_radians(),
+        );
+
+        let omega = varpi - node;
+        let m = (l - varpi).rem_euclid(2.0 * std::f64::consts::PI);
+
+        // Solve the Kepler equation
+        let mut ecc_anomaly = m + e * m.sin();
+        for _ in 0..10 {
+            let delta = (ecc_anomaly - e * ecc_anomaly.sin() - m) / (1.0 - e * ecc_anomaly.cos());
+            ecc_anomaly -= delta;
+            if delta.abs() < 1e-12 {
+                break;
+            }
+        }
+
+        // Position in the orbital plane
+        let x = a * (ecc_anomaly.cos() - e);
+        let y = a * (1.0 - e * e).sqrt() * ecc_anomaly.sin();
+
+        let (s_o, c_o) = omega.sin_cos();
+        let (s_n, c_n) = node.sin_cos();
+        let (s_i, c_i) = i.sin_cos();
+        Vec3::new(
+            (c_o * c_n - s_o * s_n * c_i) * x + (-s_o * c_n - c_o * s_n * c_i) * y,
+            (c_o * s_n + s_o * c_n * c_i) * x + (-s_o * s_n + c_o * c_n * c_i) * y,
+            (s_o * s_i) * x + (c_o * s_i) * y,
+        )
+    }
+}
+
+// Periodic terms of the lunar longitude and distance (Meeus, Astronomical Algorithms, table 47.A)
+// truncated to the largest ones: multiples of D, M, M', F, then the sine coefficient
+// of the longitude (1e-6 deg) and the cosine coefficient of the distance (1e-3 km)
+const MOON_LR: [(i8, i8, i8, i8, f64, f64); 25] = [
+    (0, 0, 1, 0, 6_288_774.0, -20_905_355.0),
+    (2, 0, -1, 0, 1_274_027.0, -3_699_111.0),
+    (2, 0, 0, 0, 658_314.0, -2_955_968.0),
+    (0, 0, 2, 0, 213_618.0, -569_925.0),
+    (0, 1, 0, 0, -185_116.0, 48_888.0),
+    (0, 0, 0, 2, -114_332.0, -3_149.0),
+    (2, 0, -2, 0, 58_793.0, 246_158.0),
+    (2, -1, -1, 0, 57_066.0, -152_138.0),
+    (2, 0, 1, 0, 53_322.0, -170_733.0),
+    (2, -1, 0, 0, 45_758.0, -204_586.0),
+    (0, 1, -1, 0, -40_923.0, -129_620.0),
+    (1, 0, 0, 0, -34_720.0, 108_743.0),
+    (0, 1, 1, 0, -30_383.0, 104_755.0),
+    (2, 0, 0, -2, 15_327.0, 10_321.0),
+    (0, 0, 1, 2, -12_528.0, 0.0),
+    (0, 0, 1, -2, 10_980.0, 79_661.0),
+    (4, 0, -1, 0, 10_675.0, -34_782.0),
+    (0, 0, 3, 0, 10_034.0, -23_210.0),
+    (4, 0, -2, 0, 8_548.0, -21_636.0),
+    (2, 1, -1, 0, -7_888.0, 24_208.0),
+    (2, 1, 0, 0, -6_766.0, 30_824.0),
+    (1, 0, -1, 0, -5_163.0, -8_379.0),
+    (1, 1, 0, 0, 4_987.0, -16_675.0),
+    (2, -1, 1, 0, 4_036.0, -12_831.0),
+    (2, 0, 2, 0, 3_994.0, -10_445.0),
+];
+// Periodic terms of the lunar latitude (Meeus, table 47.B), sine coefficient in 1e-6 deg
+const MOON_B: [(i8, i8, i8, i8, f64); 13] = [
+    (0, 0, 0, 1, 5_128_122.0),
+    (0, 0, 1, 1, 280_602.0),
+    (0, 0, 1, -1, 277_693.0),
+    (2, 0, 0, -1, 173_237.0),
+    (2, 0, -1, 1, 55_413.0),
+    (2, 0, -1, -1, 46_271.0),
+    (2, 0, 0, 1, 32_573.0),
+    (0, 0, 2, 1, 17_198.0),
+    (2, 0, 1, -1, 9_266.0),
+    (0, 0, 2, -1, 8_822.0),
+    (2, -1, 0, -1, 8_216.0),
+    (2, 0, -2, -1, 4_324.0),
+    (2, 0, 1, 1, 4_200.0),
+];
+
+/// Geocentric ecliptic longitude, latitude (referred to the mean equinox of date)
+/// and distance (km) of the Moon
+fn moon_ecliptic_of_date(t: f64) -> (f64, f64, f64) {
+    let l = (218.316_447_7 + 481_267.881_234_21 * t).to_radians();
+    let d = (297.850_192_1 + 445_267.111_403_4 * t).to_radians();
+    let m = (357.529_109_2 + 35_999.050_290_9 * t).to_radians();
+    let mp = (134.963_396_4 + 477_198.867_505_5 * t).to_radians();
+    let f = (93.272_095_0 + 483_202.017_523_3 * t).to_radians();
+    // Decreasing eccentricity of the Earth orbit
+    let e = 1.0 - 0.002_516 * t;
+
+    let a1 = (119.75 + 131.849 * t).to_radians();
+    let a2 = (53.09 + 479_264.290 * t).to_radians();
+    let a3 = (313.45 + 481_266.484 * t).to_radians();
+
+    let arg = |cd: i8, cm: i8, cmp: i8, cf: i8| {
+        let arg = (cd as f64) * d + (cm as f64) * m + (cmp as f64) * mp + (cf as f64) * f;
+        let e_factor = e.powi(cm.abs() as i32);
+        (arg, e_factor)
+    };
+
+    let (mut sum_l, mut sum_r) = (0.0, 0.0);
+    for &(cd, cm, cmp, cf, cl, cr) in MOON_LR.iter() {
+        let (arg, e_factor) = arg(cd, cm, cmp, cf);
+        sum_l += cl * e_factor * arg.sin();
+        sum_r += cr * e_factor * arg.cos();
+    }
+    let mut sum_b = 0.0;
+    for &(cd, cm, cmp, cf, cb) in MOON_B.iter() {
+        let (arg, e_factor) = arg(cd, cm, cmp, cf);
+        sum_b += cb * e_factor * arg.sin();
+    }
+
+    sum_l += 3958.0 * a1.sin() + 1962.0 * (l - f).sin() + 318.0 * a2.sin();
+    sum_b += -2235.0 * l.sin()
+        + 382.0 * a3.sin()
+        + 175.0 * (a1 - f).sin()
+        + 175.0 * (a1 + f).sin()
+        + 127.0 * (l - mp).sin()
+        - 115.0 * (l + mp).sin();
+
+    let lon = l.to_degrees() + sum_l * 1e-6;
+    let lat = sum_b * 1e-6;
+    let dist = 385_000.56 + sum_r * 1e-3;
+
+    (lon, lat, dist)
+}
+
+// Accumulated general precession in longitude from J2000 in degrees
+fn general_precession(t: f64) -> f64 {
+    (5_029.096_6 * t + 1.111_13 * t * t) / 3600.0
+}
+
+/// Heliocentric position of the Earth in au, in the J2000 ecliptic frame
+pub(crate) fn earth_heliocentric_position(epoch: &Epoch) -> Vec3<f64> {
+    EARTH_MOON_BARYCENTER.heliocentric_position(epoch.julian_centuries_since_j2000())
+}
+
+fn ecliptic_to_cartesian(lon: f64, lat: f64, dist: f64) -> Vec3<f64> {
+    let (s_lon, c_lon) = lon.to_radians().sin_cos();
+    let (s_lat, c_lat) = lat.to_radians().sin_cos();
+
+    Vec3::new(dist * c_lat * c_lon, dist * c_lat * s_lon, dist * s_lat)
+}
+
+fn cartesian_to_ecliptic(v: &Vec3<f64>) -> (f64, f64, f64) {
+    let dist = v.magnitude();
+    let lon = v.y.atan2(v.x).to_degrees();
+    let lat = (v.z / dist).asin().to_degrees();
+
+    (lon, lat, dist)
+}
+
+// Rotate a cartesian ecliptic vector into (ra, dec) given the obliquity in degrees
+fn ecliptic_to_radec(v: &Vec3<f64>, obliquity: f64) -> (Angle<f64>, Angle<f64>) {
+    let (s_e, c_e) = obliquity.to_radians().sin_cos();
+    let x = v.x;
+    let y = v.y * c_e - v.z * s_e;
+    let z = v.y * s_e + v.z * c_e;
+
+    let ra = y.atan2(x).rem_euclid(2.0 * std::f64::consts::PI);
+    let dec = (z / (x * x + y * y + z * z).sqrt()).asin();
+    (Angle(ra), Angle(dec))
+}
+
+impl Body {
+    pub const ALL: [Body; 9] = [
+        Body::Sun,
+        Body::Moon,
+        Body::Mercury,
+        Body::Venus,
+        Body::Mars,
+        Body::Jupiter,
+        Body::Saturn,
+        Body::Uranus,
+        Body::Neptune,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Body::Sun => "Sun",
+            Body::Moon => "Moon",
+            Body::Mercury => "Mercury",
+            Body::Venus => "Venus",
+            Body::Mars => "Mars",
+            Body::Jupiter => "Jupiter",
+            Body::Saturn => "Saturn",
+            Body::Uranus => "Uranus",
+            Body::Neptune => "Neptune",
+        }
+    }
+
+    fn orbital_elements(&self) -> Option<&'static OrbitalElements> {
+        match self {
+            Body::Mercury => Some(&MERCURY),
+            Body::Venus => Some(&VENUS),
+            Body::Mars => Some(&MARS),
+            Body::Jupiter => Some(&JUPITER),
+            Body::Saturn => Some(&SATURN),
+            Body::Uranus => Some(&URANUS),
+            Body::Neptune => Some(&NEPTUNE),
+            Body::Sun | Body::Moon => None,
+        }
+    }
+
+    /// Geocentric position in au, in the J2000 ecliptic frame,
+    /// corrected from the light-time
+    pub fn geocentric_ecliptic(&self, epoch: &Epoch) -> Vec3<f64> {
+        let t = epoch.julian_centuries_since_j2000();
+        let earth = EARTH_MOON_BARYCENTER.heliocentric_position(t);
+
+        match self {
+            Body::Moon => {
+                let (lon, lat, dist) = moon_ecliptic_of_date(t);
+                ecliptic_to_cartesian(lon - general_precession(t), lat, dist / KM_PER_AU)
+            }
+            // The Sun lies at the origin of the heliocentric frame
+            Body::Sun => -earth,
+            _ => {
+                let elements = self.orbital_elements().unwrap();
+                let mut pos = elements.heliocentric_position(t) - earth;
+                for _ in 0..2 {
+                    let light_time = pos.magnitude() / C_AU_PER_DAY;
+                    pos = elements.heliocentric_position(t - light_time / 36_525.0) - earth;
+                }
+                pos
+            }
+        }
+    }
+
+    /// Geocentric distance in au
+    pub fn distance(&self, epoch: &Epoch) -> f64 {
+        self.geocentric_ecliptic(epoch).magnitude()
+    }
+
+    /// Astrometric right ascension and declination in the J2000 frame
+    pub fn radec(&self, epoch: &Epoch) -> (Angle<f64>, Angle<f64>) {
+        ecliptic_to_radec(&self.geocentric_ecliptic(epoch), OBLIQUITY_J2000)
+    }
+
+    /// Apparent right ascension and declination, referred to the true equator
+    /// and equinox of date
+    pub fn apparent_radec(&self, epoch: &Epoch) -> (Angle<f64>, Angle<f64>) {
+        let (ra, dec) = self.radec(epoch);
+        frame::apparent_place(ra, dec, epoch)
+    }
+
+    /// Unit vector towards the body, in the J2000 equatorial frame
+    pub fn direction(&self, epoch: &Epoch) -> Vec3<f64> {
+        let (ra, dec) = self.radec(epoch);
+        math::radec_to_xyz(ra, dec)
+    }
+}
+
+/// Illumination of the Moon as seen from the Earth
+#[derive(Clone, Copy, Debug)]
+pub struct MoonPhase {
+    /// Angle Sun-Moon-Earth
+    pub phase_angle: Angle<f64>,
+    /// Illuminated fraction of the disk, in [0, 1]
+    pub illuminated_fraction: f64,
+    pub waxing: bool,
+}
+
+impl MoonPhase {
+    pub fn at(epoch: &Epoch) -> Self {
+        let sun = Body::Sun.geocentric_ecliptic(epoch);
+        let moon = Body::Moon.geocentric_ecliptic(epoch);
+
+        // Vectors going from the Moon to the Sun and to the Earth
+        let to_sun = sun - moon;
+        let to_earth = -moon;
+        let phase_angle = Angle(to_sun.angle(to_earth).0);
+        let illuminated_fraction = (1.0 + phase_angle.cos()) * 0.5;
+
+        // The Moon is waxing while its elongation east of the Sun increases
+        let (lon_sun, _, _) = cartesian_to_ecliptic(&sun);
+        let (lon_moon, _, _) = cartesian_to_ecliptic(&moon);
+        let waxing = (lon_moon - lon_sun).rem_euclid(360.0) < 180.0;
+
+        Self {
+            phase_angle,
+            illuminated_fraction,
+            waxing,
+        }
+    }
+
+    /// Unit vector going from the Moon to the Sun, in the J2000 equatorial frame
+    pub fn sun_direction(epoch: &Epoch) -> Vec3<f64> {
+        let to_sun = Body::Sun.geocentric_ecliptic(epoch) - Body::Moon.geocentric_ecliptic(epoch);
+        let (ra, dec) = ecliptic_to_radec(&to_sun, OBLIQUITY_J2000);
+
+        math::radec_to_xyz(ra, dec)
+    }
+}
+
+mod tests {
+    #[test]
+    fn sun() {
+        use super::*;
+        // Meeus, Astronomical Algorithms, example 25.a
+        // 1992 October 13.0 TD, apparent ra = 198.38083 deg, dec = -7.78507 deg
+        let (ra, dec) = Body::Sun.apparent_radec(&Epoch(2_448_908.5));
+        assert!((ra.to_radians().to_degrees() - 198.380_83).abs() < 0.03);
+        assert!((dec.to_radians().to_degrees() - -7.785_07).abs() < 0.03);
+    }
+
+    #[test]
+    fn venus() {
+        use super::*;
+        // Meeus, example 33.a
+        // 1992 December 20.0 TD, apparent ra = 21h04m41.454s, dec = -18°53'16.84"
+        let (ra, dec) = Body::Venus.apparent_radec(&Epoch(2_448_976.5));
+        assert!((ra.to_radians().to_degrees() - 316.172_725).abs() < 0.05);
+        assert!((dec.to_radians().to_degrees() - -18.888_011).abs() < 0.05);
+    }
+
+    #[test]
+    fn moon() {
+        use super::*;
+        // Meeus, example 47.a
+        // 1992 April 12.0 TD, apparent ra = 134.688470 deg, dec = 13.768368 deg, distance = 368409.7 km
+        let epoch = Epoch(2_448_724.5);
+        let (ra, dec) = Body::Moon.apparent_radec(&epoch);
+        assert!((ra.to_radians().to_degrees() - 134.688_470).abs() < 0.05);
+        assert!((dec.to_radians().to_degrees() - 13.768_368).abs() < 0.05);
+        assert!((Body::Moon.distance(&epoch) * KM_PER_AU - 368_409.7).abs() < 200.0);
+
+        // Meeus, example 48.a: the illuminated fraction is 0.6786
+        let phase = MoonPhase::at(&epoch);
+        assert!((phase.illuminated_fraction - 0.6786).abs() < 0.01);
+        assert!(phase.waxing);
+    }
+}
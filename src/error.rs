@@ -0,0 +1,36 @@
+use std::fmt;
+
+/// Error of the reading and the writing of the FITS files, the WCS, the HiPS, the
+/// images of the sky, the catalogs, the regions and the coverage maps
+#[derive(Debug)]
+pub enum Error {
+    MissingColumn(&'static str),
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Parse(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingColumn(name) => write!(f, "no {} column found", name),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Image(e) => write!(f, "{}", e),
+            Error::Parse(msg) => write!(f, "parse error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        Error::Image(e)
+    }
+}
@@ -0,0 +1,463 @@
+// Minimal reader of the FITS files: images, and the binary tables the HEALPix
+// coverage maps are stored in. The rendered views are written as 8-bit images.
+use crate::error::Error;
+
+use image::RgbaImage;
+use std::convert::TryInto;
+
+const BLOCK_SIZE: usize = 2880;
+const CARD_SIZE: usize = 80;
+
+/// Keyword records of a header, in their order of appearance
+#[derive(Clone, Debug, Default)]
+pub struct Header {
+    cards: Vec<(String, String)>,
+}
+
+impl Header {
+    /// Header of keyword records, e.g. the ones written by `card`
+    pub fn from_cards(cards: &[String]) -> Self {
+        let cards = cards
+            .iter()
+            .filter_map(|card| Self::parse_card(card.as_bytes()))
+            .collect();
+        Self { cards }
+    }
+
+    /// Raw value of a keyword, the comment being removed and the strings unquoted
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.cards
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_i64(&self, key: &str) -> Option<i64> {
+        self.get(key)?.parse().ok()
+    }
+
+    /// Value of a keyword as a float, the Fortran exponents (e.g. `1.0D3`) being accepted
+    pub fn get_f64(&self, key: &str) -> Option<f64> {
+        self.get(key)?.replace(['D', 'd'], "E").parse().ok()
+    }
+
+    fn parse_card(card: &[u8]) -> Option<(String, String)> {
+        let card = String::from_utf8_lossy(card);
+        if card.get(8..10) != Some("= ") {
+            return None;
+        }
+        let key = card[..8].trim().to_string();
+        let value = card[10..].trim_start();
+
+        let value = if let Some(quoted) = value.strip_prefix('\'') {
+            // Quotes inside the strings are doubled
+            let mut s = String::new();
+            let mut chars = quoted.chars().peekable();
+            while let Some(c) = chars.next() {
+                if c == '\'' {
+                    if chars.peek() == Some(&'\'') {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                s.push(c);
+            }
+            s.trim_end().to_string()
+        } else {
+            value.split('/').next().unwrap_or("").trim().to_string()
+        };
+        Some((key, value))
+    }
+}
+
+/// Pixel values of an image, NaN standing for the blank ones
+///
+/// The rows are stored from the bottom of the image, as in the file.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+}
+
+/// A header and data unit
+pub struct Hdu<'a> {
+    pub header: Header,
+    pub data: &'a [u8],
+}
+
+impl<'a> Hdu<'a> {
+    /// Whether the unit holds an image of at least two dimensions
+    pub fn is_image(&self) -> bool {
+        let is_image = match self.header.get("XTENSION") {
+            Some(xtension) => xtension == "IMAGE",
+            None => true,
+        };
+        is_image && self.header.get_i64("NAXIS").unwrap_or(0) >= 2 && !self.data.is_empty()
+    }
+
+    /// Physical values of the first plane of the image, scaled by BSCALE and BZERO
+    pub fn image(&self) -> Result<Image, Error> {
+        let err = |msg: String| Error::Parse(format!("FITS: {}", msg));
+        if !self.is_image() {
+            return Err(err("not an image".to_string()));
+        }
+        let width = self.header.get_i64("NAXIS1").unwrap_or(0).max(0) as usize;
+        let height = self.header.get_i64("NAXIS2").unwrap_or(0).max(0) as usize;
+        let bitpix = self.header.get_i64("BITPIX").unwrap_or(0);
+        let bscale = self.header.get_f64("BSCALE").unwrap_or(1.0);
+        let bzero = self.header.get_f64("BZERO").unwrap_or(0.0);
+        let blank = self.header.get_i64("BLANK");
+
+        let size = bitpix.unsigned_abs() as usize / 8;
+        let len = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(size))
+            .ok_or_else(|| err(format!("image of {}x{} pixels too large", width, height)))?;
+        let bytes = self
+            .data
+            .get(..len)
+            .ok_or_else(|| err("truncated image".to_string()))?;
+        let scale = |raw: f64| (bzero + bscale * raw) as f32;
+        let integer = |raw: i64| if blank == Some(raw) { f32::NAN } else { scale(raw as f64) };
+
+        let data = match bitpix {
+            8 => bytes.iter().map(|&v| integer(v as i64)).collect(),
+            16 => bytes
+                .chunks_exact(2)
+                .map(|v| integer(i16::from_be_bytes([v[0], v[1]]) as i64))
+                .collect(),
+            32 => bytes
+                .chunks_exact(4)
+                .map(|v| integer(i32::from_be_bytes([v[0], v[1], v[2], v[3]]) as i64))
+                .collect(),
+            64 => bytes
+                .chunks_exact(8)
+                .map(|v| integer(i64::from_be_bytes(v.try_into().unwrap())))
+                .collect(),
+            -32 => bytes
+                .chunks_exact(4)
+                .map(|v| scale(f32::from_be_bytes([v[0], v[1], v[2], v[3]]) as f64))
+                .collect(),
+            -64 => bytes
+                .chunks_exact(8)
+                .map(|v| scale(f64::from_be_bytes(v.try_into().unwrap())))
+                .collect(),
+            _ => return Err(err(format!("unsupported BITPIX {}", bitpix))),
+        };
+        Ok(Image { width, height, data })
+    }
+
+    // Byte offset in the rows, repeat count and type code of
+    // the first column matching a predicate on its index from 1 and its name
+    fn find_column<F: Fn(i64, &str) -> bool>(&self, is_column: F) -> Result<(usize, usize, char), Error> {
+        let err = |msg: String| Error::Parse(format!("FITS: {}", msg));
+        if self.header.get("XTENSION") != Some("BINTABLE") {
+            return Err(err("not a binary table".to_string()));
+        }
+        let num_fields = self.header.get_i64("TFIELDS").unwrap_or(0);
+
+        let mut offset = 0;
+        for n in 1..=num_fields {
+            let form = self
+                .header
+                .get(&format!("TFORM{}", n))
+                .ok_or_else(|| err(format!("missing TFORM{}", n)))?;
+            let (repeat, code) = parse_tform(form).ok_or_else(|| err(format!("invalid TFORM{} {}", n, form)))?;
+            if is_column(n, self.header.get(&format!("TTYPE{}", n)).unwrap_or("")) {
+                return Ok((offset, repeat, code));
+            }
+            offset += repeat * field_size(code).ok_or_else(|| err(format!("unsupported TFORM{} {}", n, form)))?;
+        }
+        Err(err("missing column".to_string()))
+    }
+
+    // Rows of a binary table
+    fn rows(&self) -> Result<std::iter::Take<std::slice::ChunksExact<'a, u8>>, Error> {
+        let row_size = self.header.get_i64("NAXIS1").unwrap_or(0).max(1) as usize;
+        let num_rows = self.header.get_i64("NAXIS2").unwrap_or(0).max(0) as usize;
+        if row_size * num_rows > self.data.len() {
+            return Err(Error::Parse("FITS: truncated table".to_string()));
+        }
+        Ok(self.data.chunks_exact(row_size).take(num_rows))
+    }
+
+    /// Values of an integer column of a binary table
+    ///
+    /// Only the first element of the vector columns is read.
+    pub fn column_i64(&self, name: &str) -> Result<Vec<i64>, Error> {
+        let (offset, _, code) = self
+            .find_column(|_, ttype| ttype.eq_ignore_ascii_case(name))
+            .map_err(|_| Error::Parse(format!("FITS: missing column {}", name)))?;
+        let rows = self.rows()?;
+        match code {
+            'B' => Ok(rows.map(|row| row[offset] as i64).collect()),
+            'I' => Ok(rows.map(|row| i16::from_be_bytes([row[offset], row[offset + 1]]) as i64).collect()),
+            'J' => Ok(rows
+                .map(|row| i32::from_be_bytes([row[offset], row[offset + 1], row[offset + 2], row[offset + 3]]) as i64)
+                .collect()),
+            'K' => Ok(rows
+                .map(|row| i64::from_be_bytes(row[offset..(offset + 8)].try_into().unwrap()))
+                .collect()),
+            _ => Err(Error::Parse(format!("FITS: column {} is not an integer one", name))),
+        }
+    }
+
+    /// All the elements of a numeric column of a binary table, given by its index from 1,
+    /// scaled by TSCALn and TZEROn
+    ///
+    /// The elements of the vector columns follow each other, and the null integers are NaN.
+    pub fn column_f32(&self, n: i64) -> Result<Vec<f32>, Error> {
+        let (offset, repeat, code) = self
+            .find_column(|k, _| k == n)
+            .map_err(|_| Error::Parse(format!("FITS: missing column {}", n)))?;
+        let size = field_size(code).unwrap_or(1);
+        let tscal = self.header.get_f64(&format!("TSCAL{}", n)).unwrap_or(1.0);
+        let tzero = self.header.get_f64(&format!("TZERO{}", n)).unwrap_or(0.0);
+        let tnull = self.header.get_i64(&format!("TNULL{}", n));
+        let scale = |raw: f64| (tzero + tscal * raw) as f32;
+        let integer = |raw: i64| if tnull == Some(raw) { f32::NAN } else { scale(raw as f64) };
+
+        let value: fn(&[u8]) -> f64 = match code {
+            'B' => |v| v[0] as f64,
+            'I' => |v| i16::from_be_bytes([v[0], v[1]]) as f64,
+            'J' => |v| i32::from_be_bytes([v[0], v[1], v[2], v[3]]) as f64,
+            'K' => |v| i64::from_be_bytes(v.try_into().unwrap()) as f64,
+            'E' => |v| f32::from_be_bytes([v[0], v[1], v[2], v[3]]) as f64,
+            'D' => |v| f64::from_be_bytes(v.try_into().unwrap()),
+            _ => return Err(Error::Parse(format!("FITS: column {} is not a numeric one", n))),
+        };
+        let is_integer = !matches!(code, 'E' | 'D');
+
+        let mut values = vec![];
+        for row in self.rows()? {
+            for v in row[offset..(offset + repeat * size)].chunks_exact(size) {
+                let v = value(v);
+                values.push(if is_integer { integer(v as i64) } else { scale(v) });
+            }
+        }
+        Ok(values)
+    }
+}
+
+// Repeat count and type code of a binary table column
+fn parse_tform(form: &str) -> Option<(usize, char)> {
+    let digits = form.find(|c: char| !c.is_ascii_digit())?;
+    let repeat = if digits == 0 { 1 } else { form[..digits].parse().ok()? };
+    Some((repeat, form[digits..].chars().next()?))
+}
+
+// Size in bytes of an element of a binary table column, the bits of 'X' being rounded per byte
+fn field_size(code: char) -> Option<usize> {
+    match code {
+        'L' | 'X' | 'B' | 'A' => Some(1),
+        'I' => Some(2),
+        'J' | 'E' => Some(4),
+        'K' | 'D' | 'C' | 'P' => Some(8),
+        'M' | 'Q' => Some(16),
+        _ => None,
+    }
+}
+
+/// First unit of a file holding an image, usually the primary one
+pub fn find_image<'a, 'b>(hdus: &'b [Hdu<'a>]) -> Option<&'b Hdu<'a>> {
+    hdus.iter().find(|hdu| hdu.is_image())
+}
+
+/// Split a FITS file into its header and data units
+pub fn parse(bytes: &[u8]) -> Result<Vec<Hdu<'_>>, Error> {
+    let err = |msg: &str| Error::Parse(format!("FITS: {}", msg));
+    if !bytes.starts_with(b"SIMPLE  =") {
+        return Err(err("missing SIMPLE keyword"));
+    }
+
+    let mut hdus = vec![];
+    let mut pos = 0;
+    while pos + BLOCK_SIZE <= bytes.len() {
+        let mut header = Header::default();
+        let mut end = false;
+        while !end {
+            let block = bytes.get(pos..(pos + BLOCK_SIZE)).ok_or_else(|| err("truncated header"))?;
+            for card in block.chunks_exact(CARD_SIZE) {
+                if card.starts_with(b"END ") {
+                    end = true;
+                    break;
+                }
+                header.cards.extend(Header::parse_card(card));
+            }
+            pos += BLOCK_SIZE;
+        }
+
+        let naxis = header.get_i64("NAXIS").unwrap_or(0);
+        let bitpix = header.get_i64("BITPIX").unwrap_or(8).unsigned_abs() as usize;
+        let num_values = if naxis == 0 {
+            Some(0)
+        } else {
+            (1..=naxis)
+                .map(|n| header.get_i64(&format!("NAXIS{}", n)).unwrap_or(0).max(0) as usize)
+                .try_fold(1usize, usize::checked_mul)
+        };
+        let pcount = header.get_i64("PCOUNT").unwrap_or(0).max(0) as usize;
+        let gcount = header.get_i64("GCOUNT").unwrap_or(1).max(1) as usize;
+        let size = num_values
+            .and_then(|n| n.checked_add(pcount))
+            .and_then(|n| n.checked_mul(gcount))
+            .and_then(|n| n.checked_mul(bitpix / 8))
+            .ok_or_else(|| err("data too large"))?;
+
+        let data = pos
+            .checked_add(size)
+            .and_then(|end| bytes.get(pos..end))
+            .ok_or_else(|| err("truncated data"))?;
+        hdus.push(Hdu { header, data });
+        pos += size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    }
+    Ok(hdus)
+}
+
+/// Keyword record of a header, the strings being given with their quotes
+pub fn card(key: &str, value: &str) -> String {
+    let card = if value.starts_with('\'') {
+        format!("{:<8}= {}", key, value)
+    } else {
+        // The other values end in the column 30
+        format!("{:<8}= {:>20}", key, value)
+    };
+    format!("{:<80.80}", card)
+}
+
+/// FITS file holding an image of 8-bit planes, e.g. its red, green and blue ones
+///
+/// # Arguments
+///
+/// * `width` - Width of the image in pixels
+/// * `height` - Height of the image in pixels
+/// * `planes` - The pixels of each plane, the rows being stored from the bottom of the image
+/// * `cards` - Records written after the mandatory keywords, e.g. the WCS ones
+pub fn write_u8_image(width: usize, height: usize, planes: &[Vec<u8>], cards: &[String]) -> Vec<u8> {
+    let mut header = vec![
+        card("SIMPLE", "T"),
+        card("BITPIX", "8"),
+        card("NAXIS", if planes.len() > 1 { "3" } else { "2" }),
+        card("NAXIS1", &width.to_string()),
+        card("NAXIS2", &height.to_string()),
+    ];
+    if planes.len() > 1 {
+        header.push(card("NAXIS3", &planes.len().to_string()));
+    }
+    header.extend(cards.iter().cloned());
+    header.push(format!("{:<80}", "END"));
+
+    let mut bytes = header.concat().into_bytes();
+    bytes.resize(bytes.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, b' ');
+    for plane in planes {
+        bytes.extend_from_slice(&plane[..(width * height)]);
+    }
+    bytes.resize(bytes.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+    bytes
+}
+
+/// FITS file holding a binary table of a single 64-bit integer column, after an
+/// empty primary header
+///
+/// # Arguments
+///
+/// * `column` - Name of the column
+/// * `values` - Values of the rows
+/// * `cards` - Records written after the mandatory keywords of the table
+pub fn write_i64_table(column: &str, values: &[i64], cards: &[String]) -> Vec<u8> {
+    let mut header = vec![
+        card("SIMPLE", "T"),
+        card("BITPIX", "8"),
+        card("NAXIS", "0"),
+        card("EXTEND", "T"),
+        format!("{:<80}", "END"),
+    ];
+    let mut bytes = header.concat().into_bytes();
+    bytes.resize(bytes.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, b' ');
+
+    header = vec![
+        card("XTENSION", "'BINTABLE'"),
+        card("BITPIX", "8"),
+        card("NAXIS", "2"),
+        card("NAXIS1", "8"),
+        card("NAXIS2", &values.len().to_string()),
+        card("PCOUNT", "0"),
+        card("GCOUNT", "1"),
+        card("TFIELDS", "1"),
+        card("TTYPE1", &format!("'{}'", column)),
+        card("TFORM1", "'1K'"),
+    ];
+    header.extend(cards.iter().cloned());
+    header.push(format!("{:<80}", "END"));
+    bytes.extend_from_slice(header.concat().as_bytes());
+    bytes.resize(bytes.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, b' ');
+    for value in values {
+        bytes.extend_from_slice(&value.to_be_bytes());
+    }
+    bytes.resize(bytes.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+    bytes
+}
+
+/// FITS file holding the red, green and blue planes of an image, the rows going down
+pub fn write_rgb_image(image: &RgbaImage, cards: &[String]) -> Vec<u8> {
+    let (w, h) = image.dimensions();
+    // The rows of the FITS images go up
+    let planes: Vec<Vec<u8>> = (0..3)
+        .map(|c| {
+            image
+                .rows()
+                .rev()
+                .flat_map(|row| row.map(|pixel| pixel.0[c]))
+                .collect()
+        })
+        .collect();
+    write_u8_image(w as usize, h as usize, &planes, cards)
+}
+
+mod tests {
+    #[test]
+    fn write_u8_image() {
+        use super::*;
+
+        let (width, height) = (3, 2);
+        let planes: Vec<Vec<u8>> = (0..3).map(|k| (0..6).map(|v| 10 * k + v).collect()).collect();
+        let cards = [card("CTYPE1", "'RA---SIN'"), card("CRVAL1", &format!("{:.15e}", 83.6))];
+        let bytes = write_u8_image(width, height, &planes, &cards);
+        assert_eq!(bytes.len(), 2 * BLOCK_SIZE);
+        assert_eq!(&bytes[..30], format!("{:<8}= {:>20}", "SIMPLE", "T").as_bytes());
+
+        let hdus = parse(&bytes).unwrap();
+        assert_eq!(hdus.len(), 1);
+        let header = &hdus[0].header;
+        assert_eq!(header.get_i64("NAXIS3"), Some(3));
+        assert_eq!(header.get("CTYPE1"), Some("RA---SIN"));
+        assert_eq!(header.get_f64("CRVAL1"), Some(83.6));
+        // The first plane is read
+        let image = find_image(&hdus).unwrap().image().unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.data, vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+
+        // The rows of the images are flipped, and the records read back
+        let rgb = RgbaImage::from_fn(3, 2, |x, y| image::Rgba([(10 * y + x) as u8, 0, 0, 255]));
+        let bytes = write_rgb_image(&rgb, &[]);
+        let image = find_image(&parse(&bytes).unwrap()).unwrap().image().unwrap();
+        assert_eq!(image.data, vec![10.0, 11.0, 12.0, 0.0, 1.0, 2.0]);
+        assert_eq!(Header::from_cards(&cards).get("CTYPE1"), Some("RA---SIN"));
+
+        // Sizes overflowing the address space are rejected
+        let header = [
+            card("SIMPLE", "T"),
+            card("BITPIX", "8"),
+            card("NAXIS", "3"),
+            card("NAXIS1", "4294967296"),
+            card("NAXIS2", "4294967296"),
+            card("NAXIS3", "4294967296"),
+            format!("{:<80}", "END"),
+        ]
+        .concat();
+        let bytes = format!("{:<2880}", header).into_bytes();
+        assert!(matches!(parse(&bytes), Err(Error::Parse(_))));
+    }
+}
@@ -0,0 +1,632 @@
+use crate::angle::{Angle, ArcDeg, ArcMin, ArcSec};
+use crate::ephemeris;
+use crate::math::{self, Mat3, Vec3};
+
+use cgmath::{InnerSpace, Matrix};
+
+// Julian date of the J2000.0 epoch
+const JD_J2000: f64 = 2_451_545.0;
+// Julian date of the unix epoch (1970-01-01T00:00:00Z)
+const JD_UNIX_EPOCH: f64 = 2_440_587.5;
+const DAYS_PER_JULIAN_YEAR: f64 = 365.25;
+// Julian date of the B1900.0 epoch and length of the tropical year used by the Besselian epochs
+const JD_B1900: f64 = 2_415_020.313_52;
+const DAYS_PER_TROPICAL_YEAR: f64 = 365.242_198_781;
+const DAYS_PER_JULIAN_CENTURY: f64 = 36_525.0;
+// Speed of light in au/day
+const C_AU_PER_DAY: f64 = 173.144_632_7;
+
+/// An instant given as a Julian date
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Epoch(pub f64);
+
+impl Epoch {
+    pub const J2000: Epoch = Epoch(JD_J2000);
+
+    pub fn from_unix_secs(secs: f64) -> Self {
+        Epoch(JD_UNIX_EPOCH + secs / 86_400.0)
+    }
+
+    pub fn now() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let secs = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0);
+            Self::from_unix_secs(secs)
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            Self::from_unix_secs(js_sys::Date::now() / 1000.0)
+        }
+    }
+
+    /// Epoch of a Julian date, or of a Julian year prefixed by J, e.g. J2025.5
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        match text.strip_prefix('J') {
+            Some(year) => year.parse().ok().map(Self::from_julian_year),
+            None => text.parse().ok().map(Epoch),
+        }
+        .filter(|epoch| epoch.0.is_finite())
+    }
+
+    pub fn from_julian_year(year: f64) -> Self {
+        Epoch(JD_J2000 + (year - 2000.0) * DAYS_PER_JULIAN_YEAR)
+    }
+
+    pub fn from_besselian_year(year: f64) -> Self {
+        Epoch(JD_B1900 + (year - 1900.0) * DAYS_PER_TROPICAL_YEAR)
+    }
+
+    pub fn julian_year(&self) -> f64 {
+        2000.0 + self.days_since_j2000() / DAYS_PER_JULIAN_YEAR
+    }
+
+    pub fn days_since_j2000(&self) -> f64 {
+        self.0 - JD_J2000
+    }
+
+    pub fn julian_centuries_since_j2000(&self) -> f64 {
+        self.days_since_j2000() / DAYS_PER_JULIAN_CENTURY
+    }
+
+    /// Greenwich mean sidereal time (IAU 1982 expression)
+    pub fn gmst(&self) -> Angle<f64> {
+        let d = self.days_since_j2000();
+        let t = self.julian_centuries_since_j2000();
+
+        let deg = 280.460_618_37 + 360.985_647_366_29 * d + 0.000_387_933 * t * t
+            - t * t * t / 38_710_000.0;
+        Angle::new(ArcDeg(deg.rem_euclid(360.0)))
+    }
+
+    /// Greenwich apparent sidereal time, i.e. corrected by the equation of the equinoxes
+    pub fn gast(&self) -> Angle<f64> {
+        let (dpsi, _) = self.nutation();
+        let gast = self.gmst() + dpsi * self.mean_obliquity().cos();
+
+        Angle(gast.to_radians().rem_euclid(2.0 * std::f64::consts::PI))
+    }
+
+    /// Mean obliquity of the ecliptic (IAU 2006)
+    pub fn mean_obliquity(&self) -> Angle<f64> {
+        let t = self.julian_centuries_since_j2000();
+        let eps = 84_381.406
+            + t * (-46.836_769
+                + t * (-0.000_183_1 + t * (0.002_003_40 + t * (-0.000_000_576 - t * 0.000_000_043_4))));
+
+        Angle::new(ArcSec(eps))
+    }
+
+    /// Nutation in longitude and in obliquity (IAU 2000B truncated to its largest terms)
+    pub fn nutation(&self) -> (Angle<f64>, Angle<f64>) {
+        let t = self.julian_centuries_since_j2000();
+        let arcsec = |a: f64| ArcSec(a.rem_euclid(1_296_000.0));
+
+        // Fundamental arguments (Simon et al., 1994)
+        let l: Angle<f64> = arcsec(485_868.249_036 + 1_717_915_923.217_8 * t).into();
+        let lp: Angle<f64> = arcsec(1_287_104.793_05 + 129_596_581.048_1 * t).into();
+        let f: Angle<f64> = arcsec(335_779.526_232 + 1_739_527_262.847_8 * t).into();
+        let d: Angle<f64> = arcsec(1_072_260.703_69 + 1_602_961_601.209_0 * t).into();
+        let om: Angle<f64> = arcsec(450_160.398_036 - 6_962_890.543_1 * t).into();
+
+        let (mut dpsi, mut deps) = (0.0, 0.0);
+        for &(nl, nlp, nf, nd, nom, ps, pst, pc, ec, ect, es) in NUTATION_2000B.iter() {
+            let arg = (l * nl + lp * nlp + f * nf + d * nd + om * nom).to_radians();
+            let (s_arg, c_arg) = arg.sin_cos();
+
+            dpsi += (ps + pst * t) * s_arg + pc * c_arg;
+            deps += (ec + ect * t) * c_arg + es * s_arg;
+        }
+
+        // Coefficients are given in 0.1 microarcsec, then add the
+        // fixed offsets standing for the planetary nutation
+        let dpsi = dpsi * 1e-7 - 0.000_135;
+        let deps = deps * 1e-7 + 0.000_388;
+
+        (Angle::new(ArcSec(dpsi)), Angle::new(ArcSec(deps)))
+    }
+
+    /// Precession matrix from the J2000 mean equator and equinox to the mean ones of date (IAU 2006)
+    pub fn precession_matrix(&self) -> Mat3<f64> {
+        let t = self.julian_centuries_since_j2000();
+        let zeta = 2.650_545
+            + t * (2_306.083_227
+                + t * (0.298_849_9 + t * (0.018_018_28 + t * (-0.000_005_971 - t * 0.000_000_317_3))));
+        let z = -2.650_545
+            + t * (2_306.077_181
+                + t * (1.092_734_8 + t * (0.018_268_37 + t * (-0.000_028_596 - t * 0.000_000_290_4))));
+        let theta = t
+            * (2_004.191_903
+                + t * (-0.429_493_4 + t * (-0.041_822_64 + t * (-0.000_007_089 - t * 0.000_000_127_4))));
+
+        let zeta = Angle::new(ArcSec(zeta));
+        let z = Angle::new(ArcSec(z));
+        let theta = Angle::new(ArcSec(theta));
+
+        to_repo_frame(&(rot_z(-z) * rot_y(theta) * rot_z(-zeta)))
+    }
+
+    /// Nutation matrix from the mean equator and equinox of date to the true ones
+    pub fn nutation_matrix(&self) -> Mat3<f64> {
+        let eps = self.mean_obliquity();
+        let (dpsi, deps) = self.nutation();
+
+        to_repo_frame(&(rot_x(-(eps + deps)) * rot_z(-dpsi) * rot_x(eps)))
+    }
+
+    /// Matrix from the J2000 frame to the true equator and equinox of date
+    pub fn precession_nutation_matrix(&self) -> Mat3<f64> {
+        self.nutation_matrix() * self.precession_matrix()
+    }
+
+    /// Velocity of the Earth in the J2000 equatorial frame, in units of the speed of light
+    pub fn earth_velocity(&self) -> Vec3<f64> {
+        // Central difference over one hour
+        let dt = 1.0 / 24.0;
+        let before = ephemeris::earth_heliocentric_position(&Epoch(self.0 - dt));
+        let after = ephemeris::earth_heliocentric_position(&Epoch(self.0 + dt));
+        let v = (after - before) / (2.0 * dt * C_AU_PER_DAY);
+
+        to_repo_frame_vec(&(rot_x(-Angle::new(ArcSec(84_381.406))) * v))
+    }
+}
+
+// Nutation series IAU 2000B truncated to the terms greater than 5 mas.
+// Multiples of l, l', F, D, Om then the longitude (sin, t.sin, cos) and
+// the obliquity (cos, t.cos, sin) coefficients in 0.1 microarcsec
+#[allow(clippy::type_complexity)]
+const NUTATION_2000B: [(f64, f64, f64, f64, f64, f64, f64, f64, f64, f64, f64); 15] = [
+    (0.0, 0.0, 0.0, 0.0, 1.0, -172_064_161.0, -174_666.0, 33_386.0, 92_052_331.0, 9_086.0, 15_377.0),
+    (0.0, 0.0, 2.0, -2.0, 2.0, -13_170_906.0, -1_675.0, -13_696.0, 5_730_336.0, -3_015.0, -4_587.0),
+    (0.0, 0.0, 2.0, 0.0, 2.0, -2_276_413.0, -234.0, 2_796.0, 978_459.0, -485.0, 1_374.0),
+    (0.0, 0.0, 0.0, 0.0, 2.0, 2_074_554.0, 207.0, -698.0, -897_492.0, 470.0, -291.0),
+    (0.0, 1.0, 0.0, 0.0, 0.0, 1_475_877.0, -3_633.0, 11_817.0, 73_871.0, -184.0, -1_924.0),
+    (0.0, 1.0, 2.0, -2.0, 2.0, -516_821.0, 1_226.0, -524.0, 224_386.0, -677.0, -174.0),
+    (1.0, 0.0, 0.0, 0.0, 0.0, 711_159.0, 73.0, -872.0, -6_750.0, 0.0, 358.0),
+    (0.0, 0.0, 2.0, 0.0, 1.0, -387_298.0, -367.0, 380.0, 200_728.0, 18.0, 318.0),
+    (1.0, 0.0, 2.0, 0.0, 2.0, -301_461.0, -36.0, 816.0, 129_025.0, -63.0, 367.0),
+    (0.0, -1.0, 2.0, -2.0, 2.0, 215_829.0, -494.0, 111.0, -95_929.0, 299.0, 132.0),
+    (0.0, 0.0, 2.0, -2.0, 1.0, 128_227.0, 137.0, 181.0, -68_982.0, -9.0, 39.0),
+    (-1.0, 0.0, 2.0, 0.0, 2.0, 123_457.0, 11.0, 19.0, -53_311.0, 32.0, -4.0),
+    (-1.0, 0.0, 0.0, 2.0, 0.0, 156_994.0, 10.0, -168.0, -1_235.0, 0.0, 82.0),
+    (1.0, 0.0, 0.0, 0.0, 1.0, 63_110.0, 63.0, 27.0, -33_228.0, 0.0, -9.0),
+    (-1.0, 0.0, 0.0, 0.0, 1.0, -57_976.0, -63.0, -189.0, 31_429.0, 0.0, -75.0),
+];
+
+// Rotations about the axes of the usual (X, Y, Z) equatorial frame where X points
+// towards the equinox and Z towards the north pole.
+// A positive angle rotates the frame anticlockwise when seen from the tip of the axis.
+fn rot_x(a: Angle<f64>) -> Mat3<f64> {
+    let (s, c) = a.to_radians().sin_cos();
+    Mat3::new(1.0, 0.0, 0.0, 0.0, c, -s, 0.0, s, c)
+}
+fn rot_y(a: Angle<f64>) -> Mat3<f64> {
+    let (s, c) = a.to_radians().sin_cos();
+    Mat3::new(c, 0.0, s, 0.0, 1.0, 0.0, -s, 0.0, c)
+}
+fn rot_z(a: Angle<f64>) -> Mat3<f64> {
+    let (s, c) = a.to_radians().sin_cos();
+    Mat3::new(c, -s, 0.0, s, c, 0.0, 0.0, 0.0, 1.0)
+}
+
+// The repo (x, y, z) frame is the (Y, Z, X) one
+fn to_repo_frame(m: &Mat3<f64>) -> Mat3<f64> {
+    let q = Mat3::new(0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0);
+    q * m * q.transpose()
+}
+fn to_repo_frame_vec(v: &Vec3<f64>) -> Vec3<f64> {
+    Vec3::new(v.y, v.z, v.x)
+}
+
+/// Apply the annual aberration to a position
+///
+/// # Arguments
+///
+/// * `pos` - Unit vector in the J2000 equatorial frame
+/// * `epoch` - Instant of the observation
+pub fn aberration(pos: &Vec3<f64>, epoch: &Epoch) -> Vec3<f64> {
+    let v = epoch.earth_velocity();
+    // Relativistic expression, the light deflection by the Sun being neglected
+    let inv_gamma = (1.0 - v.magnitude2()).sqrt();
+    let p_v = pos.dot(v);
+
+    let p = (pos * inv_gamma + v * (1.0 + p_v / (1.0 + inv_gamma))) / (1.0 + p_v);
+    p.normalize()
+}
+
+/// Apparent place of a J2000 position at a given epoch, i.e. its right ascension and
+/// declination referred to the true equator and equinox of date, corrected from the
+/// annual aberration.
+pub fn apparent_place(ra: Angle<f64>, dec: Angle<f64>, epoch: &Epoch) -> (Angle<f64>, Angle<f64>) {
+    let pos = aberration(&math::radec_to_xyz(ra, dec), epoch);
+    let pos = epoch.precession_nutation_matrix() * pos;
+
+    let (ra, dec) = math::xyz_to_radec(&pos);
+    (Angle(ra.to_radians().rem_euclid(2.0 * std::f64::consts::PI)), dec)
+}
+
+/// Rotation matrix from the ICRS to the galactic frame
+///
+/// The galactic pole and center are the ones defined by the Hipparcos catalogue
+/// (ESA, 1997, vol. 1, section 1.5.3).
+pub fn icrs_to_galactic() -> Mat3<f64> {
+    // Given row by row in the usual (X, Y, Z) frame
+    let m = Mat3::new(
+        -0.054_875_560_416_215_4, -0.873_437_090_234_885, -0.483_835_015_548_713_2,
+        0.494_109_427_875_583_7, -0.444_829_629_960_011_2, 0.746_982_244_497_219,
+        -0.867_666_149_019_004_7, -0.198_076_373_431_201_5, 0.455_983_776_175_066_9,
+    );
+    to_repo_frame(&m.transpose())
+}
+
+/// Rotation matrix from the ICRS to the ecliptic frame of J2000
+pub fn icrs_to_ecliptic() -> Mat3<f64> {
+    let (s, c) = Epoch::J2000.mean_obliquity().to_radians().sin_cos();
+    // Given row by row in the usual (X, Y, Z) frame
+    let m = Mat3::new(
+        1.0, 0.0, 0.0,
+        0.0, c, s,
+        0.0, -s, c,
+    );
+    to_repo_frame(&m.transpose())
+}
+
+/// Galactic longitude and latitude of an ICRS position
+pub fn galactic_coordinates(ra: Angle<f64>, dec: Angle<f64>) -> (Angle<f64>, Angle<f64>) {
+    let pos = icrs_to_galactic() * math::radec_to_xyz(ra, dec);
+
+    let (l, b) = math::xyz_to_radec(&pos);
+    (Angle(l.to_radians().rem_euclid(2.0 * std::f64::consts::PI)), b)
+}
+
+/// Frame in which the sky is looked at
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CooFrame {
+    Equatorial,
+    Horizontal,
+}
+
+/// What the sphere drawn stands for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SphereMode {
+    /// The celestial sphere, seen from the inside with the east on the left
+    Sky,
+    /// A solar system body seen from the outside, the (x, y, z) frame being fixed to it with
+    /// its planetocentric longitudes increasing towards the east, from z to x, i.e. from the
+    /// left to the right of the projections
+    Planet(String),
+}
+
+impl SphereMode {
+    /// Mode of a HiPS from its `hips_body` property, missing for the sky
+    pub fn from_hips_body(body: Option<&str>) -> Self {
+        match body {
+            Some(body) => SphereMode::Planet(body.to_string()),
+            None => SphereMode::Sky,
+        }
+    }
+
+    /// Name of the body, if the sphere is a planetary one
+    pub fn body(&self) -> Option<&str> {
+        match self {
+            SphereMode::Sky => None,
+            SphereMode::Planet(body) => Some(body),
+        }
+    }
+
+    /// Factor of the x axis of the world space before its projection
+    ///
+    /// The projections of projection.rs draw the longitudes increasing from left to right.
+    /// The sky is mirrored to follow the astronomical convention.
+    pub fn x_sign(&self) -> f64 {
+        match self {
+            SphereMode::Sky => -1.0,
+            SphereMode::Planet(_) => 1.0,
+        }
+    }
+}
+
+/// A location on Earth together with its atmospheric conditions
+///
+/// The horizontal frame follows the repo-wide (x, y, z) convention where
+/// y points towards the zenith and z towards the north point of the horizon.
+/// x points towards the west so that the frame stays right-handed, i.e.
+/// the azimuth (north through east) is given by `atan2(-x, z)`.
+#[derive(Clone, Copy, Debug)]
+pub struct Observer {
+    /// Geographic longitude, positive towards the east
+    pub lon: Angle<f64>,
+    /// Geographic latitude
+    pub lat: Angle<f64>,
+    /// Atmospheric pressure in hPa
+    pub pressure: f64,
+    /// Air temperature in degrees Celsius
+    pub temperature: f64,
+}
+
+impl Default for Observer {
+    fn default() -> Self {
+        // Strasbourg astronomical observatory
+        Self {
+            lon: Angle::new(ArcDeg(7.768)),
+            lat: Angle::new(ArcDeg(48.583)),
+            pressure: 1010.0,
+            temperature: 10.0,
+        }
+    }
+}
+
+impl Observer {
+    /// Local apparent sidereal time
+    pub fn local_sidereal_time(&self, epoch: &Epoch) -> Angle<f64> {
+        let lst = (epoch.gast() + self.lon).to_radians();
+        Angle(lst.rem_euclid(2.0 * std::f64::consts::PI))
+    }
+
+    /// Rotation matrix from the horizontal frame to the true equatorial frame of date
+    ///
+    /// Its columns are the west point, the zenith and the north point
+    /// expressed in the equatorial frame.
+    pub fn horizontal_to_equatorial(&self, epoch: &Epoch) -> Mat3<f64> {
+        let (s_lst, c_lst) = self.local_sidereal_time(epoch).to_radians().sin_cos();
+        let (s_lat, c_lat) = self.lat.to_radians().sin_cos();
+
+        let west = Vec3::new(-c_lst, 0.0, s_lst);
+        let zenith = Vec3::new(c_lat * s_lst, s_lat, c_lat * c_lst);
+        let north = Vec3::new(-s_lat * s_lst, c_lat, -s_lat * c_lst);
+
+        Mat3::from_cols(west, zenith, north)
+    }
+
+    /// Rotation matrix from the horizontal frame to the J2000 equatorial frame
+    pub fn horizontal_to_icrs(&self, epoch: &Epoch) -> Mat3<f64> {
+        epoch.precession_nutation_matrix().transpose() * self.horizontal_to_equatorial(epoch)
+    }
+
+    /// Scaling of the refraction for the observer pressure and temperature
+    ///
+    /// Equals 1 at 1010 hPa and 10°C
+    pub fn refraction_factor(&self) -> f64 {
+        (self.pressure / 1010.0) * (283.0 / (273.0 + self.temperature))
+    }
+
+    /// Refraction to add to a true altitude to get the apparent one (Saemundsson, 1986)
+    ///
+    /// The formulas diverge far below the horizon, where the refraction is zero.
+    pub fn refraction_from_true(&self, alt: Angle<f64>) -> Angle<f64> {
+        let h = ArcDeg::from(alt).0;
+        if h < -1.0 {
+            return Angle(0.0);
+        }
+        let r = 1.02 / (h + 10.3 / (h + 5.11)).to_radians().tan();
+
+        Angle::new(ArcMin(r * self.refraction_factor()))
+    }
+
+    /// Refraction to remove from an apparent altitude to get the true one (Bennett, 1982)
+    pub fn refraction_from_apparent(&self, alt: Angle<f64>) -> Angle<f64> {
+        let h = ArcDeg::from(alt).0;
+        if h < -1.0 {
+            return Angle(0.0);
+        }
+        let r = 1.0 / (h + 7.31 / (h + 4.4)).to_radians().tan();
+
+        Angle::new(ArcMin(r * self.refraction_factor()))
+    }
+
+    /// Convert a true horizontal position to the apparent one, i.e. lifted by the refraction
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - Unit vector in the horizontal frame
+    pub fn horizontal_to_apparent(&self, pos: &Vec3<f64>) -> Vec3<f64> {
+        let alt = Angle(pos.y.clamp(-1.0, 1.0).asin());
+        with_altitude(pos, alt + self.refraction_from_true(alt))
+    }
+
+    /// Convert an apparent horizontal position to the true one
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - Unit vector in the horizontal frame
+    pub fn apparent_to_horizontal(&self, pos: &Vec3<f64>) -> Vec3<f64> {
+        let alt = Angle(pos.y.clamp(-1.0, 1.0).asin());
+        with_altitude(pos, alt - self.refraction_from_apparent(alt))
+    }
+}
+
+// Move a position along its vertical circle so that it gets the given altitude
+fn with_altitude(pos: &Vec3<f64>, alt: Angle<f64>) -> Vec3<f64> {
+    let d = (pos.x * pos.x + pos.z * pos.z).sqrt();
+    if d < 1e-12 {
+        // The zenith and nadir are left untouched
+        return *pos;
+    }
+    let alt = alt.to_radians().clamp(-std::f64::consts::FRAC_PI_2, std::f64::consts::FRAC_PI_2);
+    let (s_alt, c_alt) = alt.sin_cos();
+
+    Vec3::new(c_alt * pos.x / d, s_alt, c_alt * pos.z / d)
+}
+
+/// Relative airmass from an apparent altitude (Pickering, 2002)
+///
+/// Returns `None` for positions below the horizon
+pub fn airmass(alt: Angle<f64>) -> Option<f64> {
+    let h = ArcDeg::from(alt).0;
+    if h <= 0.0 {
+        None
+    } else {
+        let x = 1.0 / (h + 244.0 / (165.0 + 47.0 * h.powf(1.1))).to_radians().sin();
+        Some(x)
+    }
+}
+
+mod tests {
+    #[test]
+    fn gmst() {
+        use super::*;
+        // Meeus, Astronomical Algorithms, example 12.a
+        // 1987 April 10, 0h UT => 13h10m46.3668s
+        let gmst = Epoch(2_446_895.5).gmst();
+        let expected = Angle::new(ArcDeg((13.0 + 10.0 / 60.0 + 46.3668 / 3600.0) * 15.0));
+
+        assert!((gmst - expected).abs().to_radians() < 1e-7);
+
+        // The epochs of the command line
+        assert_eq!(Epoch::parse("2446895.5"), Some(Epoch(2_446_895.5)));
+        assert_eq!(Epoch::parse("J2000"), Some(Epoch::J2000));
+        assert_eq!(Epoch::parse("2025-01-01"), None);
+    }
+
+    #[test]
+    fn nutation() {
+        use super::*;
+        // Meeus, Astronomical Algorithms, example 22.a
+        // 1987 April 10, 0h TD => dpsi = -3.788", deps = 9.443", eps0 = 23°26'27.407"
+        let epoch = Epoch(2_446_895.5);
+        let (dpsi, deps) = epoch.nutation();
+        let eps = ArcSec::from(epoch.mean_obliquity()).0 - 84_000.0;
+
+        assert!((ArcSec::from(dpsi).0 - -3.788).abs() < 0.05);
+        assert!((ArcSec::from(deps).0 - 9.443).abs() < 0.05);
+        assert!((eps - 387.407).abs() < 0.05);
+    }
+
+    #[test]
+    fn apparent_place() {
+        use super::*;
+        // Meeus, examples 21.b and 23.a: theta Persei, 2028 November 13.19 TD.
+        // The J2000 position is given at the epoch of observation (proper motion applied)
+        let epoch = Epoch(2_462_088.69);
+        let ra = Angle::new(ArcDeg(41.054_063));
+        let dec = Angle::new(ArcDeg(49.227_750));
+
+        // Mean place of date
+        let mean = epoch.precession_matrix() * math::radec_to_xyz(ra, dec);
+        let (ra_mean, dec_mean) = math::xyz_to_radec(&mean);
+        assert!((ArcDeg::from(ra_mean).0 - 41.547_214).abs() < 1e-4);
+        assert!((ArcDeg::from(dec_mean).0 - 49.348_483).abs() < 1e-4);
+
+        // Apparent place
+        let (ra_app, dec_app) = super::apparent_place(ra, dec, &epoch);
+        assert!((ArcDeg::from(ra_app).0 - 41.559_958_3).abs() < 5e-4);
+        assert!((ArcDeg::from(dec_app).0 - 49.352_068_5).abs() < 5e-4);
+    }
+
+    #[test]
+    fn galactic() {
+        use super::*;
+        // Galactic center and north galactic pole
+        let (l, b) = galactic_coordinates(
+            Angle::new(ArcDeg(266.404_988)),
+            Angle::new(ArcDeg(-28.936_175)),
+        );
+        assert!(ArcDeg::from(l).0.min(360.0 - ArcDeg::from(l).0) < 1e-4);
+        assert!(ArcDeg::from(b).0.abs() < 1e-4);
+
+        let (_, b) = galactic_coordinates(
+            Angle::new(ArcDeg(192.859_481)),
+            Angle::new(ArcDeg(27.128_251)),
+        );
+        assert!((ArcDeg::from(b).0 - 90.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn refraction() {
+        use super::*;
+        let observer = Observer::default();
+
+        // At the horizon the refraction is about 34 arcmin
+        let r = ArcMin::from(observer.refraction_from_apparent(Angle(0.0)));
+        assert!((r.0 - 34.5).abs() < 0.5);
+        // Both formulas are inverse of each other to 0.1 arcmin
+        for h in [0.5_f64, 5.0, 15.0, 45.0, 80.0] {
+            let true_alt = Angle::new(ArcDeg(h));
+            let app_alt = true_alt + observer.refraction_from_true(true_alt);
+            let back = app_alt - observer.refraction_from_apparent(app_alt);
+
+            assert!(ArcMin::from(back - true_alt).abs() < 0.1);
+        }
+        // The refraction vanishes for thin atmospheres
+        let thin = Observer {
+            pressure: 0.0,
+            ..observer
+        };
+        assert_eq!(thin.refraction_from_true(Angle(0.0)).to_radians(), 0.0);
+        // and below -1 degree
+        let below = Angle::new(ArcDeg(-2.0));
+        assert_eq!(observer.refraction_from_true(below).to_radians(), 0.0);
+        assert_eq!(observer.refraction_from_apparent(below).to_radians(), 0.0);
+    }
+
+    #[test]
+    fn horizontal_frame() {
+        use super::*;
+        let observer = Observer::default();
+        let epoch = Epoch(2_460_000.5);
+        let m = observer.horizontal_to_equatorial(&epoch);
+
+        // The zenith is at ra = lst, dec = lat in the equatorial frame of date
+        let zenith = m * Vec3::new(0.0, 1.0, 0.0);
+        let (ra, dec) = crate::math::xyz_to_radec(&zenith);
+        let lst = observer.local_sidereal_time(&epoch);
+        assert!((dec - observer.lat).abs().to_radians() < 1e-12);
+        assert!((Angle(ra.to_radians().rem_euclid(std::f64::consts::TAU)) - lst).abs().to_radians() < 1e-12);
+        // The north celestial pole stands at an altitude equal to the latitude
+        let pole_hor = m.transpose() * Vec3::new(0.0, 1.0, 0.0);
+        assert!((pole_hor.y.asin() - observer.lat.to_radians()).abs() < 1e-12);
+        assert!((pole_hor.magnitude() - 1.0).abs() < 1e-12);
+        // The precession moves the pole of date by ~20" per year from the J2000 one
+        let pole_j2000_hor = observer.horizontal_to_icrs(&epoch).transpose() * Vec3::new(0.0, 1.0, 0.0);
+        let sep = ArcSec::from(Angle(pole_j2000_hor.angle(pole_hor).0)).0;
+        assert!((sep - 20.0 * 23.16).abs() < 15.0);
+    }
+
+    #[test]
+    fn sphere_modes() {
+        use super::*;
+        use crate::overlay::View;
+        use crate::projection::{Aitoff, Projection};
+        use crate::reference;
+
+        // A position east of the center of the view
+        let east = math::radec_to_xyz(Angle::new(ArcDeg(20.0)), Angle::new(ArcDeg(10.0)));
+        let clip_x = |mode: &SphereMode| {
+            let view = View {
+                icrs_to_world: Box::new(|p| *p),
+                ndc_to_clip: Aitoff::compute_ndc_to_clip_factor(512.0, 256.0),
+                x_sign: mode.x_sign(),
+            };
+            view.project::<Aitoff>(&east).unwrap().x
+        };
+        // The east is on the left of the sky, and on the right of the planets
+        let planet = SphereMode::from_hips_body(Some("mars"));
+        assert!(clip_x(&SphereMode::Sky) < 0.0);
+        assert!(clip_x(&planet) > 0.0);
+
+        // Same for the reference renderer, the base cell 5 around the longitude 90° being
+        // on the right of the planets
+        let tiles: Vec<image::RgbaImage> = (0..12)
+            .map(|cell| image::RgbaImage::from_pixel(4, 4, image::Rgba([20 * cell as u8, 0, 0, 255])))
+            .collect();
+        let render = |mode: &SphereMode| {
+            reference::render::<Aitoff>(&tiles, &Mat3::from_scale(1.0), mode.x_sign(), 64, 32)
+        };
+        let (sky, planet) = (render(&SphereMode::Sky), render(&planet));
+        assert_eq!(planet.get_pixel(48, 16).0, [100, 0, 0, 255]);
+        for (x, y, pixel) in sky.enumerate_pixels() {
+            assert_eq!(pixel, planet.get_pixel(63 - x, y));
+        }
+    }
+
+    #[test]
+    fn airmass() {
+        use super::*;
+        assert!((airmass(Angle::new(ArcDeg(90.0))).unwrap() - 1.0).abs() < 1e-3);
+        assert!((airmass(Angle::new(ArcDeg(30.0))).unwrap() - 2.0).abs() < 1e-2);
+        assert!(airmass(Angle::new(ArcDeg(-1.0))).is_none());
+    }
+}
@@ -0,0 +1,49 @@
+// GLSL shared by the shaders, inserted after their #version line when the pipelines are created
+
+/// Constants, HEALPix hashing, horizontal frame and refraction of all the sky shaders
+pub const COMMON: &str = include_str!("shaders/common.glsl");
+/// Stretches of the colormaps, the shader defining `float cdf_value(int k)`
+pub const COLORMAP: &str = include_str!("shaders/colormap.glsl");
+
+/// Source of a shader with shared GLSL inserted after its #version line
+///
+/// # Arguments
+///
+/// * `body` - Source of the shader
+/// * `shared` - Shared sources, in their order of insertion
+pub fn with_shared(body: &str, shared: &[&str]) -> String {
+    let (version, body) = match body.find("#version") {
+        Some(start) => {
+            let end = body[start..].find('\n').map_or(body.len(), |end| start + end + 1);
+            body.split_at(end)
+        }
+        None => ("", body),
+    };
+    format!("{}{}{}", version, shared.concat(), body)
+}
+
+mod tests {
+    #[test]
+    fn shared_glsl() {
+        use super::*;
+
+        let source = with_shared("// a.frag\n#version 440\nvoid main() {}\n", &["// one\n", "// two\n"]);
+        assert_eq!(source, "// a.frag\n#version 440\n// one\n// two\nvoid main() {}\n");
+
+        // The shaders using the shared GLSL compile and validate
+        let shaders = [
+            (include_str!("shaders/allsky.frag"), &[COMMON, COLORMAP][..], naga::ShaderStage::Fragment),
+            (include_str!("shaders/images.frag"), &[COMMON, COLORMAP][..], naga::ShaderStage::Fragment),
+            (include_str!("shaders/composite.frag"), &[COMMON, COLORMAP][..], naga::ShaderStage::Fragment),
+            (include_str!("shaders/markers.vert"), &[COMMON][..], naga::ShaderStage::Vertex),
+        ];
+        for (body, shared, stage) in shaders.iter() {
+            let module = naga::front::glsl::Frontend::default()
+                .parse(&naga::front::glsl::Options::from(*stage), &with_shared(body, shared))
+                .unwrap();
+            naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+                .validate(&module)
+                .unwrap();
+        }
+    }
+}
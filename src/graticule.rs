@@ -0,0 +1,221 @@
+use crate::angle::{Angle, ArcDeg};
+use crate::cursor;
+use crate::frame;
+use crate::math::{self, Vec2, Vec3};
+use crate::overlay::View;
+use crate::projection::Projection;
+
+use cgmath::{InnerSpace, Matrix};
+
+/// Frame in which the coordinate grid is drawn
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GridFrame {
+    Equatorial,
+    Galactic,
+    /// Planetocentric longitudes and latitudes of a planetary body
+    Planetocentric,
+}
+
+// Candidate spacings in degrees, from the widest to the narrowest.
+// The right ascensions are spaced with round values of time.
+const LON_HOURS_STEPS: [f64; 14] = [
+    30.0, 15.0, 7.5, 5.0, 2.5, 1.25, 0.5, 0.25,
+    0.125, 1.0 / 12.0, 1.0 / 24.0, 1.0 / 48.0, 1.0 / 120.0, 1.0 / 240.0,
+];
+const DEG_STEPS: [f64; 15] = [
+    30.0, 15.0, 10.0, 5.0, 2.0, 1.0, 0.5, 1.0 / 3.0,
+    1.0 / 6.0, 1.0 / 12.0, 1.0 / 30.0, 1.0 / 60.0, 1.0 / 120.0, 1.0 / 180.0, 1.0 / 360.0,
+];
+// Number of grid lines wanted across the field of view
+const NUM_LINES_IN_FOV: f64 = 10.0;
+// Number of samples of a grid line across the field of view
+const NUM_SAMPLES_IN_FOV: f64 = 120.0;
+
+/// A grid label anchored at the border of the projection
+#[derive(Clone, Debug)]
+pub struct Label {
+    /// Position in clip space
+    pub pos: Vec2<f64>,
+    pub text: String,
+}
+
+/// Coordinate grid projected in clip space
+pub struct Graticule {
+    pub frame: GridFrame,
+    /// Spacing of the meridians in degrees
+    pub lon_step: f64,
+    /// Spacing of the parallels in degrees
+    pub lat_step: f64,
+    pub lines: Vec<Vec<Vec2<f64>>>,
+    pub labels: Vec<Label>,
+}
+
+impl Graticule {
+    /// Compute the grid for a view of the sky
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - Frame of the grid
+    /// * `view` - The current view
+    /// * `fov` - Angular extent of the view, the grid spacing adapts to it
+    pub fn new<P: Projection<f64>>(frame: GridFrame, view: &View, fov: Angle<f64>) -> Self {
+        let fov = ArcDeg::from(fov).0;
+        let lon_steps: &[f64] = match frame {
+            GridFrame::Equatorial => &LON_HOURS_STEPS,
+            GridFrame::Galactic | GridFrame::Planetocentric => &DEG_STEPS,
+        };
+        let lon_step = spacing(lon_steps, fov);
+        let lat_step = spacing(&DEG_STEPS, fov);
+        let sample_step = (fov / NUM_SAMPLES_IN_FOV).min(2.0);
+
+        let to_icrs = match frame {
+            GridFrame::Equatorial | GridFrame::Planetocentric => math::Mat3::from_scale(1.0),
+            GridFrame::Galactic => frame::icrs_to_galactic().transpose(),
+        };
+        let pos = |lon: f64, lat: f64| {
+            to_icrs * math::radec_to_xyz(Angle::new(ArcDeg(lon)), Angle::new(ArcDeg(lat)))
+        };
+
+        let mut lines = vec![];
+        let mut labels = vec![];
+        let mut add_line = |polylines: Vec<Vec<Vec2<f64>>>, text: String| {
+            for polyline in polylines {
+                labels.push(Label {
+                    pos: polyline[0],
+                    text: text.clone(),
+                });
+                lines.push(polyline);
+            }
+        };
+
+        // Meridians, from the south to the north pole
+        let num_samples = (180.0 / sample_step).ceil() as usize;
+        for k in 0..(360.0 / lon_step).round() as usize {
+            let lon = k as f64 * lon_step;
+            let polylines = view.trace::<P>(|t| pos(lon, -90.0 + 180.0 * t), num_samples, false);
+            add_line(polylines, format_lon(frame, lon));
+        }
+        // Parallels
+        let num_samples = (360.0 / sample_step).ceil() as usize;
+        let n = (90.0 / lat_step).round() as i64;
+        for k in (1 - n)..n {
+            let lat = k as f64 * lat_step;
+            let polylines = view.trace::<P>(|t| pos(360.0 * t, lat), num_samples, true);
+            add_line(polylines, format_lat(lat));
+        }
+
+        Self {
+            frame,
+            lon_step,
+            lat_step,
+            lines,
+            labels,
+        }
+    }
+}
+
+fn spacing(steps: &[f64], fov: f64) -> f64 {
+    let target = fov / NUM_LINES_IN_FOV;
+    steps
+        .iter()
+        .copied()
+        .find(|&step| step <= target)
+        .unwrap_or(steps[steps.len() - 1])
+}
+
+fn format_lon(frame: GridFrame, lon: f64) -> String {
+    match frame {
+        GridFrame::Equatorial => {
+            let (h, m, s) = cursor::sexagesimal(lon / 15.0, 0);
+            match (m, s as u64) {
+                (0, 0) => format!("{}h", h),
+                (_, 0) => format!("{}h{:02}m", h, m),
+                (_, s) => format!("{}h{:02}m{:02}s", h, m, s),
+            }
+        }
+        GridFrame::Galactic => format_deg(lon, false),
+        GridFrame::Planetocentric => format!("{}E", format_deg(lon, false)),
+    }
+}
+
+fn format_lat(lat: f64) -> String {
+    format_deg(lat, true)
+}
+
+fn format_deg(value: f64, signed: bool) -> String {
+    let sign = match (signed, value < 0.0) {
+        (false, _) => "",
+        (true, true) => "-",
+        (true, false) => "+",
+    };
+    let (d, m, s) = cursor::sexagesimal(value.abs(), 0);
+    match (m, s as u64) {
+        (0, 0) => format!("{}{}°", sign, d),
+        (_, 0) => format!("{}{}°{:02}'", sign, d, m),
+        (_, s) => format!("{}{}°{:02}'{:02}\"", sign, d, m, s),
+    }
+}
+
+/// Angular extent of the sky seen through a projection
+///
+/// This is twice the largest angular distance between the center of the
+/// window and the sky seen in it.
+pub fn field_of_view<P: Projection<f64>>(width: f64, height: f64) -> Angle<f64> {
+    const N: usize = 16;
+    let center = P::screen_to_world_space(&Vec2::new(0.5 * width, 0.5 * height), width, height)
+        .map(|p| p.truncate().normalize())
+        .unwrap_or_else(|| Vec3::new(0.0, 0.0, 1.0));
+
+    let mut max_dist: f64 = 0.0;
+    for i in 0..=N {
+        for j in 0..=N {
+            let pos = Vec2::new(width * i as f64 / N as f64, height * j as f64 / N as f64);
+            if let Some(p) = P::screen_to_world_space(&pos, width, height) {
+                let p = p.truncate().normalize();
+                max_dist = max_dist.max(p.dot(center).clamp(-1.0, 1.0).acos());
+            }
+        }
+    }
+
+    Angle((2.0 * max_dist).min(2.0 * std::f64::consts::PI))
+}
+
+mod tests {
+    #[test]
+    fn graticule() {
+        use super::*;
+        use crate::projection::{Aitoff, Gnomonic};
+
+        let view = |ndc_to_clip| View {
+            icrs_to_world: Box::new(|p| *p),
+            ndc_to_clip,
+            x_sign: 1.0,
+        };
+
+        // The whole sky is seen with a 30° spacing
+        let view_aitoff = view(Aitoff::compute_ndc_to_clip_factor(1024.0, 512.0));
+        let fov = field_of_view::<Aitoff>(1024.0, 512.0);
+        assert!(ArcDeg::from(fov).0 > 300.0);
+        let grid = Graticule::new::<Aitoff>(GridFrame::Equatorial, &view_aitoff, fov);
+        assert_eq!((grid.lon_step, grid.lat_step), (30.0, 30.0));
+        // The parallels are split at the seam of the projection, where they are labelled
+        let label = grid.labels.iter().find(|l| l.text == "+30°").unwrap();
+        assert!(label.pos.x * label.pos.x + 4.0 * label.pos.y * label.pos.y > 0.99);
+        assert!(grid.labels.iter().any(|l| l.text == "2h"));
+        for line in &grid.lines {
+            for pair in line.windows(2) {
+                assert!((pair[1] - pair[0]).magnitude() < 0.05);
+            }
+        }
+
+        // Nothing is drawn behind the camera of the gnomonic projection
+        let fov = field_of_view::<Gnomonic>(512.0, 512.0);
+        let view_gnomonic = view(Gnomonic::compute_ndc_to_clip_factor(512.0, 512.0));
+        let grid = Graticule::new::<Gnomonic>(GridFrame::Galactic, &view_gnomonic, fov);
+        assert!(grid.lat_step < 30.0);
+        assert!(!grid.lines.is_empty());
+
+        assert_eq!(format_lon(GridFrame::Equatorial, 7.5), "0h30m");
+        assert_eq!(format_lat(-0.5), "-0°30'");
+    }
+}
@@ -0,0 +1,295 @@
+// CPU side of the HEALPix nested hashing done in the fragment shader.
+//
+// Positions are given in the usual (X, Y, Z) frame where X points towards the
+// equinox and Z towards the north pole, i.e. the repo (z, x, y) one.
+use crate::angle::Angle;
+
+const TRANSITION_Z: f64 = 2.0 / 3.0;
+const TRANSITION_Z_INV: f64 = 1.5;
+const FOUR_OVER_PI: f64 = 4.0 / std::f64::consts::PI;
+
+/// Deepest order for which a cell number fits on a u64
+pub const MAX_DEPTH: u8 = 29;
+
+/// Number of cells at a given depth
+pub fn n_cells(depth: u8) -> u64 {
+    12 << (depth << 1)
+}
+
+fn quarter(x: f64, y: f64) -> u8 {
+    let x_neg = (x < 0.0) as u8;
+    let y_neg = (y < 0.0) as u8;
+    (x_neg + y_neg) | (y_neg << 1)
+}
+
+fn xpm1(x: f64, y: f64) -> f64 {
+    let x_neg = x < 0.0;
+    let y_neg = y < 0.0;
+    let lon = y.abs().atan2(x.abs());
+    let x02 = lon * FOUR_OVER_PI;
+    if x_neg != y_neg {
+        1.0 - x02
+    } else {
+        x02 - 1.0
+    }
+}
+
+// 1 - |z| computed from x and y near the poles to keep the precision
+fn one_minus_abs_z(x: f64, y: f64, z: f64) -> f64 {
+    let d2 = x * x + y * y;
+    if d2 < 1e-1 {
+        d2 * (0.5 + d2 * (0.125 + d2 * (0.0625 + d2 * (0.039_062_5 + d2 * 0.027_343_75))))
+    } else {
+        1.0 - z.abs()
+    }
+}
+
+// Spread the bits of v so that they occupy the even positions
+fn interleave(v: u32) -> u64 {
+    let mut x = v as u64;
+    x = (x | (x << 16)) & 0x0000_FFFF_0000_FFFF;
+    x = (x | (x << 8)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+/// Z-Order curve index of the (i, j) coordinates inside a base cell
+pub fn ij2z(i: u32, j: u32) -> u64 {
+    interleave(i) | (interleave(j) << 1)
+}
+
+/// Cell number of a position on the unit sphere, together with the offset `(dx, dy)`
+/// of the position inside the cell, from its south vertex.
+///
+/// # Arguments
+///
+/// * `depth` - in `[0, 29]`
+/// * `x`, `y`, `z` - unit vector in the usual (X, Y, Z) frame
+///
+/// # Output
+///
+/// * the cell number in `[0, 12*nside^2[`
+/// * `dx`: the offset in `[0, 1[` along the south-to-east axis
+/// * `dy`: the offset in `[0, 1[` along the south-to-west axis
+pub fn hash_with_dxdy(depth: u8, x: f64, y: f64, z: f64) -> (u64, f64, f64) {
+    assert!(depth <= MAX_DEPTH);
+    let nside = 1_u32 << depth;
+    let half_nside = nside as f64 * 0.5;
+
+    let x_pm1 = xpm1(x, y);
+    let q = quarter(x, y) as u64;
+
+    let (x_proj, y_proj, d0h) = if z > TRANSITION_Z {
+        // North polar cap, Collignon projection
+        let sqrt_3_one_min_z = (3.0 * one_minus_abs_z(x, y, z)).sqrt();
+        (x_pm1 * sqrt_3_one_min_z, 2.0 - sqrt_3_one_min_z, q)
+    } else if z < -TRANSITION_Z {
+        // South polar cap, Collignon projection
+        let sqrt_3_one_min_z = (3.0 * one_minus_abs_z(x, y, z)).sqrt();
+        (x_pm1 * sqrt_3_one_min_z, sqrt_3_one_min_z, q + 8)
+    } else {
+        // Equatorial region, cylindrical equal area projection
+        let y_pm1 = z * TRANSITION_Z_INV;
+        let q01 = (x_pm1 > y_pm1) as u64;
+        let q12 = (x_pm1 >= -y_pm1) as u64;
+        let q03 = 1 - q12;
+        let q1 = q01 & q12;
+        (
+            x_pm1 - (q01 + q12) as f64 + 1.0,
+            y_pm1 + (q01 + q03) as f64,
+            ((q01 + q03) << 2) + ((q + q1) & 3),
+        )
+    };
+
+    // Coordinates inside the base cell
+    let x = half_nside * (x_proj + y_proj);
+    let y = half_nside * (y_proj - x_proj);
+    let i = (x.max(0.0) as u32).min(nside - 1);
+    let j = (y.max(0.0) as u32).min(nside - 1);
+
+    (
+        (d0h << (depth << 1)) | ij2z(i, j),
+        x - i as f64,
+        y - j as f64,
+    )
+}
+
+// Inverse of interleave, gathering the even bits of v
+fn deinterleave(v: u64) -> u32 {
+    let mut x = v & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x >> 4)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x >> 8)) & 0x0000_FFFF_0000_FFFF;
+    (x | (x >> 16)) as u32
+}
+
+/// (i, j) coordinates inside a base cell of a Z-Order curve index
+pub fn z2ij(z: u64) -> (u32, u32) {
+    (deinterleave(z), deinterleave(z >> 1))
+}
+
+/// Position on the unit sphere of a point of a cell, the inverse of `hash_with_dxdy`
+///
+/// # Arguments
+///
+/// * `depth` - in `[0, 29]`
+/// * `hash` - the cell number
+/// * `dx`, `dy` - offsets in `[0, 1]` from the south vertex of the cell, e.g. `(0.5, 0.5)`
+///   for its center and `(1, 0)` for its east vertex
+///
+/// # Output
+///
+/// * unit vector in the usual (X, Y, Z) frame
+pub fn sph_coo(depth: u8, hash: u64, dx: f64, dy: f64) -> (f64, f64, f64) {
+    assert!(depth <= MAX_DEPTH);
+    let nside = (1_u64 << depth) as f64;
+    let d0h = hash >> (depth << 1);
+    let (i, j) = z2ij(hash & ((1 << (depth << 1)) - 1));
+
+    // Center of the base cell in the HEALPix projection plane, where the
+    // abscissa is in units of pi/4 and the ordinate in [-2, 2]
+    let (x_base, y_base) = match d0h >> 2 {
+        0 => (1.0 + 2.0 * (d0h & 3) as f64, 1.0),
+        1 => (2.0 * (d0h & 3) as f64, 0.0),
+        _ => (1.0 + 2.0 * (d0h & 3) as f64, -1.0),
+    };
+    let u = (i as f64 + dx) / nside;
+    let v = (j as f64 + dy) / nside;
+    let x = (x_base + u - v).rem_euclid(8.0);
+    let y = y_base - 1.0 + u + v;
+
+    let (lon, z) = if y.abs() <= 1.0 {
+        (x, y / TRANSITION_Z_INV)
+    } else {
+        // Polar caps, the meridians converging towards the center of the quarter
+        let sigma = 2.0 - y.abs();
+        let x_quarter = 1.0 + 2.0 * (x * 0.5).floor().min(3.0);
+        let lon = if sigma > 1e-15 {
+            x_quarter + (x - x_quarter) / sigma
+        } else {
+            x_quarter
+        };
+        (lon, (1.0 - sigma * sigma / 3.0).copysign(y))
+    };
+    let (s_lon, c_lon) = (lon / FOUR_OVER_PI).sin_cos();
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    (r * c_lon, r * s_lon, z)
+}
+
+// Integer square root
+fn isqrt(v: u64) -> u64 {
+    let mut r = (v as f64).sqrt() as u64;
+    while r * r > v {
+        r -= 1;
+    }
+    while (r + 1) * (r + 1) <= v {
+        r += 1;
+    }
+    r
+}
+
+/// Nested cell number of a cell given in the RING scheme, where the cells are
+/// numbered along the iso-latitude rings from the north pole
+pub fn ring_to_nested(depth: u8, ring: u64) -> u64 {
+    // Ring and longitude indices of the southernmost vertex of each base cell, in units of nside
+    const JRLL: [i64; 12] = [2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4];
+    const JPLL: [i64; 12] = [1, 3, 5, 7, 0, 2, 4, 6, 1, 3, 5, 7];
+
+    let nside = 1_i64 << depth;
+    let n_cap = 2 * nside * (nside - 1);
+    let pix = ring as i64;
+
+    // Ring index from the north pole in [1, 4 nside - 1], index in the ring from 1,
+    // shift of the ring and number of cells of the ring per quarter
+    let (iring, iphi, kshift, nr, d0h) = if pix < n_cap {
+        let iring = ((1 + isqrt(1 + 2 * ring)) >> 1) as i64;
+        let iphi = pix + 1 - 2 * iring * (iring - 1);
+        (iring, iphi, 0, iring, (iphi - 1) / iring)
+    } else if pix < 12 * nside * nside - n_cap {
+        let ip = pix - n_cap;
+        let tmp = ip / (4 * nside);
+        let iring = tmp + nside;
+        let iphi = ip - tmp * 4 * nside + 1;
+        let ire = tmp + 1;
+        let irm = 2 * nside + 2 - ire;
+        let ifm = (iphi - ire / 2 + nside - 1) >> depth;
+        let ifp = (iphi - irm / 2 + nside - 1) >> depth;
+        let d0h = match ifp.cmp(&ifm) {
+            std::cmp::Ordering::Equal => ifp | 4,
+            std::cmp::Ordering::Less => ifp,
+            std::cmp::Ordering::Greater => ifm + 8,
+        };
+        (iring, iphi, (iring + nside) & 1, nside, d0h)
+    } else {
+        let ip = 12 * nside * nside - pix;
+        let iring = ((1 + isqrt(2 * ip as u64 - 1)) >> 1) as i64;
+        let iphi = 4 * iring + 1 - (ip - 2 * iring * (iring - 1));
+        (4 * nside - iring, iphi, 0, iring, 8 + (iphi - 1) / iring)
+    };
+
+    let irt = iring - JRLL[d0h as usize] * nside + 1;
+    let mut ipt = 2 * iphi - JPLL[d0h as usize] * nr - kshift - 1;
+    if ipt >= 2 * nside {
+        ipt -= 8 * nside;
+    }
+    let i = (ipt - irt) >> 1;
+    let j = (-ipt - irt) >> 1;
+
+    ((d0h as u64) << (depth << 1)) | ij2z(i as u32, j as u32)
+}
+
+/// Cell number containing a position given by its longitude and latitude
+pub fn hash(depth: u8, lon: Angle<f64>, lat: Angle<f64>) -> u64 {
+    let (s_lon, c_lon) = lon.to_radians().sin_cos();
+    let (s_lat, c_lat) = lat.to_radians().sin_cos();
+
+    hash_with_dxdy(depth, c_lat * c_lon, c_lat * s_lon, s_lat).0
+}
+
+mod tests {
+    #[test]
+    fn hash() {
+        use super::*;
+        use crate::angle::ArcDeg;
+        let hash = |depth, lon, lat| super::hash(depth, Angle::new(ArcDeg(lon)), Angle::new(ArcDeg(lat)));
+
+        // Base cells
+        assert_eq!(hash(0, 0.1, 0.0), 4);
+        assert_eq!(hash(0, 45.0, 60.0), 0);
+        assert_eq!(hash(0, 135.0, -60.0), 9);
+        assert_eq!(hash(0, 270.0, 1.0), 7);
+
+        // The east and north children of the base cell 4
+        assert_eq!(hash(1, 1.0, 0.0), 17);
+        assert_eq!(hash(1, 0.0, 1.0), 19);
+
+        assert_eq!(n_cells(3), 768);
+        assert!(hash(MAX_DEPTH, 12.0, -34.0) < n_cells(MAX_DEPTH));
+
+        // The centers of the cells fall back in them
+        for depth in [0, 1, 5, 12] {
+            for k in 0..200 {
+                let h = (k * 7_919 + k * k * 104_729) % n_cells(depth);
+                let (x, y, z) = sph_coo(depth, h, 0.5, 0.5);
+                assert_eq!(hash_with_dxdy(depth, x, y, z).0, h);
+            }
+        }
+        assert_eq!(z2ij(ij2z(12_345, 678)), (12_345, 678));
+
+        // The RING scheme goes along the rings from the north pole, eastwards
+        for depth in [0, 1, 3] {
+            let centers: Vec<(f64, f64, f64)> = (0..n_cells(depth))
+                .map(|ring| sph_coo(depth, ring_to_nested(depth, ring), 0.5, 0.5))
+                .collect();
+            for pair in centers.windows(2) {
+                let ((x0, y0, z0), (x1, y1, z1)) = (pair[0], pair[1]);
+                let lon = |x: f64, y: f64| y.atan2(x).rem_euclid(2.0 * std::f64::consts::PI);
+                assert!(z1 < z0 - 1e-9 || ((z1 - z0).abs() < 1e-9 && lon(x1, y1) > lon(x0, y0)));
+            }
+        }
+        assert_eq!(ring_to_nested(1, 0), 3);
+        assert_eq!(ring_to_nested(1, 47), 44);
+    }
+}
@@ -0,0 +1,302 @@
+use crate::colormap::ColorScale;
+use crate::cuts::SkyStats;
+use crate::error::Error;
+use crate::fits::{self, Hdu};
+use crate::frame;
+use crate::healpix::{self, MAX_DEPTH};
+use crate::math::{Mat3, Vec3};
+
+use std::path::Path;
+
+// Value of the missing pixels in the HEALPix maps
+const UNSEEN: f32 = -1.6375e30;
+
+/// Full sky HEALPix map holding one value per cell, e.g. a CMB temperature map
+pub struct HealpixMap {
+    pub name: String,
+    depth: u8,
+    // Values of the cells in the NESTED scheme, NaN for the missing ones
+    values: Vec<f32>,
+    pub scale: ColorScale,
+    /// Histograms of the values for the automatic cuts
+    pub stats: SkyStats,
+}
+
+impl HealpixMap {
+    /// # Arguments
+    ///
+    /// * `depth` - HEALPix order of the cells
+    /// * `values` - The values of the `12 * 4^depth` cells in the NESTED scheme
+    pub fn new(name: String, depth: u8, values: Vec<f32>) -> Result<Self, Error> {
+        let n_cells = healpix::n_cells(depth);
+        if values.len() as u64 != n_cells {
+            return Err(Error::Parse(format!(
+                "HEALPix map: {} values for {} cells",
+                values.len(),
+                n_cells
+            )));
+        }
+        let scale = ColorScale::new(&values);
+        let stats = SkyStats::from_nested(depth, &values);
+        Ok(Self {
+            name,
+            depth,
+            values,
+            scale,
+            stats,
+        })
+    }
+
+    /// Read the first column of a HEALPix binary table, in the RING or NESTED scheme
+    ///
+    /// The galactic and ecliptic maps given by COORDSYS are resampled in the ICRS.
+    pub fn from_fits(name: String, bytes: &[u8]) -> Result<Self, Error> {
+        let err = |msg: String| Error::Parse(format!("HEALPix map: {}", msg));
+        let hdus = fits::parse(bytes)?;
+        let table = find_map(&hdus).ok_or_else(|| err("missing NSIDE or ORDERING keyword".to_string()))?;
+        let header = &table.header;
+
+        if header.get("INDXSCHM").is_some_and(|s| s.eq_ignore_ascii_case("EXPLICIT")) {
+            return Err(err("partial sky maps are not supported".to_string()));
+        }
+        let nside = header.get_i64("NSIDE").unwrap_or(0);
+        if nside <= 0 || nside.count_ones() != 1 || nside.trailing_zeros() > MAX_DEPTH as u32 {
+            return Err(err(format!("NSIDE {} is not a power of two", nside)));
+        }
+        let depth = nside.trailing_zeros() as u8;
+        let n_cells = healpix::n_cells(depth) as usize;
+        let coordsys = header.get("COORDSYS").unwrap_or("C");
+        let icrs_to_map = match coordsys.chars().next().map(|c| c.to_ascii_uppercase()) {
+            Some('C') | Some('Q') => None,
+            Some('G') => Some(frame::icrs_to_galactic()),
+            Some('E') => Some(frame::icrs_to_ecliptic()),
+            _ => return Err(err(format!("unsupported COORDSYS '{}'", coordsys))),
+        };
+
+        let mut values = table.column_f32(1)?;
+        if values.len() < n_cells {
+            return Err(err(format!("{} values for {} cells", values.len(), n_cells)));
+        }
+        values.truncate(n_cells);
+        for v in &mut values {
+            if *v <= UNSEEN * 0.99 {
+                *v = f32::NAN;
+            }
+        }
+
+        let is_ring = header.get("ORDERING").is_some_and(|o| o.eq_ignore_ascii_case("RING"));
+        if is_ring {
+            let mut nested = vec![f32::NAN; n_cells];
+            for (ring, v) in values.into_iter().enumerate() {
+                nested[healpix::ring_to_nested(depth, ring as u64) as usize] = v;
+            }
+            values = nested;
+        }
+        if let Some(icrs_to_map) = icrs_to_map {
+            values = resample(depth, &values, &icrs_to_map);
+        }
+        Self::new(name, depth, values)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let name = path
+            .as_ref()
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::from_fits(name, &std::fs::read(path)?)
+    }
+
+    /// HEALPix order of the cells
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// Values of the cells in the NESTED scheme, NaN for the missing ones
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    /// Value of the cell containing a position given in the ICRS frame
+    pub fn value(&self, pos_icrs: &Vec3<f64>) -> f32 {
+        // The repo (x, y, z) frame is the usual (Y, Z, X) one
+        let (hash, _, _) = healpix::hash_with_dxdy(self.depth, pos_icrs.z, pos_icrs.x, pos_icrs.y);
+        self.values[hash as usize]
+    }
+
+    /// Change the values drawn at the bottom and at the top of the colormap
+    pub fn set_cuts(&mut self, cuts: (f32, f32)) {
+        self.scale.set_cuts(cuts, &self.values);
+    }
+
+    /// Same map at a lower order, each cell holding the mean of its children
+    pub fn degrade(&self, depth: u8) -> Self {
+        if depth >= self.depth {
+            return Self {
+                name: self.name.clone(),
+                depth: self.depth,
+                values: self.values.clone(),
+                scale: self.scale.clone(),
+            stats: self.stats.clone(),
+            };
+        }
+        let num_children = 1 << (2 * (self.depth - depth));
+        let values = self
+            .values
+            .chunks_exact(num_children)
+            .map(|children| {
+                let (sum, count) = children
+                    .iter()
+                    .filter(|v| v.is_finite())
+                    .fold((0.0, 0), |(sum, count), &v| (sum + v as f64, count + 1));
+                if count > 0 {
+                    (sum / count as f64) as f32
+                } else {
+                    f32::NAN
+                }
+            })
+            .collect();
+        Self {
+            name: self.name.clone(),
+            depth,
+            values,
+            scale: self.scale.clone(),
+            stats: self.stats.clone(),
+        }
+    }
+
+    /// Values of the base cells in the layout of the tiles of the sky texture,
+    /// row after row
+    ///
+    /// The columns of a tile go along the dy axis of its cell and its rows along the dx one.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Width and height of the tiles, a power of two
+    pub fn tiles(&self, size: u32) -> Vec<Vec<f32>> {
+        let tile_depth = size.trailing_zeros() as u8;
+        let degraded;
+        let map = if self.depth > tile_depth {
+            degraded = self.degrade(tile_depth);
+            &degraded
+        } else {
+            self
+        };
+        let shift = tile_depth - map.depth;
+
+        (0..12)
+            .map(|base: u64| {
+                let mut tile = Vec::with_capacity((size * size) as usize);
+                for row in 0..size {
+                    for col in 0..size {
+                        let hash = (base << (2 * map.depth)) | healpix::ij2z(row >> shift, col >> shift);
+                        tile.push(map.values[hash as usize]);
+                    }
+                }
+                tile
+            })
+            .collect()
+    }
+}
+
+// Values of the NESTED cells of the ICRS, each one taken from the cell of the map frame
+// containing its center
+fn resample(depth: u8, values: &[f32], icrs_to_map: &Mat3<f64>) -> Vec<f32> {
+    (0..healpix::n_cells(depth))
+        .map(|hash| {
+            let (x, y, z) = healpix::sph_coo(depth, hash, 0.5, 0.5);
+            // The repo (x, y, z) frame is the usual (Y, Z, X) one
+            let pos = icrs_to_map * Vec3::new(y, z, x);
+            let (hash, _, _) = healpix::hash_with_dxdy(depth, pos.z, pos.x, pos.y);
+            values[hash as usize]
+        })
+        .collect()
+}
+
+/// Binary table of a FITS file holding a HEALPix map
+pub fn find_map<'a, 'b>(hdus: &'b [Hdu<'a>]) -> Option<&'b Hdu<'a>> {
+    hdus.iter().find(|hdu| {
+        let header = &hdu.header;
+        header.get("XTENSION") == Some("BINTABLE")
+            && header.get_i64("NSIDE").is_some()
+            && header
+                .get("ORDERING")
+                .is_some_and(|o| o.eq_ignore_ascii_case("RING") || o.eq_ignore_ascii_case("NESTED"))
+    })
+}
+
+mod tests {
+    #[test]
+    fn healpix_map() {
+        use super::*;
+        use cgmath::{InnerSpace, Matrix};
+
+        // NSIDE 2 map in the RING scheme, holding the ring numbers in rows of 8 cells
+        let card = |text: &str| format!("{:<80}", text);
+        let block = |cards: &[String]| {
+            let mut text: String = cards.concat();
+            text.push_str(&card("END"));
+            format!("{:<2880}", text).into_bytes()
+        };
+        let mut bytes = block(&[
+            card("SIMPLE  =                    T"),
+            card("BITPIX  =                    8"),
+            card("NAXIS   =                    0"),
+        ]);
+        bytes.extend(block(&[
+            card("XTENSION= 'BINTABLE'"),
+            card("BITPIX  =                    8"),
+            card("NAXIS   =                    2"),
+            card("NAXIS1  =                   32"),
+            card("NAXIS2  =                    6"),
+            card("TFIELDS =                    1"),
+            card("TTYPE1  = 'TEMPERATURE'"),
+            card("TFORM1  = '8E'"),
+            card("PIXTYPE = 'HEALPIX'"),
+            card("ORDERING= 'RING'"),
+            card("NSIDE   =                    2"),
+        ]));
+        let mut data = vec![];
+        for ring in 0..48 {
+            let v = if ring == 47 { UNSEEN } else { ring as f32 };
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        data.resize(2880, 0);
+        bytes.extend(data);
+
+        let map = HealpixMap::from_fits("map".to_string(), &bytes).unwrap();
+        assert_eq!(map.depth(), 1);
+        for ring in 0..47 {
+            assert_eq!(map.values()[healpix::ring_to_nested(1, ring) as usize], ring as f32);
+        }
+        assert!(map.values()[healpix::ring_to_nested(1, 47) as usize].is_nan());
+        assert_eq!(map.scale.cuts(), (0.0, 46.0));
+
+        // The missing cell is left out of the mean of the last base cell
+        let degraded = map.degrade(0);
+        let last: Vec<f32> = (0..47)
+            .filter(|&ring| healpix::ring_to_nested(1, ring) >= 44)
+            .map(|ring| ring as f32)
+            .collect();
+        assert_eq!(last.len(), 3);
+        assert_eq!(degraded.values()[11], last.iter().sum::<f32>() / 3.0);
+
+        let tiles = map.tiles(4);
+        assert_eq!(tiles.len(), 12);
+        assert_eq!(tiles[0].len(), 16);
+        // The northern cell of the base cell 0 holds the first ring
+        assert_eq!(tiles[0][3 * 4 + 3], 0.0);
+
+        // A galactic map holding the numbers of the base cells, resampled in the ICRS
+        let values: Vec<f32> = (0..healpix::n_cells(3)).map(|hash| (hash >> 6) as f32).collect();
+        let map = HealpixMap::new("map".to_string(), 3, resample(3, &values, &frame::icrs_to_galactic())).unwrap();
+        // The galactic center is at the center of the base cell 4, and the north
+        // galactic pole in the base cell 0
+        let galactic_to_icrs = frame::icrs_to_galactic().transpose();
+        assert_eq!(map.value(&(galactic_to_icrs * Vec3::unit_z())), 4.0);
+        let pole = galactic_to_icrs * Vec3::new(0.3, 1.0, 0.3).normalize();
+        assert_eq!(map.value(&pole), 0.0);
+
+        assert!(HealpixMap::new("map".to_string(), 1, vec![0.0; 47]).is_err());
+    }
+}
@@ -0,0 +1,323 @@
+// HiPS (hierarchical progressive surveys) directories, of which the tiles of the order 0
+// are drawn as the base survey and the deeper ones are sampled by the cutouts.
+use crate::error::Error;
+use crate::frame;
+use crate::healpix;
+use crate::math::{Mat3, Vec2, Vec3};
+use crate::sky_source;
+use crate::wcs::Wcs;
+
+use cgmath::SquareMatrix;
+
+use image::RgbaImage;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The `key = value` metadata of a HiPS, from its `properties` file
+#[derive(Clone, Debug, Default)]
+pub struct HipsProperties {
+    entries: Vec<(String, String)>,
+}
+
+impl HipsProperties {
+    /// Parse the lines of a properties file, the ones starting with # being comments
+    pub fn parse(text: &str) -> Self {
+        let entries = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                Some((key.trim().to_string(), value.trim().to_string()))
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// Value of a key, the last one if it is given several times
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Replace the value of a key, or add it
+    pub fn set(&mut self, key: &str, value: String) {
+        self.entries.retain(|(k, _)| k != key);
+        self.entries.push((key.to_string(), value));
+    }
+
+    /// Solar system body mapped by the HiPS, None for the sky
+    pub fn body(&self) -> Option<&str> {
+        self.get("hips_body")
+            .filter(|body| !body.is_empty() && !body.eq_ignore_ascii_case("sky"))
+    }
+
+    /// Rotation from the ICRS to the frame of the tiles of the sky
+    pub fn icrs_to_frame(&self) -> Result<Mat3<f64>, Error> {
+        match self.get("hips_frame").unwrap_or("equatorial") {
+            "equatorial" => Ok(Mat3::identity()),
+            "galactic" => Ok(frame::icrs_to_galactic()),
+            frame => Err(Error::Parse(format!("unsupported HiPS frame {}", frame))),
+        }
+    }
+
+    /// Deepest order of the tiles
+    pub fn max_order(&self) -> u8 {
+        self.get("hips_order").and_then(|order| order.parse().ok()).unwrap_or(0)
+    }
+
+    /// Order of the least deep tiles
+    pub fn min_order(&self) -> u8 {
+        self.get("hips_order_min").and_then(|order| order.parse().ok()).unwrap_or(0)
+    }
+
+    /// Width of the tiles in pixels
+    pub fn tile_width(&self) -> u32 {
+        self.get("hips_tile_width").and_then(|width| width.parse().ok()).unwrap_or(512)
+    }
+
+    /// Extension of the image tiles, preferring the JPEG ones
+    fn tile_extension(&self) -> Option<&'static str> {
+        let formats = self.get("hips_tile_format").unwrap_or("jpeg");
+        if formats.split_whitespace().any(|f| f == "jpeg") {
+            Some("jpg")
+        } else if formats.split_whitespace().any(|f| f == "png") {
+            Some("png")
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for HipsProperties {
+    /// The lines of a properties file
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.entries {
+            writeln!(f, "{:<20}= {}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// The properties of a HiPS and its tiles of the 12 base HEALPix cells
+pub struct Hips {
+    pub properties: HipsProperties,
+    /// Tiles indexed by their cell number, transparent where the HiPS has no tile,
+    /// resampled in the ICRS for the galactic skies
+    pub tiles: Vec<RgbaImage>,
+}
+
+impl Hips {
+    /// Read a HiPS from its directory
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        let mut reader = HipsReader::open(dir)?;
+        let tiles = (0..12)
+            .map(|cell| Ok(reader.tile(0, cell)?.cloned()))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let size = tiles
+            .iter()
+            .flatten()
+            .map(|tile| tile.width())
+            .next()
+            .ok_or_else(|| Error::Parse("no tile of the order 0".to_string()))?;
+        let mut tiles: Vec<RgbaImage> = tiles
+            .into_iter()
+            .map(|tile| tile.unwrap_or_else(|| RgbaImage::new(size, size)))
+            .collect();
+        // The planets are drawn in their body-fixed frame
+        if reader.properties.body().is_none() {
+            let icrs_to_frame = reader.properties.icrs_to_frame()?;
+            if icrs_to_frame != Mat3::identity() {
+                tiles = resample(&tiles, &icrs_to_frame);
+            }
+        }
+
+        Ok(Self {
+            properties: reader.properties,
+            tiles,
+        })
+    }
+}
+
+// Tiles of the order 0 of the ICRS, each texel being the one of the tiles of another
+// frame containing its center
+fn resample(tiles: &[RgbaImage], icrs_to_frame: &Mat3<f64>) -> Vec<RgbaImage> {
+    (0..12)
+        .map(|cell| {
+            let (w, h) = tiles[cell as usize].dimensions();
+            RgbaImage::from_fn(w, h, |col, row| {
+                // The columns of a tile go along the dy axis of its cell and its rows along the dx one
+                let dx = (row as f64 + 0.5) / h as f64;
+                let dy = (col as f64 + 0.5) / w as f64;
+                let (x, y, z) = healpix::sph_coo(0, cell, dx, dy);
+                // The repo (x, y, z) frame is the usual (Y, Z, X) one
+                let pos = icrs_to_frame * Vec3::new(y, z, x);
+                image::Rgba(sky_source::tile_pixel(tiles, 0, &pos).unwrap_or_default())
+            })
+        })
+        .collect()
+}
+
+/// Tiles of a HiPS directory, each one being read when it is first needed
+pub struct HipsReader {
+    dir: PathBuf,
+    pub properties: HipsProperties,
+    ext: &'static str,
+    // None for the tiles missing from a HiPS covering a part of the sphere only
+    tiles: HashMap<(u8, u64), Option<RgbaImage>>,
+}
+
+impl HipsReader {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        let properties = HipsProperties::parse(&std::fs::read_to_string(dir.join("properties"))?);
+        let ext = properties.tile_extension().ok_or_else(|| {
+            Error::Parse(format!(
+                "no JPEG or PNG tiles in the {} format",
+                properties.get("hips_tile_format").unwrap_or_default()
+            ))
+        })?;
+        Ok(Self {
+            dir,
+            properties,
+            ext,
+            tiles: HashMap::new(),
+        })
+    }
+
+    /// Tile of a cell, None if the HiPS has none
+    pub fn tile(&mut self, order: u8, cell: u64) -> Result<Option<&RgbaImage>, Error> {
+        if !self.tiles.contains_key(&(order, cell)) {
+            let path = self.dir.join(tile_path(order, cell, self.ext));
+            let tile = match image::open(&path) {
+                Ok(tile) => Some(tile.to_rgba8()),
+                Err(image::ImageError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(Error::Parse(format!("{}: {}", path.display(), e))),
+            };
+            self.tiles.insert((order, cell), tile);
+        }
+        Ok(self.tiles[&(order, cell)].as_ref())
+    }
+
+    /// RGBA value of the pixel of the tiles of an order containing a position, None
+    /// where the HiPS has no tile
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - Order of the tiles
+    /// * `pos_icrs` - Unit vector in the frame of the HiPS
+    pub fn pixel(&mut self, order: u8, pos_icrs: &Vec3<f64>) -> Result<Option<[u8; 4]>, Error> {
+        // The repo (x, y, z) frame is the usual (Y, Z, X) one
+        let (cell, dx, dy) = healpix::hash_with_dxdy(order, pos_icrs.z, pos_icrs.x, pos_icrs.y);
+        Ok(self.tile(order, cell)?.map(|tile| sky_source::tile_texel(tile, dx, dy)))
+    }
+
+    /// Reproject the tiles into an image, transparent where the HiPS has no tile or
+    /// the projection no position, the rows going down
+    ///
+    /// The order is chosen from the size of the pixels at the reference point.
+    ///
+    /// # Arguments
+    ///
+    /// * `wcs` - WCS of the image
+    /// * `size` - Width and height of the image in pixels
+    pub fn cutout(&mut self, wcs: &Wcs, size: (u32, u32)) -> Result<RgbaImage, Error> {
+        let icrs_to_hips = self.properties.icrs_to_frame()?;
+        let order = self.order_for(wcs.cd.determinant().abs().sqrt());
+
+        let (w, h) = size;
+        let mut image = RgbaImage::new(w, h);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            // The first row of the FITS images is the bottom one
+            let pos = match wcs.pixel_to_icrs(&Vec2::new(x as f64 + 1.0, (h - y) as f64)) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            if let Some(rgba) = self.pixel(order, &(icrs_to_hips * pos))? {
+                *pixel = image::Rgba(rgba);
+            }
+        }
+        Ok(image)
+    }
+
+    /// Order of the tiles whose pixels are at least as fine as a resolution, or the
+    /// deepest one
+    ///
+    /// # Arguments
+    ///
+    /// * `resolution` - Angular size in degrees
+    pub fn order_for(&self, resolution: f64) -> u8 {
+        let (min_order, max_order) = (self.properties.min_order(), self.properties.max_order());
+        let tile_order = self.properties.tile_width().max(1).ilog2() as u8;
+        (min_order..=max_order)
+            .find(|&order| pixel_size(order + tile_order) <= resolution)
+            .unwrap_or(max_order.max(min_order))
+    }
+}
+
+/// Path of a tile in a HiPS directory, the tiles being grouped by 10000
+pub fn tile_path(order: u8, cell: u64, ext: &str) -> String {
+    format!("Norder{}/Dir{}/Npix{}.{}", order, (cell / 10_000) * 10_000, cell, ext)
+}
+
+/// Mean angular size in degrees of the HEALPix cells of an order
+pub fn pixel_size(order: u8) -> f64 {
+    (4.0 * std::f64::consts::PI / healpix::n_cells(order) as f64).sqrt().to_degrees()
+}
+
+mod tests {
+    #[test]
+    fn hips() {
+        use super::*;
+        use cgmath::Matrix;
+
+        let properties = HipsProperties::parse(
+            "# Mars\n\
+             creator_did = ivo://CDS/P/Mars/MOLA-color\n\
+             hips_body   = mars\n\
+             hips_tile_format = png jpeg\n\
+             hips_frame = equatorial\n",
+        );
+        assert_eq!(properties.get("creator_did"), Some("ivo://CDS/P/Mars/MOLA-color"));
+        assert_eq!(properties.body(), Some("mars"));
+        assert_eq!(properties.tile_extension(), Some("jpg"));
+        assert_eq!(properties.get("# Mars"), None);
+        let mut written = HipsProperties::parse(&properties.to_string());
+        assert_eq!(written.body(), Some("mars"));
+        written.set("hips_body", "moon".to_string());
+        assert_eq!((written.body(), written.entries.len()), (Some("moon"), 4));
+
+        let properties = HipsProperties::parse("hips_body = sky\nhips_tile_format = fits\n");
+        assert_eq!(properties.body(), None);
+        assert_eq!(properties.tile_extension(), None);
+        assert_eq!(HipsProperties::parse("").tile_extension(), Some("jpg"));
+
+        assert_eq!(tile_path(3, 123_456, "png"), "Norder3/Dir120000/Npix123456.png");
+        assert!((pixel_size(0) - 58.6).abs() < 0.1);
+        let reader = HipsReader {
+            dir: PathBuf::new(),
+            properties: HipsProperties::parse("hips_order = 5\nhips_order_min = 1\nhips_tile_width = 256\n"),
+            ext: "jpg",
+            tiles: HashMap::new(),
+        };
+        // The pixels of the tiles of 256 pixels of the order 3 are 0.029° wide
+        assert_eq!(reader.order_for(0.03), 3);
+        assert_eq!(reader.order_for(10.0), 1);
+        assert_eq!(reader.order_for(1e-6), 5);
+
+        // The tiles of a galactic sky resampled in the ICRS, each one holding the number
+        // of its cell
+        let tiles: Vec<RgbaImage> = (0..12)
+            .map(|cell| RgbaImage::from_pixel(8, 8, image::Rgba([cell, 0, 0, 255])))
+            .collect();
+        let resampled = resample(&tiles, &frame::icrs_to_galactic());
+        // The galactic center is at the center of the cell 4
+        let galactic_center = frame::icrs_to_galactic().transpose() * Vec3::unit_z();
+        assert_eq!(sky_source::tile_pixel(&resampled, 0, &galactic_center), Some([4, 0, 0, 255]));
+        assert_eq!(resample(&tiles, &Mat3::identity()), tiles);
+    }
+}
@@ -0,0 +1,351 @@
+// Generation of HiPS from FITS images with a WCS: the images are resampled into the tiles
+// of the deepest order, from which the tiles of the lower orders are downsampled.
+use crate::angle::ArcDeg;
+use crate::cuts::{AutoCuts, SkyStats};
+use crate::error::Error;
+use crate::frame::Epoch;
+use crate::healpix;
+use crate::hips::{self, HipsProperties};
+use crate::images::ImageLayer;
+use crate::math::{self, Vec3};
+use crate::moc::{self, Moc};
+use crate::sky_source;
+
+use cgmath::SquareMatrix;
+use image::RgbaImage;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+// Order of the Allsky previews, or the deepest one if it is lower
+const ALLSKY_ORDER: u8 = 3;
+// Width of the tiles in the Allsky previews
+const ALLSKY_TILE_WIDTH: u32 = 64;
+// Percentiles of the values drawn at the bottom and at the top of the grayscale
+const CUTS: AutoCuts = AutoCuts::Percentile { low: 0.5, high: 99.5 };
+
+/// Order whose tiles have pixels at least as fine as the ones of the images
+///
+/// # Arguments
+///
+/// * `images` - FITS images with their WCS
+/// * `tile_width` - Width of the tiles in pixels, a power of two
+pub fn default_order(images: &[ImageLayer], tile_width: u32) -> u8 {
+    let resolution = images
+        .iter()
+        .map(|layer| layer.wcs.cd.determinant().abs().sqrt())
+        .fold(f64::INFINITY, f64::min);
+    let tile_order = tile_width.max(1).ilog2() as u8;
+    // The pixels of the tiles are cells of the order of the tiles plus the one of their width
+    let max_order = healpix::MAX_DEPTH.saturating_sub(tile_order);
+    (0..=max_order)
+        .find(|&order| hips::pixel_size(order + tile_order) <= resolution)
+        .unwrap_or(max_order)
+}
+
+/// Write the HiPS of FITS images into a directory: the PNG tiles of the orders up to the
+/// deepest one, the Allsky preview, the properties and the coverage, which is returned
+///
+/// The images share their grayscale, between the 0.5 and 99.5 percentiles of their
+/// values, and the mean of their values is drawn where they overlap.
+///
+/// # Arguments
+///
+/// * `images` - FITS images with their WCS
+/// * `dir` - Directory of the HiPS, created if needed
+/// * `order` - Deepest order of the tiles
+/// * `tile_width` - Width of the tiles in pixels, a power of two
+/// * `title` - Title of the HiPS
+pub fn generate(
+    images: &[ImageLayer],
+    dir: &Path,
+    order: u8,
+    tile_width: u32,
+    title: &str,
+) -> Result<Moc, Error> {
+    let first = images
+        .first()
+        .ok_or_else(|| Error::Parse("no image to generate the HiPS from".to_string()))?;
+    if !tile_width.is_power_of_two() {
+        return Err(Error::Parse(format!("the tile width {} is not a power of two", tile_width)));
+    }
+    if order > healpix::MAX_DEPTH {
+        return Err(Error::Parse(format!("the order {} is deeper than {}", order, healpix::MAX_DEPTH)));
+    }
+
+    // Shared cuts, around the ones of each image
+    let all_cells: Vec<u64> = (0..healpix::n_cells(SkyStats::DEPTH)).collect();
+    let (low, high) = images
+        .iter()
+        .filter_map(|layer| layer.stats.cuts(CUTS, &all_cells))
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), (l, h)| (low.min(l), high.max(h)));
+    let mut scale = first.scale.clone();
+    if low <= high {
+        scale.set_cuts((low, high), &first.image.data);
+    }
+
+    // Tiles of the deepest order holding values
+    let mut tiles = BTreeMap::new();
+    for cell in coverage(images, order, tile_width) {
+        let tile = RgbaImage::from_fn(tile_width, tile_width, |col, row| {
+            let (dx, dy) = texel_center(col, row, tile_width);
+            let pos = moc::cell_point(order, cell, dx, dy);
+            let color = value(images, &pos).and_then(|value| scale.color(value));
+            image::Rgba(color.map_or([0; 4], |[r, g, b]| [r, g, b, 255]))
+        });
+        if tile.pixels().any(|pixel| pixel.0[3] > 0) {
+            tiles.insert(cell, tile);
+        }
+    }
+    let moc = Moc::from_cells(order, tiles.keys().map(|&cell| (order, cell)));
+
+    // The orders from the deepest one to 0
+    let allsky_order = ALLSKY_ORDER.min(order);
+    for depth in (0..=order).rev() {
+        for (&cell, tile) in &tiles {
+            write_tile(dir, depth, cell, tile)?;
+        }
+        if depth == allsky_order {
+            let path = dir.join(format!("Norder{}", depth));
+            std::fs::create_dir_all(&path)?;
+            allsky(depth, &tiles, tile_width)
+                .save(path.join("Allsky.png"))?;
+        }
+        if depth > 0 {
+            tiles = downsample(depth, &tiles, tile_width);
+        }
+    }
+
+    std::fs::write(dir.join("Moc.fits"), moc.to_fits())?;
+    let properties = properties(images, title, order, tile_width, (scale.cuts(), &moc));
+    std::fs::write(dir.join("properties"), properties.to_string())?;
+    Ok(moc)
+}
+
+// Offset in its cell of the center of a pixel of a tile, the columns going along dy
+// and the rows along dx as in `sky_source::tile_texel`
+fn texel_center(col: u32, row: u32, tile_width: u32) -> (f64, f64) {
+    let w = tile_width as f64;
+    ((row as f64 + 0.5) / w, (col as f64 + 0.5) / w)
+}
+
+// Cells of an order containing pixels of the images, the images being sampled finely
+// enough for the cells to contain several of the sampled pixels
+fn coverage(images: &[ImageLayer], order: u8, tile_width: u32) -> BTreeSet<u64> {
+    let cell_size = hips::pixel_size(order);
+    let mut cells = BTreeSet::new();
+    for layer in images {
+        let (w, h) = (layer.image.width, layer.image.height);
+        let resolution = layer.wcs.cd.determinant().abs().sqrt();
+        let step = ((0.25 * cell_size / resolution) as usize).clamp(1, tile_width as usize);
+        // The last row and column are sampled too
+        let cols = (0..w).step_by(step).chain(std::iter::once(w.saturating_sub(1)));
+        for col in cols {
+            let rows = (0..h).step_by(step).chain(std::iter::once(h.saturating_sub(1)));
+            for row in rows {
+                let pixel = math::Vec2::new(col as f64 + 1.0, row as f64 + 1.0);
+                if let Some(pos) = layer.wcs.pixel_to_icrs(&pixel) {
+                    // The repo (x, y, z) frame is the usual (Y, Z, X) one
+                    cells.insert(healpix::hash_with_dxdy(order, pos.z, pos.x, pos.y).0);
+                }
+            }
+        }
+    }
+    cells
+}
+
+// Mean of the values of the images at a position, None where none has a value
+fn value(images: &[ImageLayer], pos: &Vec3<f64>) -> Option<f32> {
+    let (sum, n) = images
+        .iter()
+        .filter_map(|layer| {
+            let pixel = layer.wcs.icrs_to_pixel(pos)?;
+            // Nearest pixel, the center of the first one being (1, 1)
+            let (col, row) = ((pixel.x - 0.5).floor(), (pixel.y - 0.5).floor());
+            let (w, h) = (layer.image.width, layer.image.height);
+            if col < 0.0 || row < 0.0 || col >= w as f64 || row >= h as f64 {
+                return None;
+            }
+            let value = layer.image.data[row as usize * w + col as usize];
+            value.is_finite().then_some(value)
+        })
+        .fold((0.0, 0), |(sum, n), value| (sum + value, n + 1));
+    (n > 0).then(|| sum / n as f32)
+}
+
+// Tiles of the parent cells, each pixel being the mean of the 4 pixels of the children
+// it covers weighted by their opacity
+fn downsample(depth: u8, tiles: &BTreeMap<u64, RgbaImage>, tile_width: u32) -> BTreeMap<u64, RgbaImage> {
+    let parents: BTreeSet<u64> = tiles.keys().map(|cell| cell >> 2).collect();
+    let w = tile_width as f64;
+    parents
+        .into_iter()
+        .map(|parent| {
+            let tile = RgbaImage::from_fn(tile_width, tile_width, |col, row| {
+                let (mut rgb, mut alpha) = ([0.0; 3], 0.0);
+                for (ddx, ddy) in [(0.25, 0.25), (0.25, 0.75), (0.75, 0.25), (0.75, 0.75)] {
+                    let (dx, dy) = ((row as f64 + ddx) / w, (col as f64 + ddy) / w);
+                    let pos = moc::cell_point(depth - 1, parent, dx, dy);
+                    let (cell, dx, dy) = healpix::hash_with_dxdy(depth, pos.z, pos.x, pos.y);
+                    if let Some(child) = tiles.get(&cell) {
+                        let [r, g, b, a] = sky_source::tile_texel(child, dx, dy);
+                        let a = a as f64 / 255.0;
+                        for (sum, c) in rgb.iter_mut().zip([r, g, b]) {
+                            *sum += a * c as f64;
+                        }
+                        alpha += a;
+                    }
+                }
+                if alpha == 0.0 {
+                    return image::Rgba([0; 4]);
+                }
+                let [r, g, b] = rgb.map(|c| (c / alpha).round() as u8);
+                image::Rgba([r, g, b, (alpha / 4.0 * 255.0).round() as u8])
+            });
+            (parent, tile)
+        })
+        .collect()
+}
+
+fn write_tile(dir: &Path, depth: u8, cell: u64, tile: &RgbaImage) -> Result<(), Error> {
+    let path = dir.join(hips::tile_path(depth, cell, "png"));
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    tile.save(&path)
+        .map_err(|e| Error::Parse(format!("{}: {}", path.display(), e)))
+}
+
+// Preview of the tiles of an order side by side, from left to right then top to bottom
+fn allsky(depth: u8, tiles: &BTreeMap<u64, RgbaImage>, tile_width: u32) -> RgbaImage {
+    let n = healpix::n_cells(depth);
+    let cols = (n as f64).sqrt() as u64;
+    let rows = n.div_ceil(cols);
+    let w = ALLSKY_TILE_WIDTH.min(tile_width);
+    let mut allsky = RgbaImage::new(cols as u32 * w, rows as u32 * w);
+    for (&cell, tile) in tiles {
+        let tile = image::imageops::resize(tile, w, w, image::imageops::FilterType::Triangle);
+        let (x, y) = ((cell % cols) as u32 * w, (cell / cols) as u32 * w);
+        image::imageops::replace(&mut allsky, &tile, x, y);
+    }
+    allsky
+}
+
+fn properties(
+    images: &[ImageLayer],
+    title: &str,
+    order: u8,
+    tile_width: u32,
+    (cuts, moc): ((f32, f32), &Moc),
+) -> HipsProperties {
+    // Initial view on the center of the first image
+    let layer = &images[0];
+    let (w, h) = (layer.image.width as f64, layer.image.height as f64);
+    let center = layer.wcs.pixel_to_icrs(&math::Vec2::new(0.5 * (w + 1.0), 0.5 * (h + 1.0)));
+    let (ra, dec) = center.map_or((0.0, 0.0), |pos| {
+        let (ra, dec) = math::xyz_to_radec(&pos);
+        (ArcDeg::from(ra).0.rem_euclid(360.0), ArcDeg::from(dec).0)
+    });
+    let fov = layer.wcs.cd.determinant().abs().sqrt() * w.max(h);
+
+    let mut properties = HipsProperties::default();
+    let id: String = title
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect();
+    for (key, value) in [
+        ("creator_did", format!("ivo://PRIVATE_USER/P/{}", id)),
+        ("obs_title", title.to_string()),
+        ("dataproduct_type", "image".to_string()),
+        ("hips_version", "1.4".to_string()),
+        ("hips_builder", "sky-hipsgen".to_string()),
+        ("hips_release_date", iso_date(&Epoch::now())),
+        ("hips_frame", "equatorial".to_string()),
+        ("hips_order", order.to_string()),
+        ("hips_order_min", "0".to_string()),
+        ("hips_tile_width", tile_width.to_string()),
+        ("hips_tile_format", "png".to_string()),
+        ("hips_pixel_cut", format!("{} {}", cuts.0, cuts.1)),
+        ("hips_initial_ra", format!("{:.6}", ra)),
+        ("hips_initial_dec", format!("{:.6}", dec)),
+        ("hips_initial_fov", format!("{:.6}", fov)),
+        ("moc_sky_fraction", format!("{:.6e}", moc.sky_fraction())),
+    ] {
+        properties.set(key, value);
+    }
+    properties
+}
+
+// UTC date and time of an epoch, e.g. 2024-03-05T14:07Z
+fn iso_date(epoch: &Epoch) -> String {
+    let minutes = ((epoch.0 - 2_440_587.5) * 1440.0).floor() as i64;
+    let (days, minutes) = (minutes.div_euclid(1440), minutes.rem_euclid(1440));
+    // Civil date of the days since 1970-01-01 (H. Hinnant)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}Z", year, month, day, minutes / 60, minutes % 60)
+}
+
+mod tests {
+    #[test]
+    fn hipsgen() {
+        use super::*;
+        use crate::angle::Angle;
+        use crate::fits;
+        use crate::hips::HipsReader;
+
+        assert_eq!(iso_date(&Epoch(2_451_545.0)), "2000-01-01T12:00Z");
+        assert_eq!(iso_date(&Epoch(2_460_374.5 + 0.25)), "2024-03-05T06:00Z");
+
+        // Gradient along the rows of a 4° wide image on the equator
+        let (w, h) = (64, 48);
+        let data: Vec<f32> = (0..(w * h)).map(|k| (k / w) as f32).collect();
+        let planes = [data.iter().map(|&v| v as u8).collect::<Vec<u8>>()];
+        let cards = [
+            fits::card("CTYPE1", "'RA---TAN'"),
+            fits::card("CTYPE2", "'DEC--TAN'"),
+            fits::card("CRPIX1", "32.5"),
+            fits::card("CRPIX2", "24.5"),
+            fits::card("CRVAL1", "45.0"),
+            fits::card("CRVAL2", "0.0"),
+            fits::card("CDELT1", "-0.0625"),
+            fits::card("CDELT2", "0.0625"),
+        ];
+        let bytes = fits::write_u8_image(w, h, &planes, &cards);
+        let images = [ImageLayer::from_fits("gradient".to_string(), &bytes).unwrap()];
+        assert_eq!(default_order(&images, 512), 1);
+        assert_eq!(default_order(&images, 16), 6);
+        // Tiles too wide for any order of the sphere
+        assert_eq!(default_order(&images, 1 << 31), 0);
+
+        let dir = std::env::temp_dir().join(format!("sky-hipsgen-{}", std::process::id()));
+        let moc = generate(&images, &dir, 4, 16, "Test gradient").unwrap();
+        let center = math::radec_to_xyz(Angle::new(ArcDeg(45.0)), Angle::new(ArcDeg(0.0)));
+        assert!(moc.contains(&center));
+        assert!(!moc.contains(&Vec3::unit_y()));
+
+        // Read back at every order, the north of the image being brighter where
+        // the pixels are small enough
+        let mut reader = HipsReader::open(&dir).unwrap();
+        assert_eq!(reader.properties.get("hips_order"), Some("4"));
+        assert_eq!(reader.properties.get("hips_tile_format"), Some("png"));
+        let north = math::radec_to_xyz(Angle::new(ArcDeg(45.0)), Angle::new(ArcDeg(1.4)));
+        for order in 0..=4 {
+            let [c, ..] = reader.pixel(order, &center).unwrap().unwrap();
+            let [n, _, _, a] = reader.pixel(order, &north).unwrap().unwrap();
+            assert!(a > 0 && (order < 3 || n > c), "order {}: {} {}", order, n, c);
+        }
+        assert_eq!(reader.pixel(4, &Vec3::unit_y()).unwrap(), None);
+        let allsky = image::image_dimensions(dir.join("Norder3/Allsky.png")).unwrap();
+        assert_eq!(allsky, (27 * 16, 29 * 16));
+        let moc = Moc::from_file(dir.join("Moc.fits")).unwrap();
+        assert!(moc.contains(&center));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
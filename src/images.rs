@@ -0,0 +1,467 @@
+use crate::colormap::{ColorScale, Colormap, ColormapUniform};
+use crate::cuts::SkyStats;
+use crate::error::Error;
+use crate::healpix;
+use crate::fits::{self, Image};
+use crate::glsl;
+use crate::layers::BlendMode;
+use crate::math;
+use crate::vertex::Vertex;
+use crate::wcs::Wcs;
+
+use cgmath::SquareMatrix;
+use std::path::Path;
+
+/// A FITS image reprojected onto the sky
+pub struct ImageLayer {
+    pub name: String,
+    pub image: Image,
+    pub wcs: Wcs,
+    pub scale: ColorScale,
+    /// Histograms of the pixel values for the automatic cuts
+    pub stats: SkyStats,
+    pub opacity: f32,
+    pub visible: bool,
+    pub blend: BlendMode,
+}
+
+impl ImageLayer {
+    /// Read the first image of a FITS file and its world coordinate system
+    pub fn from_fits(name: String, bytes: &[u8]) -> Result<Self, Error> {
+        let hdus = fits::parse(bytes)?;
+        let hdu = fits::find_image(&hdus).ok_or_else(|| Error::Parse("FITS: no image".to_string()))?;
+        let image = hdu.image()?;
+        let wcs = Wcs::from_header(&hdu.header)?;
+
+        let mut scale = ColorScale::new(&image.data);
+        scale.colormap = Colormap::Grayscale;
+
+        let (width, data) = (image.width, &image.data);
+        let step = SkyStats::sampling_step(data.len());
+        let pixels = (0..data.len()).step_by(step).filter_map(|k| {
+            let pixel = math::Vec2::new((k % width) as f64 + 1.0, (k / width) as f64 + 1.0);
+            // The repo (x, y, z) frame is the usual (Y, Z, X) one
+            let pos = wcs.pixel_to_icrs(&pixel)?;
+            let (cell, _, _) = healpix::hash_with_dxdy(SkyStats::DEPTH, pos.z, pos.x, pos.y);
+            Some((cell, data[k]))
+        });
+        let stats = SkyStats::new(SkyStats::DEPTH, pixels);
+
+        Ok(Self {
+            name,
+            image,
+            wcs,
+            scale,
+            stats,
+            opacity: 1.0,
+            visible: true,
+            blend: BlendMode::Alpha,
+        })
+    }
+
+    /// Change the values drawn at the bottom and at the top of the colormap
+    pub fn set_cuts(&mut self, cuts: (f32, f32)) {
+        self.scale.set_cuts(cuts, &self.image.data);
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let name = path
+            .as_ref()
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::from_fits(name, &std::fs::read(path)?)
+    }
+}
+
+// Average blocks of pixels so that the image fits in a texture, ignoring the blank ones
+fn bin(image: &Image, factor: usize) -> Image {
+    if factor == 1 {
+        return image.clone();
+    }
+    let (width, height) = (image.width / factor, image.height / factor);
+    let mut data = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let (mut sum, mut count) = (0.0, 0);
+            for j in 0..factor {
+                let row = &image.data[((y * factor + j) * image.width + x * factor)..][..factor];
+                for &v in row.iter().filter(|v| v.is_finite()) {
+                    sum += v;
+                    count += 1;
+                }
+            }
+            data.push(if count > 0 { sum / count as f32 } else { f32::NAN });
+        }
+    }
+    Image { width, height, data }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ImageUniform {
+    icrs_to_native: [[f32; 4]; 4],
+    crpix: [f32; 4],
+    cd_inv: [f32; 4],
+    params: [f32; 4],
+    display: [f32; 4],
+    sip_a: [[f32; 4]; 25],
+    sip_b: [[f32; 4]; 25],
+}
+
+impl ImageUniform {
+    fn new(layer: &ImageLayer, texture_size: (u32, u32), factor: usize) -> Self {
+        let wcs = &layer.wcs;
+        let icrs_to_native: math::Mat4<f32> = wcs.icrs_to_native.cast::<f32>().unwrap().into();
+        let cd_inv = wcs.cd.invert().unwrap();
+        let scale = wcs.projection.clip_to_plane();
+
+        let mut sip_a = [[0.0; 4]; 25];
+        let mut sip_b = [[0.0; 4]; 25];
+        if let Some(sip) = &wcs.sip {
+            for k in 0..100 {
+                sip_a[k / 4][k % 4] = sip.a[k] as f32;
+                sip_b[k / 4][k % 4] = sip.b[k] as f32;
+            }
+        }
+
+        Self {
+            icrs_to_native: icrs_to_native.into(),
+            crpix: [
+                wcs.crpix.x as f32,
+                wcs.crpix.y as f32,
+                texture_size.0 as f32,
+                texture_size.1 as f32,
+            ],
+            cd_inv: [
+                cd_inv.x.x as f32,
+                cd_inv.y.x as f32,
+                cd_inv.x.y as f32,
+                cd_inv.y.y as f32,
+            ],
+            params: [
+                wcs.projection.index() as f32,
+                scale.x as f32,
+                scale.y as f32,
+                wcs.sip.as_ref().map_or(0, |sip| sip.order) as f32,
+            ],
+            display: [layer.opacity, factor as f32, 0.0, 0.0],
+            sip_a,
+            sip_b,
+        }
+    }
+}
+
+struct GpuImage {
+    texture_size: (u32, u32),
+    factor: usize,
+    uniform_buffer: wgpu::Buffer,
+    colormap_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    visible: bool,
+    blend: BlendMode,
+}
+
+/// Draws FITS images by inverse mapping the fragments of the sky into their pixels
+pub struct ImageRenderer {
+    // One pipeline per blend mode
+    pipelines: Vec<wgpu::RenderPipeline>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    images: Vec<GpuImage>,
+}
+
+impl ImageRenderer {
+    /// # Arguments
+    ///
+    /// * `sky_bind_group_layout` - Layout of the bind group of the sky, whose view uniforms are reused
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, sky_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<ImageUniform>() as wgpu::BufferAddress,
+                        ),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<ColormapUniform>() as wgpu::BufferAddress,
+                        ),
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("images_bind_group_layout"),
+        });
+
+        let vs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("allsky vert shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: include_str!("shaders/allsky.vert").into(),
+                stage: naga::ShaderStage::Vertex,
+                defines: Default::default(),
+            },
+        });
+        let fs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("images frag shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: glsl::with_shared(include_str!("shaders/images.frag"), &[glsl::COMMON, glsl::COLORMAP]).into(),
+                stage: naga::ShaderStage::Fragment,
+                defines: Default::default(),
+            },
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Images Pipeline Layout"),
+            bind_group_layouts: &[sky_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |mode: &BlendMode| device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Images Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vs_shader,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                buffers: &[Vertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_shader,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(mode.blend_state()),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
+        let pipelines = BlendMode::ALL.iter().map(create_pipeline).collect();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            pipelines,
+            bind_group_layout,
+            sampler,
+            images: vec![],
+        }
+    }
+
+    /// Upload the pixels of the layers, binned if they exceed the texture size limit
+    ///
+    /// This must be called whenever layers are added or removed, not at every frame.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, layers: &[ImageLayer]) {
+        let max_size = device.limits().max_texture_dimension_2d as usize;
+        self.images = layers
+            .iter()
+            .map(|layer| {
+                let factor = layer.image.width.max(layer.image.height).div_ceil(max_size).max(1);
+                let image = bin(&layer.image, factor);
+                let size = wgpu::Extent3d {
+                    width: image.width.max(1) as u32,
+                    height: image.height.max(1) as u32,
+                    depth_or_array_layers: 1,
+                };
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some(&layer.name),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::R32Float,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                });
+                if !image.data.is_empty() {
+                    queue.write_texture(
+                        wgpu::ImageCopyTexture {
+                            texture: &texture,
+                            mip_level: 0,
+                            origin: wgpu::Origin3d::ZERO,
+                            aspect: wgpu::TextureAspect::All,
+                        },
+                        bytemuck::cast_slice(&image.data),
+                        wgpu::ImageDataLayout {
+                            offset: 0,
+                            bytes_per_row: Some(4 * size.width),
+                            rows_per_image: Some(size.height),
+                        },
+                        size,
+                    );
+                }
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+                let texture_size = (image.width as u32, image.height as u32);
+                let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("image uniform"),
+                    size: std::mem::size_of::<ImageUniform>() as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let uniform = ImageUniform::new(layer, texture_size, factor);
+                queue.write_buffer(&uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+                let colormap_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("image colormap uniform"),
+                    size: std::mem::size_of::<ColormapUniform>() as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                queue.write_buffer(&colormap_buffer, 0, bytemuck::bytes_of(&layer.scale.uniform()));
+
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: uniform_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: colormap_buffer.as_entire_binding(),
+                        },
+                    ],
+                    label: Some("images_bind_group"),
+                });
+
+                GpuImage {
+                    texture_size,
+                    factor,
+                    uniform_buffer,
+                    colormap_buffer,
+                    bind_group,
+                    visible: layer.visible,
+                    blend: layer.blend,
+                }
+            })
+            .collect();
+    }
+
+    /// Update the color scales, opacity, visibility and blend mode of the layers for the next frame
+    pub fn prepare(&mut self, queue: &wgpu::Queue, layers: &[ImageLayer]) {
+        for (gpu, layer) in self.images.iter_mut().zip(layers) {
+            let uniform = ImageUniform::new(layer, gpu.texture_size, gpu.factor);
+            queue.write_buffer(&gpu.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+            queue.write_buffer(&gpu.colormap_buffer, 0, bytemuck::bytes_of(&layer.scale.uniform()));
+            gpu.visible = layer.visible;
+            gpu.blend = layer.blend;
+        }
+    }
+
+    /// Draw an image over the sky mesh
+    ///
+    /// The sky bind group, vertex and index buffers must be set in the render pass.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - Index of the layer in the list given to `upload`
+    pub fn draw_layer<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, idx: usize, num_indices: u32) {
+        if let Some(image) = self.images.get(idx).filter(|image| image.visible) {
+            render_pass.set_pipeline(&self.pipelines[image.blend.index()]);
+            render_pass.set_bind_group(1, &image.bind_group, &[]);
+            render_pass.draw_indexed(0..num_indices, 0, 0..1);
+        }
+    }
+}
+
+mod tests {
+    #[test]
+    fn images() {
+        use super::*;
+
+        // A 4x2 image of 16 bit integers with a blank pixel
+        let mut text = String::new();
+        for card in [
+            "SIMPLE  =                    T",
+            "BITPIX  =                   16",
+            "NAXIS   =                    2",
+            "NAXIS1  =                    4",
+            "NAXIS2  =                    2",
+            "BZERO   =                100.0",
+            "BSCALE  =                  0.5",
+            "BLANK   =                   -1",
+            "CTYPE1  = 'RA---TAN'",
+            "CTYPE2  = 'DEC--TAN'",
+            "CRPIX1  =                  2.5",
+            "CRPIX2  =                  1.5",
+            "CRVAL1  =                 10.0",
+            "CRVAL2  =                -30.0",
+            "CDELT1  =               -0.001",
+            "CDELT2  =                0.001",
+            "END",
+        ] {
+            text.push_str(&format!("{:<80}", card));
+        }
+        let mut bytes = format!("{:<2880}", text).into_bytes();
+        for v in [0i16, 2, 4, 6, -1, 10, 12, 14] {
+            bytes.extend_from_slice(&v.to_be_bytes());
+        }
+        bytes.resize(2 * 2880, 0);
+
+        let layer = ImageLayer::from_fits("test".to_string(), &bytes).unwrap();
+        assert_eq!((layer.image.width, layer.image.height), (4, 2));
+        assert_eq!(layer.image.data[1], 101.0);
+        assert!(layer.image.data[4].is_nan());
+        assert_eq!(layer.scale.cuts(), (100.0, 107.0));
+
+        // The blank pixel is left out of the averages
+        let binned = bin(&layer.image, 2);
+        assert_eq!((binned.width, binned.height), (2, 1));
+        assert_eq!(binned.data, vec![(100.0 + 101.0 + 105.0) / 3.0, 104.5]);
+    }
+}
@@ -0,0 +1,175 @@
+// The images, composites, coverage maps and catalogs drawn over the base survey, in the order of a stack.
+// The layer shaders output premultiplied colors, so that each blend mode takes the opacity into account.
+
+/// How a layer is combined with the layers below it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    Alpha,
+    Additive,
+    Multiply,
+    Screen,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 4] = [
+        BlendMode::Alpha,
+        BlendMode::Additive,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BlendMode::Alpha => "alpha",
+            BlendMode::Additive => "additive",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Screen => "screen",
+        }
+    }
+
+    /// Mode following this one, cycling back to the first
+    pub fn next(self) -> Self {
+        Self::ALL[(self.index() + 1) % Self::ALL.len()]
+    }
+
+    /// Index of the mode in `BlendMode::ALL`, e.g. of its pipeline
+    pub fn index(self) -> usize {
+        Self::ALL.iter().position(|&m| m == self).unwrap()
+    }
+
+    /// Blend state of the pipelines drawing the layers in this mode, for premultiplied colors
+    pub fn blend_state(self) -> wgpu::BlendState {
+        let (src_factor, dst_factor) = match self {
+            BlendMode::Alpha => (wgpu::BlendFactor::One, wgpu::BlendFactor::OneMinusSrcAlpha),
+            BlendMode::Additive => (wgpu::BlendFactor::One, wgpu::BlendFactor::One),
+            // dst * (1 - a + a * src), i.e. the product faded by the opacity
+            BlendMode::Multiply => (wgpu::BlendFactor::Dst, wgpu::BlendFactor::OneMinusSrcAlpha),
+            // dst + a * src * (1 - dst)
+            BlendMode::Screen => (wgpu::BlendFactor::OneMinusDst, wgpu::BlendFactor::One),
+        };
+        wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor,
+                dst_factor,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::OVER,
+        }
+    }
+}
+
+/// Kind of data a layer draws
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerKind {
+    Image,
+    Composite,
+    Moc,
+    Catalog,
+}
+
+/// A layer of the stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayerRef {
+    pub kind: LayerKind,
+    /// Index of the data of the layer among the layers of its kind
+    pub index: usize,
+}
+
+/// Draw order of the layers over the base survey
+///
+/// The data of the layers is kept in one list per kind, in the order they were added,
+/// the stack referring to them by index.
+#[derive(Default)]
+pub struct LayerStack {
+    // From the bottom to the top
+    layers: Vec<LayerRef>,
+}
+
+impl LayerStack {
+    /// Put a layer added last to the list of its kind on top of the others
+    ///
+    /// Returns its position in the stack.
+    pub fn push(&mut self, kind: LayerKind) -> usize {
+        let index = self.layers.iter().filter(|layer| layer.kind == kind).count();
+        self.layers.push(LayerRef { kind, index });
+        self.layers.len() - 1
+    }
+
+    /// Remove the layer at a position, its data being removed from the list of its kind
+    /// by the caller
+    pub fn remove(&mut self, pos: usize) -> Option<LayerRef> {
+        if pos >= self.layers.len() {
+            return None;
+        }
+        let removed = self.layers.remove(pos);
+        for layer in &mut self.layers {
+            if layer.kind == removed.kind && layer.index > removed.index {
+                layer.index -= 1;
+            }
+        }
+        Some(removed)
+    }
+
+    /// Move the layer at a position to another one, shifting the layers in between
+    pub fn move_layer(&mut self, from: usize, to: usize) {
+        if from < self.layers.len() && to < self.layers.len() {
+            let layer = self.layers.remove(from);
+            self.layers.insert(to, layer);
+        }
+    }
+
+    pub fn get(&self, pos: usize) -> Option<LayerRef> {
+        self.layers.get(pos).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// The layers from the bottom to the top
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = LayerRef> + ExactSizeIterator + '_ {
+        self.layers.iter().copied()
+    }
+}
+
+mod tests {
+    #[test]
+    fn layers() {
+        use super::*;
+
+        let mut stack = LayerStack::default();
+        assert_eq!(stack.push(LayerKind::Image), 0);
+        stack.push(LayerKind::Catalog);
+        stack.push(LayerKind::Image);
+        assert_eq!(stack.push(LayerKind::Moc), 3);
+        assert_eq!(stack.get(2), Some(LayerRef { kind: LayerKind::Image, index: 1 }));
+
+        // The second image goes to the bottom
+        stack.move_layer(2, 0);
+        let order: Vec<_> = stack.iter().map(|layer| (layer.kind, layer.index)).collect();
+        assert_eq!(
+            order,
+            vec![
+                (LayerKind::Image, 1),
+                (LayerKind::Image, 0),
+                (LayerKind::Catalog, 0),
+                (LayerKind::Moc, 0)
+            ]
+        );
+
+        // Removing the first image shifts the index of the second one
+        assert_eq!(stack.remove(1), Some(LayerRef { kind: LayerKind::Image, index: 0 }));
+        assert_eq!(stack.get(0), Some(LayerRef { kind: LayerKind::Image, index: 0 }));
+        assert_eq!(stack.remove(3), None);
+        assert_eq!(stack.len(), 3);
+        assert_eq!(stack.push(LayerKind::Image), 3);
+        assert_eq!(stack.get(3).unwrap().index, 1);
+
+        assert_eq!(BlendMode::Screen.next(), BlendMode::Alpha);
+        assert_eq!(BlendMode::Alpha.blend_state(), wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING);
+    }
+}
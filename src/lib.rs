@@ -21,13 +21,58 @@ mod vertex;
 mod time;
 
 use time::Clock;
-use vertex::Vertex;
+use vertex::{LineVertex, Vertex};
 use texture::Texture;
 use crate::math::Vec4;
+// Distance in pixels under which a click picks a source
+const PICK_RADIUS_PX: f64 = 8.0;
+// Number of columns of a picked source shown
+const MAX_PICKED_FIELDS: usize = 12;
+// Width and height of the tiles of the base HEALPix cells
+const TILE_SIZE: u32 = 512;
+// Points of the window sampled along each axis to find the cells in view
+const VIEW_SAMPLES: u32 = 32;
 const NUM_PROJECTIONS: i32 = 6;
+#[cfg(not(target_arch = "wasm32"))]
+const USAGE: &str = "usage: wgpu-sky-rendering [CATALOG...] [--stars FILE] [--constellations FILE] \
+                     [--boundaries FILE] [--regions FILE] [--moc FILE] [--image FILE] [--healpix FILE] \
+                     [--sky FILE] [--hips DIR] [--rgb RED GREEN BLUE] [--projection NAME|INDEX] \
+                     [--epoch JD|JYEAR] [--rotation DEG] [--pressure HPA] [--temperature CELSIUS] \
+                     [--output FILE [--size WIDTHxHEIGHT] [--reference]]";
+// Options of the command line, the other arguments being the catalogs to load
+#[cfg(not(target_arch = "wasm32"))]
+const OPTIONS: &[&str] = &[
+    "--stars",
+    "--constellations",
+    "--boundaries",
+    "--regions",
+    "--moc",
+    "--image",
+    "--healpix",
+    "--sky",
+    "--hips",
+    "--rgb",
+    "--projection",
+    "--epoch",
+    "--rotation",
+    "--pressure",
+    "--temperature",
+    "--output",
+    "--size",
+    "--reference",
+];
+const PROJECTION_NAMES: [&str; NUM_PROJECTIONS as usize] = [
+    "Aitoff",
+    "Orthographic",
+    "Mollweide",
+    "Mercator",
+    "Azimuthal equidistant",
+    "Gnomonic",
+];
 
 struct State<'a> {
-    surface: wgpu::Surface<'a>,
+    // None when rendering offscreen
+    surface: Option<wgpu::Surface<'a>>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
@@ -36,7 +81,7 @@ struct State<'a> {
     // The window must be declared after the surface so
     // it gets dropped after it as the surface contains
     // unsafe references to the window's resources.
-    window: &'a Window,
+    window: Option<&'a Window>,
 
     render_pipeline: wgpu::RenderPipeline,
     vertex_buffer: wgpu::Buffer,
@@ -44,24 +89,252 @@ struct State<'a> {
     num_indices: u32,
 
     map_texture: texture::Texture,
+    // Scalar values of the HEALPix map, drawn through the colormap
+    values_texture: texture::Texture,
+    // Panoramas drawn instead of the tiles of the base survey
+    equirect_texture: texture::Texture,
+    cube_texture: texture::Texture,
     texture_bind_group_layout: wgpu::BindGroupLayout,
     diffuse_bind_group: wgpu::BindGroup,
 
     // uniforms
     rot_mat_buf: wgpu::Buffer,
     window_size_buf: wgpu::Buffer,
+    horizon_buf: wgpu::Buffer,
+    solar_system_buf: wgpu::Buffer,
+    colormap_buf: wgpu::Buffer,
+    sky_source_buf: wgpu::Buffer,
 
     clock: Clock,
+    // Epoch of the view and its rotation in radians, given on the command line or frozen
+    // offscreen, the current time and the clock otherwise
+    epoch: Option<Epoch>,
+    rotation: Option<f32>,
+
+    observer: Observer,
+    frame: CooFrame,
+    // The sky, or a planetary body seen from the outside
+    sphere: SphereMode,
+    refraction: bool,
+    airmass_contours: bool,
+    solar_system: bool,
+
+    // Decoded images of the base survey kept on the CPU side for the cursor readout
+    sky_source: SkySource,
+    // Metadata of the HiPS the base survey was loaded from
+    hips_properties: Option<HipsProperties>,
+    // HEALPix map drawn instead of the tiles of the base survey
+    sky_map: Option<HealpixMap>,
+    // HEALPix cells in view, from which the automatic cuts are computed
+    visible_cells: Vec<u64>,
+    projection: usize,
+    rot: Mat4<f32>,
+    cursor: Option<winit::dpi::PhysicalPosition<f64>>,
+    cursor_info: Option<CursorInfo>,
+
+    // Line overlays
+    line_pipeline: wgpu::RenderPipeline,
+    grid_vertex_buffer: wgpu::Buffer,
+    num_grid_vertices: u32,
+    grid: Option<GridFrame>,
+    graticule: Option<Graticule>,
+    // Triangles of the coverage maps then of the filled regions, drawn with one pipeline
+    // per blend mode
+    fill_pipelines: Vec<wgpu::RenderPipeline>,
+    fill_vertex_buffer: wgpu::Buffer,
+    num_fill_vertices: u32,
+    // Vertices of each coverage map in the fill buffer
+    moc_fills: Vec<std::ops::Range<u32>>,
+    // Lines of the grid, the constellations, the coverage maps and the regions, kept until
+    // what they depend on changes
+    overlay_lines: Vec<LineVertex>,
+    overlay_key: Option<OverlayKey>,
+    // Changed when overlays are loaded or removed
+    overlays_generation: u64,
+
+    text: TextRenderer,
+
+    // Catalog overlays
+    catalogs: Vec<CatalogLayer>,
+    markers: MarkerRenderer,
+    modifiers: winit::keyboard::ModifiersState,
+    drag: Option<Drag>,
+    // Layer and row of the source picked with the last click
+    picked: Option<(usize, usize)>,
+
+    // Bright star layer
+    stars: Option<StarCatalog>,
+    star_renderer: StarRenderer,
+    show_stars: bool,
+    limiting_mag: f64,
+
+    constellations: Constellations,
+    show_figures: bool,
+    show_boundaries: bool,
+    show_constellation_names: bool,
+
+    // Footprints and regions of interest
+    regions: Vec<Region>,
+    show_regions: bool,
+
+    // Coverage maps
+    mocs: Vec<MocLayer>,
+    show_moc_cells: bool,
+
+    // FITS images reprojected onto the sky
+    images: Vec<ImageLayer>,
+    image_renderer: ImageRenderer,
+
+    // False color composites of three HEALPix maps
+    composites: Vec<CompositeLayer>,
+    composite_renderer: CompositeRenderer,
+
+    // Draw order of the images, composites, coverage maps and catalogs over the base survey
+    layers: LayerStack,
+    // Position in the stack of the layer changed with the keyboard
+    selected_layer: usize,
 }
 
-mod angle;
+// Selection gestures of the mouse
+enum DragKind {
+    Click,
+    Box,
+    Lasso,
+}
+
+struct Drag {
+    kind: DragKind,
+    // Window positions in pixels
+    points: Vec<Vec2<f64>>,
+}
+
+// What the clip space geometry of the overlays depends on
+#[derive(Clone, PartialEq)]
+struct OverlayKey {
+    projection: usize,
+    sphere: SphereMode,
+    rot: Mat4<f32>,
+    size: (u32, u32),
+    frame: CooFrame,
+    refraction: bool,
+    // Seconds since J2000 in the horizontal frame, which turns with the epoch
+    second: Option<i64>,
+    grid: Option<GridFrame>,
+    show_figures: bool,
+    show_boundaries: bool,
+    show_regions: bool,
+    show_moc_cells: bool,
+    // Visibility, color and opacity of the coverage maps
+    mocs: Vec<(bool, [f32; 4], f32)>,
+    generation: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct HorizonUniform {
+    hor_to_icrs: [[f32; 4]; 4],
+    params: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SolarSystemUniform {
+    // For each body: its direction and marker radius, then its color and marker kind
+    bodies: [[[f32; 4]; 2]; 9],
+    moon_to_sun: [f32; 4],
+    params: [f32; 4],
+}
+
+impl SolarSystemUniform {
+    fn new(epoch: &Epoch, visible: bool) -> Self {
+        let mut bodies = [[[0.0; 4]; 2]; 9];
+        for (body, marker) in Body::ALL.iter().zip(bodies.iter_mut()) {
+            let dir = body.direction(epoch);
+            // Markers are drawn with a fixed angular radius, the Sun and the Moon
+            // are drawn as disks, the planets as rings
+            let (radius, color, kind) = match body {
+                Body::Sun => (2.0_f32, [1.0, 0.9, 0.4], 1.0),
+                Body::Moon => (2.0, [0.9, 0.9, 0.85], 2.0),
+                Body::Mercury => (0.8, [0.7, 0.7, 0.7], 0.0),
+                Body::Venus => (0.8, [1.0, 1.0, 0.8], 0.0),
+                Body::Mars => (0.8, [1.0, 0.4, 0.2], 0.0),
+                Body::Jupiter => (0.8, [0.9, 0.75, 0.55], 0.0),
+                Body::Saturn => (0.8, [0.95, 0.85, 0.5], 0.0),
+                Body::Uranus => (0.8, [0.6, 0.9, 0.95], 0.0),
+                Body::Neptune => (0.8, [0.4, 0.5, 1.0], 0.0),
+            };
+            *marker = [
+                [dir.x as f32, dir.y as f32, dir.z as f32, radius.to_radians()],
+                [color[0], color[1], color[2], kind],
+            ];
+        }
+        let moon_to_sun = MoonPhase::sun_direction(epoch);
+
+        Self {
+            bodies,
+            moon_to_sun: [moon_to_sun.x as f32, moon_to_sun.y as f32, moon_to_sun.z as f32, 0.0],
+            params: [visible as u8 as f32, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+pub mod angle;
+pub mod catalog;
+pub mod colormap;
+pub mod composite;
+pub mod constellations;
+pub mod cursor;
+pub mod cuts;
+pub mod ephemeris;
+pub mod error;
+pub mod fits;
+mod font;
+pub mod frame;
+mod glsl;
+pub mod graticule;
+pub mod healpix;
+pub mod healpix_map;
+pub mod hips;
+pub mod hipsgen;
+pub mod images;
+pub mod layers;
+pub mod markers;
+pub mod moc;
 mod math;
-mod projection;
+pub mod overlay;
+pub mod picking;
+pub mod projection;
+pub mod reference;
+pub mod regions;
+pub mod sky_source;
+pub mod stars;
+pub mod text;
+pub mod wcs;
 mod triangulation;
 use crate::projection::*;
 use crate::triangulation::Triangulation;
 use math::Vec2;
 use crate::math::Vec3;
+use crate::frame::{CooFrame, Epoch, Observer, SphereMode};
+use crate::ephemeris::{Body, MoonPhase};
+use crate::cursor::CursorInfo;
+use crate::graticule::{Graticule, GridFrame};
+use crate::markers::{CatalogLayer, MarkerRenderer, MarkerShape, MarkerStyle, MarkerView};
+use crate::stars::{StarCatalog, StarRenderer};
+use crate::constellations::Constellations;
+use crate::regions::Region;
+use crate::sky_source::SkySource;
+use crate::hips::{Hips, HipsProperties};
+use crate::moc::{Moc, MocLayer};
+use crate::healpix_map::HealpixMap;
+use crate::colormap::{ColorScale, ColormapUniform};
+use crate::cuts::{AutoCuts, SkyStats};
+use crate::images::{ImageLayer, ImageRenderer};
+use crate::composite::{CompositeLayer, CompositeRenderer};
+use crate::layers::{BlendMode, LayerKind, LayerRef, LayerStack};
+use crate::overlay::View;
+use crate::angle::{Angle, ArcDeg};
+use crate::text::{TextLayout, TextRenderer};
 fn generate_position<P: Projection<f32>>(size: u32) -> Vec<u8> {
     let (w, h) = (size as f32, size as f32);
     let mut data = vec![];
@@ -70,8 +343,8 @@ fn generate_position<P: Projection<f32>>(size: u32) -> Vec<u8> {
         for x in 0..(w as u32) {
             let xy = Vec2::new(x, y);
             let clip_xy = Vec2::new(
-                2.0 * ((xy.x as f32) / (w as f32)) - 1.0,
-                2.0 * ((xy.y as f32) / (h as f32)) - 1.0,
+                2.0 * ((xy.x as f32) / w) - 1.0,
+                2.0 * ((xy.y as f32) / h) - 1.0,
             );
             if let Some(pos) = P::clip_to_world_space(&clip_xy) {
                 let pos = Vec3::new(
@@ -100,34 +373,62 @@ pub fn create_position_texture<P: Projection<f32>>(
 
     let dimensions = (size, size, 1);
     let num_bytes_per_pixel = 4;
-    Texture::from_raw_bytes::<u8>(&device, &queue, Some(bytes), dimensions, num_bytes_per_pixel, "position")
+    Texture::from_raw_bytes::<u8>(device, queue, Some(bytes), dimensions, num_bytes_per_pixel, "position")
+}
+
+fn create_line_buffer(device: &wgpu::Device, num_vertices: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("line vertex buffer"),
+        size: (num_vertices * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
 }
 
 use crate::math::Mat4;
+use cgmath::{InnerSpace, Matrix};
 impl<'a> State<'a> {
     async fn new(window: &'a Window) -> Self {
-        let size = window.inner_size();
+        Self::with_window(Some(window), window.inner_size()).await
+    }
+
+    /// State drawing offscreen, without any window
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn new_headless(size: winit::dpi::PhysicalSize<u32>) -> State<'static> {
+        let mut state = State::with_window(None, size).await;
+        // The image does not depend on how long the loading takes
+        state.epoch = Some(Epoch::now());
+        state.rotation = Some(0.0);
+        state
+    }
 
+    async fn with_window(window: Option<&'a Window>, size: winit::dpi::PhysicalSize<u32>) -> Self {
         // The instance is a handle to our GPU
         // BackendBit::all => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            // Offscreen, any adapter will do, e.g. a software OpenGL one
             #[cfg(not(target_arch="wasm32"))]
-            backends: wgpu::Backends::PRIMARY,
+            backends: if window.is_some() { wgpu::Backends::PRIMARY } else { wgpu::Backends::all() },
             #[cfg(target_arch="wasm32")]
             backends: wgpu::Backends::GL,
             ..Default::default()
         });
 
-        let surface = instance.create_surface(window).unwrap();
+        let surface = window.map(|window| instance.create_surface(window).unwrap());
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
+        let mut options = wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: surface.as_ref(),
+            force_fallback_adapter: false,
+        };
+        let adapter = match instance.request_adapter(&options).await {
+            Some(adapter) => adapter,
+            None => {
+                // A software implementation
+                options.force_fallback_adapter = true;
+                instance.request_adapter(&options).await.expect("no graphics adapter found")
+            }
+        };
 
         let (device, queue) = adapter
             .request_device(
@@ -152,23 +453,38 @@ impl<'a> State<'a> {
             .await
             .unwrap();
 
-        let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an sRGB surface texture. Using a different
-        // one will result in all the colors coming out darker. If you want to support non
-        // sRGB surfaces, you'll need to account for that when drawing to the frame.
-        let surface_format = surface_caps.formats.iter()
-            .find(|f| f.is_srgb())
-            .copied()
-            .unwrap_or(surface_caps.formats[0]);
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![surface_format.add_srgb_suffix()],
-            desired_maximum_frame_latency: 2,
+        let config = match &surface {
+            Some(surface) => {
+                let surface_caps = surface.get_capabilities(&adapter);
+                // Shader code in this tutorial assumes an sRGB surface texture. Using a different
+                // one will result in all the colors coming out darker. If you want to support non
+                // sRGB surfaces, you'll need to account for that when drawing to the frame.
+                let surface_format = surface_caps.formats.iter()
+                    .find(|f| f.is_srgb())
+                    .copied()
+                    .unwrap_or(surface_caps.formats[0]);
+                wgpu::SurfaceConfiguration {
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    format: surface_format,
+                    width: size.width,
+                    height: size.height,
+                    present_mode: surface_caps.present_modes[0],
+                    alpha_mode: surface_caps.alpha_modes[0],
+                    view_formats: vec![surface_format.add_srgb_suffix()],
+                    desired_maximum_frame_latency: 2,
+                }
+            }
+            // Format of the offscreen textures
+            None => wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                width: size.width,
+                height: size.height,
+                present_mode: wgpu::PresentMode::Fifo,
+                alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+                view_formats: vec![],
+                desired_maximum_frame_latency: 2,
+            },
         };
 
         /*let bytes = include_bytes!("../img/map.png");
@@ -179,7 +495,7 @@ impl<'a> State<'a> {
             &device,
             &queue,
             None,
-            (512, 512, 12),
+            (TILE_SIZE, TILE_SIZE, 12),
             4,
             "base HEALPix cells"
         );
@@ -199,13 +515,16 @@ impl<'a> State<'a> {
             include_bytes!("../img/Npix11.jpg").to_vec()
         ];
 
-        for (idx, tile_bytes) in tiles.iter().enumerate() {
-            let rgba_tile = image::load_from_memory(&tile_bytes).unwrap().to_rgba8();
+        let tiles: Vec<image::RgbaImage> = tiles
+            .iter()
+            .map(|tile_bytes| image::load_from_memory(tile_bytes).unwrap().to_rgba8())
+            .collect();
+        for (idx, rgba_tile) in tiles.iter().enumerate() {
             map_texture.write_data(
                 &queue,
                 (0, 0, idx as u32),
-                &rgba_tile,
-                (512, 512, 1)
+                rgba_tile,
+                (TILE_SIZE, TILE_SIZE, 1)
             );
         }
 
@@ -224,6 +543,44 @@ impl<'a> State<'a> {
             mapped_at_creation: false,
         });
 
+        let horizon_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("horizon uniform"),
+            size: std::mem::size_of::<HorizonUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let solar_system_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("solar system uniform"),
+            size: std::mem::size_of::<SolarSystemUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let colormap_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("colormap uniform"),
+            size: std::mem::size_of::<ColormapUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // Replaced by the tiles of the HEALPix maps when they are loaded
+        let values_texture = Texture::from_scalar_values(
+            &device,
+            &queue,
+            Some(&[0.0; 12]),
+            (1, 1, 12),
+            "scalar values of the base HEALPix cells"
+        );
+
+        let sky_source_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("sky source uniform"),
+            contents: bytemuck::cast_slice(&[0.0_f32; 4]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        // Replaced by the panoramas when they are loaded
+        let equirect_texture = Texture::from_raw_bytes::<u8>(&device, &queue, None, (1, 1, 1), 4, "equirectangular image");
+        let cube_texture = Texture::from_cube_faces(&device, &queue, &[], "cubemap");
+
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[
@@ -269,6 +626,89 @@ impl<'a> State<'a> {
                         },
                         count: None,
                     },
+                    // horizon uniform
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(
+                                std::mem::size_of::<HorizonUniform>() as wgpu::BufferAddress,
+                            ),
+                        },
+                        count: None,
+                    },
+                    // solar system uniform
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(
+                                std::mem::size_of::<SolarSystemUniform>() as wgpu::BufferAddress,
+                            ),
+                        },
+                        count: None,
+                    },
+                    // scalar values of the HEALPix maps
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    // colormap uniform
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(
+                                std::mem::size_of::<ColormapUniform>() as wgpu::BufferAddress,
+                            ),
+                        },
+                        count: None,
+                    },
+                    // sky source uniform
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(16),
+                        },
+                        count: None,
+                    },
+                    // equirectangular image
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 9,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    // cubemap
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 10,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("texture_bind_group_layout"),
             });
@@ -304,6 +744,34 @@ impl<'a> State<'a> {
                         ),
                     }),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: horizon_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: solar_system_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&values_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: colormap_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: sky_source_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::TextureView(&equirect_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::TextureView(&cube_texture.view),
+                },
             ],
             label: Some("diffuse_bind_group"),
         });
@@ -322,7 +790,7 @@ impl<'a> State<'a> {
             device.create_shader_module(wgpu::ShaderModuleDescriptor {
                 label: Some("allsky frag shader"),
                 source: wgpu::ShaderSource::Glsl {
-                    shader: include_str!("shaders/allsky.frag").into(),
+                    shader: glsl::with_shared(include_str!("shaders/allsky.frag"), &[glsl::COMMON, glsl::COLORMAP]).into(),
                     stage: naga::ShaderStage::Fragment,
                     defines: Default::default()
                 },
@@ -376,6 +844,82 @@ impl<'a> State<'a> {
             cache: None, // 6.
         });
 
+        let lines_vs_shader =
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("lines vert shader"),
+                source: wgpu::ShaderSource::Glsl {
+                    shader: include_str!("shaders/lines.vert").into(),
+                    stage: naga::ShaderStage::Vertex,
+                    defines: Default::default()
+                }
+            });
+        let lines_fs_shader =
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("lines frag shader"),
+                source: wgpu::ShaderSource::Glsl {
+                    shader: include_str!("shaders/lines.frag").into(),
+                    stage: naga::ShaderStage::Fragment,
+                    defines: Default::default()
+                },
+            });
+        // The line overlays and the region fills are drawn over the sky with the same bind group
+        let create_overlay_pipeline = |label, topology, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&render_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &lines_vs_shader,
+                    entry_point: "main",
+                    compilation_options: Default::default(),
+                    buffers: &[LineVertex::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &lines_fs_shader,
+                    entry_point: "main",
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: config.format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+                cache: None,
+            })
+        };
+        let line_pipeline = create_overlay_pipeline(
+            "Line Pipeline",
+            wgpu::PrimitiveTopology::LineList,
+            BlendMode::Alpha.blend_state(),
+        );
+        let fill_pipelines = BlendMode::ALL
+            .iter()
+            .map(|mode| create_overlay_pipeline("Fill Pipeline", wgpu::PrimitiveTopology::TriangleList, mode.blend_state()))
+            .collect();
+        let grid_vertex_buffer = create_line_buffer(&device, 1 << 16);
+        let fill_vertex_buffer = create_line_buffer(&device, 1 << 12);
+
+        let text = TextRenderer::new(&device, &queue, config.format);
+        let markers = MarkerRenderer::new(&device, config.format);
+        let star_renderer = StarRenderer::new(&device, config.format);
+        let image_renderer = ImageRenderer::new(&device, config.format, &texture_bind_group_layout);
+        let composite_renderer = CompositeRenderer::new(&device, config.format, &texture_bind_group_layout);
+
         let (vertices, indices) = Triangulation::create::<Aitoff>();
 
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -404,6 +948,9 @@ impl<'a> State<'a> {
             num_indices,
 
             map_texture,
+            values_texture,
+            equirect_texture,
+            cube_texture,
 
             texture_bind_group_layout,
             diffuse_bind_group,
@@ -411,13 +958,82 @@ impl<'a> State<'a> {
             // uniforms
             window_size_buf,
             rot_mat_buf,
+            horizon_buf,
+            solar_system_buf,
+            colormap_buf,
+            sky_source_buf,
             clock,
+            epoch: None,
+            rotation: None,
+
+            observer: Observer::default(),
+            frame: CooFrame::Equatorial,
+            sphere: SphereMode::Sky,
+            refraction: true,
+            airmass_contours: false,
+            solar_system: true,
+
+            sky_source: SkySource::Healpix(tiles),
+            hips_properties: None,
+            sky_map: None,
+            visible_cells: vec![],
+            projection: 0,
+            rot: Mat4::from_scale(1.0),
+            cursor: None,
+            cursor_info: None,
+
+            line_pipeline,
+            grid_vertex_buffer,
+            num_grid_vertices: 0,
+            grid: None,
+            graticule: None,
+            fill_pipelines,
+            fill_vertex_buffer,
+            num_fill_vertices: 0,
+            moc_fills: vec![],
+            overlay_lines: vec![],
+            overlay_key: None,
+            overlays_generation: 0,
+
+            text,
+
+            catalogs: vec![],
+            markers,
+            modifiers: Default::default(),
+            drag: None,
+            picked: None,
+
+            stars: None,
+            star_renderer,
+            show_stars: true,
+            limiting_mag: 0.0,
+
+            constellations: Constellations::default(),
+            show_figures: true,
+            show_boundaries: true,
+            show_constellation_names: true,
+
+            regions: vec![],
+            show_regions: true,
+
+            mocs: vec![],
+            show_moc_cells: false,
+
+            images: vec![],
+            image_renderer,
+
+            composites: vec![],
+            composite_renderer,
+
+            layers: LayerStack::default(),
+            selected_layer: 0,
         };
         app.resize::<Aitoff>(size);
 
         app
     }
 
+    #[cfg_attr(not(target_arch = "wasm32"), allow(unused_mut))]
     fn resize<P: Projection<f32>>(&mut self, mut new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             #[cfg(target_arch="wasm32")] {
@@ -428,77 +1044,1265 @@ impl<'a> State<'a> {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            }
         }
 
         let ndc = P::compute_ndc_to_clip_factor(self.size.width as f32, self.size.height as f32);
         self.queue.write_buffer(
             &self.window_size_buf,
             0,
-            bytemuck::bytes_of(&[ndc.x, ndc.y, 0.0, 0.0]),
+            bytemuck::bytes_of(&[ndc.x, ndc.y, self.sphere.x_sign() as f32, 0.0]),
         );
     }
 
-    #[allow(unused_variables)]
     fn input(&mut self, event: &WindowEvent) -> bool {
-        false
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(code),
+                        ..
+                    },
+                ..
+            } => match code {
+                // Toggle between the equatorial and the horizontal frames, the planetary
+                // bodies having no horizon
+                KeyCode::KeyH if self.sphere == SphereMode::Sky => {
+                    self.frame = match self.frame {
+                        CooFrame::Equatorial => CooFrame::Horizontal,
+                        CooFrame::Horizontal => CooFrame::Equatorial,
+                    };
+                    true
+                }
+                KeyCode::KeyR => {
+                    self.refraction = !self.refraction;
+                    true
+                }
+                KeyCode::KeyA => {
+                    self.airmass_contours = !self.airmass_contours;
+                    true
+                }
+                KeyCode::KeyP => {
+                    self.solar_system = !self.solar_system;
+                    true
+                }
+                // Cycle through no grid, the equatorial and the galactic grids, or toggle
+                // the planetocentric one
+                KeyCode::KeyG => {
+                    self.grid = match (&self.sphere, self.grid) {
+                        (SphereMode::Planet(_), None) => Some(GridFrame::Planetocentric),
+                        (SphereMode::Planet(_), Some(_)) => None,
+                        (SphereMode::Sky, None) => Some(GridFrame::Equatorial),
+                        (SphereMode::Sky, Some(GridFrame::Equatorial)) => Some(GridFrame::Galactic),
+                        (SphereMode::Sky, Some(_)) => None,
+                    };
+                    true
+                }
+                // Toggle between the sky and the planetary mode, for the body of the HiPS if it has one
+                KeyCode::KeyE => {
+                    let mode = match self.sphere {
+                        SphereMode::Sky => {
+                            let body = self.hips_properties.as_ref().and_then(|properties| properties.body());
+                            SphereMode::Planet(body.unwrap_or("planet").to_string())
+                        }
+                        SphereMode::Planet(_) => SphereMode::Sky,
+                    };
+                    self.set_sphere_mode(mode);
+                    true
+                }
+                KeyCode::KeyS => {
+                    self.show_stars = !self.show_stars;
+                    true
+                }
+                // Constellation lines, boundaries and names
+                KeyCode::KeyL => {
+                    self.show_figures = !self.show_figures;
+                    true
+                }
+                KeyCode::KeyB => {
+                    self.show_boundaries = !self.show_boundaries;
+                    true
+                }
+                KeyCode::KeyN => {
+                    self.show_constellation_names = !self.show_constellation_names;
+                    true
+                }
+                // Coverage maps and the outlines of their cells
+                KeyCode::KeyM => {
+                    let visible = !self.mocs.iter().any(|layer| layer.visible);
+                    for layer in &mut self.mocs {
+                        layer.visible = visible;
+                    }
+                    true
+                }
+                KeyCode::KeyK => {
+                    self.show_moc_cells = !self.show_moc_cells;
+                    true
+                }
+                KeyCode::KeyI => {
+                    let visible = !self.images.iter().any(|layer| layer.visible);
+                    for layer in &mut self.images {
+                        layer.visible = visible;
+                    }
+                    true
+                }
+                // Colormap, stretch and cuts of the HEALPix map and of the images
+                KeyCode::KeyV => {
+                    self.update_scales(|scale| scale.colormap = scale.colormap.next());
+                    true
+                }
+                KeyCode::KeyT => {
+                    self.update_scales(|scale| scale.stretch = scale.stretch.next());
+                    true
+                }
+                // Independent or color preserving stretch of the composites
+                KeyCode::KeyU => {
+                    for layer in &mut self.composites {
+                        layer.stretch = layer.stretch.next();
+                    }
+                    true
+                }
+                KeyCode::KeyZ => {
+                    self.update_scales(|scale| scale.auto_cuts = scale.auto_cuts.next());
+                    // Compute the new cuts at the next update
+                    self.visible_cells.clear();
+                    true
+                }
+                KeyCode::BracketLeft | KeyCode::BracketRight => {
+                    let step = if *code == KeyCode::BracketLeft { -0.1 } else { 0.1 };
+                    self.update_cuts(|(min, max)| (min + step * (max - min), max + step * (max - min)));
+                    true
+                }
+                KeyCode::Minus | KeyCode::Equal => {
+                    let step = if *code == KeyCode::Minus { -0.1 } else { 0.1 };
+                    self.update_cuts(|(min, max)| (min + step * (max - min), max - step * (max - min)));
+                    true
+                }
+                KeyCode::KeyO => {
+                    self.show_regions = !self.show_regions;
+                    true
+                }
+                // Show or hide the catalogs
+                KeyCode::KeyC => {
+                    let visible = !self.catalogs.iter().any(|layer| layer.visible);
+                    for layer in &mut self.catalogs {
+                        layer.visible = visible;
+                    }
+                    true
+                }
+                // Select the layer above or below, or move the selected one up or down the stack with shift
+                KeyCode::ArrowUp | KeyCode::ArrowDown => {
+                    let pos = self.selected_layer;
+                    let to = if *code == KeyCode::ArrowUp {
+                        (pos + 1).min(self.layers.len().saturating_sub(1))
+                    } else {
+                        pos.saturating_sub(1)
+                    };
+                    if self.modifiers.shift_key() {
+                        self.move_layer(pos, to);
+                    } else {
+                        self.selected_layer = to;
+                    }
+                    true
+                }
+                // Visibility, opacity and blend mode of the selected layer
+                KeyCode::Space => {
+                    self.update_layer(self.selected_layer, |visible, _, _| *visible = !*visible);
+                    true
+                }
+                KeyCode::Comma | KeyCode::Period => {
+                    let step = if *code == KeyCode::Comma { -0.1 } else { 0.1 };
+                    self.update_layer(self.selected_layer, |_, opacity, _| {
+                        *opacity = (*opacity + step).clamp(0.0, 1.0)
+                    });
+                    true
+                }
+                KeyCode::KeyX => {
+                    self.update_layer(self.selected_layer, |_, _, blend| *blend = blend.next());
+                    true
+                }
+                KeyCode::Delete => {
+                    self.remove_layer(self.selected_layer);
+                    true
+                }
+                _ => false,
+            },
+            WindowEvent::DroppedFile(path) => {
+                if path.is_dir() {
+                    self.load_hips(path);
+                    return true;
+                }
+                let ext = path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| ext.to_ascii_lowercase())
+                    .unwrap_or_default();
+                if ["reg", "stc", "stcs"].contains(&ext.as_str()) {
+                    self.load_regions(path);
+                } else if ["fits", "fit"].contains(&ext.as_str()) {
+                    self.load_fits(path);
+                } else if ext == "moc" {
+                    self.load_moc(path);
+                } else if ["png", "jpg", "jpeg"].contains(&ext.as_str()) {
+                    self.load_sky_source(path);
+                } else {
+                    self.add_catalog(path);
+                }
+                true
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+                true
+            }
+            // Click to pick a source, shift-drag to select a box and ctrl-drag to select a lasso
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                let kind = if self.modifiers.shift_key() {
+                    DragKind::Box
+                } else if self.modifiers.control_key() {
+                    DragKind::Lasso
+                } else {
+                    DragKind::Click
+                };
+                self.drag = self.cursor.map(|pos| Drag {
+                    kind,
+                    points: vec![Vec2::new(pos.x, pos.y)],
+                });
+                true
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } => {
+                if let Some(drag) = self.drag.take() {
+                    let epoch = self.epoch();
+                    match self.projection {
+                        0 => self.select::<Aitoff>(drag, &epoch),
+                        1 => self.select::<Ortho>(drag, &epoch),
+                        2 => self.select::<Mollweide>(drag, &epoch),
+                        3 => self.select::<Mercator>(drag, &epoch),
+                        4 => self.select::<AzimuthalEquidistant>(drag, &epoch),
+                        5 => self.select::<Gnomonic>(drag, &epoch),
+                        _ => unimplemented!(),
+                    }
+                }
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = Some(*position);
+                let pos = Vec2::new(position.x, position.y);
+                match &mut self.drag {
+                    Some(Drag { kind: DragKind::Box, points }) => {
+                        points.truncate(1);
+                        points.push(pos);
+                    }
+                    Some(Drag { kind: DragKind::Lasso, points })
+                        if (pos - points[points.len() - 1]).magnitude() > 2.0 =>
+                    {
+                        points.push(pos);
+                    }
+                    _ => (),
+                }
+                true
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+                true
+            }
+            _ => false,
+        }
     }
 
-    fn update(&mut self) {
-        let elapsed = self.clock.elapsed_as_secs();
+    /// Load a CSV, TSV or VOTable catalog and draw it over the sky
+    fn add_catalog(&mut self, path: &std::path::Path) {
+        const COLORS: [[f32; 4]; 4] = [
+            [1.0, 0.3, 0.3, 1.0],
+            [0.3, 1.0, 0.4, 1.0],
+            [1.0, 0.85, 0.2, 1.0],
+            [0.9, 0.4, 1.0, 1.0],
+        ];
 
-        let rot = Mat4::from_angle_y(cgmath::Rad(elapsed));
-        let rot: &[[f32; 4]; 4] = rot.as_ref();
+        match catalog::Catalog::from_file(path, &self.epoch()) {
+            Ok(catalog) => {
+                let idx = self.catalogs.len();
+                let style = MarkerStyle {
+                    shape: MarkerShape::ALL[idx % MarkerShape::ALL.len()],
+                    color: COLORS[idx % COLORS.len()],
+                    size: 8.0 * self.scale_factor() as f32,
+                };
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                self.catalogs.push(CatalogLayer::new(name, catalog, style));
+                self.markers.upload(&self.device, &self.catalogs);
+                self.selected_layer = self.layers.push(LayerKind::Catalog);
+            }
+            Err(e) => log::error!("cannot load the catalog {}: {}", path.display(), e),
+        }
+    }
 
-        self.queue
-            .write_buffer(&self.rot_mat_buf, 0, bytemuck::bytes_of(rot));
+    /// Load a bright star catalog, replacing the current one
+    fn load_stars(&mut self, path: &std::path::Path) {
+        match StarCatalog::from_file(path, &self.epoch()) {
+            Ok(stars) => {
+                self.star_renderer.upload(&self.device, &stars);
+                self.constellations.resolve_figures(&stars);
+                self.stars = Some(stars);
+                self.overlays_generation += 1;
+            }
+            Err(e) => log::error!("cannot load the star catalog {}: {}", path.display(), e),
+        }
     }
 
-    fn set_projection(&mut self, idx: usize) {
-        // Update the vertex and index buffers
-        let (vertices, indices) = match idx {
-            0 => Triangulation::create::<Aitoff>(),
-            1 => Triangulation::create::<Ortho>(),
-            2 => Triangulation::create::<Mollweide>(),
-            3 => Triangulation::create::<Mercator>(),
-            4 => Triangulation::create::<AzimuthalEquidistant>(),
-            5 => Triangulation::create::<Gnomonic>(),
-            _ => unimplemented!(),
-        };
+    /// Load the constellation figures, their stars being looked for in the star catalog
+    fn load_constellation_figures(&mut self, path: &std::path::Path) {
+        let figures = std::fs::read_to_string(path)
+            .map_err(error::Error::from)
+            .and_then(|text| Constellations::parse_figures(&text));
+        match figures {
+            Ok(figures) => {
+                self.constellations.figures = figures;
+                if let Some(stars) = &self.stars {
+                    self.constellations.resolve_figures(stars);
+                }
+                self.overlays_generation += 1;
+            }
+            Err(e) => log::error!("cannot load the constellation figures {}: {}", path.display(), e),
+        }
+    }
 
-        self.vertex_buffer = self
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-        self.index_buffer = self
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(&indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-        self.num_indices = indices.len() as u32;
+    fn load_constellation_boundaries(&mut self, path: &std::path::Path) {
+        let boundaries = std::fs::read_to_string(path)
+            .map_err(error::Error::from)
+            .and_then(|text| Constellations::parse_boundaries(&text));
+        match boundaries {
+            Ok(boundaries) => {
+                self.constellations.boundaries = boundaries;
+                self.overlays_generation += 1;
+            }
+            Err(e) => log::error!("cannot load the constellation boundaries {}: {}", path.display(), e),
+        }
+    }
 
-        // Update the uniforms
-        let aspect = match idx {
-            0 => {
-                Aitoff::compute_ndc_to_clip_factor(self.size.width as f32, self.size.height as f32)
+    /// Load the regions of a DS9 or STC-S file, adding them to the current ones
+    fn load_regions(&mut self, path: &std::path::Path) {
+        match regions::from_file(path) {
+            Ok(regions) => {
+                self.regions.extend(regions);
+                self.overlays_generation += 1;
             }
-            1 => Ortho::compute_ndc_to_clip_factor(self.size.width as f32, self.size.height as f32),
-            2 => Mollweide::compute_ndc_to_clip_factor(
-                self.size.width as f32,
-                self.size.height as f32,
-            ),
-            3 => Mercator::compute_ndc_to_clip_factor(
-                self.size.width as f32,
-                self.size.height as f32,
-            ),
-            4 => AzimuthalEquidistant::compute_ndc_to_clip_factor(
-                self.size.width as f32,
-                self.size.height as f32,
+            Err(e) => log::error!("cannot load the regions {}: {}", path.display(), e),
+        }
+    }
+
+    /// Load a coverage map in the FITS, JSON or ASCII serialization
+    fn load_moc(&mut self, path: &std::path::Path) {
+        const COLORS: [[f32; 4]; 3] = [[0.3, 0.6, 1.0, 1.0], [1.0, 0.5, 0.2, 1.0], [0.6, 1.0, 0.3, 1.0]];
+
+        match Moc::from_file(path) {
+            Ok(moc) => {
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let color = COLORS[self.mocs.len() % COLORS.len()];
+                self.mocs.push(MocLayer::new(name, moc, color));
+                self.overlays_generation += 1;
+                self.selected_layer = self.layers.push(LayerKind::Moc);
+            }
+            Err(e) => log::error!("cannot load the MOC {}: {}", path.display(), e),
+        }
+    }
+
+    /// Remove the layer at a position of the stack, with its data
+    fn remove_layer(&mut self, pos: usize) {
+        match self.layers.remove(pos) {
+            Some(LayerRef { kind: LayerKind::Image, index }) => {
+                self.images.remove(index);
+                self.image_renderer.upload(&self.device, &self.queue, &self.images);
+            }
+            Some(LayerRef { kind: LayerKind::Composite, index }) => {
+                self.composites.remove(index);
+                self.composite_renderer.upload(&self.device, &self.queue, &self.composites, TILE_SIZE);
+            }
+            Some(LayerRef { kind: LayerKind::Moc, index }) => {
+                self.mocs.remove(index);
+                self.overlays_generation += 1;
+            }
+            Some(LayerRef { kind: LayerKind::Catalog, index }) => {
+                self.catalogs.remove(index);
+                self.markers.upload(&self.device, &self.catalogs);
+                self.picked = None;
+            }
+            None => (),
+        }
+        self.selected_layer = self.selected_layer.min(self.layers.len().saturating_sub(1));
+    }
+
+    /// Move the layer at a position of the stack to another one, keeping it selected
+    fn move_layer(&mut self, from: usize, to: usize) {
+        self.layers.move_layer(from, to);
+        if from == self.selected_layer && to < self.layers.len() {
+            self.selected_layer = to;
+        }
+    }
+
+    /// Change the visibility, opacity or blend mode of the layer at a position of the stack
+    fn update_layer<F: FnOnce(&mut bool, &mut f32, &mut BlendMode)>(&mut self, pos: usize, f: F) {
+        match self.layers.get(pos) {
+            Some(LayerRef { kind: LayerKind::Image, index }) => {
+                let layer = &mut self.images[index];
+                f(&mut layer.visible, &mut layer.opacity, &mut layer.blend);
+            }
+            Some(LayerRef { kind: LayerKind::Composite, index }) => {
+                let layer = &mut self.composites[index];
+                f(&mut layer.visible, &mut layer.opacity, &mut layer.blend);
+            }
+            Some(LayerRef { kind: LayerKind::Moc, index }) => {
+                let layer = &mut self.mocs[index];
+                f(&mut layer.visible, &mut layer.opacity, &mut layer.blend);
+            }
+            Some(LayerRef { kind: LayerKind::Catalog, index }) => {
+                let layer = &mut self.catalogs[index];
+                f(&mut layer.visible, &mut layer.opacity, &mut layer.blend);
+            }
+            None => (),
+        }
+    }
+
+    /// Apply a change to the color scales of the HEALPix map, of the images and of the
+    /// bands of the composites
+    fn update_scales<F: Fn(&mut ColorScale)>(&mut self, f: F) {
+        let bands = self.composites.iter_mut().flat_map(|layer| layer.bands.iter_mut());
+        for map in self.sky_map.iter_mut().chain(bands) {
+            f(&mut map.scale);
+        }
+        for layer in &mut self.images {
+            f(&mut layer.scale);
+        }
+    }
+
+    /// Change the cuts of the HEALPix map, of the images and of the bands of the composites
+    /// from their current ones, leaving the automatic cuts
+    fn update_cuts<F: Fn((f32, f32)) -> (f32, f32)>(&mut self, f: F) {
+        let bands = self.composites.iter_mut().flat_map(|layer| layer.bands.iter_mut());
+        for map in self.sky_map.iter_mut().chain(bands) {
+            map.scale.auto_cuts = AutoCuts::Manual;
+            map.set_cuts(f(map.scale.cuts()));
+        }
+        for layer in &mut self.images {
+            layer.scale.auto_cuts = AutoCuts::Manual;
+            layer.set_cuts(f(layer.scale.cuts()));
+        }
+    }
+
+    /// Update the automatic cuts from the histograms of the cells in view, when they change
+    fn update_auto_cuts(&mut self, epoch: &Epoch) {
+        let (w, h) = (self.size.width as f64, self.size.height as f64);
+        let mut cells: Vec<u64> = (0..(VIEW_SAMPLES * VIEW_SAMPLES))
+            .filter_map(|k| {
+                let x = ((k % VIEW_SAMPLES) as f64 + 0.5) / VIEW_SAMPLES as f64 * w;
+                let y = ((k / VIEW_SAMPLES) as f64 + 0.5) / VIEW_SAMPLES as f64 * h;
+                self.screen_to_icrs(winit::dpi::PhysicalPosition::new(x, y), epoch)
+            })
+            // The repo (x, y, z) frame is the usual (Y, Z, X) one
+            .map(|pos| healpix::hash_with_dxdy(SkyStats::DEPTH, pos.z, pos.x, pos.y).0)
+            .collect();
+        cells.sort_unstable();
+        cells.dedup();
+        if cells == self.visible_cells {
+            return;
+        }
+        self.visible_cells = cells;
+
+        let bands = self.composites.iter_mut().flat_map(|layer| layer.bands.iter_mut());
+        for map in self.sky_map.iter_mut().chain(bands) {
+            if let Some(cuts) = map.stats.cuts(map.scale.auto_cuts, &self.visible_cells) {
+                map.set_cuts(cuts);
+            }
+        }
+        for layer in &mut self.images {
+            if let Some(cuts) = layer.stats.cuts(layer.scale.auto_cuts, &self.visible_cells) {
+                layer.set_cuts(cuts);
+            }
+        }
+    }
+
+    /// Draw a HEALPix map instead of the base survey
+    fn set_sky_map(&mut self, map: HealpixMap) {
+        // Tiles as deep as the map, up to the size of the survey ones
+        let size = TILE_SIZE.min(1 << map.depth());
+        let values = map.tiles(size).concat();
+        self.values_texture = Texture::from_scalar_values(
+            &self.device,
+            &self.queue,
+            Some(&values),
+            (size, size, 12),
+            &map.name
+        );
+        self.update_bind_group();
+        self.sky_map = Some(map);
+    }
+
+    /// Draw a panorama instead of the base survey
+    fn set_sky_source(&mut self, source: SkySource) {
+        // Panoramas larger than the textures of the device are downscaled
+        let max_size = self.device.limits().max_texture_dimension_2d;
+        let fit = |image: &image::RgbaImage, max_w: u32, max_h: u32| {
+            let (w, h) = image.dimensions();
+            if w <= max_w && h <= max_h {
+                return image.clone();
+            }
+            let scale = (max_w as f64 / w as f64).min(max_h as f64 / h as f64);
+            let (w, h) = (((w as f64 * scale) as u32).max(1), ((h as f64 * scale) as u32).max(1));
+            image::imageops::resize(image, w, h, image::imageops::FilterType::Triangle)
+        };
+        match &source {
+            SkySource::Healpix(tiles) => {
+                for (idx, tile) in tiles.iter().enumerate() {
+                    let tile = image::imageops::resize(tile, TILE_SIZE, TILE_SIZE, image::imageops::FilterType::Triangle);
+                    self.map_texture.write_data(&self.queue, (0, 0, idx as u32), &tile, (TILE_SIZE, TILE_SIZE, 1));
+                }
+            }
+            SkySource::Equirectangular(image) => {
+                let image = fit(image, max_size, max_size / 2);
+                let (w, h) = image.dimensions();
+                self.equirect_texture = Texture::from_raw_bytes::<u8>(
+                    &self.device,
+                    &self.queue,
+                    Some(&image),
+                    (w, h, 1),
+                    4,
+                    "equirectangular image"
+                );
+            }
+            SkySource::Cubemap(faces) => {
+                let faces: Vec<_> = faces.iter().map(|face| fit(face, max_size, max_size)).collect();
+                self.cube_texture = Texture::from_cube_faces(&self.device, &self.queue, &faces, "cubemap");
+            }
+        }
+        let uniform = [source.index() as f32, 0.0, 0.0, 0.0];
+        self.queue
+            .write_buffer(&self.sky_source_buf, 0, bytemuck::cast_slice(&uniform));
+        // The panorama is drawn instead of the HEALPix map too
+        self.sky_map = None;
+        self.sky_source = source;
+        self.hips_properties = None;
+        self.update_bind_group();
+    }
+
+    /// Load an equirectangular image or the faces of a cube as the base survey
+    fn load_sky_source(&mut self, path: &std::path::Path) {
+        match SkySource::from_file(path) {
+            Ok(source) => self.set_sky_source(source),
+            Err(e) => log::error!("cannot load the panorama {}: {}", path.display(), e),
+        }
+    }
+
+    /// Load the tiles of the order 0 of a HiPS as the base survey, switching to the
+    /// planetary mode if it maps a solar system body
+    fn load_hips(&mut self, dir: &std::path::Path) {
+        match Hips::from_dir(dir) {
+            Ok(hips) => {
+                self.set_sky_source(SkySource::Healpix(hips.tiles));
+                self.set_sphere_mode(SphereMode::from_hips_body(hips.properties.body()));
+                self.hips_properties = Some(hips.properties);
+            }
+            Err(e) => log::error!("cannot load the HiPS {}: {}", dir.display(), e),
+        }
+    }
+
+    /// Draw the sky or a planetary body
+    ///
+    /// The planetary bodies are looked at in their body-fixed frame, without the horizon, the
+    /// solar system, the stars and the constellations of the sky.
+    fn set_sphere_mode(&mut self, mode: SphereMode) {
+        let planet = mode != SphereMode::Sky;
+        if planet {
+            self.frame = CooFrame::Equatorial;
+            self.airmass_contours = false;
+            self.show_figures = false;
+            self.show_boundaries = false;
+            self.show_constellation_names = false;
+        }
+        self.solar_system = !planet;
+        self.show_stars = !planet;
+        self.grid = self.grid.map(|_| if planet { GridFrame::Planetocentric } else { GridFrame::Equatorial });
+        // Only the planets are seen from the outside
+        self.queue.write_buffer(&self.window_size_buf, 8, bytemuck::bytes_of(&(mode.x_sign() as f32)));
+        self.sphere = mode;
+    }
+
+    /// Load three HEALPix maps as the red, green and blue bands of a composite
+    fn load_composite(&mut self, paths: &[String; 3]) {
+        match CompositeLayer::from_files(paths) {
+            Ok(layer) => {
+                self.composites.push(layer);
+                self.composite_renderer.upload(&self.device, &self.queue, &self.composites, TILE_SIZE);
+                self.selected_layer = self.layers.push(LayerKind::Composite);
+            }
+            Err(e) => log::error!("cannot load the composite {}: {}", paths.join(", "), e),
+        }
+    }
+
+    /// Load a FITS file holding an image, a HEALPix map or a coverage map
+    fn load_fits(&mut self, path: &std::path::Path) {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                log::error!("cannot read {}: {}", path.display(), e);
+                return;
+            }
+        };
+        let (has_image, has_map) = fits::parse(&bytes)
+            .map(|hdus| (fits::find_image(&hdus).is_some(), healpix_map::find_map(&hdus).is_some()))
+            .unwrap_or_default();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if has_map && !has_image {
+            match HealpixMap::from_fits(name, &bytes) {
+                Ok(map) => self.set_sky_map(map),
+                Err(e) => log::error!("cannot load the HEALPix map {}: {}", path.display(), e),
+            }
+            return;
+        } else if !has_image {
+            self.load_moc(path);
+            return;
+        }
+
+        match ImageLayer::from_fits(name, &bytes) {
+            Ok(layer) => {
+                self.images.push(layer);
+                self.image_renderer.upload(&self.device, &self.queue, &self.images);
+                self.selected_layer = self.layers.push(LayerKind::Image);
+            }
+            Err(e) => log::error!("cannot load the image {}: {}", path.display(), e),
+        }
+    }
+
+    /// Closest source to a point of the window, as a layer and a row index
+    fn pick(&self, pos: Vec2<f64>, epoch: &Epoch) -> Option<(usize, usize)> {
+        let to_icrs = |x: f64| self.screen_to_icrs(winit::dpi::PhysicalPosition::new(x, pos.y), epoch);
+        let center = to_icrs(pos.x)?;
+        // Angular size of the picking radius around the position
+        let edge = to_icrs(pos.x + PICK_RADIUS_PX).or_else(|| to_icrs(pos.x - PICK_RADIUS_PX))?;
+        let radius = Angle(center.dot(edge).clamp(-1.0, 1.0).acos());
+
+        self.catalogs
+            .iter()
+            .enumerate()
+            .filter(|(_, layer)| layer.visible)
+            .filter_map(|(i, layer)| {
+                let row = layer.index.nearest(&center, radius)?;
+                let dist = (layer.index.points()[row] - center).magnitude();
+                Some((dist, i, row))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, i, row)| (i, row))
+    }
+
+    fn select<P: Projection<f64>>(&mut self, drag: Drag, epoch: &Epoch) {
+        let polygon = match drag.kind {
+            DragKind::Click => {
+                self.picked = self.pick(drag.points[0], epoch);
+                for (i, layer) in self.catalogs.iter_mut().enumerate() {
+                    match self.picked {
+                        Some((picked_layer, row)) if picked_layer == i => layer.set_selection(vec![row]),
+                        _ => layer.set_selection(vec![]),
+                    }
+                }
+                self.markers.upload_selection(&self.device, &self.catalogs);
+                return;
+            }
+            DragKind::Box => {
+                let (a, b) = (drag.points[0], drag.points[drag.points.len() - 1]);
+                vec![a, Vec2::new(b.x, a.y), b, Vec2::new(a.x, b.y)]
+            }
+            DragKind::Lasso => drag.points,
+        };
+
+        let view = self.view(epoch);
+        let (w, h) = (self.size.width as f64, self.size.height as f64);
+        for layer in &mut self.catalogs {
+            let rows = if layer.visible {
+                picking::select_in_polygon::<P>(layer.index.points(), &view, &polygon, w, h)
+            } else {
+                vec![]
+            };
+            layer.set_selection(rows);
+        }
+        self.picked = None;
+        self.markers.upload_selection(&self.device, &self.catalogs);
+    }
+
+    /// ICRS position under a point of the window, `None` if it lies outside the projection
+    fn screen_to_icrs(&self, pos: winit::dpi::PhysicalPosition<f64>, epoch: &Epoch) -> Option<Vec3<f64>> {
+        let pos = Vec2::new(pos.x, pos.y);
+        let (w, h) = (self.size.width as f64, self.size.height as f64);
+        let pos_ws = match self.projection {
+            0 => Aitoff::screen_to_world_space(&pos, w, h),
+            1 => Ortho::screen_to_world_space(&pos, w, h),
+            2 => Mollweide::screen_to_world_space(&pos, w, h),
+            3 => Mercator::screen_to_world_space(&pos, w, h),
+            4 => AzimuthalEquidistant::screen_to_world_space(&pos, w, h),
+            5 => Gnomonic::screen_to_world_space(&pos, w, h),
+            _ => unimplemented!(),
+        }?;
+        let pos_ws = Vec4::new(self.sphere.x_sign() * pos_ws.x, pos_ws.y, pos_ws.z, pos_ws.w);
+
+        // Same as the vertex and fragment shaders
+        let rot: Mat4<f64> = self.rot.cast::<f64>().unwrap();
+        let rotated_p = (rot * pos_ws).truncate().normalize();
+        let pos_icrs = match self.frame {
+            CooFrame::Horizontal => {
+                let pos_hor = if self.refraction {
+                    self.observer.apparent_to_horizontal(&rotated_p)
+                } else {
+                    rotated_p
+                };
+                self.observer.horizontal_to_icrs(epoch) * pos_hor
+            }
+            CooFrame::Equatorial => rotated_p,
+        };
+
+        Some(pos_icrs)
+    }
+
+    /// The current view, used to project the overlays
+    fn view(&self, epoch: &Epoch) -> View<'static> {
+        let rot: Mat4<f64> = self.rot.cast::<f64>().unwrap();
+        let world_to_rotated = math::Mat3::from_cols(rot.x.truncate(), rot.y.truncate(), rot.z.truncate());
+        let rotated_to_world = world_to_rotated.transpose();
+        let icrs_to_hor = self.observer.horizontal_to_icrs(epoch).transpose();
+        let observer = self.observer;
+        let frame = self.frame;
+        let refraction = self.refraction;
+
+        let icrs_to_world = move |pos_icrs: &Vec3<f64>| {
+            let rotated_p = match frame {
+                CooFrame::Horizontal => {
+                    let pos_hor = icrs_to_hor * pos_icrs;
+                    if refraction {
+                        observer.horizontal_to_apparent(&pos_hor)
+                    } else {
+                        pos_hor
+                    }
+                }
+                CooFrame::Equatorial => *pos_icrs,
+            };
+            rotated_to_world * rotated_p
+        };
+
+        let (w, h) = (self.size.width as f64, self.size.height as f64);
+        let ndc_to_clip = match self.projection {
+            0 => Aitoff::compute_ndc_to_clip_factor(w, h),
+            1 => Ortho::compute_ndc_to_clip_factor(w, h),
+            2 => Mollweide::compute_ndc_to_clip_factor(w, h),
+            3 => Mercator::compute_ndc_to_clip_factor(w, h),
+            4 => AzimuthalEquidistant::compute_ndc_to_clip_factor(w, h),
+            5 => Gnomonic::compute_ndc_to_clip_factor(w, h),
+            _ => unimplemented!(),
+        };
+
+        View {
+            icrs_to_world: Box::new(icrs_to_world),
+            ndc_to_clip,
+            x_sign: self.sphere.x_sign(),
+        }
+    }
+
+    fn overlay_key(&self, epoch: &Epoch) -> OverlayKey {
+        let horizontal = self.frame == CooFrame::Horizontal;
+        OverlayKey {
+            projection: self.projection,
+            sphere: self.sphere.clone(),
+            rot: self.rot,
+            size: (self.size.width, self.size.height),
+            frame: self.frame,
+            refraction: self.refraction,
+            second: horizontal.then(|| ((epoch.0 - 2_451_545.0) * 86400.0).floor() as i64),
+            grid: self.grid,
+            show_figures: self.show_figures,
+            show_boundaries: self.show_boundaries,
+            show_regions: self.show_regions,
+            show_moc_cells: self.show_moc_cells,
+            mocs: self.mocs.iter().map(|layer| (layer.visible, layer.color, layer.opacity)).collect(),
+            generation: self.overlays_generation,
+        }
+    }
+
+    /// Project the grid, the constellations, the coverage maps and the regions into clip
+    /// space, keeping the lines and uploading the fills
+    fn update_overlay_geometry<P: Projection<f64>>(&mut self, view: &View) {
+        let (w, h) = (self.size.width as f64, self.size.height as f64);
+        self.graticule = self.grid.map(|frame| {
+            let fov = graticule::field_of_view::<P>(w, h);
+            Graticule::new::<P>(frame, view, fov)
+        });
+
+        let mut constellation_lines = vec![];
+        if self.show_figures {
+            let lines = self.constellations.figure_lines::<P>(view);
+            constellation_lines.push((lines, [0.45, 0.75, 0.7, 0.7]));
+        }
+        if self.show_boundaries {
+            let lines = self.constellations.boundary_lines::<P>(view);
+            constellation_lines.push((lines, [0.75, 0.5, 0.3, 0.5]));
+        }
+        let mut fill_vertices = vec![];
+        let fov = ArcDeg::from(graticule::field_of_view::<P>(w, h)).0;
+        self.moc_fills.clear();
+        for layer in &self.mocs {
+            let start = fill_vertices.len() as u32;
+            if layer.visible {
+                let [r, g, b, _] = layer.color;
+                for triangle in layer.fill::<P>(view, fov) {
+                    fill_vertices.extend(triangle.iter().map(|p| LineVertex {
+                        clip: [p.x as f32, p.y as f32],
+                        color: [r, g, b, 0.3 * layer.opacity],
+                    }));
+                }
+                if self.show_moc_cells {
+                    let color = [r, g, b, 0.6 * layer.opacity];
+                    constellation_lines.push((layer.cell_outlines::<P>(view, fov), color));
+                }
+            }
+            self.moc_fills.push(start..fill_vertices.len() as u32);
+        }
+        if self.show_regions {
+            for region in &self.regions {
+                constellation_lines.push((region.outline::<P>(view), region.style.color));
+                if let Some(color) = region.fill_color() {
+                    for triangle in region.fill::<P>(view) {
+                        fill_vertices.extend(triangle.iter().map(|p| LineVertex {
+                            clip: [p.x as f32, p.y as f32],
+                            color,
+                        }));
+                    }
+                }
+            }
+        }
+        let size = (fill_vertices.len() * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress;
+        if size > self.fill_vertex_buffer.size() {
+            self.fill_vertex_buffer = create_line_buffer(&self.device, fill_vertices.len().next_power_of_two());
+        }
+        if !fill_vertices.is_empty() {
+            self.queue
+                .write_buffer(&self.fill_vertex_buffer, 0, bytemuck::cast_slice(&fill_vertices));
+        }
+        self.num_fill_vertices = fill_vertices.len() as u32;
+
+        let mut vertices: Vec<LineVertex> = self
+            .graticule
+            .iter()
+            .flat_map(|graticule| {
+                let color = match graticule.frame {
+                    GridFrame::Equatorial => [0.55, 0.75, 1.0, 0.6],
+                    GridFrame::Galactic => [1.0, 0.7, 0.4, 0.6],
+                    GridFrame::Planetocentric => [0.7, 0.9, 0.6, 0.6],
+                };
+                graticule.lines.iter().flat_map(move |line| {
+                    line.windows(2).flat_map(move |segment| {
+                        segment.iter().map(move |p| LineVertex {
+                            clip: [p.x as f32, p.y as f32],
+                            color,
+                        })
+                    })
+                })
+            })
+            .collect();
+        for (lines, color) in &constellation_lines {
+            for line in lines {
+                for segment in line.windows(2) {
+                    vertices.extend(segment.iter().map(|p| LineVertex {
+                        clip: [p.x as f32, p.y as f32],
+                        color: *color,
+                    }));
+                }
+            }
+        }
+
+        self.overlay_lines = vertices;
+    }
+
+    fn update_overlays<P: Projection<f64>>(&mut self, epoch: &Epoch) {
+        let view = self.view(epoch);
+        let (w, h) = (self.size.width as f64, self.size.height as f64);
+        let key = self.overlay_key(epoch);
+        let changed = self.overlay_key.as_ref() != Some(&key);
+        if changed {
+            self.update_overlay_geometry::<P>(&view);
+            self.overlay_key = Some(key);
+        }
+
+        // Outline of the selection being drawn
+        let outline = match &self.drag {
+            Some(Drag { kind: DragKind::Box, points }) if points.len() > 1 => {
+                let (a, b) = (points[0], points[points.len() - 1]);
+                vec![a, Vec2::new(b.x, a.y), b, Vec2::new(a.x, b.y)]
+            }
+            Some(Drag { kind: DragKind::Lasso, points }) => points.clone(),
+            _ => vec![],
+        };
+        let mut vertices = vec![];
+        if outline.len() > 1 {
+            let to_vertex = |p: &Vec2<f64>| {
+                let clip = picking::screen_to_clip(p, &view.ndc_to_clip, w, h);
+                LineVertex {
+                    clip: [clip.x as f32, clip.y as f32],
+                    color: [1.0, 1.0, 1.0, 0.8],
+                }
+            };
+            for (i, p) in outline.iter().enumerate() {
+                vertices.push(to_vertex(p));
+                vertices.push(to_vertex(&outline[(i + 1) % outline.len()]));
+            }
+        }
+
+        // The outline follows the cached lines in the buffer
+        let num_vertices = self.overlay_lines.len() + vertices.len();
+        let size = (num_vertices * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress;
+        let grown = size > self.grid_vertex_buffer.size();
+        if grown {
+            self.grid_vertex_buffer = create_line_buffer(&self.device, num_vertices.next_power_of_two());
+        }
+        if (changed || grown) && !self.overlay_lines.is_empty() {
+            self.queue
+                .write_buffer(&self.grid_vertex_buffer, 0, bytemuck::cast_slice(&self.overlay_lines));
+        }
+        if !vertices.is_empty() {
+            let offset = (self.overlay_lines.len() * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress;
+            self.queue
+                .write_buffer(&self.grid_vertex_buffer, offset, bytemuck::cast_slice(&vertices));
+        }
+        self.num_grid_vertices = num_vertices as u32;
+
+        // The catalogs are projected on the GPU
+        let rot: Mat4<f64> = self.rot.cast::<f64>().unwrap();
+        let world_to_rotated = math::Mat3::from_cols(rot.x.truncate(), rot.y.truncate(), rot.z.truncate());
+        let horizontal = self.frame == CooFrame::Horizontal;
+        let marker_view = MarkerView {
+            icrs_to_frame: if horizontal {
+                self.observer.horizontal_to_icrs(epoch).transpose()
+            } else {
+                math::Mat3::from_scale(1.0)
+            },
+            frame_to_world: world_to_rotated.transpose(),
+            refraction: (horizontal && self.refraction).then(|| self.observer.refraction_factor()),
+            projection: self.projection,
+            ndc_to_clip: view.ndc_to_clip,
+            window_size: Vec2::new(w, h),
+            x_sign: view.x_sign,
+        };
+        self.markers.prepare(&self.queue, &self.catalogs, &marker_view);
+        if let Some(stars) = &self.stars {
+            // Fainter stars are shown as the field of view narrows
+            self.limiting_mag = if self.show_stars {
+                stars::limiting_magnitude(graticule::field_of_view::<P>(w, h))
+            } else {
+                f64::NEG_INFINITY
+            };
+            self.star_renderer
+                .prepare(&self.queue, stars, &marker_view, self.limiting_mag);
+        }
+
+        // Texts, from the most to the least important
+        let scale = (2.0 * self.scale_factor()).round() as u32;
+        let mut layout = TextLayout::new(w, h, scale);
+        let line_height = layout.text_size(" ").y * 1.5;
+        let hud_color = [0.9, 0.9, 0.9, 1.0];
+
+        let frame = match (&self.sphere, self.frame) {
+            (SphereMode::Planet(body), _) => format!("{} planetocentric", body),
+            (SphereMode::Sky, CooFrame::Equatorial) => "equatorial".to_string(),
+            (SphereMode::Sky, CooFrame::Horizontal) => "horizontal".to_string(),
+        };
+        let mut title = format!("{} - {} frame", PROJECTION_NAMES[self.projection], frame);
+        if let Some(map) = &self.sky_map {
+            let (min, max) = map.scale.cuts();
+            title = format!(
+                "{} - {} (order {}) - {} {} [{:.4e}, {:.4e}] ({})",
+                title,
+                map.name,
+                map.depth(),
+                map.scale.stretch.name(),
+                map.scale.colormap.name(),
+                min,
+                max,
+                map.scale.auto_cuts.name()
+            );
+        } else if !matches!(self.sky_source, SkySource::Healpix(_)) {
+            title = format!("{} - {}", title, self.sky_source.name());
+        }
+        layout.add_hud(&title, Vec2::new(line_height, line_height), hud_color);
+        // The layers from the top to the bottom of the stack, the selected one being marked
+        for (i, (pos, layer)) in self.layers.iter().enumerate().rev().enumerate() {
+            let (text, color, visible, opacity, blend) = match layer.kind {
+                LayerKind::Catalog => {
+                    let layer = &self.catalogs[layer.index];
+                    let text = format!("{} ({} sources)", layer.name, layer.catalog.len());
+                    (text, layer.style.color, layer.visible, layer.opacity, layer.blend)
+                }
+                LayerKind::Moc => {
+                    let layer = &self.mocs[layer.index];
+                    let text = format!("{} ({:.3}% of the sky)", layer.name, 100.0 * layer.moc.sky_fraction());
+                    (text, layer.color, layer.visible, layer.opacity, layer.blend)
+                }
+                LayerKind::Image => {
+                    let layer = &self.images[layer.index];
+                    let (min, max) = layer.scale.cuts();
+                    let text = format!(
+                        "{} ({}x{} pixels) - {} {} [{:.4e}, {:.4e}] ({})",
+                        layer.name,
+                        layer.image.width,
+                        layer.image.height,
+                        layer.scale.stretch.name(),
+                        layer.scale.colormap.name(),
+                        min,
+                        max,
+                        layer.scale.auto_cuts.name()
+                    );
+                    (text, hud_color, layer.visible, layer.opacity, layer.blend)
+                }
+                LayerKind::Composite => {
+                    let layer = &self.composites[layer.index];
+                    let bands: Vec<String> = layer
+                        .bands
+                        .iter()
+                        .map(|band| {
+                            let (min, max) = band.scale.cuts();
+                            format!("{} [{:.4e}, {:.4e}]", band.scale.stretch.name(), min, max)
+                        })
+                        .collect();
+                    let text = format!("{} - {} - {}", layer.name, layer.stretch.name(), bands.join(" / "));
+                    (text, hud_color, layer.visible, layer.opacity, layer.blend)
+                }
+            };
+            let marker = if pos == self.selected_layer { "> " } else { "" };
+            let text = format!("{}{} - {} {:.0}%", marker, text, blend.name(), 100.0 * opacity);
+            let color = if visible { color } else { [0.5, 0.5, 0.5, 1.0] };
+            layout.add_hud(&text, Vec2::new(line_height, line_height * (2.0 + i as f64)), color);
+        }
+        if let Some(stars) = self.stars.as_ref().filter(|_| self.show_stars) {
+            let text = format!(
+                "{} stars brighter than magnitude {:.1}",
+                stars.count_brighter(self.limiting_mag),
+                self.limiting_mag
+            );
+            let y = h - line_height * 6.0;
+            layout.add_hud(&text, Vec2::new(line_height, y), hud_color);
+        }
+
+        // The row of the picked source, or the number of selected ones
+        let mut selection_lines = vec![];
+        if let Some((i, row)) = self.picked {
+            let layer = &self.catalogs[i];
+            selection_lines.push(format!("{} row {}", layer.name, row));
+            let table = &layer.catalog.table;
+            for (name, value) in table.names.iter().zip(&table.rows[row]).take(MAX_PICKED_FIELDS) {
+                selection_lines.push(format!("  {} = {}", name, value));
+            }
+        } else {
+            for layer in self.catalogs.iter().filter(|layer| !layer.selection().is_empty()) {
+                selection_lines.push(format!("{}: {} selected", layer.name, layer.selection().len()));
+            }
+        }
+        for (i, line) in selection_lines.iter().enumerate() {
+            let y = line_height * (3.0 + (self.layers.len() + i) as f64);
+            layout.add_hud(line, Vec2::new(line_height, y), hud_color);
+        }
+        if let Some(info) = &self.cursor_info {
+            let info = info.to_string();
+            for (i, part) in info.split(" | ").enumerate() {
+                let y = h - line_height * (5.0 - i as f64);
+                layout.add_hud(part, Vec2::new(line_height, y), hud_color);
+            }
+        }
+
+        if self.solar_system {
+            for body in Body::ALL.iter() {
+                layout.add_world_label::<P>(body.name(), &body.direction(epoch), &view, [1.0, 0.95, 0.8, 1.0]);
+            }
+        }
+
+        if self.show_regions {
+            for region in &self.regions {
+                if let (Some(text), Some(pos)) = (&region.text, region.shape.center()) {
+                    layout.add_world_label::<P>(text, &pos, &view, region.style.color);
+                }
+            }
+        }
+
+        if self.show_constellation_names {
+            for (name, pos) in self.constellations.labels() {
+                layout.add_world_label::<P>(name, &pos, &view, [0.6, 0.85, 0.8, 1.0]);
+            }
+        }
+
+        if let Some(graticule) = &self.graticule {
+            let color = match graticule.frame {
+                GridFrame::Equatorial => [0.55, 0.75, 1.0, 1.0],
+                GridFrame::Galactic => [1.0, 0.7, 0.4, 1.0],
+                GridFrame::Planetocentric => [0.7, 0.9, 0.6, 1.0],
+            };
+            for label in &graticule.labels {
+                layout.add_clip_label(&label.text, &label.pos, &view.ndc_to_clip, color);
+            }
+        }
+
+        self.text.prepare(&self.device, &self.queue, &layout);
+    }
+
+    fn update_cursor_info(&mut self, epoch: &Epoch) {
+        let cursor_info = self
+            .cursor
+            .and_then(|pos| self.screen_to_icrs(pos, epoch))
+            .map(|pos_icrs| match &self.sky_map {
+                Some(map) => CursorInfo::new(&pos_icrs, map.depth(), &[]).with_value(map.value(&pos_icrs)),
+                None => match &self.sky_source {
+                    SkySource::Healpix(tiles) => CursorInfo::new(&pos_icrs, sky_source::tiles_order(tiles), tiles),
+                    source => CursorInfo::new(&pos_icrs, 0, &[]).with_pixel(source.pixel(&pos_icrs)),
+                },
+            })
+            .map(|info| match self.sphere.body() {
+                Some(body) => info.with_body(body),
+                None => info,
+            });
+
+        if cursor_info != self.cursor_info {
+            if let Some(window) = self.window {
+                match &cursor_info {
+                    Some(info) => window.set_title(&format!("allsky projections - {}", info)),
+                    None => window.set_title("allsky projections"),
+                }
+            }
+            self.cursor_info = cursor_info;
+        }
+    }
+
+    /// Epoch the sky is looked at
+    fn epoch(&self) -> Epoch {
+        self.epoch.unwrap_or_else(Epoch::now)
+    }
+
+    /// Pixels per logical pixel of the window, 1 offscreen
+    fn scale_factor(&self) -> f64 {
+        self.window.map_or(1.0, |window| window.scale_factor())
+    }
+
+    fn update(&mut self) {
+        let elapsed = self.rotation.unwrap_or_else(|| self.clock.elapsed_as_secs());
+
+        // In the horizontal frame, the sky turns around the zenith
+        self.rot = Mat4::from_angle_y(cgmath::Rad(elapsed));
+        let rot: &[[f32; 4]; 4] = self.rot.as_ref();
+
+        self.queue
+            .write_buffer(&self.rot_mat_buf, 0, bytemuck::bytes_of(rot));
+
+        let epoch = self.epoch();
+        let hor_to_icrs: Mat4<f32> = self
+            .observer
+            .horizontal_to_icrs(&epoch)
+            .cast::<f32>()
+            .unwrap()
+            .into();
+        let horizon = HorizonUniform {
+            hor_to_icrs: hor_to_icrs.into(),
+            params: [
+                (self.frame == CooFrame::Horizontal) as u8 as f32,
+                self.refraction as u8 as f32,
+                self.observer.refraction_factor() as f32,
+                self.airmass_contours as u8 as f32,
+            ],
+        };
+        self.queue
+            .write_buffer(&self.horizon_buf, 0, bytemuck::bytes_of(&horizon));
+
+        let solar_system = SolarSystemUniform::new(&epoch, self.solar_system);
+        self.queue
+            .write_buffer(&self.solar_system_buf, 0, bytemuck::bytes_of(&solar_system));
+
+        let colormap = self
+            .sky_map
+            .as_ref()
+            .map_or_else(ColormapUniform::disabled, |map| map.scale.uniform());
+        self.queue
+            .write_buffer(&self.colormap_buf, 0, bytemuck::bytes_of(&colormap));
+
+        self.image_renderer.prepare(&self.queue, &self.images);
+        self.composite_renderer.prepare(&self.queue, &self.composites);
+
+        self.update_cursor_info(&epoch);
+        self.update_auto_cuts(&epoch);
+        match self.projection {
+            0 => self.update_overlays::<Aitoff>(&epoch),
+            1 => self.update_overlays::<Ortho>(&epoch),
+            2 => self.update_overlays::<Mollweide>(&epoch),
+            3 => self.update_overlays::<Mercator>(&epoch),
+            4 => self.update_overlays::<AzimuthalEquidistant>(&epoch),
+            5 => self.update_overlays::<Gnomonic>(&epoch),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn set_projection(&mut self, idx: usize) {
+        self.projection = idx;
+
+        // Update the vertex and index buffers
+        let (vertices, indices) = match idx {
+            0 => Triangulation::create::<Aitoff>(),
+            1 => Triangulation::create::<Ortho>(),
+            2 => Triangulation::create::<Mollweide>(),
+            3 => Triangulation::create::<Mercator>(),
+            4 => Triangulation::create::<AzimuthalEquidistant>(),
+            5 => Triangulation::create::<Gnomonic>(),
+            _ => unimplemented!(),
+        };
+
+        self.vertex_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Vertex Buffer"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+        self.index_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Index Buffer"),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+        self.num_indices = indices.len() as u32;
+
+        // Update the uniforms
+        let aspect = match idx {
+            0 => {
+                Aitoff::compute_ndc_to_clip_factor(self.size.width as f32, self.size.height as f32)
+            }
+            1 => Ortho::compute_ndc_to_clip_factor(self.size.width as f32, self.size.height as f32),
+            2 => Mollweide::compute_ndc_to_clip_factor(
+                self.size.width as f32,
+                self.size.height as f32,
+            ),
+            3 => Mercator::compute_ndc_to_clip_factor(
+                self.size.width as f32,
+                self.size.height as f32,
+            ),
+            4 => AzimuthalEquidistant::compute_ndc_to_clip_factor(
+                self.size.width as f32,
+                self.size.height as f32,
             ),
             5 => Gnomonic::compute_ndc_to_clip_factor(
                 self.size.width as f32,
@@ -509,10 +2313,14 @@ impl<'a> State<'a> {
         self.queue.write_buffer(
             &self.window_size_buf,
             0,
-            bytemuck::bytes_of(&[aspect.x, aspect.y, 0.0, 0.0]),
+            bytemuck::bytes_of(&[aspect.x, aspect.y, self.sphere.x_sign() as f32, 0.0]),
         );
 
-        // Update the bind group with the texture position from the current projection
+        self.update_bind_group();
+    }
+
+    /// Update the bind group of the sky with the current textures
+    fn update_bind_group(&mut self) {
         self.diffuse_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &self.texture_bind_group_layout,
             entries: &[
@@ -544,63 +2352,286 @@ impl<'a> State<'a> {
                         ),
                     }),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.horizon_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: self.solar_system_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&self.values_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: self.colormap_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: self.sky_source_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::TextureView(&self.equirect_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::TextureView(&self.cube_texture.view),
+                },
             ],
             label: Some("diffuse_bind_group"),
         });
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let size = self.window.inner_size();
-        if size.width == 0 || size.height == 0 {
+        if self.size.width == 0 || self.size.height == 0 {
             return Ok(())
         }
 
-        if let Ok(frame) = self.surface.get_current_texture() {
+        if let Some(Ok(frame)) = self.surface.as_ref().map(|surface| surface.get_current_texture()) {
             let view = frame.texture.create_view(&wgpu::TextureViewDescriptor {
                 format: Some(self.config.format.add_srgb_suffix()),
                 ..Default::default()
             });
-    
-            let mut encoder = self
-                .device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("Render Encoder"),
-                });
-    
-            {
-                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: Some("Render Pass"),
-                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.01,
-                                g: 0.01,
-                                b: 0.01,
-                                a: 1.0,
-                            }),
-                            store: wgpu::StoreOp::Store,
-                        },
-                    })],
-                    depth_stencil_attachment: None,
-                    occlusion_query_set: None,
-                    timestamp_writes: None,
-                });
-    
-                render_pass.set_pipeline(&self.render_pipeline);
-                render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-                render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-                render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
-            }
-    
-            self.queue.submit(iter::once(encoder.finish()));
+            self.draw(&view);
             frame.present();
         }
 
         Ok(())
     }
+
+    /// Draw the scene into a texture
+    fn draw(&self, view: &wgpu::TextureView) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color {
+                            r: 0.01,
+                            g: 0.01,
+                            b: 0.01,
+                            a: 1.0,
+                        }),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+
+            self.star_renderer.draw(&mut render_pass);
+
+            // The layers from the bottom to the top of the stack
+            for layer in self.layers.iter() {
+                match layer.kind {
+                    LayerKind::Image => {
+                        render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
+                        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                        self.image_renderer.draw_layer(&mut render_pass, layer.index, self.num_indices);
+                    }
+                    LayerKind::Composite => {
+                        render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
+                        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                        self.composite_renderer.draw_layer(&mut render_pass, layer.index, self.num_indices);
+                    }
+                    LayerKind::Moc => {
+                        let range = self.moc_fills.get(layer.index).cloned().unwrap_or_default();
+                        if !range.is_empty() {
+                            let blend = self.mocs[layer.index].blend;
+                            render_pass.set_pipeline(&self.fill_pipelines[blend.index()]);
+                            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
+                            render_pass.set_vertex_buffer(0, self.fill_vertex_buffer.slice(..));
+                            render_pass.draw(range, 0..1);
+                        }
+                    }
+                    LayerKind::Catalog => self.markers.draw_layer(&mut render_pass, layer.index),
+                }
+            }
+
+            // The regions, the grids and the selected sources are drawn over the layers
+            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
+            let regions_start = self.moc_fills.last().map_or(0, |range| range.end);
+            if self.num_fill_vertices > regions_start {
+                render_pass.set_pipeline(&self.fill_pipelines[BlendMode::Alpha.index()]);
+                render_pass.set_vertex_buffer(0, self.fill_vertex_buffer.slice(..));
+                render_pass.draw(regions_start..self.num_fill_vertices, 0..1);
+            }
+            if self.num_grid_vertices > 0 {
+                render_pass.set_pipeline(&self.line_pipeline);
+                render_pass.set_vertex_buffer(0, self.grid_vertex_buffer.slice(..));
+                render_pass.draw(0..self.num_grid_vertices, 0..1);
+            }
+
+            self.markers.draw_selection(&mut render_pass);
+
+            self.text.draw(&mut render_pass);
+        }
+
+        self.queue.submit(iter::once(encoder.finish()));
+    }
+
+    /// Draw the scene offscreen and read it back, the rows going down
+    fn render_to_image(&self) -> image::RgbaImage {
+        let (width, height) = (self.size.width.max(1), self.size.height.max(1));
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        self.draw(&texture.create_view(&wgpu::TextureViewDescriptor::default()));
+
+        // The rows of the copies are aligned
+        let row_size = 4 * width;
+        let padded_row_size = row_size.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("offscreen readback buffer"),
+            size: (padded_row_size * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_size),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| {
+            if let Err(e) = result {
+                log::error!("cannot read the offscreen texture back: {}", e);
+            }
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        let mut pixels: Vec<u8> = slice
+            .get_mapped_range()
+            .chunks_exact(padded_row_size as usize)
+            .flat_map(|row| row[..row_size as usize].iter().copied())
+            .collect();
+        buffer.unmap();
+        // The surfaces of some windows are BGRA ones
+        if matches!(self.config.format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        image::RgbaImage::from_raw(width, height, pixels).unwrap()
+    }
+
+    /// Write the scene into a PNG file, or a FITS one with the WCS of the view
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_image(&self, image: &image::RgbaImage, path: &std::path::Path) -> Result<(), error::Error> {
+        let is_fits = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ["fits", "fit"].contains(&ext.to_ascii_lowercase().as_str()));
+        if !is_fits {
+            image.save(path)?;
+            return Ok(());
+        }
+
+        let epoch = self.epoch();
+        let mut cards = vec![fits::card("MJD-OBS", &format!("{:E}", epoch.0 - 2_400_000.5))];
+        match self.wcs(&epoch) {
+            Some(wcs) => cards.extend(wcs.cards()),
+            None => log::warn!(
+                "{}: no WCS describes the {} projection in this frame",
+                path.display(),
+                PROJECTION_NAMES[self.projection]
+            ),
+        }
+        std::fs::write(path, fits::write_rgb_image(image, &cards))?;
+        Ok(())
+    }
+
+    /// FITS world coordinate system of the view, if it has one
+    ///
+    /// The Aitoff and Mercator projections of projection.rs are not the FITS ones, and
+    /// the refraction bends the horizontal frame.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn wcs(&self, epoch: &Epoch) -> Option<wcs::Wcs> {
+        let projection = wcs::WcsProjection::from_index(self.projection)?;
+        let world_to_icrs = self.world_to_icrs(epoch)?;
+        let (w, h) = (self.size.width, self.size.height);
+        let ndc_to_clip = self.view(epoch).ndc_to_clip;
+        Some(wcs::Wcs::from_view(projection, (w, h), ndc_to_clip, self.sphere.x_sign(), world_to_icrs))
+    }
+
+    /// Rotation from the world space to the ICRS frame, None if the refraction bends
+    /// the horizontal frame
+    #[cfg(not(target_arch = "wasm32"))]
+    fn world_to_icrs(&self, epoch: &Epoch) -> Option<math::Mat3<f64>> {
+        let rot: Mat4<f64> = self.rot.cast::<f64>().unwrap();
+        let world_to_rotated = math::Mat3::from_cols(rot.x.truncate(), rot.y.truncate(), rot.z.truncate());
+        match self.frame {
+            CooFrame::Equatorial => Some(world_to_rotated),
+            CooFrame::Horizontal if !self.refraction => Some(self.observer.horizontal_to_icrs(epoch) * world_to_rotated),
+            CooFrame::Horizontal => None,
+        }
+    }
+
+    /// Draw the base survey of HEALPix tiles on the CPU, as the GPU does without the overlays
+    #[cfg(not(target_arch = "wasm32"))]
+    fn render_reference(&self, epoch: &Epoch) -> Option<image::RgbaImage> {
+        let tiles = match &self.sky_source {
+            SkySource::Healpix(tiles) if self.sky_map.is_none() => tiles,
+            _ => return None,
+        };
+        // The tiles as uploaded to the texture of the base survey
+        let tiles: Vec<image::RgbaImage> = tiles
+            .iter()
+            .map(|tile| image::imageops::resize(tile, TILE_SIZE, TILE_SIZE, image::imageops::FilterType::Triangle))
+            .collect();
+        let world_to_icrs = self.world_to_icrs(epoch)?;
+        let (w, h) = (self.size.width.max(1), self.size.height.max(1));
+        reference::render_projection(self.projection, &tiles, &world_to_icrs, self.sphere.x_sign(), w, h)
+    }
 }
 
 #[cfg_attr(target_arch="wasm32", wasm_bindgen(start))]
@@ -612,7 +2643,59 @@ pub async fn run() {
     #[cfg(not(target_arch = "wasm32"))]
     env_logger::init();
 
+    // With --output, the scene is written into an image file without opening any window
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+            println!("{}", USAGE);
+            return;
+        }
+        // A misspelled option is not read as the path of a catalog
+        if let Some(arg) = args.iter().find(|arg| arg.starts_with("--") && !OPTIONS.contains(&arg.as_str())) {
+            log::error!("unknown option {}", arg);
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+        if let Some(output) = option_value(&args, "--output") {
+            let size = option_value(&args, "--size")
+                .and_then(|size| {
+                    let (w, h) = size.split_once('x')?;
+                    Some(winit::dpi::PhysicalSize::new(w.parse().ok()?, h.parse().ok()?))
+                })
+                .unwrap_or(winit::dpi::PhysicalSize::new(1024, 512));
+            let mut state = State::new_headless(size).await;
+            load_args(&mut state, &args);
+            state.set_projection(state.projection);
+            state.update();
+            let image = state.render_to_image();
+            // With --reference, the base survey drawn on the CPU instead
+            let image = if args.iter().any(|arg| arg == "--reference") {
+                match state.render_reference(&state.epoch()) {
+                    Some(reference) => {
+                        if let Some(diff) = reference::max_difference(&reference, &image) {
+                            log::info!("largest difference with the GPU image: {}", diff);
+                        }
+                        reference
+                    }
+                    None => {
+                        log::error!("the reference renderer only draws the HEALPix tiles without refraction");
+                        std::process::exit(1);
+                    }
+                }
+            } else {
+                image
+            };
+            if let Err(e) = state.save_image(&image, std::path::Path::new(output)) {
+                log::error!("cannot write {}: {}", output, e);
+                std::process::exit(1);
+            }
+            return;
+        }
+    }
+
     let event_loop = EventLoop::new().unwrap();
+    #[cfg_attr(not(target_arch = "wasm32"), allow(unused_mut))]
     let mut builder = WindowBuilder::new();
 
     #[cfg(target_arch = "wasm32")]
@@ -642,15 +2725,19 @@ pub async fn run() {
     }
 
     let mut state = State::new(&window).await;
+    #[cfg(not(target_arch = "wasm32"))]
+    load_args(&mut state, &std::env::args().skip(1).collect::<Vec<_>>());
+    let window = &window;
 
-    let mut count: i32 = 0;
+    let mut count = state.projection as i32;
 
+    #[allow(clippy::collapsible_match)]
     event_loop.run(move |event, control_flow| {
         match event {
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == state.window.id() => {
+            } if window_id == window.id() => {
                 if !state.input(event) {
                     match event {
                         #[cfg(not(target_arch="wasm32"))]
@@ -688,7 +2775,7 @@ pub async fn run() {
                             ..
                         } => {
                             // toggle fullscreen
-                            state.window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+                            window.set_fullscreen(Some(Fullscreen::Borderless(None)));
                         },
                         WindowEvent::KeyboardInput {
                             event:
@@ -757,10 +2844,154 @@ pub async fn run() {
             Event::AboutToWait => {
                 // RedrawRequested will only trigger once unless we manually
                 // request it.
-                state.window.request_redraw();
+                window.request_redraw();
             }
             _ => {}
         }
     }).unwrap();
 }
 
+/// Value following an option of the command line
+#[cfg(not(target_arch = "wasm32"))]
+fn option_value<'a>(args: &'a [String], option: &str) -> Option<&'a str> {
+    let pos = args.iter().position(|arg| arg == option)?;
+    args.get(pos + 1).map(String::as_str)
+}
+
+/// Load what the command line gives: the bright stars, the constellations, the regions, the
+/// coverage maps, the images, the HEALPix maps, the panoramas, the HiPS and the composites
+/// following their options, the catalogs otherwise, and the projection
+#[cfg(not(target_arch = "wasm32"))]
+fn load_args(state: &mut State, args: &[String]) {
+    use std::convert::TryInto;
+
+    // Julian date, or Julian year such as J2025.5, of the view, to which the catalogs are
+    // propagated
+    if args.iter().any(|arg| arg == "--epoch") {
+        match option_value(args, "--epoch").and_then(Epoch::parse) {
+            Some(epoch) => state.epoch = Some(epoch),
+            None => log::error!("missing or invalid epoch after --epoch"),
+        }
+    }
+    // Angle in degrees of the rotation of the view around the poles
+    if args.iter().any(|arg| arg == "--rotation") {
+        match option_value(args, "--rotation").and_then(|angle| angle.parse::<f32>().ok()) {
+            Some(angle) => state.rotation = Some(angle.to_radians()),
+            None => log::error!("missing or invalid angle after --rotation"),
+        }
+    }
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--stars" | "--constellations" | "--boundaries" | "--regions" | "--moc" | "--image" | "--healpix" | "--sky" | "--hips" => match args.next() {
+                Some(path) => {
+                    let path = std::path::Path::new(path);
+                    match arg.as_str() {
+                        "--stars" => state.load_stars(path),
+                        "--constellations" => state.load_constellation_figures(path),
+                        "--regions" => state.load_regions(path),
+                        "--moc" => state.load_moc(path),
+                        "--image" | "--healpix" => state.load_fits(path),
+                        "--sky" => state.load_sky_source(path),
+                        "--hips" => state.load_hips(path),
+                        _ => state.load_constellation_boundaries(path),
+                    }
+                }
+                None => log::error!("missing path after {}", arg),
+            },
+            // The red, green and blue bands of a composite
+            "--rgb" => {
+                let paths: Vec<String> = args.by_ref().take(3).cloned().collect();
+                match paths.try_into() {
+                    Ok(paths) => state.load_composite(&paths),
+                    Err(_) => log::error!("missing paths after {}", arg),
+                }
+            }
+            // Index or name of the projection
+            "--projection" => {
+                let projection = args.next().and_then(|name| {
+                    name.parse::<usize>().ok().filter(|&idx| idx < PROJECTION_NAMES.len()).or_else(|| {
+                        PROJECTION_NAMES
+                            .iter()
+                            .position(|projection| projection.to_lowercase().starts_with(&name.to_lowercase()))
+                    })
+                });
+                match projection {
+                    Some(idx) => state.set_projection(idx),
+                    None => log::error!("unknown projection after {}", arg),
+                }
+            }
+            // Atmospheric pressure in hPa and air temperature in degrees Celsius of the
+            // refraction
+            "--pressure" | "--temperature" => {
+                let value = args.next().and_then(|value| value.parse::<f64>().ok());
+                match value {
+                    Some(pressure) if arg == "--pressure" && pressure >= 0.0 => state.observer.pressure = pressure,
+                    Some(temperature) if arg == "--temperature" && temperature > -273.0 => {
+                        state.observer.temperature = temperature
+                    }
+                    _ => log::error!("missing or invalid value after {}", arg),
+                }
+            }
+            // Read before the catalogs, and offscreen rendering
+            "--epoch" | "--rotation" | "--output" | "--size" => {
+                args.next();
+            }
+            "--reference" => (),
+            _ => state.add_catalog(std::path::Path::new(arg)),
+        }
+    }
+}
+
+mod tests {
+    #[test]
+    fn image_layers() {
+        use super::*;
+
+        // The layers are drawn on the GPU, when there is one
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        if instance.enumerate_adapters(wgpu::Backends::all()).is_empty() {
+            return;
+        }
+        let (w, h) = (256, 256);
+        let mut state = pollster::block_on(State::new_headless(winit::dpi::PhysicalSize::new(w, h)));
+        state.solar_system = false;
+        state.set_projection(5);
+
+        // Images of 16x16 pixels ahead of the view, their left half being white,
+        // with the projections of the WCS
+        let plane: Vec<u8> = (0..256).map(|k| if k % 16 < 8 { 255 } else { 0 }).collect();
+        for code in ["TAN", "SIN", "ARC", "MOL"] {
+            let cards = [
+                fits::card("CTYPE1", &format!("'RA---{}'", code)),
+                fits::card("CTYPE2", &format!("'DEC--{}'", code)),
+                fits::card("CRPIX1", "8.5"),
+                fits::card("CRPIX2", "8.5"),
+                fits::card("CRVAL1", "0.0"),
+                fits::card("CRVAL2", "0.0"),
+                fits::card("CDELT1", "-3.0"),
+                fits::card("CDELT2", "3.0"),
+            ];
+            let bytes = fits::write_u8_image(16, 16, std::slice::from_ref(&plane), &cards);
+            let layer = ImageLayer::from_fits(code.to_string(), &bytes).unwrap();
+            let wcs = layer.wcs.clone();
+            state.images.push(layer);
+            state.image_renderer.upload(&state.device, &state.queue, &state.images);
+            state.layers.push(LayerKind::Image);
+            state.update();
+            let image = state.render_to_image();
+
+            // The pixels land where the WCS and the overlays put them
+            let view = state.view(&state.epoch());
+            let brightness = |x: f64, y: f64| {
+                let pos = wcs.pixel_to_icrs(&Vec2::new(x, y)).unwrap();
+                let clip = view.project::<Gnomonic>(&pos).unwrap();
+                let screen = picking::clip_to_screen(&clip, &view.ndc_to_clip, w as f64, h as f64);
+                image.get_pixel(screen.x as u32, screen.y as u32).0[0]
+            };
+            assert!(brightness(4.0, 8.0) > 200, "{}", code);
+            assert!(brightness(13.0, 8.0) < 100, "{}", code);
+        }
+    }
+}
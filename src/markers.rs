@@ -0,0 +1,463 @@
+use crate::catalog::Catalog;
+use crate::glsl;
+use crate::layers::BlendMode;
+use crate::math::{self, Mat3, Vec2};
+use crate::picking::SpatialIndex;
+use crate::vertex::MarkerInstance;
+
+use wgpu::util::DeviceExt;
+
+// Range of the marker scaling given by the magnitude or size columns
+const MIN_SCALE: f64 = 0.5;
+const MAX_SCALE: f64 = 2.0;
+// Selected sources are drawn bigger and in white on top of the others
+const SELECTION_SCALE: f32 = 1.5;
+const SELECTION_COLOR: [u8; 4] = [255, 255, 255, 255];
+
+/// Shape of the markers of a catalog
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarkerShape {
+    Circle,
+    Cross,
+    Square,
+    Plus,
+}
+
+impl MarkerShape {
+    pub const ALL: [MarkerShape; 4] = [
+        MarkerShape::Circle,
+        MarkerShape::Cross,
+        MarkerShape::Square,
+        MarkerShape::Plus,
+    ];
+}
+
+/// How the sources of a catalog are drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MarkerStyle {
+    pub shape: MarkerShape,
+    /// Color used when the catalog has no color column
+    pub color: [f32; 4],
+    /// Width of the markers in pixels, scaled by the magnitude or size columns
+    pub size: f32,
+}
+
+/// A catalog drawn over the sky
+pub struct CatalogLayer {
+    pub name: String,
+    pub catalog: Catalog,
+    pub style: MarkerStyle,
+    pub opacity: f32,
+    pub visible: bool,
+    pub blend: BlendMode,
+    /// Positions of the sources, built when the layer is created
+    pub index: SpatialIndex,
+    selection: Vec<usize>,
+}
+
+impl CatalogLayer {
+    pub fn new(name: String, catalog: Catalog, style: MarkerStyle) -> Self {
+        let index = SpatialIndex::new(catalog.positions());
+        Self {
+            name,
+            catalog,
+            style,
+            opacity: 1.0,
+            visible: true,
+            blend: BlendMode::Alpha,
+            index,
+            selection: vec![],
+        }
+    }
+
+    /// Row indices of the selected sources, in increasing order
+    pub fn selection(&self) -> &[usize] {
+        &self.selection
+    }
+
+    /// Replace the selected sources by the given rows
+    pub fn set_selection(&mut self, mut rows: Vec<usize>) {
+        rows.retain(|&row| row < self.catalog.len());
+        rows.sort_unstable();
+        rows.dedup();
+        self.selection = rows;
+    }
+
+    pub(crate) fn selection_instances(&self) -> Vec<MarkerInstance> {
+        let instances = self.instances();
+        self.selection
+            .iter()
+            .map(|&row| MarkerInstance {
+                size: instances[row].size * SELECTION_SCALE + 4.0,
+                color: SELECTION_COLOR,
+                ..instances[row]
+            })
+            .collect()
+    }
+
+    pub(crate) fn instances(&self) -> Vec<MarkerInstance> {
+        let catalog = &self.catalog;
+        // Brighter (i.e. lower magnitude) sources are drawn bigger
+        let scale = match (&catalog.size, &catalog.mag) {
+            (Some(size), _) => normalize(size)
+                .into_iter()
+                .map(|t| MIN_SCALE + (MAX_SCALE - MIN_SCALE) * t)
+                .collect(),
+            (None, Some(mag)) => normalize(mag)
+                .into_iter()
+                .map(|t| MAX_SCALE - (MAX_SCALE - MIN_SCALE) * t)
+                .collect(),
+            (None, None) => vec![1.0; catalog.len()],
+        };
+        let colors = catalog.color.as_ref().map(|color| normalize(color));
+
+        let to_u8 = |c: [f32; 4]| c.map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8);
+        let default_color = to_u8(self.style.color);
+
+        self.index
+            .points()
+            .iter()
+            .enumerate()
+            .map(|(i, pos)| {
+                let scale = if scale[i].is_finite() { scale[i] } else { 1.0 };
+                let color = match &colors {
+                    Some(t) if t[i].is_finite() => {
+                        let [r, g, b] = color_index_to_rgb(t[i]);
+                        to_u8([r, g, b, self.style.color[3]])
+                    }
+                    _ => default_color,
+                };
+
+                MarkerInstance {
+                    pos_icrs: [pos.x as f32, pos.y as f32, pos.z as f32],
+                    size: self.style.size * scale as f32,
+                    color,
+                }
+            })
+            .collect()
+    }
+}
+
+// Map the finite values of a column to [0, 1], the others to NaN
+fn normalize(values: &[f64]) -> Vec<f64> {
+    let (min, max) = values
+        .iter()
+        .filter(|v| v.is_finite())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &v| (min.min(v), max.max(v)));
+
+    values
+        .iter()
+        .map(|&v| {
+            if max > min {
+                (v - min) / (max - min)
+            } else if v.is_finite() {
+                0.5
+            } else {
+                f64::NAN
+            }
+        })
+        .collect()
+}
+
+// From blue for the lowest color indices to red for the highest ones
+fn color_index_to_rgb(t: f64) -> [f32; 3] {
+    const BLUE: [f32; 3] = [0.6, 0.7, 1.0];
+    const WHITE: [f32; 3] = [1.0, 1.0, 1.0];
+    const RED: [f32; 3] = [1.0, 0.55, 0.35];
+
+    let (from, to, t) = if t < 0.5 {
+        (BLUE, WHITE, 2.0 * t)
+    } else {
+        (WHITE, RED, 2.0 * t - 1.0)
+    };
+    let t = t as f32;
+    [
+        from[0] + (to[0] - from[0]) * t,
+        from[1] + (to[1] - from[1]) * t,
+        from[2] + (to[2] - from[2]) * t,
+    ]
+}
+
+/// How the sky is currently looked at, used to project the markers on the GPU
+pub struct MarkerView {
+    /// Rotation from the ICRS to the frame the sky is looked at in
+    pub icrs_to_frame: Mat3<f64>,
+    /// Rotation from that frame to the world space
+    pub frame_to_world: Mat3<f64>,
+    /// Refraction scaling factor, if the atmospheric refraction is applied
+    pub refraction: Option<f64>,
+    /// Index of the projection
+    pub projection: usize,
+    pub ndc_to_clip: Vec2<f64>,
+    /// Size of the window in pixels
+    pub window_size: Vec2<f64>,
+    /// Factor of the x axis of the world space, see `SphereMode::x_sign`
+    pub x_sign: f64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct MarkerUniform {
+    icrs_to_frame: [[f32; 4]; 4],
+    frame_to_world: [[f32; 4]; 4],
+    params: [f32; 4],
+    window: [f32; 4],
+    style: [f32; 4],
+}
+
+impl MarkerUniform {
+    pub(crate) fn new(view: &MarkerView, shape: MarkerShape, opacity: f32) -> Self {
+        let to_mat4 = |m: &Mat3<f64>| -> [[f32; 4]; 4] {
+            let m: math::Mat4<f32> = m.cast::<f32>().unwrap().into();
+            m.into()
+        };
+        let shape = MarkerShape::ALL.iter().position(|&s| s == shape).unwrap();
+
+        Self {
+            icrs_to_frame: to_mat4(&view.icrs_to_frame),
+            frame_to_world: to_mat4(&view.frame_to_world),
+            params: [
+                view.projection as f32,
+                view.refraction.is_some() as u8 as f32,
+                view.refraction.unwrap_or(0.0) as f32,
+                shape as f32,
+            ],
+            window: [
+                view.ndc_to_clip.x as f32,
+                view.ndc_to_clip.y as f32,
+                2.0 / view.window_size.x as f32,
+                2.0 / view.window_size.y as f32,
+            ],
+            style: [opacity, view.x_sign as f32, 0.0, 0.0],
+        }
+    }
+}
+
+struct LayerBuffers {
+    instance_buffer: wgpu::Buffer,
+    num_instances: u32,
+    selection_buffer: wgpu::Buffer,
+    num_selected: u32,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    visible: bool,
+    blend: BlendMode,
+}
+
+/// Draws catalogs as instanced markers, projected on the GPU
+pub struct MarkerRenderer {
+    // One pipeline per blend mode
+    pipelines: Vec<wgpu::RenderPipeline>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    layers: Vec<LayerBuffers>,
+}
+
+impl MarkerRenderer {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(
+                        std::mem::size_of::<MarkerUniform>() as wgpu::BufferAddress,
+                    ),
+                },
+                count: None,
+            }],
+            label: Some("markers_bind_group_layout"),
+        });
+
+        let vs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("markers vert shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: glsl::with_shared(include_str!("shaders/markers.vert"), &[glsl::COMMON]).into(),
+                stage: naga::ShaderStage::Vertex,
+                defines: Default::default(),
+            },
+        });
+        let fs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("markers frag shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: include_str!("shaders/markers.frag").into(),
+                stage: naga::ShaderStage::Fragment,
+                defines: Default::default(),
+            },
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Markers Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |mode: &BlendMode| device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Markers Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vs_shader,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                buffers: &[MarkerInstance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_shader,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(mode.blend_state()),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
+        let pipelines = BlendMode::ALL.iter().map(create_pipeline).collect();
+
+        Self {
+            pipelines,
+            bind_group_layout,
+            layers: vec![],
+        }
+    }
+
+    /// Upload the sources of the layers
+    ///
+    /// This must be called whenever the layers or their catalogs change, not at every frame.
+    pub fn upload(&mut self, device: &wgpu::Device, layers: &[CatalogLayer]) {
+        self.layers = layers
+            .iter()
+            .map(|layer| {
+                let instances = layer.instances();
+                let instance_buffer = create_instance_buffer(device, &instances);
+                let selection = layer.selection_instances();
+                let selection_buffer = create_instance_buffer(device, &selection);
+                let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("markers uniform"),
+                    size: std::mem::size_of::<MarkerUniform>() as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    }],
+                    label: Some("markers_bind_group"),
+                });
+
+                LayerBuffers {
+                    instance_buffer,
+                    num_instances: instances.len() as u32,
+                    selection_buffer,
+                    num_selected: selection.len() as u32,
+                    uniform_buffer,
+                    bind_group,
+                    visible: layer.visible,
+                    blend: layer.blend,
+                }
+            })
+            .collect();
+    }
+
+    /// Upload the selected sources of the layers, after their selection changed
+    pub fn upload_selection(&mut self, device: &wgpu::Device, layers: &[CatalogLayer]) {
+        for (buffers, layer) in self.layers.iter_mut().zip(layers) {
+            let selection = layer.selection_instances();
+            buffers.selection_buffer = create_instance_buffer(device, &selection);
+            buffers.num_selected = selection.len() as u32;
+        }
+    }
+
+    /// Update the view, the opacity, the visibility and the blend mode of the layers for the next frame
+    pub fn prepare(&mut self, queue: &wgpu::Queue, layers: &[CatalogLayer], view: &MarkerView) {
+        for (buffers, layer) in self.layers.iter_mut().zip(layers) {
+            let uniform = MarkerUniform::new(view, layer.style.shape, layer.opacity);
+            queue.write_buffer(&buffers.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+            buffers.visible = layer.visible;
+            buffers.blend = layer.blend;
+        }
+    }
+
+    /// Draw the sources of a layer
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - Index of the layer in the list given to `upload`
+    pub fn draw_layer<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, idx: usize) {
+        if let Some(layer) = self.layers.get(idx).filter(|l| l.visible && l.num_instances > 0) {
+            render_pass.set_pipeline(&self.pipelines[layer.blend.index()]);
+            render_pass.set_bind_group(0, &layer.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, layer.instance_buffer.slice(..));
+            render_pass.draw(0..6, 0..layer.num_instances);
+        }
+    }
+
+    /// Draw the selected sources of all the layers, on top of everything else
+    pub fn draw_selection<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipelines[BlendMode::Alpha.index()]);
+        for layer in self.layers.iter().filter(|l| l.visible && l.num_selected > 0) {
+            render_pass.set_bind_group(0, &layer.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, layer.selection_buffer.slice(..));
+            render_pass.draw(0..6, 0..layer.num_selected);
+        }
+    }
+}
+
+fn create_instance_buffer(device: &wgpu::Device, instances: &[MarkerInstance]) -> wgpu::Buffer {
+    // Buffers cannot be empty
+    let contents: &[u8] = if instances.is_empty() {
+        &[0; std::mem::size_of::<MarkerInstance>()]
+    } else {
+        bytemuck::cast_slice(instances)
+    };
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("marker instance buffer"),
+        contents,
+        usage: wgpu::BufferUsages::VERTEX,
+    })
+}
+
+mod tests {
+    #[test]
+    fn instances() {
+        use super::*;
+        use crate::catalog::Table;
+        use crate::frame::Epoch;
+
+        let table = Table::from_csv("ra,dec,vmag,b-v\n0,0,2,-0.3\n90,0,10,1.5\n0,90,,0.6\n").unwrap();
+        let catalog = Catalog::from_table(table, &Epoch::J2000).unwrap();
+        let style = MarkerStyle {
+            shape: MarkerShape::Circle,
+            color: [0.0, 1.0, 0.0, 1.0],
+            size: 10.0,
+        };
+        let instances = CatalogLayer::new("test".to_string(), catalog, style).instances();
+
+        // The brightest source is the biggest, the one without magnitude keeps the base size
+        assert_eq!(instances.iter().map(|i| i.size).collect::<Vec<_>>(), vec![20.0, 5.0, 10.0]);
+        // Bluish, reddish and white
+        assert!(instances[0].color[2] > instances[0].color[0]);
+        assert!(instances[1].color[0] > instances[1].color[2]);
+        // The repo frame has the vernal point along z and the north pole along y
+        let pos = |i: usize| instances[i].pos_icrs;
+        assert!((pos(0)[2] - 1.0).abs() < 1e-6 && (pos(1)[0] - 1.0).abs() < 1e-6 && (pos(2)[1] - 1.0).abs() < 1e-6);
+    }
+}
@@ -54,6 +54,14 @@ pub fn radec_to_xyz<S: BaseFloat>(theta: Angle<S>, delta: Angle<S>) -> Vec3<S> {
     Vec3::<S>::new(d_c * t_s, d_s, d_c * t_c)
 }
 
+/// Directions of the increasing longitudes and latitudes at a position of the unit sphere
+pub fn local_basis(pos: &Vec3<f64>) -> (Vec3<f64>, Vec3<f64>) {
+    let (lon, lat) = xyz_to_radec(pos);
+    let (s_lon, c_lon) = lon.0.sin_cos();
+    let (s_lat, c_lat) = lat.0.sin_cos();
+    (Vec3::new(c_lon, 0.0, -s_lon), Vec3::new(-s_lat * s_lon, c_lat, -s_lat * c_lon))
+}
+
 #[inline]
 pub fn asinc_positive<T: Float>(mut x: T) -> T {
     assert!(x >= T::zero());
@@ -0,0 +1,495 @@
+use crate::error::Error;
+use crate::fits;
+use crate::healpix::{self, MAX_DEPTH};
+use crate::layers::BlendMode;
+use crate::math::{Vec2, Vec3};
+use crate::overlay::View;
+use crate::projection::Projection;
+
+use cgmath::InnerSpace;
+use std::ops::Range;
+use std::path::Path;
+
+// Cells drawn across the field of view at most, the MOC being degraded beyond
+const MAX_CELLS_ACROSS_VIEW: f64 = 128.0;
+// Angular size in degrees of the base cells
+const BASE_CELL_SIZE_DEG: f64 = 58.6;
+// Triangles stretched further than that in clip space cross a seam of the projection
+const MAX_FILL_EDGE_CLIP: f64 = 0.25;
+
+/// Multi-Order Coverage map, i.e. a set of HEALPix cells
+///
+/// The cells are stored as sorted and disjoint ranges of cell numbers at the
+/// deepest order, as in the MOC standard.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Moc {
+    depth: u8,
+    ranges: Vec<Range<u64>>,
+}
+
+impl Moc {
+    /// Build a MOC from cells given by their depth and number
+    pub fn from_cells(depth: u8, cells: impl IntoIterator<Item = (u8, u64)>) -> Self {
+        let ranges = cells
+            .into_iter()
+            .filter(|&(d, _)| d <= MAX_DEPTH)
+            .map(|(d, hash)| {
+                let shift = 2 * (MAX_DEPTH - d);
+                (hash << shift)..((hash + 1) << shift)
+            })
+            .collect();
+        Self::from_ranges(depth, ranges)
+    }
+
+    /// Build a MOC from ranges of cell numbers at the deepest order, in any order
+    pub fn from_ranges(depth: u8, mut ranges: Vec<Range<u64>>) -> Self {
+        let depth = depth.min(MAX_DEPTH);
+        ranges.retain(|r| r.start < r.end);
+        ranges.sort_unstable_by_key(|r| r.start);
+        Self {
+            depth: MAX_DEPTH,
+            ranges: merge(ranges),
+        }
+        .degrade(depth)
+    }
+
+    /// Order of the smallest cells of the MOC
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    pub fn ranges(&self) -> &[Range<u64>] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Fraction of the sky covered
+    pub fn sky_fraction(&self) -> f64 {
+        let count: u64 = self.ranges.iter().map(|r| r.end - r.start).sum();
+        count as f64 / healpix::n_cells(MAX_DEPTH) as f64
+    }
+
+    /// Whether an ICRS position is covered
+    pub fn contains(&self, pos: &Vec3<f64>) -> bool {
+        // The repo frame is the usual (Y, Z, X) one
+        let hash = healpix::hash_with_dxdy(MAX_DEPTH, pos.z, pos.x, pos.y).0;
+        let idx = self.ranges.partition_point(|r| r.end <= hash);
+        self.ranges.get(idx).is_some_and(|r| r.start <= hash)
+    }
+
+    /// The same coverage at a shallower order, the partially covered cells becoming covered
+    pub fn degrade(&self, depth: u8) -> Self {
+        if depth >= self.depth {
+            return self.clone();
+        }
+        let mask = (1_u64 << (2 * (MAX_DEPTH - depth))) - 1;
+        let ranges = self
+            .ranges
+            .iter()
+            .map(|r| (r.start & !mask)..((r.end + mask) & !mask))
+            .collect();
+        Self {
+            depth,
+            ranges: merge(ranges),
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let mut ranges: Vec<_> = self.ranges.iter().chain(&other.ranges).cloned().collect();
+        ranges.sort_unstable_by_key(|r| r.start);
+        Self {
+            depth: self.depth.max(other.depth),
+            ranges: merge(ranges),
+        }
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        let mut ranges = vec![];
+        let (mut i, mut j) = (0, 0);
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a, b) = (&self.ranges[i], &other.ranges[j]);
+            let start = a.start.max(b.start);
+            let end = a.end.min(b.end);
+            if start < end {
+                ranges.push(start..end);
+            }
+            if a.end < b.end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        Self {
+            depth: self.depth.max(other.depth),
+            ranges,
+        }
+    }
+
+    /// The cells of the sky not covered
+    pub fn complement(&self) -> Self {
+        let mut ranges = vec![];
+        let mut start = 0;
+        for r in &self.ranges {
+            if r.start > start {
+                ranges.push(start..r.start);
+            }
+            start = r.end;
+        }
+        let end = healpix::n_cells(MAX_DEPTH);
+        if start < end {
+            ranges.push(start..end);
+        }
+        Self {
+            depth: self.depth,
+            ranges,
+        }
+    }
+
+    pub fn difference(&self, other: &Self) -> Self {
+        let mut moc = self.intersection(&other.complement());
+        moc.depth = self.depth.max(other.depth);
+        moc
+    }
+
+    /// Fewest cells covering the MOC, as their depth and number
+    pub fn cells(&self) -> Vec<(u8, u64)> {
+        let mut cells = vec![];
+        for r in &self.ranges {
+            let mut start = r.start;
+            while start < r.end {
+                // Largest cell starting there and fitting in the range
+                let mut shift = (start.trailing_zeros() / 2).min(MAX_DEPTH as u32) as u8;
+                while start + (1 << (2 * shift)) > r.end {
+                    shift -= 1;
+                }
+                cells.push((MAX_DEPTH - shift, start >> (2 * shift)));
+                start += 1 << (2 * shift);
+            }
+        }
+        cells
+    }
+
+    /// Parse the ASCII serialization, e.g. `3/1-5 8 4/30 5/`
+    pub fn from_ascii(text: &str) -> Result<Self, Error> {
+        let err = |msg: String| Error::Parse(format!("MOC: {}", msg));
+        let text = text.trim();
+        if text.starts_with('t') {
+            return Err(err("time MOCs are not supported".to_string()));
+        }
+
+        let mut ranges = vec![];
+        let mut depth = None;
+        let mut max_depth = 0;
+        for token in text.trim_start_matches('s').split(|c: char| c == ',' || c.is_whitespace()) {
+            let token = match token.split_once('/') {
+                Some((d, rest)) => {
+                    let d: u8 = d.parse().map_err(|_| err(format!("invalid depth {}", d)))?;
+                    if d > MAX_DEPTH {
+                        return Err(err(format!("depth {} is too deep", d)));
+                    }
+                    depth = Some(d);
+                    max_depth = max_depth.max(d);
+                    rest
+                }
+                None => token,
+            };
+            if token.is_empty() {
+                continue;
+            }
+            let d = depth.ok_or_else(|| err(format!("missing depth before {}", token)))?;
+            let parse = |s: &str| s.parse::<u64>().ok().filter(|&h| h < healpix::n_cells(d));
+            let (first, last) = match token.split_once('-') {
+                Some((a, b)) => (parse(a), parse(b)),
+                None => (parse(token), parse(token)),
+            };
+            match (first, last) {
+                (Some(first), Some(last)) if first <= last => {
+                    let shift = 2 * (MAX_DEPTH - d);
+                    ranges.push((first << shift)..((last + 1) << shift));
+                }
+                _ => return Err(err(format!("invalid cells {}", token))),
+            }
+        }
+        Ok(Self::from_ranges(max_depth, ranges))
+    }
+
+    /// Parse the JSON serialization, e.g. `{"3": [1, 2, 5], "4": [30]}`
+    pub fn from_json(text: &str) -> Result<Self, Error> {
+        let err = |msg: &str| Error::Parse(format!("MOC: {}", msg));
+        let mut cells = vec![];
+        let mut max_depth = 0;
+
+        let mut rest = text.trim().strip_prefix('{').ok_or_else(|| err("expected a JSON object"))?;
+        while let Some(start) = rest.find('"') {
+            let end = start + 1 + rest[(start + 1)..].find('"').ok_or_else(|| err("unterminated key"))?;
+            let depth: u8 = rest[(start + 1)..end]
+                .parse()
+                .ok()
+                .filter(|&d| d <= MAX_DEPTH)
+                .ok_or_else(|| err("invalid depth"))?;
+            let open = end + rest[end..].find('[').ok_or_else(|| err("expected ["))?;
+            let close = open + rest[open..].find(']').ok_or_else(|| err("expected ]"))?;
+            for value in rest[(open + 1)..close].split(',').map(str::trim).filter(|v| !v.is_empty()) {
+                let hash = value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&h| h < healpix::n_cells(depth))
+                    .ok_or_else(|| err("invalid cell"))?;
+                cells.push((depth, hash));
+            }
+            max_depth = max_depth.max(depth);
+            rest = &rest[(close + 1)..];
+        }
+        Ok(Self::from_cells(max_depth, cells))
+    }
+
+    /// Read a MOC from a FITS file, with its cells as NUNIQ numbers or as ranges
+    pub fn from_fits(bytes: &[u8]) -> Result<Self, Error> {
+        let err = |msg: &str| Error::Parse(format!("MOC: {}", msg));
+        let hdus = fits::parse(bytes)?;
+        let table = hdus.get(1).ok_or_else(|| err("missing binary table"))?;
+        let header = &table.header;
+        let depth = header
+            .get_i64("MOCORDER")
+            .or_else(|| header.get_i64("MOCORD_S"))
+            .map(|d| d.clamp(0, MAX_DEPTH as i64) as u8);
+
+        if header.get("ORDERING").is_some_and(|o| o.eq_ignore_ascii_case("RANGE")) {
+            let values = table.column_i64("RANGE")?;
+            let ranges = values
+                .chunks_exact(2)
+                .map(|r| (r[0] as u64)..(r[1] as u64))
+                .collect();
+            return Ok(Self::from_ranges(depth.unwrap_or(MAX_DEPTH), ranges));
+        }
+
+        let uniqs = table.column_i64("UNIQ").or_else(|_| table.column_i64("NUNIQ"))?;
+        let mut max_depth = 0;
+        let cells = uniqs
+            .iter()
+            .map(|&uniq| {
+                let cell = uniq_to_cell(uniq as u64).ok_or_else(|| err("invalid NUNIQ number"))?;
+                max_depth = max_depth.max(cell.0);
+                Ok(cell)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self::from_cells(depth.unwrap_or(max_depth), cells))
+    }
+
+    /// FITS serialization, with the cells as NUNIQ numbers
+    pub fn to_fits(&self) -> Vec<u8> {
+        let mut uniqs: Vec<i64> = self
+            .cells()
+            .iter()
+            .map(|&(depth, hash)| ((4 << (2 * depth as u64)) + hash) as i64)
+            .collect();
+        uniqs.sort_unstable();
+        let cards = [
+            fits::card("PIXTYPE", "'HEALPIX'"),
+            fits::card("ORDERING", "'NUNIQ'"),
+            fits::card("COORDSYS", "'C'"),
+            fits::card("MOCDIM", "'SPACE'"),
+            fits::card("MOCORDER", &self.depth.to_string()),
+            fits::card("MOCVERS", "'2.0'"),
+        ];
+        fits::write_i64_table("UNIQ", &uniqs, &cards)
+    }
+
+    /// Read a MOC file, in the FITS, JSON or ASCII serialization
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let bytes = std::fs::read(path)?;
+        if bytes.starts_with(b"SIMPLE") {
+            return Self::from_fits(&bytes);
+        }
+        let text = String::from_utf8(bytes).map_err(|_| Error::Parse("MOC: not a text file".to_string()))?;
+        if text.trim_start().starts_with('{') {
+            Self::from_json(&text)
+        } else {
+            Self::from_ascii(&text)
+        }
+    }
+}
+
+// Merge the overlapping or contiguous ranges, sorted by their start
+fn merge(ranges: Vec<Range<u64>>) -> Vec<Range<u64>> {
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for r in ranges {
+        match merged.last_mut() {
+            Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+            _ => merged.push(r),
+        }
+    }
+    merged
+}
+
+/// Depth and number of a cell from its NUNIQ number, i.e. `4 * 4^depth + hash`
+pub fn uniq_to_cell(uniq: u64) -> Option<(u8, u64)> {
+    if uniq < 4 {
+        return None;
+    }
+    let depth = ((63 - uniq.leading_zeros()) / 2 - 1) as u8;
+    (depth <= MAX_DEPTH).then(|| (depth, uniq - (4 << (2 * depth))))
+}
+
+/// ICRS position of a point of a cell, see `healpix::sph_coo`
+pub fn cell_point(depth: u8, hash: u64, dx: f64, dy: f64) -> Vec3<f64> {
+    let (x, y, z) = healpix::sph_coo(depth, hash, dx, dy);
+    Vec3::new(y, z, x)
+}
+
+// Segments each cell edge is sampled with, the large cells being curved
+fn edge_samples(depth: u8) -> usize {
+    (16 >> depth.min(4)).max(1)
+}
+
+/// A coverage map drawn over the sky
+pub struct MocLayer {
+    pub name: String,
+    pub moc: Moc,
+    pub color: [f32; 4],
+    pub opacity: f32,
+    pub visible: bool,
+    pub blend: BlendMode,
+}
+
+impl MocLayer {
+    pub fn new(name: String, moc: Moc, color: [f32; 4]) -> Self {
+        Self {
+            name,
+            moc,
+            color,
+            opacity: 1.0,
+            visible: true,
+            blend: BlendMode::Alpha,
+        }
+    }
+
+    /// The cells to draw, the MOC being degraded so that they stay a few pixels wide
+    ///
+    /// # Arguments
+    ///
+    /// * `fov` - Angular size of the field of view in degrees
+    fn display_cells(&self, fov: f64) -> Vec<(u8, u64)> {
+        let depth = (BASE_CELL_SIZE_DEG * MAX_CELLS_ACROSS_VIEW / fov).log2().floor().clamp(0.0, MAX_DEPTH as f64);
+        self.moc.degrade(depth as u8).cells()
+    }
+
+    /// Project the coverage into clip space triangles
+    pub fn fill<P: Projection<f64>>(&self, view: &View, fov: f64) -> Vec<[Vec2<f64>; 3]> {
+        let mut triangles = vec![];
+        for (depth, hash) in self.display_cells(fov) {
+            let n = edge_samples(depth);
+            let step = 1.0 / n as f64;
+            let grid: Vec<Option<Vec2<f64>>> = (0..=n)
+                .flat_map(|j| (0..=n).map(move |i| (i as f64 * step, j as f64 * step)))
+                .map(|(dx, dy)| view.project::<P>(&cell_point(depth, hash, dx, dy)))
+                .collect();
+            let vertex = |i: usize, j: usize| grid[j * (n + 1) + i];
+
+            for j in 0..n {
+                for i in 0..n {
+                    let quad = [vertex(i, j), vertex(i + 1, j), vertex(i + 1, j + 1), vertex(i, j + 1)];
+                    for [a, b, c] in [[quad[0], quad[1], quad[2]], [quad[0], quad[2], quad[3]]] {
+                        if let (Some(a), Some(b), Some(c)) = (a, b, c) {
+                            let max_edge = (b - a).magnitude().max((c - b).magnitude()).max((a - c).magnitude());
+                            if max_edge < MAX_FILL_EDGE_CLIP {
+                                triangles.push([a, b, c]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        triangles
+    }
+
+    /// Project the outlines of the cells into clip space polylines
+    pub fn cell_outlines<P: Projection<f64>>(&self, view: &View, fov: f64) -> Vec<Vec<Vec2<f64>>> {
+        const CORNERS: [(f64, f64); 4] = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        self.display_cells(fov)
+            .into_iter()
+            .flat_map(|(depth, hash)| {
+                let curve = move |t: f64| {
+                    let t = 4.0 * t;
+                    let k = (t.floor() as usize).min(3);
+                    let (a, b) = (CORNERS[k], CORNERS[(k + 1) % 4]);
+                    let s = t - k as f64;
+                    cell_point(depth, hash, a.0 + (b.0 - a.0) * s, a.1 + (b.1 - a.1) * s)
+                };
+                view.trace::<P>(curve, 4 * edge_samples(depth), true)
+            })
+            .collect()
+    }
+}
+
+mod tests {
+    #[test]
+    fn moc() {
+        use super::*;
+
+        let a = Moc::from_ascii("1/1-3 2/16 17\n3/").unwrap();
+        assert_eq!(a.depth(), 3);
+        assert_eq!(a.cells(), vec![(1, 1), (1, 2), (1, 3), (2, 16), (2, 17)]);
+        assert_eq!(Moc::from_json(r#"{"1": [1, 2, 3], "2": [16, 17], "3": []}"#).unwrap(), a);
+        assert!(Moc::from_ascii("1/48").is_err());
+        assert!((a.sky_fraction() - (3.0 / 48.0 + 2.0 / 192.0)).abs() < 1e-12);
+
+        // The four children of a cell are merged back
+        let b = Moc::from_ascii("0/0 2/18 19").unwrap();
+        assert_eq!(b.cells(), vec![(0, 0), (2, 18), (2, 19)]);
+        let union = a.union(&b);
+        assert_eq!(union.cells(), vec![(0, 0), (1, 4)]);
+        assert_eq!(a.intersection(&b).cells(), vec![(1, 1), (1, 2), (1, 3)]);
+        assert_eq!(a.difference(&b).cells(), vec![(2, 16), (2, 17)]);
+        assert_eq!(a.complement().complement(), a);
+        assert_eq!(a.degrade(0).cells(), vec![(0, 0), (0, 1)]);
+
+        let center = cell_point(2, 17, 0.5, 0.5);
+        assert!(a.contains(&center));
+        assert!(!a.contains(&cell_point(2, 18, 0.5, 0.5)));
+        assert_eq!(uniq_to_cell(4 * 16 + 17), Some((2, 17)));
+
+        // A FITS file with a NUNIQ column of 32 bits integers
+        let mut header = String::new();
+        for card in [
+            "SIMPLE  =                    T",
+            "BITPIX  =                    8",
+            "NAXIS   =                    0",
+            "END",
+        ] {
+            header.push_str(&format!("{:<80}", card));
+        }
+        let mut bytes = format!("{:<2880}", header).into_bytes();
+        let mut header = String::new();
+        for card in [
+            "XTENSION= 'BINTABLE'",
+            "BITPIX  =                    8",
+            "NAXIS   =                    2",
+            "NAXIS1  =                    4",
+            "NAXIS2  =                    2",
+            "PCOUNT  =                    0",
+            "GCOUNT  =                    1",
+            "TFIELDS =                    1",
+            "TTYPE1  = 'UNIQ    '",
+            "TFORM1  = '1J      '",
+            "MOCORDER=                    3 / deepest order",
+            "END",
+        ] {
+            header.push_str(&format!("{:<80}", card));
+        }
+        bytes.extend(format!("{:<2880}", header).bytes());
+        let mut data = vec![0; 2880];
+        data[..4].copy_from_slice(&(4 * 16 + 16_i32).to_be_bytes());
+        data[4..8].copy_from_slice(&(4 * 4 + 1_i32).to_be_bytes());
+        bytes.extend(data);
+        let moc = Moc::from_fits(&bytes).unwrap();
+        assert_eq!(moc.depth(), 3);
+        assert_eq!(moc.cells(), vec![(1, 1), (2, 16)]);
+        // Written back
+        let read = Moc::from_fits(&moc.to_fits()).unwrap();
+        assert_eq!((read.depth(), read.cells()), (3, vec![(1, 1), (2, 16)]));
+    }
+}
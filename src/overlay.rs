@@ -0,0 +1,162 @@
+use crate::math::{Vec2, Vec3};
+use crate::projection::Projection;
+
+use cgmath::InnerSpace;
+
+// Largest gap allowed between two consecutive points of a line, in clip space.
+// Beyond it the curve is sampled more finely, and split if it still jumps.
+const MAX_GAP_CLIP: f64 = 0.02;
+const MAX_SUBDIVISIONS: u32 = 10;
+const BOUNDARY_ITERATIONS: u32 = 16;
+
+/// Transformation of a position of the unit sphere
+pub type Transform<'a> = Box<dyn Fn(&Vec3<f64>) -> Vec3<f64> + 'a>;
+
+/// How the sky is currently looked at, used to project the overlays on the CPU
+pub struct View<'a> {
+    /// Transformation from the ICRS to the world space, i.e. the inverse of what
+    /// the fragment shader does
+    pub icrs_to_world: Transform<'a>,
+    /// Normalized device to clip space factor of the projection
+    pub ndc_to_clip: Vec2<f64>,
+    /// Factor of the x axis of the world space, see `SphereMode::x_sign`
+    pub x_sign: f64,
+}
+
+impl<'a> View<'a> {
+    /// Clip space position of an ICRS position
+    ///
+    /// Returns `None` if the position is behind the camera, cannot be projected
+    /// or falls outside of the window.
+    pub fn project<P: Projection<f64>>(&self, pos_icrs: &Vec3<f64>) -> Option<Vec2<f64>> {
+        let mut pos_world_space = (self.icrs_to_world)(pos_icrs).normalize();
+        pos_world_space.x *= self.x_sign;
+        let pos_world_space = pos_world_space.extend(1.0);
+        if !P::is_front_of_camera(&pos_world_space) {
+            return None;
+        }
+
+        P::world_to_clip_space(&pos_world_space).filter(|pos_clip_space| {
+            let x = pos_clip_space.x * self.ndc_to_clip.x;
+            let y = pos_clip_space.y * self.ndc_to_clip.y;
+            x.abs() <= 1.0 && y.abs() <= 1.0 && x.is_finite() && y.is_finite()
+        })
+    }
+
+    /// Project a curve of the sphere into clip space polylines
+    ///
+    /// The curve is split where it leaves the window or the projection (e.g. the
+    /// horizon of the gnomonic projection) and where its projection jumps (e.g.
+    /// the seam of the Aitoff and Mollweide projections).
+    ///
+    /// # Arguments
+    ///
+    /// * `curve` - ICRS position of the curve for a parameter in [0, 1]
+    /// * `num_samples` - Number of segments the curve is initially sampled with
+    /// * `closed` - Whether the curve ends where it starts
+    pub fn trace<P: Projection<f64>>(
+        &self,
+        curve: impl Fn(f64) -> Vec3<f64>,
+        num_samples: usize,
+        closed: bool,
+    ) -> Vec<Vec<Vec2<f64>>> {
+        let mut tracer = Tracer {
+            view: self,
+            curve: &curve,
+            current: vec![],
+            polylines: vec![],
+        };
+
+        let first = self.project::<P>(&curve(0.0));
+        if let Some(p) = first {
+            tracer.current.push(p);
+        }
+        let mut prev = (0.0, first);
+        for i in 1..=num_samples {
+            let t = i as f64 / num_samples as f64;
+            let next = (t, self.project::<P>(&curve(t)));
+            tracer.connect::<P>(prev, next, MAX_SUBDIVISIONS);
+            prev = next;
+        }
+
+        let Tracer {
+            mut current,
+            mut polylines,
+            ..
+        } = tracer;
+        let starts_first = first.is_some() && polylines.first().map(|p| p[0]) == first;
+        if closed && starts_first && !current.is_empty() {
+            // The last polyline goes on with the first one
+            current.extend(polylines[0].drain(1..));
+            polylines[0] = current;
+        } else if current.len() > 1 {
+            polylines.push(current);
+        }
+
+        polylines
+    }
+}
+
+struct Tracer<'a, 'b, F: Fn(f64) -> Vec3<f64>> {
+    view: &'a View<'b>,
+    curve: &'a F,
+    current: Vec<Vec2<f64>>,
+    polylines: Vec<Vec<Vec2<f64>>>,
+}
+
+impl<'a, 'b, F: Fn(f64) -> Vec3<f64>> Tracer<'a, 'b, F> {
+    fn flush(&mut self) {
+        let polyline = std::mem::take(&mut self.current);
+        if polyline.len() > 1 {
+            self.polylines.push(polyline);
+        }
+    }
+
+    // Last visible point when going from a visible parameter to an invisible one
+    fn boundary<P: Projection<f64>>(&self, mut visible: (f64, Vec2<f64>), mut invisible: f64) -> Vec2<f64> {
+        for _ in 0..BOUNDARY_ITERATIONS {
+            let t = 0.5 * (visible.0 + invisible);
+            match self.view.project::<P>(&(self.curve)(t)) {
+                Some(p) => visible = (t, p),
+                None => invisible = t,
+            }
+        }
+        visible.1
+    }
+
+    // Invariant: if `a` is visible, the current polyline ends with it
+    fn connect<P: Projection<f64>>(
+        &mut self,
+        a: (f64, Option<Vec2<f64>>),
+        b: (f64, Option<Vec2<f64>>),
+        depth: u32,
+    ) {
+        match (a.1, b.1) {
+            (Some(pa), Some(pb)) => {
+                if (pb - pa).magnitude() <= MAX_GAP_CLIP {
+                    self.current.push(pb);
+                } else if depth == 0 {
+                    // Discontinuity of the projection
+                    self.flush();
+                    self.current.push(pb);
+                } else {
+                    let t = 0.5 * (a.0 + b.0);
+                    let m = (t, self.view.project::<P>(&(self.curve)(t)));
+                    self.connect::<P>(a, m, depth - 1);
+                    self.connect::<P>(m, b, depth - 1);
+                }
+            }
+            (Some(pa), None) => {
+                let p = self.boundary::<P>((a.0, pa), b.0);
+                self.current.push(p);
+                self.flush();
+            }
+            (None, Some(pb)) => {
+                let p = self.boundary::<P>((b.0, pb), a.0);
+                self.current.push(p);
+                self.current.push(pb);
+            }
+            (None, None) => (),
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::angle::Angle;
+use crate::math::{Vec2, Vec3};
+use crate::overlay::View;
+use crate::projection::Projection;
+
+use cgmath::InnerSpace;
+
+/// k-d tree over positions of the unit sphere, for the nearest source lookups
+///
+/// The tree is stored implicitly: the node of a range of `order` is its middle
+/// element, its children being the two halves of that range.
+pub struct SpatialIndex {
+    points: Vec<Vec3<f64>>,
+    order: Vec<u32>,
+}
+
+impl SpatialIndex {
+    /// Build the index
+    ///
+    /// # Arguments
+    ///
+    /// * `points` - Unit vectors, their indices are the ones returned by the lookups
+    pub fn new(points: Vec<Vec3<f64>>) -> Self {
+        // Sources without a valid position are not indexed
+        let mut order: Vec<u32> = (0..points.len() as u32)
+            .filter(|&i| {
+                let p = points[i as usize];
+                p.x.is_finite() && p.y.is_finite() && p.z.is_finite()
+            })
+            .collect();
+        build(&points, &mut order, 0);
+
+        Self { points, order }
+    }
+
+    pub fn points(&self) -> &[Vec3<f64>] {
+        &self.points
+    }
+
+    /// Index of the closest point to a position, if closer than the given angular distance
+    pub fn nearest(&self, pos: &Vec3<f64>, max_dist: Angle<f64>) -> Option<usize> {
+        let mut best = (None, chord(max_dist));
+        self.nearest_in(&self.order, 0, pos, &mut best);
+        best.0
+    }
+
+    /// Indices of all the points within an angular distance of a position
+    pub fn within(&self, pos: &Vec3<f64>, dist: Angle<f64>) -> Vec<usize> {
+        let mut found = vec![];
+        self.within_in(&self.order, 0, pos, chord(dist), &mut found);
+        found.sort_unstable();
+        found
+    }
+
+    fn nearest_in(&self, order: &[u32], depth: usize, pos: &Vec3<f64>, best: &mut (Option<usize>, f64)) {
+        if order.is_empty() {
+            return;
+        }
+        let mid = order.len() / 2;
+        let idx = order[mid] as usize;
+        let node = self.points[idx];
+
+        let d = (node - pos).magnitude();
+        if d <= best.1 {
+            *best = (Some(idx), d);
+        }
+
+        let axis = depth % 3;
+        let delta = pos[axis] - node[axis];
+        let (near, far) = if delta < 0.0 {
+            (&order[..mid], &order[(mid + 1)..])
+        } else {
+            (&order[(mid + 1)..], &order[..mid])
+        };
+        self.nearest_in(near, depth + 1, pos, best);
+        if delta.abs() <= best.1 {
+            self.nearest_in(far, depth + 1, pos, best);
+        }
+    }
+
+    fn within_in(&self, order: &[u32], depth: usize, pos: &Vec3<f64>, radius: f64, found: &mut Vec<usize>) {
+        if order.is_empty() {
+            return;
+        }
+        let mid = order.len() / 2;
+        let idx = order[mid] as usize;
+        let node = self.points[idx];
+
+        if (node - pos).magnitude() <= radius {
+            found.push(idx);
+        }
+
+        let axis = depth % 3;
+        let delta = pos[axis] - node[axis];
+        if delta - radius <= 0.0 {
+            self.within_in(&order[..mid], depth + 1, pos, radius, found);
+        }
+        if delta + radius >= 0.0 {
+            self.within_in(&order[(mid + 1)..], depth + 1, pos, radius, found);
+        }
+    }
+}
+
+fn build(points: &[Vec3<f64>], order: &mut [u32], depth: usize) {
+    if order.len() <= 1 {
+        return;
+    }
+    let axis = depth % 3;
+    let mid = order.len() / 2;
+    order.select_nth_unstable_by(mid, |&a, &b| {
+        points[a as usize][axis].total_cmp(&points[b as usize][axis])
+    });
+
+    let (left, right) = order.split_at_mut(mid);
+    build(points, left, depth + 1);
+    build(points, &mut right[1..], depth + 1);
+}
+
+// Length of the chord subtending an angle of the unit sphere
+fn chord(angle: Angle<f64>) -> f64 {
+    2.0 * (0.5 * angle.0.clamp(0.0, std::f64::consts::PI)).sin()
+}
+
+/// Window position in pixels of a clip space position
+pub fn clip_to_screen(pos_clip: &Vec2<f64>, ndc_to_clip: &Vec2<f64>, width: f64, height: f64) -> Vec2<f64> {
+    Vec2::new(
+        (pos_clip.x * ndc_to_clip.x + 1.0) * 0.5 * width,
+        (1.0 - pos_clip.y * ndc_to_clip.y) * 0.5 * height,
+    )
+}
+
+/// Clip space position of a window position in pixels
+pub fn screen_to_clip(pos: &Vec2<f64>, ndc_to_clip: &Vec2<f64>, width: f64, height: f64) -> Vec2<f64> {
+    Vec2::new(
+        (2.0 * pos.x / width - 1.0) / ndc_to_clip.x,
+        (1.0 - 2.0 * pos.y / height) / ndc_to_clip.y,
+    )
+}
+
+/// Whether a point lies inside a polygon, following the even-odd rule
+pub fn polygon_contains(polygon: &[Vec2<f64>], p: &Vec2<f64>) -> bool {
+    let mut inside = false;
+    let mut prev = match polygon.last() {
+        Some(prev) => prev,
+        None => return false,
+    };
+    for cur in polygon {
+        if (cur.y > p.y) != (prev.y > p.y) {
+            let x = prev.x + (p.y - prev.y) * (cur.x - prev.x) / (cur.y - prev.y);
+            if p.x < x {
+                inside = !inside;
+            }
+        }
+        prev = cur;
+    }
+    inside
+}
+
+/// Indices of the points whose projection falls inside a polygon of the window
+///
+/// # Arguments
+///
+/// * `points` - ICRS unit vectors
+/// * `view` - The current view
+/// * `polygon` - Vertices of the polygon in pixels
+/// * `width` - Width of the window in pixels
+/// * `height` - Height of the window in pixels
+pub fn select_in_polygon<P: Projection<f64>>(
+    points: &[Vec3<f64>],
+    view: &View,
+    polygon: &[Vec2<f64>],
+    width: f64,
+    height: f64,
+) -> Vec<usize> {
+    if polygon.len() < 3 {
+        return vec![];
+    }
+    let (min, max) = polygon.iter().fold(
+        (Vec2::new(f64::INFINITY, f64::INFINITY), Vec2::new(f64::NEG_INFINITY, f64::NEG_INFINITY)),
+        |(min, max), p| (Vec2::new(min.x.min(p.x), min.y.min(p.y)), Vec2::new(max.x.max(p.x), max.y.max(p.y))),
+    );
+
+    points
+        .iter()
+        .enumerate()
+        .filter_map(|(i, p)| {
+            let pos_clip = view.project::<P>(p)?;
+            let pos = clip_to_screen(&pos_clip, &view.ndc_to_clip, width, height);
+            let in_bbox = pos.x >= min.x && pos.x <= max.x && pos.y >= min.y && pos.y <= max.y;
+            (in_bbox && polygon_contains(polygon, &pos)).then_some(i)
+        })
+        .collect()
+}
+
+mod tests {
+    #[test]
+    fn spatial_index() {
+        use super::*;
+        use crate::angle::ArcDeg;
+        use crate::math;
+
+        // A grid of points every 5 degrees, plus one invalid position
+        let mut points = vec![];
+        for i in 0..72 {
+            for j in -17..=17 {
+                points.push(math::radec_to_xyz(
+                    Angle::new(ArcDeg(5.0 * i as f64)),
+                    Angle::new(ArcDeg(5.0 * j as f64)),
+                ));
+            }
+        }
+        points.push(Vec3::new(f64::NAN, 0.0, 0.0));
+        let index = SpatialIndex::new(points.clone());
+
+        let brute_force = |pos: &Vec3<f64>| {
+            (0..points.len() - 1)
+                .min_by(|&a, &b| (points[a] - pos).magnitude().total_cmp(&(points[b] - pos).magnitude()))
+                .unwrap()
+        };
+        for k in 0..50 {
+            let pos = math::radec_to_xyz(
+                Angle::new(ArcDeg(7.3 * k as f64)),
+                Angle::new(ArcDeg(-80.0 + 3.1 * k as f64)),
+            );
+            assert_eq!(index.nearest(&pos, Angle::new(ArcDeg(10.0))), Some(brute_force(&pos)));
+        }
+        let pos = math::radec_to_xyz(Angle::new(ArcDeg(2.5)), Angle::new(ArcDeg(2.5)));
+        assert_eq!(index.nearest(&pos, Angle::new(ArcDeg(1.0))), None);
+        // The four surrounding grid points
+        assert_eq!(index.within(&pos, Angle::new(ArcDeg(4.0))).len(), 4);
+
+        // Screen space selection in a view looking at RA = 0, Dec = 0
+        let view = View {
+            icrs_to_world: Box::new(|p| *p),
+            ndc_to_clip: crate::projection::Gnomonic::compute_ndc_to_clip_factor(512.0, 512.0),
+            x_sign: 1.0,
+        };
+        let square = [
+            Vec2::new(200.0, 200.0),
+            Vec2::new(312.0, 200.0),
+            Vec2::new(312.0, 312.0),
+            Vec2::new(200.0, 312.0),
+        ];
+        let selected = select_in_polygon::<crate::projection::Gnomonic>(&points, &view, &square, 512.0, 512.0);
+        // The center of the window is the origin of the grid
+        let center = brute_force(&Vec3::new(0.0, 0.0, 1.0));
+        assert!(selected.contains(&center));
+        let clip = screen_to_clip(&Vec2::new(100.0, 400.0), &view.ndc_to_clip, 512.0, 512.0);
+        assert!((clip_to_screen(&clip, &view.ndc_to_clip, 512.0, 512.0) - Vec2::new(100.0, 400.0)).magnitude() < 1e-9);
+        assert!(!polygon_contains(&square, &Vec2::new(100.0, 400.0)));
+    }
+}
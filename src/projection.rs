@@ -29,6 +29,30 @@ pub trait Projection<T: Float> {
         }
     }
 
+    /// Screen to world space deprojection
+    ///
+    /// # Arguments
+    ///
+    /// * `pos_screen_space` - The position in pixels from the top left corner of the screen
+    fn screen_to_world_space(
+        pos_screen_space: &Vector2<T>,
+        width_screen: T,
+        height_screen: T,
+    ) -> Option<Vector4<T>> {
+        let two = T::from(2.0).unwrap();
+        let pos_normalized_device = Vector2::new(
+            two * pos_screen_space.x / width_screen - T::one(),
+            T::one() - two * pos_screen_space.y / height_screen,
+        );
+
+        let ndc_to_clip = Self::compute_ndc_to_clip_factor(width_screen, height_screen);
+        let pos_clip_space = Vector2::new(
+            pos_normalized_device.x / ndc_to_clip.x,
+            pos_normalized_device.y / ndc_to_clip.y,
+        );
+        Self::clip_to_world_space(&pos_clip_space)
+    }
+
     /// Perform a clip to the world space deprojection
     ///
     /// # Arguments
@@ -123,6 +147,7 @@ where
     ///
     /// * `x` - in normalized device coordinates between [-1; 1]
     /// * `y` - in normalized device coordinates between [-1; 1]
+    #[allow(clippy::needless_borrow)]
     fn clip_to_world_space(pos_clip_space: &Vector2<T>) -> Option<cgmath::Vector4<T>> {
         if Self::is_included_inside_projection(&pos_clip_space) {
             let u = pos_clip_space.x * T::PI() * T::from(0.5).unwrap();
@@ -180,8 +205,8 @@ where
             alpha / alpha.sin()
         };
 
-        // The minus is an astronomical convention.
-        // longitudes are increasing from right to left
+        // The longitudes increase from left to right, the sky being mirrored
+        // afterwards (see SphereMode::x_sign)
         let x = T::from(2.0).unwrap() * inv_sinc_alpha * delta.0.cos() * theta_by_two.0.sin();
         let y = inv_sinc_alpha * delta.0.sin();
 
@@ -340,6 +365,7 @@ where
     ///
     /// * `x` - in normalized device coordinates between [-1; 1]
     /// * `y` - in normalized device coordinates between [-1; 1]
+    #[allow(clippy::needless_borrow)]
     fn clip_to_world_space(pos_clip_space: &Vector2<T>) -> Option<cgmath::Vector4<T>> {
         if Self::is_included_inside_projection(&pos_clip_space) {
             let y2 = pos_clip_space.y * pos_clip_space.y;
@@ -352,8 +378,8 @@ where
                 / T::PI())
             .asin();
 
-            // The minus is an astronomical convention.
-            // longitudes are increasing from right to left
+            // The longitudes increase from left to right, the sky being mirrored
+            // afterwards (see SphereMode::x_sign)
             let pos_world_space = cgmath::Vector4::new(
                 theta.sin() * delta.cos(),
                 delta.sin(),
@@ -397,8 +423,8 @@ where
 
         theta /= T::from(2.0).unwrap();
 
-        // The minus is an astronomical convention.
-        // longitudes are increasing from right to left
+        // The longitudes increase from left to right, the sky being mirrored
+        // afterwards (see SphereMode::x_sign)
         let x = (lon.0 / T::PI()) * theta.cos();
         let y = T::from(0.5).unwrap() * theta.sin();
 
@@ -475,7 +501,7 @@ where
             let z = r.cos();
             r = math::sinc_positive(r);
 
-            let pos_world_space = Vector4::new(-x * r, y * r, z, T::one());
+            let pos_world_space = Vector4::new(x * r, y * r, z, T::one());
 
             Some(pos_world_space)
         }
@@ -657,6 +683,7 @@ where
     /// # Arguments
     ///
     /// * `pos_world_space` - Position in the world space. Must be a normalized vector
+    #[allow(clippy::needless_borrow)]
     fn world_to_clip_space(pos_world_space: &Vector4<T>) -> Option<Vector2<T>> {
         let (theta, delta) = math::xyzw_to_radec(&pos_world_space);
 
@@ -680,6 +707,7 @@ mod tests {
         use cgmath::InnerSpace;
         use cgmath::Vector2;
         use image::{Rgba, RgbaImage};
+        #[allow(clippy::unnecessary_cast)]
         fn generate_projection_map<P: Projection<f32>>(filename: &str) {
             let (w, h) = (1024.0, 1024.0);
             let mut img = RgbaImage::new(w as u32, h as u32);
@@ -715,4 +743,31 @@ mod tests {
         generate_projection_map::<Mercator>("./img/mercator.png");
         generate_projection_map::<Orthographic>("./img/sinus.png");*/
     }
+
+    #[test]
+    fn round_trip() {
+        use super::*;
+        use crate::angle::ArcDeg;
+        use cgmath::InnerSpace;
+        fn check<P: Projection<f64>>() {
+            for &(x, y) in &[(0.1, 0.05), (-0.3, 0.2), (0.25, -0.1), (-0.05, -0.3)] {
+                let pos_clip_space = Vector2::new(x, y);
+                let pos_world_space = P::clip_to_world_space(&pos_clip_space).unwrap();
+                let pos_world_space = pos_world_space.truncate().normalize().extend(1.0);
+                let back = P::world_to_clip_space(&pos_world_space).unwrap();
+                assert!((back - pos_clip_space).magnitude() < 1e-9);
+            }
+            // The longitudes increase along the x axis of the clip space
+            let east = math::radec_to_xyz(Angle::new(ArcDeg(10.0)), Angle::new(ArcDeg(5.0)));
+            assert!(P::world_to_clip_space(&east.extend(1.0)).unwrap().x > 0.0);
+            assert!(P::clip_to_world_space(&Vector2::new(0.1, 0.0)).unwrap().x > 0.0);
+        }
+
+        check::<Aitoff>();
+        check::<Ortho>();
+        check::<Mollweide>();
+        check::<Mercator>();
+        check::<AzimuthalEquidistant>();
+        check::<Gnomonic>();
+    }
 }
@@ -0,0 +1,218 @@
+// Reference renderer of the base survey on the CPU, doing for each pixel what allsky.vert
+// and allsky.frag do on the GPU, to get golden images on machines without any GPU.
+use crate::healpix;
+use crate::math::{Mat3, Vec2, Vec3};
+use crate::projection::*;
+
+use cgmath::InnerSpace;
+use image::RgbaImage;
+
+/// Clear color of the render pass, 0.01 in linear RGB, encoded in sRGB
+pub const BACKGROUND: [u8; 4] = [25, 25, 25, 255];
+
+/// Draw the HEALPix tiles of the base survey as the sky shaders do
+///
+/// The GPU interpolates the directions between the vertices of the triangulation of the
+/// projection whereas they are deprojected exactly here, and it samples the tiles without
+/// filtering where they are minified, so the two images differ slightly.
+///
+/// # Arguments
+///
+/// * `tiles` - Tiles of the 12 base cells, as uploaded to the texture of the base survey
+/// * `world_to_icrs` - Rotation from the world space to the ICRS frame
+/// * `x_sign` - Factor of the x axis of the world space, see `SphereMode::x_sign`
+/// * `width` - Width of the image in pixels
+/// * `height` - Height of the image in pixels
+pub fn render<P: Projection<f64>>(
+    tiles: &[RgbaImage],
+    world_to_icrs: &Mat3<f64>,
+    x_sign: f64,
+    width: u32,
+    height: u32,
+) -> RgbaImage {
+    let (w, h) = (width as f64, height as f64);
+    RgbaImage::from_fn(width, height, |x, y| {
+        // The fragments are shaded at the center of the pixels
+        let pos_screen_space = Vec2::new(x as f64 + 0.5, y as f64 + 0.5);
+        let pixel = P::screen_to_world_space(&pos_screen_space, w, h).and_then(|pos_world_space| {
+            let pos_world_space = Vec3::new(x_sign * pos_world_space.x, pos_world_space.y, pos_world_space.z);
+            let pos_icrs = world_to_icrs * pos_world_space.normalize();
+            sample_tiles(tiles, &pos_icrs)
+        });
+        image::Rgba(pixel.unwrap_or(BACKGROUND))
+    })
+}
+
+/// Draw the tiles with the projection of an index of the sky shaders, if there is one
+pub fn render_projection(
+    idx: usize,
+    tiles: &[RgbaImage],
+    world_to_icrs: &Mat3<f64>,
+    x_sign: f64,
+    width: u32,
+    height: u32,
+) -> Option<RgbaImage> {
+    let image = match idx {
+        0 => render::<Aitoff>(tiles, world_to_icrs, x_sign, width, height),
+        1 => render::<Ortho>(tiles, world_to_icrs, x_sign, width, height),
+        2 => render::<Mollweide>(tiles, world_to_icrs, x_sign, width, height),
+        3 => render::<Mercator>(tiles, world_to_icrs, x_sign, width, height),
+        4 => render::<AzimuthalEquidistant>(tiles, world_to_icrs, x_sign, width, height),
+        5 => render::<Gnomonic>(tiles, world_to_icrs, x_sign, width, height),
+        _ => return None,
+    };
+    Some(image)
+}
+
+/// RGBA value of the tiles at a position, with the bilinear filtering and the repeat
+/// addressing of the sampler of the base survey
+///
+/// The tiles are sRGB textures, filtered in linear RGB.
+///
+/// # Arguments
+///
+/// * `tiles` - Tiles of the 12 base cells
+/// * `pos_icrs` - Unit vector in the ICRS frame
+pub fn sample_tiles(tiles: &[RgbaImage], pos_icrs: &Vec3<f64>) -> Option<[u8; 4]> {
+    // The repo (x, y, z) frame is the usual (Y, Z, X) one
+    let (cell, dx, dy) = healpix::hash_with_dxdy(0, pos_icrs.z, pos_icrs.x, pos_icrs.y);
+    let tile = tiles.get(cell as usize)?;
+    let (w, h) = tile.dimensions();
+    if w == 0 || h == 0 {
+        return None;
+    }
+
+    // The columns go along dy and the rows along dx, the texels being centered
+    // on half integers
+    let s = dy * w as f64 - 0.5;
+    let t = dx * h as f64 - 0.5;
+    let (s0, t0) = (s.floor(), t.floor());
+    let (fs, ft) = (s - s0, t - t0);
+    let texel = |i: f64, j: f64, c: usize| {
+        let col = (i as i64).rem_euclid(w as i64) as u32;
+        let row = (j as i64).rem_euclid(h as i64) as u32;
+        let value = tile.get_pixel(col, row).0[c] as f64 / 255.0;
+        if c < 3 {
+            srgb_to_linear(value)
+        } else {
+            value
+        }
+    };
+
+    let mut rgba = [0; 4];
+    for (c, value) in rgba.iter_mut().enumerate() {
+        let v = (1.0 - ft) * ((1.0 - fs) * texel(s0, t0, c) + fs * texel(s0 + 1.0, t0, c))
+            + ft * ((1.0 - fs) * texel(s0, t0 + 1.0, c) + fs * texel(s0 + 1.0, t0 + 1.0, c));
+        let v = if c < 3 { linear_to_srgb(v) } else { v };
+        *value = (v * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    Some(rgba)
+}
+
+/// Largest difference between the channels of two images, None if their sizes differ
+pub fn max_difference(a: &RgbaImage, b: &RgbaImage) -> Option<u8> {
+    if a.dimensions() != b.dimensions() {
+        return None;
+    }
+    Some(
+        a.as_raw()
+            .iter()
+            .zip(b.as_raw())
+            .map(|(&u, &v)| u.abs_diff(v))
+            .max()
+            .unwrap_or(0),
+    )
+}
+
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+mod tests {
+    #[test]
+    fn reference() {
+        use super::*;
+        use cgmath::SquareMatrix;
+
+        // Tiles of uniform colors, and one of vertical stripes
+        let mut tiles: Vec<RgbaImage> = (0..12)
+            .map(|cell| RgbaImage::from_pixel(8, 8, image::Rgba([20 * cell as u8, 100, 200, 255])))
+            .collect();
+        tiles[4] = RgbaImage::from_fn(8, 8, |col, _| image::Rgba([if col % 2 == 0 { 0 } else { 255 }, 0, 0, 255]));
+        assert_eq!((linear_to_srgb(0.01) * 255.0) as u8, BACKGROUND[0]);
+
+        let identity = Mat3::identity();
+        for idx in 0..6 {
+            let image = render_projection(idx, &tiles, &identity, 1.0, 64, 32).unwrap();
+            assert_eq!(image.dimensions(), (64, 32));
+            assert_eq!(max_difference(&image, &image), Some(0));
+            // The center of the view looks along +z, in the base cell 4
+            let center = image.get_pixel(32, 16).0;
+            assert_eq!((center[1], center[2]), (0, 0));
+            // The sky of the Aitoff, orthographic, Mollweide and azimuthal equidistant
+            // projections does not reach the corners
+            if [0, 1, 2, 4].contains(&idx) {
+                assert_eq!(image.get_pixel(0, 0).0, BACKGROUND);
+            }
+        }
+
+        assert!(render_projection(6, &tiles, &identity, 1.0, 64, 32).is_none());
+
+        // Rotated to look at the longitude 90°, in the base cell 5
+        let rot = Mat3::from_cols(Vec3::new(0.0, 0.0, -1.0), Vec3::unit_y(), Vec3::unit_x());
+        let image = render::<Ortho>(&tiles, &rot, 1.0, 33, 33);
+        assert_eq!(image.get_pixel(16, 16).0, [100, 100, 200, 255]);
+        assert_eq!(max_difference(&image, &RgbaImage::new(32, 33)), None);
+
+        // Center of the base cell 0, the uniform tiles keeping their colors
+        let (lon, lat) = (45f64.to_radians(), (2.0 / 3f64).asin());
+        let pos = Vec3::new(lat.cos() * lon.sin(), lat.sin(), lat.cos() * lon.cos());
+        assert_eq!(sample_tiles(&tiles, &pos), Some([0, 100, 200, 255]));
+        // Halfway between two stripes, blended in linear RGB
+        assert_eq!(sample_tiles(&tiles, &Vec3::unit_z()), Some([188, 0, 0, 255]));
+    }
+
+    #[test]
+    fn goldens() {
+        use super::*;
+
+        // Largest difference allowed with the golden images, for the decoders of other
+        // platforms and the rounding of the floats
+        const TOLERANCE: u8 = 2;
+
+        let tiles: Vec<RgbaImage> = (0..12)
+            .map(|cell| {
+                let tile = image::open(format!("./img/Npix{}.jpg", cell)).unwrap().to_rgba8();
+                image::imageops::resize(&tile, 64, 64, image::imageops::FilterType::Triangle)
+            })
+            .collect();
+        // Looking at the longitude 30° and the latitude 20°, rolled by 10°
+        let world_to_icrs = Mat3::from_angle_y(cgmath::Deg(30.0))
+            * Mat3::from_angle_x(cgmath::Deg(-20.0))
+            * Mat3::from_angle_z(cgmath::Deg(10.0));
+        for idx in 0..6 {
+            // The sky, mirrored to have the east on the left
+            let image = render_projection(idx, &tiles, &world_to_icrs, -1.0, 128, 64).unwrap();
+            let path = format!("./img/golden/projection{}.png", idx);
+            // UPDATE_GOLDENS=1 cargo test writes them again after a deliberate change
+            if std::env::var_os("UPDATE_GOLDENS").is_some() {
+                image.save(&path).unwrap();
+            }
+            let golden = image::open(&path).unwrap().to_rgba8();
+            let diff = max_difference(&image, &golden);
+            assert!(diff.is_some_and(|diff| diff <= TOLERANCE), "{}: {:?}", path, diff);
+        }
+    }
+}
@@ -0,0 +1,762 @@
+use crate::angle::{Angle, ArcDeg};
+use crate::error::Error;
+use crate::frame;
+use crate::math::{self, Vec2, Vec3};
+use crate::overlay::View;
+use crate::projection::Projection;
+
+use cgmath::{InnerSpace, Matrix};
+use std::path::Path;
+
+// Number of vertices of the circles and ellipses
+const NUM_CURVE_VERTICES: usize = 72;
+// Angular step in degrees used to sample the edges before projecting them
+const SAMPLE_STEP_DEG: f64 = 1.0;
+// Longest edge in degrees of the triangles filling a region
+const MAX_FILL_EDGE_DEG: f64 = 3.0;
+const MAX_FILL_SUBDIVISIONS: u32 = 6;
+// Triangles stretched further than that in clip space cross a seam of the projection
+const MAX_FILL_EDGE_CLIP: f64 = 0.25;
+// Opacity of the fills relative to the outlines
+const FILL_OPACITY: f32 = 0.25;
+// Number of samples of the dashes of the excluded regions, and of the gaps between them
+const DASH_SAMPLES: usize = 3;
+
+const DEFAULT_COLOR: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+
+/// A shape of the sphere, its positions being ICRS unit vectors
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    /// Vertices linked by great circle arcs
+    Polygon(Vec<Vec3<f64>>),
+    Circle {
+        center: Vec3<f64>,
+        radius: Angle<f64>,
+    },
+    /// The position angle is counted from the east (first axis) towards the north
+    Ellipse {
+        center: Vec3<f64>,
+        semi_major: Angle<f64>,
+        semi_minor: Angle<f64>,
+        angle: Angle<f64>,
+    },
+    Box {
+        center: Vec3<f64>,
+        width: Angle<f64>,
+        height: Angle<f64>,
+        angle: Angle<f64>,
+    },
+    Union(Vec<Shape>),
+}
+
+impl Shape {
+    /// Position the shape is labelled at
+    pub fn center(&self) -> Option<Vec3<f64>> {
+        match self {
+            Shape::Polygon(vertices) => {
+                let sum = vertices.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, p| sum + p);
+                (sum.magnitude2() > 1e-12).then(|| sum.normalize())
+            }
+            Shape::Circle { center, .. } | Shape::Ellipse { center, .. } | Shape::Box { center, .. } => {
+                Some(*center)
+            }
+            Shape::Union(shapes) => shapes.first().and_then(Shape::center),
+        }
+    }
+
+    /// Closed polygons bounding the shape, their edges being great circle arcs
+    pub fn contours(&self) -> Vec<Vec<Vec3<f64>>> {
+        let curve = |center: &Vec3<f64>, a: f64, b: f64, angle: f64| {
+            (0..NUM_CURVE_VERTICES)
+                .map(|i| {
+                    let phi = 2.0 * std::f64::consts::PI * i as f64 / NUM_CURVE_VERTICES as f64;
+                    offset(center, a * phi.cos(), b * phi.sin(), angle)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        match self {
+            Shape::Polygon(vertices) => vec![vertices.clone()],
+            Shape::Circle { center, radius } => vec![curve(center, radius.0, radius.0, 0.0)],
+            Shape::Ellipse {
+                center,
+                semi_major,
+                semi_minor,
+                angle,
+            } => vec![curve(center, semi_major.0, semi_minor.0, angle.0)],
+            Shape::Box {
+                center,
+                width,
+                height,
+                angle,
+            } => {
+                let (w, h) = (0.5 * width.0, 0.5 * height.0);
+                let corners = [(-w, -h), (w, -h), (w, h), (-w, h)];
+                vec![corners.iter().map(|&(x, y)| offset(center, x, y, angle.0)).collect()]
+            }
+            Shape::Union(shapes) => shapes.iter().flat_map(|shape| shape.contours()).collect(),
+        }
+    }
+}
+
+// Position at the given offsets from a center along the rotated east and north
+// directions, the offsets being the angular distances along these directions
+fn offset(center: &Vec3<f64>, x: f64, y: f64, angle: f64) -> Vec3<f64> {
+    let (east, north) = math::local_basis(center);
+    let (s, c) = angle.sin_cos();
+    let u = east * c + north * s;
+    let v = north * c - east * s;
+
+    let dist = x.hypot(y);
+    if dist < 1e-15 {
+        return *center;
+    }
+    let dir = (u * x + v * y) / dist;
+    center * dist.cos() + dir * dist.sin()
+}
+
+/// How a region is drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegionStyle {
+    pub color: [f32; 4],
+    pub fill: bool,
+    /// Region excluded from the others, drawn dashed and unfilled
+    pub exclude: bool,
+}
+
+impl Default for RegionStyle {
+    fn default() -> Self {
+        Self {
+            color: DEFAULT_COLOR,
+            fill: false,
+            exclude: false,
+        }
+    }
+}
+
+/// A footprint or region of interest
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub shape: Shape,
+    pub style: RegionStyle,
+    pub text: Option<String>,
+    // Triangles filling the shape, fixed in the ICRS frame
+    triangles: Vec<[Vec3<f64>; 3]>,
+}
+
+impl Region {
+    /// Region whose fill is triangulated once, the triangles being projected at each view
+    pub fn new(shape: Shape, style: RegionStyle, text: Option<String>) -> Self {
+        let mut triangles = vec![];
+        for contour in shape.contours() {
+            for [a, b, c] in triangulate(&contour) {
+                subdivide(a, b, c, MAX_FILL_SUBDIVISIONS, &mut |a, b, c| triangles.push([a, b, c]));
+            }
+        }
+        Self {
+            shape,
+            style,
+            text,
+            triangles,
+        }
+    }
+
+    /// Project the outline of the region into clip space polylines, the dashes of the
+    /// excluded regions being separate polylines
+    pub fn outline<P: Projection<f64>>(&self, view: &View) -> Vec<Vec<Vec2<f64>>> {
+        let polylines = self
+            .shape
+            .contours()
+            .into_iter()
+            .filter(|contour| contour.len() > 1)
+            .flat_map(|contour| {
+                let n = contour.len();
+                let length: f64 = (0..n).map(|i| contour[i].angle(contour[(i + 1) % n]).0).sum();
+                let num_samples = ((length.to_degrees() / SAMPLE_STEP_DEG).ceil() as usize).max(n);
+                let curve = |t: f64| {
+                    let t = t * n as f64;
+                    let i = (t.floor() as usize).min(n - 1);
+                    slerp(&contour[i], &contour[(i + 1) % n], t - i as f64)
+                };
+                view.trace::<P>(curve, num_samples, true)
+            });
+        if !self.style.exclude {
+            return polylines.collect();
+        }
+        polylines
+            .flat_map(|polyline| {
+                (0..polyline.len().saturating_sub(1))
+                    .step_by(2 * DASH_SAMPLES)
+                    .map(|start| polyline[start..=(start + DASH_SAMPLES).min(polyline.len() - 1)].to_vec())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Project the inside of the region into clip space triangles
+    ///
+    /// The triangles crossing the borders of the projection are dropped.
+    pub fn fill<P: Projection<f64>>(&self, view: &View) -> Vec<[Vec2<f64>; 3]> {
+        self.triangles
+            .iter()
+            .filter_map(|triangle| match triangle.map(|p| view.project::<P>(&p)) {
+                [Some(a), Some(b), Some(c)] => {
+                    let max_edge = (b - a).magnitude().max((c - b).magnitude()).max((a - c).magnitude());
+                    (max_edge < MAX_FILL_EDGE_CLIP).then_some([a, b, c])
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Color of the fill, if the region is filled
+    pub fn fill_color(&self) -> Option<[f32; 4]> {
+        let [r, g, b, a] = self.style.color;
+        (self.style.fill && !self.style.exclude).then_some([r, g, b, a * FILL_OPACITY])
+    }
+}
+
+fn slerp(a: &Vec3<f64>, b: &Vec3<f64>, t: f64) -> Vec3<f64> {
+    let angle = a.angle(*b).0;
+    if angle < 1e-12 {
+        return *a;
+    }
+    (a * ((1.0 - t) * angle).sin() + b * (t * angle).sin()) / angle.sin()
+}
+
+// Split a spherical triangle until its edges are short enough
+fn subdivide(a: Vec3<f64>, b: Vec3<f64>, c: Vec3<f64>, depth: u32, emit: &mut impl FnMut(Vec3<f64>, Vec3<f64>, Vec3<f64>)) {
+    let max_edge = a.angle(b).0.max(b.angle(c).0).max(c.angle(a).0);
+    if depth == 0 || max_edge <= MAX_FILL_EDGE_DEG.to_radians() {
+        emit(a, b, c);
+        return;
+    }
+    let (ab, bc, ca) = ((a + b).normalize(), (b + c).normalize(), (c + a).normalize());
+    subdivide(a, ab, ca, depth - 1, emit);
+    subdivide(ab, b, bc, depth - 1, emit);
+    subdivide(ca, bc, c, depth - 1, emit);
+    subdivide(ab, bc, ca, depth - 1, emit);
+}
+
+// Ear clipping of a spherical polygon in the gnomonic plane tangent at its center.
+// Polygons not fitting in a hemisphere are not triangulated.
+fn triangulate(contour: &[Vec3<f64>]) -> Vec<[Vec3<f64>; 3]> {
+    if contour.len() < 3 {
+        return vec![];
+    }
+    let center = contour.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, p| sum + p);
+    if center.magnitude2() < 1e-12 {
+        return vec![];
+    }
+    let center = center.normalize();
+    if contour.iter().any(|p| p.dot(center) <= 1e-6) {
+        return vec![];
+    }
+
+    let (east, north) = math::local_basis(&center);
+    let plane: Vec<Vec2<f64>> = contour
+        .iter()
+        .map(|p| Vec2::new(p.dot(east), p.dot(north)) / p.dot(center))
+        .collect();
+
+    let cross = |o: Vec2<f64>, a: Vec2<f64>, b: Vec2<f64>| (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x);
+    let area: f64 = (0..plane.len())
+        .map(|i| cross(Vec2::new(0.0, 0.0), plane[i], plane[(i + 1) % plane.len()]))
+        .sum();
+    let mut remaining: Vec<usize> = (0..plane.len()).collect();
+    if area < 0.0 {
+        remaining.reverse();
+    }
+
+    let mut triangles = vec![];
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let (ia, ib, ic) = (remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]);
+            let (a, b, c) = (plane[ia], plane[ib], plane[ic]);
+            cross(a, b, c) > 0.0
+                && remaining.iter().all(|&j| {
+                    j == ia
+                        || j == ib
+                        || j == ic
+                        || cross(a, b, plane[j]) < 0.0
+                        || cross(b, c, plane[j]) < 0.0
+                        || cross(c, a, plane[j]) < 0.0
+                })
+        });
+        // A degenerate polygon has no ear left
+        let i = match ear {
+            Some(i) => i,
+            None => break,
+        };
+        triangles.push([
+            contour[remaining[(i + n - 1) % n]],
+            contour[remaining[i]],
+            contour[remaining[(i + 1) % n]],
+        ]);
+        remaining.remove(i);
+    }
+    if remaining.len() == 3 {
+        triangles.push([contour[remaining[0]], contour[remaining[1]], contour[remaining[2]]]);
+    }
+    triangles
+}
+
+// Frames of the coordinates of the region files
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RegionFrame {
+    Icrs,
+    Galactic,
+}
+
+impl RegionFrame {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "icrs" | "fk5" | "j2000" | "unknownframe" => Some(RegionFrame::Icrs),
+            "galactic" => Some(RegionFrame::Galactic),
+            _ => None,
+        }
+    }
+
+    // ICRS unit vector of a position given in degrees
+    fn to_icrs(self, lon: f64, lat: f64) -> Vec3<f64> {
+        let pos = math::radec_to_xyz(Angle::new(ArcDeg(lon)), Angle::new(ArcDeg(lat)));
+        match self {
+            RegionFrame::Icrs => pos,
+            RegionFrame::Galactic => frame::icrs_to_galactic().transpose() * pos,
+        }
+    }
+
+    // Position angles are given with respect to the axes of the frame
+    fn to_icrs_angle(self, lon: f64, lat: f64, angle: f64) -> Angle<f64> {
+        let angle = angle.to_radians();
+        match self {
+            RegionFrame::Icrs => Angle(angle),
+            RegionFrame::Galactic => {
+                // Angle between the galactic and equatorial east directions at the position
+                let pos = self.to_icrs(lon, lat);
+                let to_icrs = frame::icrs_to_galactic().transpose();
+                let (east_gal, _) = math::local_basis(&(frame::icrs_to_galactic() * pos));
+                let east_gal = to_icrs * east_gal;
+                let (east, north) = math::local_basis(&pos);
+                Angle(angle + east_gal.dot(north).atan2(east_gal.dot(east)))
+            }
+        }
+    }
+}
+
+/// Parse STC-S regions (Polygon, Circle, Box, Union), e.g. as written by the VO services
+///
+/// Positions and sizes are given in degrees. The ICRS, FK5 and GALACTIC frames are supported.
+pub fn parse_stcs(text: &str) -> Result<Vec<Region>, Error> {
+    let spaced = text.replace('(', " ( ").replace(')', " ) ");
+    let mut tokens = spaced.split_whitespace().peekable();
+
+    let mut regions = vec![];
+    while tokens.peek().is_some() {
+        let shape = parse_stcs_shape(&mut tokens, RegionFrame::Icrs)?;
+        regions.push(Region::new(shape, RegionStyle::default(), None));
+    }
+    Ok(regions)
+}
+
+fn parse_stcs_shape<'a>(
+    tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a str>>,
+    mut frame: RegionFrame,
+) -> Result<Shape, Error> {
+    let err = |msg: String| Error::Parse(format!("STC-S: {}", msg));
+    let name = tokens.next().ok_or_else(|| err("missing shape".to_string()))?.to_ascii_lowercase();
+
+    // Frame, reference position and flavor
+    while let Some(&token) = tokens.peek() {
+        if token == "(" || token.parse::<f64>().is_ok() || is_stcs_shape(token) {
+            break;
+        }
+        if let Some(f) = RegionFrame::parse(token) {
+            frame = f;
+        } else if ["fk4", "b1950", "ecliptic", "geo_c", "geo_d"].contains(&token.to_ascii_lowercase().as_str()) {
+            return Err(err(format!("unsupported frame {}", token)));
+        }
+        tokens.next();
+    }
+
+    if name == "union" {
+        if tokens.next() != Some("(") {
+            return Err(err("expected ( after Union".to_string()));
+        }
+        let mut shapes = vec![];
+        loop {
+            match tokens.peek() {
+                Some(&")") => {
+                    tokens.next();
+                    break;
+                }
+                Some(_) => shapes.push(parse_stcs_shape(tokens, frame)?),
+                None => return Err(err("unterminated Union".to_string())),
+            }
+        }
+        return Ok(Shape::Union(shapes));
+    }
+
+    let mut values = vec![];
+    while let Some(value) = tokens.peek().and_then(|token| token.parse::<f64>().ok()) {
+        values.push(value);
+        tokens.next();
+    }
+
+    match (name.as_str(), values.len()) {
+        ("polygon", n) if n >= 6 && n % 2 == 0 => Ok(Shape::Polygon(
+            values.chunks(2).map(|v| frame.to_icrs(v[0], v[1])).collect(),
+        )),
+        ("circle", 3) => Ok(Shape::Circle {
+            center: frame.to_icrs(values[0], values[1]),
+            radius: Angle::new(ArcDeg(values[2])),
+        }),
+        ("box", 4) => Ok(Shape::Box {
+            center: frame.to_icrs(values[0], values[1]),
+            width: Angle::new(ArcDeg(values[2])),
+            height: Angle::new(ArcDeg(values[3])),
+            angle: frame.to_icrs_angle(values[0], values[1], 0.0),
+        }),
+        (name, n) => Err(err(format!("unsupported shape {} with {} values", name, n))),
+    }
+}
+
+fn is_stcs_shape(token: &str) -> bool {
+    ["polygon", "circle", "box", "union", "intersection", "not", "position"]
+        .contains(&token.to_ascii_lowercase().as_str())
+}
+
+/// Parse a DS9 region file
+///
+/// The circle, ellipse, box, polygon and annulus shapes are read in the fk5,
+/// icrs and galactic systems, with their color, fill and text properties.
+/// The annuli are only outlined, and the regions excluded by a leading - are dashed.
+pub fn parse_ds9(text: &str) -> Result<Vec<Region>, Error> {
+    let mut regions = vec![];
+    let mut frame = RegionFrame::Icrs;
+    let mut default_style = RegionStyle::default();
+
+    for (i, line) in text.lines().enumerate() {
+        let err = |msg: String| Error::Parse(format!("DS9 line {}: {}", i + 1, msg));
+        let line = line.trim();
+        // A leading # marks a comment, elsewhere it starts the properties
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (statements, properties) = match line.find('#') {
+            Some(idx) => (&line[..idx], &line[(idx + 1)..]),
+            None => (line, ""),
+        };
+
+        if let Some(global) = statements.trim().strip_prefix("global") {
+            default_style = parse_ds9_style(global, default_style);
+            continue;
+        }
+        let style = parse_ds9_style(properties, default_style);
+        let text = ds9_property(properties, "text");
+
+        for statement in statements.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+            let mut style = style;
+            style.exclude = statement.starts_with('-');
+            let statement = statement.trim_start_matches(['+', '-']);
+            let (name, args) = match statement.find('(') {
+                Some(idx) => {
+                    let end = statement.rfind(')').ok_or_else(|| err("missing )".to_string()))?;
+                    (statement[..idx].trim().to_ascii_lowercase(), &statement[(idx + 1)..end])
+                }
+                None => (statement.to_ascii_lowercase(), ""),
+            };
+
+            if args.is_empty() {
+                match RegionFrame::parse(&name) {
+                    Some(f) => frame = f,
+                    None if ["image", "physical", "fk4", "b1950", "ecliptic", "linear", "amplifier", "detector"]
+                        .contains(&name.as_str()) =>
+                    {
+                        return Err(err(format!("unsupported coordinate system {}", name)))
+                    }
+                    // e.g. the format line of the header
+                    None => (),
+                }
+                continue;
+            }
+
+            let args: Vec<&str> = args
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|a| !a.is_empty())
+                .collect();
+            let coord = |idx: usize| -> Result<(f64, f64), Error> {
+                let lon = args.get(idx).ok_or_else(|| err("missing coordinate".to_string()))?;
+                let lat = args.get(idx + 1).ok_or_else(|| err("missing coordinate".to_string()))?;
+                let lon_hours = frame == RegionFrame::Icrs;
+                Ok((
+                    parse_ds9_coordinate(lon, lon_hours).ok_or_else(|| err(format!("invalid coordinate {}", lon)))?,
+                    parse_ds9_coordinate(lat, false).ok_or_else(|| err(format!("invalid coordinate {}", lat)))?,
+                ))
+            };
+            let size = |idx: usize| -> Result<Angle<f64>, Error> {
+                let arg = args.get(idx).ok_or_else(|| err("missing size".to_string()))?;
+                parse_ds9_size(arg)
+                    .map(|deg| Angle::new(ArcDeg(deg)))
+                    .ok_or_else(|| err(format!("invalid size {}", arg)))
+            };
+            let angle = |idx: usize| -> Result<f64, Error> {
+                match args.get(idx) {
+                    Some(arg) => arg.parse().map_err(|_| err(format!("invalid angle {}", arg))),
+                    None => Ok(0.0),
+                }
+            };
+
+            let shape = match name.as_str() {
+                "circle" => {
+                    let (lon, lat) = coord(0)?;
+                    Shape::Circle {
+                        center: frame.to_icrs(lon, lat),
+                        radius: size(2)?,
+                    }
+                }
+                "ellipse" => {
+                    let (lon, lat) = coord(0)?;
+                    Shape::Ellipse {
+                        center: frame.to_icrs(lon, lat),
+                        semi_major: size(2)?,
+                        semi_minor: size(3)?,
+                        angle: frame.to_icrs_angle(lon, lat, angle(4)?),
+                    }
+                }
+                "box" => {
+                    let (lon, lat) = coord(0)?;
+                    Shape::Box {
+                        center: frame.to_icrs(lon, lat),
+                        width: size(2)?,
+                        height: size(3)?,
+                        angle: frame.to_icrs_angle(lon, lat, angle(4)?),
+                    }
+                }
+                "polygon" => {
+                    if args.len() < 6 || args.len() % 2 == 1 {
+                        return Err(err("a polygon needs at least 3 vertices".to_string()));
+                    }
+                    let vertices = (0..args.len())
+                        .step_by(2)
+                        .map(|idx| coord(idx).map(|(lon, lat)| frame.to_icrs(lon, lat)))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Shape::Polygon(vertices)
+                }
+                "annulus" => {
+                    let (lon, lat) = coord(0)?;
+                    let center = frame.to_icrs(lon, lat);
+                    let circles = (2..args.len())
+                        .map(|idx| size(idx).map(|radius| Shape::Circle { center, radius }))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    // Filling the circles would cover the holes
+                    style.fill = false;
+                    Shape::Union(circles)
+                }
+                // Points, lines, texts...
+                _ => continue,
+            };
+            regions.push(Region::new(shape, style, text.clone()));
+        }
+    }
+    Ok(regions)
+}
+
+fn ds9_property(properties: &str, key: &str) -> Option<String> {
+    let start = properties.find(&format!("{}=", key))? + key.len() + 1;
+    let rest = &properties[start..];
+    let (open, close) = match rest.chars().next()? {
+        '{' => ('{', '}'),
+        '"' => ('"', '"'),
+        '\'' => ('\'', '\''),
+        _ => return rest.split_whitespace().next().map(str::to_string),
+    };
+    let rest = &rest[open.len_utf8()..];
+    rest.find(close).map(|end| rest[..end].to_string())
+}
+
+fn parse_ds9_style(properties: &str, mut style: RegionStyle) -> RegionStyle {
+    if let Some(color) = ds9_property(properties, "color").and_then(|c| parse_color(&c)) {
+        style.color = color;
+    }
+    if let Some(fill) = ds9_property(properties, "fill") {
+        style.fill = fill == "1";
+    }
+    style
+}
+
+/// RGBA color of a DS9 color name or of a #rrggbb code
+pub fn parse_color(name: &str) -> Option<[f32; 4]> {
+    let rgb = match name.to_ascii_lowercase().as_str() {
+        "white" => [1.0, 1.0, 1.0],
+        "black" => [0.0, 0.0, 0.0],
+        "red" => [1.0, 0.0, 0.0],
+        "green" => [0.0, 1.0, 0.0],
+        "blue" => [0.0, 0.0, 1.0],
+        "cyan" => [0.0, 1.0, 1.0],
+        "magenta" => [1.0, 0.0, 1.0],
+        "yellow" => [1.0, 1.0, 0.0],
+        hex => {
+            let hex = hex.strip_prefix('#')?;
+            if hex.len() != 6 {
+                return None;
+            }
+            let channel = |i: usize| u8::from_str_radix(&hex[(2 * i)..(2 * i + 2)], 16).ok().map(|c| c as f32 / 255.0);
+            [channel(0)?, channel(1)?, channel(2)?]
+        }
+    };
+    Some([rgb[0], rgb[1], rgb[2], 1.0])
+}
+
+// Degrees from a decimal value or from a sexagesimal one, in hours for the right ascensions
+fn parse_ds9_coordinate(value: &str, hours: bool) -> Option<f64> {
+    if !value.contains(':') {
+        return value.trim_end_matches('d').parse().ok();
+    }
+    let negative = value.trim_start().starts_with('-');
+    let mut parts = value.trim_start_matches(['-', '+']).split(':').map(str::parse::<f64>);
+    let (d, m, s) = (parts.next()?.ok()?, parts.next()?.ok()?, parts.next().unwrap_or(Ok(0.0)).ok()?);
+    let value = d + m / 60.0 + s / 3600.0;
+    let value = if hours { 15.0 * value } else { value };
+    Some(if negative { -value } else { value })
+}
+
+// Degrees from a size in degrees, arcminutes (') or arcseconds (")
+fn parse_ds9_size(value: &str) -> Option<f64> {
+    if let Some(v) = value.strip_suffix('"') {
+        v.parse::<f64>().ok().map(|v| v / 3600.0)
+    } else if let Some(v) = value.strip_suffix('\'') {
+        v.parse::<f64>().ok().map(|v| v / 60.0)
+    } else {
+        value.trim_end_matches('d').parse().ok()
+    }
+}
+
+/// Read a region file, DS9 files being recognized by their .reg extension or header
+pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Vec<Region>, Error> {
+    let is_reg = path
+        .as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("reg"));
+    let text = std::fs::read_to_string(path)?;
+    if is_reg || text.trim_start().starts_with("# Region file format") {
+        parse_ds9(&text)
+    } else {
+        parse_stcs(&text)
+    }
+}
+
+mod tests {
+    #[test]
+    fn regions() {
+        use super::*;
+        let deg = |a: Angle<f64>| ArcDeg::from(a).0;
+        let radec = |p: &Vec3<f64>| {
+            let (ra, dec) = math::xyz_to_radec(p);
+            ((deg(ra) + 360.0) % 360.0, deg(dec))
+        };
+
+        let regions = parse_stcs(
+            "Polygon ICRS TOPOCENTER 10 20 12 20 12 22 10 22\n\
+             Union ICRS (Circle 83.6 22.0 0.5 Box GALACTIC 0 0 2 1)",
+        )
+        .unwrap();
+        assert_eq!(regions.len(), 2);
+        let contours = regions[0].shape.contours();
+        assert_eq!(contours[0].len(), 4);
+        let (ra, dec) = radec(&contours[0][2]);
+        assert!((ra - 12.0).abs() < 1e-9 && (dec - 22.0).abs() < 1e-9);
+        match &regions[1].shape {
+            Shape::Union(shapes) => {
+                assert_eq!(shapes.len(), 2);
+                // The galactic center
+                let (ra, dec) = match &shapes[1] {
+                    Shape::Box { center, .. } => radec(center),
+                    _ => panic!(),
+                };
+                assert!((ra - 266.405).abs() < 1e-2 && (dec + 28.936).abs() < 1e-2);
+            }
+            _ => panic!(),
+        }
+        assert!(parse_stcs("Circle FK4 10 20 1").is_err());
+
+        let regions = parse_ds9(
+            "# Region file format: DS9 version 4.1\n\
+             global color=green fill=0\n\
+             fk5\n\
+             circle(05:34:31.94,+22:00:52.2,30') # color=red fill=1 text={Crab}\n\
+             ellipse(10.68,41.27,1.5,0.5,45)\n\
+             point(10,10)\n\
+             galactic;box(0,0,3600\",1,30) # color=#00ff80",
+        )
+        .unwrap();
+        assert_eq!(regions.len(), 3);
+        match &regions[0].shape {
+            Shape::Circle { center, radius } => {
+                let (ra, dec) = radec(center);
+                assert!((ra - 83.633_08).abs() < 1e-4 && (dec - 22.014_5).abs() < 1e-4);
+                assert!((deg(*radius) - 0.5).abs() < 1e-12);
+            }
+            _ => panic!(),
+        }
+        assert_eq!(
+            regions[0].style,
+            RegionStyle {
+                color: [1.0, 0.0, 0.0, 1.0],
+                fill: true,
+                exclude: false
+            }
+        );
+        assert_eq!(regions[0].text.as_deref(), Some("Crab"));
+        assert_eq!(regions[1].style, RegionStyle::default());
+        assert_eq!(regions[2].style.color, [0.0, 1.0, 128.0 / 255.0, 1.0]);
+        assert!(parse_ds9("image\ncircle(100,100,20)").is_err());
+
+        // The excluded regions and the annuli are not filled
+        let unfilled = parse_ds9("global fill=1\nfk5\n-circle(10,20,1)\nannulus(10,20,1,2)").unwrap();
+        assert!(unfilled[0].style.exclude && unfilled[0].fill_color().is_none());
+        assert!(!unfilled[1].style.exclude && unfilled[1].fill_color().is_none());
+
+        // The major axis of the ellipse is rotated by 45 degrees from the east
+        let contour = &regions[1].shape.contours()[0];
+        let (ra, dec) = radec(&contour[0]);
+        let expected = 1.5 / 2.0_f64.sqrt();
+        assert!((dec - 41.27 - expected).abs() < 0.01);
+        assert!(((ra - 10.68) * 41.27_f64.to_radians().cos() - expected).abs() < 0.02);
+
+        // Filling a circle looking at it through the gnomonic projection
+        let view = View {
+            icrs_to_world: Box::new(|p| *p),
+            ndc_to_clip: crate::projection::Gnomonic::compute_ndc_to_clip_factor(512.0, 512.0),
+            x_sign: 1.0,
+        };
+        let circle = Region::new(
+            Shape::Circle {
+                center: Vec3::new(0.0, 0.0, 1.0),
+                radius: Angle::new(ArcDeg(10.0)),
+            },
+            RegionStyle::default(),
+            None,
+        );
+        let area: f64 = circle
+            .fill::<crate::projection::Gnomonic>(&view)
+            .iter()
+            .map(|[a, b, c]| 0.5 * ((b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)).abs())
+            .sum();
+        let r = 10.0_f64.to_radians().tan() / std::f64::consts::PI;
+        assert!((area / (std::f64::consts::PI * r * r) - 1.0).abs() < 0.01);
+        assert_eq!(circle.outline::<crate::projection::Gnomonic>(&view).len(), 1);
+        // and drawn dashed when excluded
+        let excluded = Region::new(
+            circle.shape.clone(),
+            RegionStyle {
+                exclude: true,
+                ..RegionStyle::default()
+            },
+            None,
+        );
+        let dashes = excluded.outline::<crate::projection::Gnomonic>(&view);
+        assert!(dashes.len() > 5 && dashes.iter().all(|dash| dash.len() == DASH_SAMPLES + 1));
+    }
+}
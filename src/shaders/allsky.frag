@@ -11,182 +11,148 @@ layout(set = 0, binding = 2)
 uniform RotationMatrix {
     mat4 rot;
 };
-const float TWICE_PI = 6.28318530718f;
-const float PI = 3.141592653589793f;
-const float FOUR_OVER_PI = 1.27323954474f;
-const float TRANSITION_Z = 0.66666666666f;
-const float TRANSITION_Z_INV = 1.5f;
-
-int quarter(vec2 p) {
-    int x_neg = int(p.x < 0.0f);
-    int y_neg = int(p.y < 0.0f);
-    int q = (x_neg + y_neg) | (y_neg << 1);
-    return q;
-}
+layout(set = 0, binding = 4)
+uniform Horizon {
+    // Rotation from the horizontal frame of the observer to the equatorial one
+    mat4 hor_to_icrs;
+    // x: 1 if the sky is looked at in the horizontal frame, 0 if in the equatorial one
+    // y: 1 if the atmospheric refraction is applied
+    // z: refraction scaling factor given the pressure and temperature
+    // w: 1 if the airmass contours are drawn
+    vec4 horizon;
+};
 
-float xpm1(vec2 p) {
-    bool x_neg = (p.x < 0.0f);
-    //debug_assert!(x_neg <= 1);
-    bool y_neg = (p.y < 0.0f);
-    //debug_assert!(y_neg <= 1);
-    // The purpose it to have the same numerical precision for each base cell
-    // by avoiding subtraction by 1 or 3 or 5 or 7
-    float lon = atan(abs(p.y), abs(p.x));
-    //debug_assert!(0.0 <= lon && lon <= PI / 2.0);
-    float x02 = lon * FOUR_OVER_PI;
-    //debug_assert!(0.0 <= x02 && x02 <= 2.0);
-    if (x_neg != y_neg) { // Could be replaced by a sign copy from (x_neg ^ y_neg) << 32
-        return 1.0f - x02;
-    } else {
-        return x02 - 1.0f;
-    }
-}
+const int NUM_BODIES = 9;
+struct Body {
+    // xyz: direction in the equatorial frame, w: angular radius of the marker
+    vec4 pos;
+    // rgb: color, a: 0 for a ring, 1 for a disk, 2 for the Moon disk
+    vec4 color;
+};
+layout(set = 0, binding = 5)
+uniform SolarSystem {
+    Body bodies[NUM_BODIES];
+    // Direction from the Moon to the Sun in the equatorial frame
+    vec4 moon_to_sun;
+    // x: 1 if the solar system bodies are drawn
+    vec4 solar_system;
+};
 
-float one_minus_z_pos(vec3 p) {
-    //debug_assert!(z > 0.0);
-    float d2 = dot(p.xy, p.xy); // z = sqrt(1 - d2) AND sqrt(1 - x) = 1 - x / 2 - x^2 / 8 - x^3 / 16 - 5 x^4/128 - 7 * x^5/256
+// Scalar values of the base cells, drawn instead of the colors of t_map when scale.w is 1
+layout(set = 0, binding = 6) uniform texture3D t_values;
+layout(set = 0, binding = 7)
+uniform Colormap {
+    // x: value at the bottom of the colormap, y: value at its top,
+    // z: index of the stretch, w: 1 if the scalar values are drawn
+    vec4 scale;
+    // Colors of the colormap in linear RGB
+    vec4 lut[LUT_SIZE];
+    // Cumulative histogram of the normalized values, for the equalization
+    vec4 cdf[CDF_SIZE / 4];
+};
+// Panoramas drawn instead of the tiles of t_map
+layout(set = 0, binding = 8)
+uniform SkySource {
+    // x: 0 for the HEALPix tiles, 1 for the equirectangular image, 2 for the cubemap
+    vec4 source;
+};
+layout(set = 0, binding = 9) uniform texture2D t_equirect;
+layout(set = 0, binding = 10) uniform textureCube t_cube;
 
-    if (d2 < 1e-1f) { // <=> dec > 84.27 deg
-        return d2 * (0.5f + d2 * (0.125f + d2 * (0.0625f + d2 * (0.0390625f + d2 * 0.02734375f))));
-    }
-    return 1.0f - p.z;
+float cdf_value(int k) {
+    return cdf[k / 4][k % 4];
 }
 
-float one_minus_z_neg(vec3 p) {
-    //debug_assert!(z < 0.0);
-    float d2 = dot(p.xy, p.xy); // z = sqrt(1 - d2) AND sqrt(1 - x) = 1 - x / 2 - x^2 / 8 - x^3 / 16 - 5 x^4/128 - 7 * x^5/256
-    if (d2 < 1e-1f) { // <=> dec < -84.27 deg
-        // 0.5 * d2 + 0.125 * d2 * d2
-        return d2 * (0.5f + d2 * (0.125f + d2 * (0.0625f + d2 * (0.0390625f + d2 * 0.02734375f))));
+// Stretch followed by the lookup of the color
+vec4 apply_colormap(float value) {
+    // Blank values
+    if (value != value) {
+        return vec4(0.0f, 0.0f, 0.0f, 1.0f);
     }
-    return p.z + 1.0f;
+    float x = stretch(value, scale.xyz, 0) * float(LUT_SIZE - 1);
+    int k = min(int(x), LUT_SIZE - 2);
+    return mix(lut[k], lut[k + 1], x - float(k));
 }
 
-// Z-Order curve projection.
-int ij2z(int i, int j) {
-    int i1 = i | (j << 16);
-
-    int j1 = (i1 ^ (i1 >> 8)) & 0x0000FF00;
-    int i2 = i1 ^ j1 ^ (j1 << 8);
-
-    int j2 = (i2 ^ (i2 >> 4)) & 0x00F000F0;
-    int i3 = i2 ^ j2 ^ (j2 << 4);
+vec4 get_color(vec3 pos) {
+    HashDxDy result = hash_with_dxdy(0, pos.zxy);
+    int idx = result.idx;
+    vec2 uv = vec2(result.dx, result.dy);
 
-    int j3 = (i3 ^ (i3 >> 2)) & 0x0C0C0C0C;
-    int i4 = i3 ^ j3 ^ (j3 << 2);
+    if (scale.w > 0.5f) {
+        ivec3 size = textureSize(sampler3D(t_values, s_map), 0);
+        ivec3 texel = ivec3(min(ivec2(uv.yx * vec2(size.xy)), size.xy - 1), idx);
+        return apply_colormap(texelFetch(sampler3D(t_values, s_map), texel, 0).r);
+    }
+    // Same lookups as sky_source.rs, x being flipped in the left-handed frame of the cube textures
+    if (source.x > 1.5f) {
+        return texture(samplerCube(t_cube, s_map), vec3(-pos.x, pos.y, pos.z));
+    }
+    if (source.x > 0.5f) {
+        float lon = atan(pos.x, pos.z);
+        float lat = asin(clamp(pos.y, -1.0f, 1.0f));
+        vec2 st = vec2(fract(0.5f - lon / TWICE_PI), 0.5f - lat / PI);
+        return texture(sampler2D(t_equirect, s_map), st);
+    }
 
-    int j4 = (i4 ^ (i4 >> 1)) & 0x22222222;
-    int i5 = i4 ^ j4 ^ (j4 << 1);
+    vec3 tq = vec3(uv.y, uv.x, ((float(idx) + 0.5)/12.0));
 
-    return i5;
+    return texture(sampler3D(t_map, s_map), tq);
 }
 
-struct HashDxDy {
-    int idx;
-    float dx;
-    float dy;
-};
+// Relative airmass from the apparent altitude (Pickering, 2002)
+float airmass(float alt) {
+    float h = max(degrees(alt), 1e-3f);
+    return 1.0f / sin(radians(h + 244.0f / (165.0f + 47.0f * pow(h, 1.1f))));
+}
 
-// Returns the cell number (hash value) associated with the given position on the unit sphere, 
-// together with the offset `(dx, dy)` on the Euclidean plane of the projected position with
-// respect to the origin of the cell (South vertex).
-// # Inputs:
-// - `depth` in `[0, 14]` (so that and HEALPix cell number can be stored on an unsigned integer)
-// - `x`: in `[-1.0, 1.0]`
-// - `y`: in `[-1.0, 1.0]`
-// - `z`: in `[-1.0, 1.0]`
-// # Output
-// - the cell number (hash value) associated with the given position on the unit sphere,
-//   in `[0, 12*nside^2[`
-// - `dx`: the positional offset $\in [0, 1[$ along the south-to-east axis
-// - `dy`: the positional offset $\in [0, 1[$ along the south-to-west axis
-// # WARNING
-// - The function assumes, without checking, that the input vector is a unit vector 
-//   (hence `x^2 + y^2 + z^2 = 1`) !!
-// - Operations being made on simple precision float, the precision is lower than `~0.2 arcsec` only!!
-// - At depth 13, the precision on `(dx, dy)` is better than `(1/512, 1/512)`, i.e. 2e-3.
-HashDxDy hash_with_dxdy(int depth, vec3 p) {
-    //assert!(depth <= 14);
-    //assert!(-1.0 <= x && x <= 1.0);
-    //assert!(-1.0 <= y && y <= 1.0);
-    //assert!(-1.0 <= z && z <= 1.0);
-    //debug_assert!(1.0 - (x * x + y * y + z * z) < 1e-5);
-    // A f32 mantissa contains 23 bits.
-    // - it basically means that when storing (x, y) coordinates,
-    //   we can go as deep as depth 24 (or maybe 25)
-    
-    int nside = 1 << depth;
-    float half_nside = float(nside) * 0.5f;
-
-    float x_pm1 = xpm1(p.xy);
-    int q = quarter(p.xy);
-
-    int d0h = 0;
-    vec2 p_proj = vec2(0.f);
-    if (p.z > TRANSITION_Z) {
-        // North polar cap, Collignon projection.
-        // - set the origin to (PI/4, 0)
-        float sqrt_3_one_min_z = sqrt(3.0f * one_minus_z_pos(p));
-        p_proj = vec2(x_pm1 * sqrt_3_one_min_z, 2.0f - sqrt_3_one_min_z);
-        d0h = q;
-    } else if (p.z < -TRANSITION_Z) {
-        // South polar cap, Collignon projection
-        // - set the origin to (PI/4, -PI/2)
-        float sqrt_3_one_min_z = sqrt(3.0f * one_minus_z_neg(p));
-        p_proj = vec2(x_pm1 * sqrt_3_one_min_z, sqrt_3_one_min_z);
-        d0h = q + 8;
-    } else {
-        // Equatorial region, Cylindrical equal area projection
-        // - set the origin to (PI/4, 0)               if q = 2
-        // - set the origin to (PI/4, -PI/2)           if q = 0
-        // - set the origin to (0, -TRANSITION_LAT)    if q = 3
-        // - set the origin to (PI/2, -TRANSITION_LAT) if q = 1
-        // let zero_or_one = (x_cea as u8) & 1;
-        float y_pm1 = p.z * TRANSITION_Z_INV;
-        // |\2/|
-        // .3X1.
-        // |/0\|
-        int q01 = int(x_pm1 > y_pm1);  // 0/1
-        //debug_assert!(q01 == 0 || q01 == 1);
-        int q12 = int(x_pm1 >= -y_pm1); // 0\1
-        //debug_assert!(q12 == 0 || q12 == 1);
-        int q03 = 1 - q12; // 1\0
-        //let q13 = q01 ^ q12; debug_assert!(q13 == 0 || q13 == 1);
-        int q1 = q01 & q12; // = 1 if q1, 0 else
-        //debug_assert!( q1 == 0 ||  q1 == 1);
-        // x: xcea - 0 if q3 | xcea - 2 if q1 | xcea - 1 if q0 or q2
-        //let x_proj = x_pm1 - ((q01 + q12) as i8 - 1) as f32;
-        // y: y - 0 if q2 | y - 1 if q1 or q3 | y - 2 if q0 
-        //let y_proj = y_pm1 + (q01 + q03) as f32;
-        p_proj = vec2(
-            x_pm1 - float(q01 + q12 - 1),
-            y_pm1 + float(q01 + q03)
-        );
-        // d0h: +8 if q0 | +4 if q3 | +5 if q1
-        d0h = ((q01 + q03) << 2) + ((q + q1) & 3);
+vec4 draw_airmass_contours(vec4 color, float alt) {
+    if (alt < 0.0f) {
+        // Below the horizon
+        return vec4(color.rgb * 0.4f, color.a);
     }
 
-    // Coords inside the base cell
-    float x = (half_nside * (p_proj.x + p_proj.y));
-    float y = (half_nside * (p_proj.y - p_proj.x));
-    int i = int(x);
-    int j = int(y);
-
-    return HashDxDy(
-        (d0h << (depth << 1)) | ij2z(i, j),
-        x - float(i),
-        y - float(j)
-    );
-}
+    // One contour every 0.5 airmass up to 3
+    float x = airmass(alt) * 2.0f;
+    float d = abs(fract(x + 0.5f) - 0.5f) / max(fwidth(x), 1e-6f);
+    float line = (1.0f - clamp(d, 0.0f, 1.0f)) * float(x > 2.5f && x < 6.5f);
 
-vec4 get_color(vec3 pos) {
-    HashDxDy result = hash_with_dxdy(0, pos.zxy);
-    int idx = result.idx;
-    vec2 uv = vec2(result.dx, result.dy);
+    return mix(color, vec4(1.0f, 0.6f, 0.1f, 1.0f), line);
+}
 
-    vec3 tq = vec3(uv.y, uv.x, ((float(idx) + 0.5)/12.0));
+vec4 draw_bodies(vec4 color, vec3 p) {
+    for (int i = 0; i < NUM_BODIES; i++) {
+        vec3 c = bodies[i].pos.xyz;
+        float r = bodies[i].pos.w;
+        float kind = bodies[i].color.a;
+
+        // Angular distance to the center of the marker
+        float d = 2.0f * asin(clamp(0.5f * length(p - c), 0.0f, 1.0f));
+        float aa = max(fwidth(d), 1e-6f);
+
+        vec3 rgb = bodies[i].color.rgb;
+        float alpha = 0.0f;
+        if (kind < 0.5f) {
+            // Ring
+            alpha = 1.0f - smoothstep(0.0f, aa, abs(d - r) - aa);
+        } else {
+            // Disk
+            alpha = 1.0f - smoothstep(r - aa, r, d);
+            if (kind > 1.5f && alpha > 0.0f) {
+                // Moon: light the hemisphere facing the Sun.
+                // u is the position on the apparent disk, n is the normal
+                // of the lunar surface pointing towards the observer
+                vec3 u = (p - c * dot(p, c)) / sin(r);
+                vec3 n = u - c * sqrt(max(1.0f - dot(u, u), 0.0f));
+                float lit = smoothstep(-0.05f, 0.05f, dot(n, moon_to_sun.xyz));
+                rgb = mix(rgb * 0.15f, rgb, lit);
+            }
+        }
+
+        color = mix(color, vec4(rgb, 1.0f), alpha);
+    }
 
-    return texture(sampler3D(t_map, s_map), tq);
+    return color;
 }
 
 void main() {
@@ -196,7 +162,34 @@ void main() {
     // Rotate it
     vec3 rotated_p = vec3(rot * vec4(pos_ws, 1.0));
 
-    f_color = get_color(rotated_p);
+    mat3 hor_to_icrs_3 = mat3(hor_to_icrs);
+    // Apparent altitude of the position in the horizontal frame
+    float alt = 0.0f;
+    vec3 pos_icrs = rotated_p;
+    if (horizon.x > 0.5f) {
+        // The view is looked through the atmosphere,
+        // remove the refraction to get the true position
+        alt = asin(clamp(rotated_p.y, -1.0f, 1.0f));
+        vec3 pos_hor = rotated_p;
+        if (horizon.y > 0.5f) {
+            pos_hor = with_altitude(rotated_p, alt - refraction_from_apparent(alt, horizon.z));
+        }
+        pos_icrs = hor_to_icrs_3 * pos_hor;
+    } else {
+        vec3 pos_hor = transpose(hor_to_icrs_3) * rotated_p;
+        alt = asin(clamp(pos_hor.y, -1.0f, 1.0f));
+        if (horizon.y > 0.5f) {
+            alt += refraction_from_true(alt, horizon.z);
+        }
+    }
+
+    f_color = get_color(pos_icrs);
+    if (solar_system.x > 0.5f) {
+        f_color = draw_bodies(f_color, pos_icrs);
+    }
+    if (horizon.w > 0.5f) {
+        f_color = draw_airmass_contours(f_color, alt);
+    }
     //f_color = vec4(rotated_p, 1.0);
 }
  
\ No newline at end of file
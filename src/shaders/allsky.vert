@@ -9,11 +9,13 @@ layout(location=1) out vec3 pos_xyz;
 
 layout(set = 0, binding = 3)
 uniform Window {
+    // xy: normalized device to clip space factor,
+    // z: factor of the x axis of the world space, -1 to mirror the sky
     vec4 size;
 };
 
 void main() {
     gl_Position = vec4(vec2(a_ndc.x * size.x, a_ndc.y * size.y), 0.0, 1.0);
     pos_cs = a_ndc*0.5 + 0.5;
-    pos_xyz = a_xyz;
+    pos_xyz = vec3(size.z * a_xyz.x, a_xyz.yz);
 }
\ No newline at end of file
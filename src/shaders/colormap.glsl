@@ -0,0 +1,37 @@
+// colormap.glsl
+// Shared by the sky, image and composite shaders, inserted after common.glsl
+
+const int LUT_SIZE = 256;
+const int CDF_SIZE = 256;
+
+// Cumulative histogram of the normalized values at the end of a bin, read by each
+// shader from its uniforms
+float cdf_value(int k);
+
+// Same as the stretches of colormap.rs
+//
+// The values between the cuts x and y of the scale are stretched with the stretch of
+// index z, the histogram of the equalization starting at the bin offset.
+float stretch(float value, vec3 scale, int offset) {
+    float range = scale.y - scale.x;
+    float t = clamp((value - scale.x) / (abs(range) < 1e-30f ? 1e-30f : range), 0.0f, 1.0f);
+    int kind = int(scale.z + 0.5f);
+    if (kind == 1) {
+        t = log(1000.0f * t + 1.0f) / log(1001.0f);
+    } else if (kind == 2) {
+        t = sqrt(t);
+    } else if (kind == 3) {
+        t = asinh(10.0f * t) / asinh(10.0f);
+    } else if (kind == 4) {
+        // The bin k ends at (k + 1) / CDF_SIZE
+        float x = t * float(CDF_SIZE) - 1.0f;
+        if (x < 0.0f) {
+            t = cdf_value(offset) * t * float(CDF_SIZE);
+        } else {
+            int k = min(int(x), CDF_SIZE - 2);
+            float f = min(x - float(k), 1.0f);
+            t = mix(cdf_value(offset + k), cdf_value(offset + k + 1), f);
+        }
+    }
+    return t;
+}
@@ -0,0 +1,85 @@
+// composite.frag
+#version 440
+
+layout(location=0) in vec2 pos_cs;
+layout(location=0) out vec4 f_color;
+layout(location=1) in vec3 pos_xyz;
+
+layout(set = 0, binding = 2)
+uniform RotationMatrix {
+    mat4 rot;
+};
+layout(set = 0, binding = 4)
+uniform Horizon {
+    mat4 hor_to_icrs;
+    vec4 horizon;
+};
+
+// Tiles of the base cells of the red, then the green, then the blue band
+layout(set = 1, binding = 0) uniform texture3D t_bands;
+layout(set = 1, binding = 1) uniform sampler s_bands;
+layout(set = 1, binding = 2)
+uniform Composite {
+    // Per band, x: value at the bottom, y: value at the top, z: index of the stretch
+    vec4 bands[3];
+    // x: 1 for the Lupton stretch, y: its softening parameter, z: opacity
+    vec4 params;
+    // Cumulative histograms of the normalized values of the bands, for the equalization
+    vec4 cdf[3 * CDF_SIZE / 4];
+};
+
+float cdf_value(int k) {
+    return cdf[k / 4][k % 4];
+}
+
+// Same as lupton of composite.rs
+vec3 lupton(vec3 t, float q) {
+    float intensity = (t.r + t.g + t.b) / 3.0f;
+    if (intensity <= 0.0f) {
+        return vec3(0.0f);
+    }
+    vec3 rgb = t * (asinh(q * intensity) / asinh(q) / intensity);
+    float m = max(rgb.r, max(rgb.g, rgb.b));
+    return m > 1.0f ? rgb / m : rgb;
+}
+
+vec3 srgb_to_linear(vec3 c) {
+    return mix(c / 12.92f, pow((c + 0.055f) / 1.055f, vec3(2.4f)), greaterThan(c, vec3(0.04045f)));
+}
+
+void main() {
+    // ICRS position of the fragment, as in the sky fragment shader
+    vec3 rotated_p = vec3(rot * vec4(normalize(pos_xyz), 1.0));
+    vec3 pos_icrs = fragment_icrs(rotated_p, hor_to_icrs, horizon);
+
+    HashDxDy cell = hash_with_dxdy(0, normalize(pos_icrs.zxy));
+    ivec3 size = textureSize(sampler3D(t_bands, s_bands), 0);
+    ivec2 texel = min(ivec2(vec2(cell.dy, cell.dx) * vec2(size.xy)), size.xy - 1);
+    vec3 values;
+    bvec3 blank;
+    for (int k = 0; k < 3; k++) {
+        values[k] = texelFetch(sampler3D(t_bands, s_bands), ivec3(texel, cell.idx + 12 * k), 0).r;
+        blank[k] = values[k] != values[k];
+    }
+    if (all(blank)) {
+        discard;
+    }
+
+    // The blank values count as the bottom of their band
+    vec3 rgb;
+    if (params.x > 0.5f) {
+        vec3 t;
+        for (int k = 0; k < 3; k++) {
+            float range = bands[k].y - bands[k].x;
+            t[k] = blank[k] ? 0.0f : max((values[k] - bands[k].x) / (abs(range) < 1e-30f ? 1e-30f : range), 0.0f);
+        }
+        rgb = lupton(t, params.y);
+    } else {
+        for (int k = 0; k < 3; k++) {
+            rgb[k] = blank[k] ? 0.0f : stretch(values[k], bands[k].xyz, k * CDF_SIZE);
+        }
+    }
+
+    // Premultiplied by the opacity, for the blend modes of the layers
+    f_color = vec4(srgb_to_linear(rgb) * params.z, params.z);
+}
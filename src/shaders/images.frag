@@ -0,0 +1,147 @@
+// images.frag
+#version 440
+
+layout(location=0) in vec2 pos_cs;
+layout(location=0) out vec4 f_color;
+layout(location=1) in vec3 pos_xyz;
+
+layout(set = 0, binding = 2)
+uniform RotationMatrix {
+    mat4 rot;
+};
+layout(set = 0, binding = 4)
+uniform Horizon {
+    mat4 hor_to_icrs;
+    vec4 horizon;
+};
+
+layout(set = 1, binding = 0) uniform texture2D t_image;
+layout(set = 1, binding = 1) uniform sampler s_image;
+layout(set = 1, binding = 2)
+uniform Image {
+    // Rotation from the ICRS to the native frame of the projection
+    mat4 icrs_to_native;
+    // xy: reference pixel, zw: size of the texture in pixels
+    vec4 crpix;
+    // Inverse of the CD matrix, row major
+    vec4 cd_inv;
+    // x: index of the projection
+    // yz: degrees of the projection plane per clip space unit
+    // w: order of the SIP distortion
+    vec4 params;
+    // x: opacity, y: image pixels per texel
+    vec4 display;
+    // Coefficients of u^p v^q at index p * 10 + q
+    vec4 sip_a[25];
+    vec4 sip_b[25];
+};
+layout(set = 1, binding = 3)
+uniform Colormap {
+    // x: value at the bottom of the colormap, y: value at its top, z: index of the stretch
+    vec4 scale;
+    // Colors of the colormap in linear RGB
+    vec4 lut[LUT_SIZE];
+    // Cumulative histogram of the normalized values, for the equalization
+    vec4 cdf[CDF_SIZE / 4];
+};
+
+const int SIP_ITERATIONS = 5;
+
+float cdf_value(int k) {
+    return cdf[k / 4][k % 4];
+}
+
+// Same as the world_to_clip_space methods of projection.rs, for the projections of the WCS.
+// Returns false if the position cannot be projected.
+bool world_to_clip(vec3 p, int projection, out vec2 clip) {
+    if (projection == 1) {
+        // Orthographic
+        clip = p.xy;
+        return p.z > 0.0f;
+    } else if (projection == 2) {
+        // Mollweide
+        float lon = atan(p.x, p.z);
+        float lat = atan(p.y, length(p.xz));
+        float cst = PI * sin(lat);
+        float theta = lat;
+        for (int k = 0; k < 10; k++) {
+            float f = theta + sin(theta) - cst;
+            if (abs(f) > 1e-6f) {
+                theta -= f / (1.0f + cos(theta));
+            }
+        }
+        theta *= 0.5f;
+        clip = vec2((lon / PI) * cos(theta), 0.5f * sin(theta));
+        return true;
+    } else if (projection == 4) {
+        // Azimuthal equidistant
+        float r = length(p.xy);
+        if (p.z <= -1.0f || r < 1e-6f) {
+            clip = vec2(0.0f);
+            return p.z > 0.0f;
+        }
+        r = p.z > 0.0f ? asin(r) / r : acos(p.z) / r;
+        clip = p.xy * r / PI;
+        return true;
+    } else {
+        // Gnomonic
+        clip = p.xy / (p.z * PI);
+        return p.z >= 1e-2f;
+    }
+}
+
+// Same as in the sky fragment shader
+vec4 apply_colormap(float value) {
+    float x = stretch(value, scale.xyz, 0) * float(LUT_SIZE - 1);
+    int k = min(int(x), LUT_SIZE - 2);
+    return mix(lut[k], lut[k + 1], x - float(k));
+}
+
+vec2 sip_distortion(vec2 uv) {
+    int order = int(params.w + 0.5f);
+    vec2 d = vec2(0.0f);
+    float up = 1.0f;
+    for (int p = 0; p <= order; p++) {
+        float vq = 1.0f;
+        for (int q = 0; q <= order - p; q++) {
+            int k = p * 10 + q;
+            d += vec2(sip_a[k / 4][k % 4], sip_b[k / 4][k % 4]) * up * vq;
+            vq *= uv.y;
+        }
+        up *= uv.x;
+    }
+    return d;
+}
+
+void main() {
+    // ICRS position of the fragment, as in the sky fragment shader
+    vec3 rotated_p = vec3(rot * vec4(normalize(pos_xyz), 1.0));
+    vec3 pos_icrs = fragment_icrs(rotated_p, hor_to_icrs, horizon);
+
+    // Inverse mapping to the pixel of the image
+    vec2 clip;
+    if (!world_to_clip(normalize(mat3(icrs_to_native) * pos_icrs), int(params.x + 0.5f), clip)) {
+        discard;
+    }
+    vec2 plane = clip * params.yz;
+    vec2 distorted = vec2(dot(cd_inv.xy, plane), dot(cd_inv.zw, plane));
+    vec2 uv = distorted;
+    if (params.w > 0.5f) {
+        for (int i = 0; i < SIP_ITERATIONS; i++) {
+            uv = distorted - sip_distortion(uv);
+        }
+    }
+    // The center of the first pixel is (1, 1)
+    vec2 texel = (crpix.xy + uv - 0.5f) / display.y;
+    if (any(lessThan(texel, vec2(0.0f))) || any(greaterThanEqual(texel, crpix.zw))) {
+        discard;
+    }
+
+    float value = texelFetch(sampler2D(t_image, s_image), ivec2(texel), 0).r;
+    // Blank pixels
+    if (value != value) {
+        discard;
+    }
+    // Premultiplied by the opacity, for the blend modes of the layers
+    f_color = vec4(apply_colormap(value).rgb * display.x, display.x);
+}
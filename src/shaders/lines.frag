@@ -0,0 +1,10 @@
+// lines.frag
+#version 440
+
+layout(location=0) in vec4 color;
+layout(location=0) out vec4 f_color;
+
+void main() {
+    // Premultiplied, for the blend modes of the layers
+    f_color = vec4(color.rgb * color.a, color.a);
+}
@@ -0,0 +1,17 @@
+// lines.vert
+#version 440
+
+layout(location=0) in vec2 a_clip;
+layout(location=1) in vec4 a_color;
+
+layout(location=0) out vec4 color;
+
+layout(set = 0, binding = 3)
+uniform Window {
+    vec4 size;
+};
+
+void main() {
+    gl_Position = vec4(a_clip * size.xy, 0.0, 1.0);
+    color = a_color;
+}
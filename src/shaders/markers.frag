@@ -0,0 +1,48 @@
+// markers.frag
+#version 440
+
+layout(location=0) in vec2 uv;
+layout(location=1) in vec4 color;
+layout(location=2) in float size;
+
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0)
+uniform Markers {
+    mat4 icrs_to_frame;
+    mat4 frame_to_world;
+    vec4 params;
+    vec4 window;
+    vec4 style;
+};
+
+// Width of the strokes in pixels
+const float STROKE = 1.5f;
+
+void main() {
+    // Half of the stroke width in the [-1, 1] marker space
+    float w = STROKE / size;
+    int shape = int(params.w + 0.5f);
+
+    bool inside;
+    if (shape == 0) {
+        // Circle
+        inside = abs(length(uv) - (1.0f - w)) < w;
+    } else if (shape == 1) {
+        // Cross
+        inside = abs(uv.x - uv.y) < 1.4142f * w || abs(uv.x + uv.y) < 1.4142f * w;
+    } else if (shape == 2) {
+        // Square
+        inside = max(abs(uv.x), abs(uv.y)) > 1.0f - 2.0f * w;
+    } else {
+        // Plus
+        inside = abs(uv.x) < w || abs(uv.y) < w;
+    }
+
+    if (!inside) {
+        discard;
+    }
+    // Premultiplied by the opacity of the layer, for its blend mode
+    float alpha = color.a * style.x;
+    f_color = vec4(color.rgb * alpha, alpha);
+}
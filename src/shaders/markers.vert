@@ -0,0 +1,107 @@
+// markers.vert
+#version 440
+
+// Per instance attributes
+layout(location=0) in vec3 a_pos_icrs;
+layout(location=1) in float a_size;
+layout(location=2) in vec4 a_color;
+
+layout(location=0) out vec2 uv;
+layout(location=1) out vec4 color;
+layout(location=2) out float size;
+
+layout(set = 0, binding = 0)
+uniform Markers {
+    // Rotation from the ICRS to the frame the sky is looked at in
+    mat4 icrs_to_frame;
+    // Inverse of the rotation done by the sky fragment shader
+    mat4 frame_to_world;
+    // x: index of the projection
+    // y: 1 if the atmospheric refraction is applied
+    // z: refraction scaling factor given the pressure and temperature
+    // w: shape of the markers
+    vec4 params;
+    // xy: normalized device to clip space factor, zw: size of a pixel in NDC
+    vec4 window;
+    // x: opacity of the layer, y: factor of the x axis of the world space
+    vec4 style;
+};
+
+// Same as the world_to_clip_space methods of projection.rs.
+// Returns false if the position is behind the camera.
+bool world_to_clip(vec3 p, out vec2 clip) {
+    int projection = int(params.x + 0.5f);
+    float lon = atan(p.x, p.z);
+    float lat = atan(p.y, length(p.xz));
+
+    if (projection == 0) {
+        // Aitoff
+        float alpha = acos(cos(lat) * cos(0.5f * lon));
+        float inv_sinc_alpha = alpha < 1e-3f ? 1.0f : alpha / sin(alpha);
+        clip = vec2(2.0f * cos(lat) * sin(0.5f * lon), sin(lat)) * inv_sinc_alpha / PI;
+        return true;
+    } else if (projection == 1) {
+        // Orthographic
+        clip = p.xy;
+        return p.z > 0.0f;
+    } else if (projection == 2) {
+        // Mollweide
+        float cst = PI * sin(lat);
+        float theta = lat;
+        for (int k = 0; k < 10; k++) {
+            float f = theta + sin(theta) - cst;
+            if (abs(f) > 1e-6f) {
+                theta -= f / (1.0f + cos(theta));
+            }
+        }
+        theta *= 0.5f;
+        clip = vec2((lon / PI) * cos(theta), 0.5f * sin(theta));
+        return true;
+    } else if (projection == 3) {
+        // Mercator
+        clip = vec2(lon / PI, asinh(tan(lat / PI)));
+        return true;
+    } else if (projection == 4) {
+        // Azimuthal equidistant
+        float r = length(p.xy);
+        if (p.z <= -1.0f || r < 1e-6f) {
+            clip = p.z > 0.0f ? vec2(0.0f) : vec2(1.0f, 0.0f);
+        } else {
+            r = p.z > 0.0f ? asin(r) / r : acos(p.z) / r;
+            clip = p.xy * r / PI;
+        }
+        return true;
+    } else {
+        // Gnomonic
+        clip = p.xy / (p.z * PI);
+        return p.z >= 1e-2f;
+    }
+}
+
+void main() {
+    // Two triangles covering the marker
+    int idx = int(gl_VertexIndex);
+    int k = idx < 3 ? idx : 6 - idx;
+    vec2 corner = vec2(float(k & 1), float(k >> 1)) * 2.0f - 1.0f;
+
+    vec3 p = (icrs_to_frame * vec4(a_pos_icrs, 0.0f)).xyz;
+    if (params.y > 0.5f) {
+        float alt = asin(clamp(p.y, -1.0f, 1.0f));
+        p = with_altitude(p, alt + refraction_from_true(alt, params.z));
+    }
+    p = normalize((frame_to_world * vec4(p, 0.0f)).xyz);
+    p.x *= style.y;
+
+    vec2 clip;
+    if (world_to_clip(p, clip)) {
+        vec2 ndc = clip * window.xy + 0.5f * a_size * corner * window.zw;
+        gl_Position = vec4(ndc, 0.0f, 1.0f);
+    } else {
+        // Outside of the clip volume
+        gl_Position = vec4(2.0f, 2.0f, 2.0f, 1.0f);
+    }
+
+    uv = corner;
+    color = a_color;
+    size = a_size;
+}
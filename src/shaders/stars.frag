@@ -0,0 +1,17 @@
+// stars.frag
+#version 440
+
+layout(location=0) in vec2 uv;
+layout(location=1) in vec4 color;
+layout(location=2) in float size;
+
+layout(location=0) out vec4 f_color;
+
+void main() {
+    // Gaussian profile, the disk of the marker being 3 sigmas wide
+    float r2 = dot(uv, uv);
+    if (r2 > 1.0f) {
+        discard;
+    }
+    f_color = vec4(color.rgb, color.a * exp(-4.5f * r2));
+}
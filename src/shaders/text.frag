@@ -0,0 +1,18 @@
+// text.frag
+#version 440
+
+layout(location=0) in vec2 uv;
+layout(location=1) in vec4 color;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_atlas;
+layout(set = 0, binding = 1) uniform sampler s_atlas;
+
+void main() {
+    // The glyphs are drawn at an integer scale, each fragment falls into one texel
+    float a = texelFetch(sampler2D(t_atlas, s_atlas), ivec2(floor(uv)), 0).a;
+    if (a < 0.5f) {
+        discard;
+    }
+    f_color = color;
+}
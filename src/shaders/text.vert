@@ -0,0 +1,15 @@
+// text.vert
+#version 440
+
+layout(location=0) in vec2 a_ndc;
+layout(location=1) in vec2 a_uv;
+layout(location=2) in vec4 a_color;
+
+layout(location=0) out vec2 uv;
+layout(location=1) out vec4 color;
+
+void main() {
+    gl_Position = vec4(a_ndc, 0.0, 1.0);
+    uv = a_uv;
+    color = a_color;
+}
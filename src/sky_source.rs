@@ -0,0 +1,219 @@
+use crate::error::Error;
+use crate::healpix;
+use crate::math::Vec3;
+
+use image::RgbaImage;
+use std::f64::consts::PI;
+use std::path::Path;
+
+/// Image the base survey is drawn from, looked up from the direction of each fragment
+pub enum SkySource {
+    /// Tiles of the 12 base HEALPix cells
+    Healpix(Vec<RgbaImage>),
+    /// Plate carrée image centered on the origin of the longitudes, the longitudes
+    /// increasing to the left and the north being at the top, as seen from the inside
+    /// of the sphere
+    Equirectangular(RgbaImage),
+    /// The 6 square faces of a cube in the +X, -X, +Y, -Y, +Z, -Z order of the cube textures,
+    /// seen from the inside, the north being along +Y, the origin of the longitudes along +Z
+    /// and the longitude 90° along -X
+    Cubemap(Vec<RgbaImage>),
+}
+
+impl SkySource {
+    /// Read a panorama from its aspect ratio
+    ///
+    /// A 2:1 image is an equirectangular one, a 6:1 one holds the faces of a cube side by side
+    /// and a 4:3 one holds them laid out in a horizontal cross, +Y being above +Z:
+    ///
+    /// ```text
+    ///      +Y
+    /// -X   +Z   +X   -Z
+    ///      -Y
+    /// ```
+    pub fn from_image(image: RgbaImage) -> Result<Self, Error> {
+        let (w, h) = image.dimensions();
+        let face = |col: u32, row: u32, size: u32| {
+            image::imageops::crop_imm(&image, col * size, row * size, size, size).to_image()
+        };
+        if w == 2 * h {
+            Ok(SkySource::Equirectangular(image))
+        } else if w == 6 * h {
+            Ok(SkySource::Cubemap((0..6).map(|k| face(k, 0, h)).collect()))
+        } else if 3 * w == 4 * h && w % 4 == 0 {
+            let size = w / 4;
+            let faces = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)];
+            Ok(SkySource::Cubemap(faces.iter().map(|&(col, row)| face(col, row, size)).collect()))
+        } else {
+            Err(Error::Parse(format!(
+                "{}x{} pixels: neither an equirectangular image nor the faces of a cube",
+                w, h
+            )))
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let image = image::open(path)?;
+        Self::from_image(image.to_rgba8())
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SkySource::Healpix(_) => "HEALPix tiles",
+            SkySource::Equirectangular(_) => "equirectangular image",
+            SkySource::Cubemap(_) => "cubemap",
+        }
+    }
+
+    /// Index of the source in the sky fragment shader
+    pub fn index(&self) -> u32 {
+        match self {
+            SkySource::Healpix(_) => 0,
+            SkySource::Equirectangular(_) => 1,
+            SkySource::Cubemap(_) => 2,
+        }
+    }
+
+    /// RGBA value of the pixel drawn at a position, with the nearest lookup of the
+    /// sky fragment shader
+    ///
+    /// # Arguments
+    ///
+    /// * `pos_icrs` - Unit vector in the ICRS frame
+    pub fn pixel(&self, pos_icrs: &Vec3<f64>) -> Option<[u8; 4]> {
+        let lookup = |image: &RgbaImage, u: f64, v: f64| {
+            let (w, h) = image.dimensions();
+            if w == 0 || h == 0 {
+                return None;
+            }
+            let col = ((u * w as f64) as u32).min(w - 1);
+            let row = ((v * h as f64) as u32).min(h - 1);
+            Some(image.get_pixel(col, row).0)
+        };
+        match self {
+            SkySource::Healpix(tiles) => tile_pixel(tiles, tiles_order(tiles), pos_icrs),
+            SkySource::Equirectangular(image) => {
+                let (u, v) = equirectangular_uv(pos_icrs);
+                lookup(image, u, v)
+            }
+            SkySource::Cubemap(faces) => {
+                let (face, u, v) = cube_face_uv(pos_icrs);
+                lookup(faces.get(face)?, u, v)
+            }
+        }
+    }
+}
+
+/// HEALPix order of the tiles covering the whole sky, 12 × 4^order of them
+pub fn tiles_order(tiles: &[RgbaImage]) -> u8 {
+    ((tiles.len() / 12).max(1).trailing_zeros() / 2) as u8
+}
+
+/// RGBA value of the pixel of the tiles of a HEALPix order containing a position
+///
+/// The columns of a tile go along the dy axis of its cell and its rows along the dx one.
+pub fn tile_pixel(tiles: &[RgbaImage], order: u8, pos_icrs: &Vec3<f64>) -> Option<[u8; 4]> {
+    // The repo (x, y, z) frame is the usual (Y, Z, X) one
+    let (cell, dx, dy) = healpix::hash_with_dxdy(order, pos_icrs.z, pos_icrs.x, pos_icrs.y);
+    tiles.get(cell as usize).map(|tile| tile_texel(tile, dx, dy))
+}
+
+/// RGBA value of the pixel of a tile at an offset `(dx, dy)` in `[0, 1[` in its cell
+pub fn tile_texel(tile: &RgbaImage, dx: f64, dy: f64) -> [u8; 4] {
+    let (w, h) = tile.dimensions();
+    let col = ((dy * w as f64) as u32).min(w - 1);
+    let row = ((dx * h as f64) as u32).min(h - 1);
+    tile.get_pixel(col, row).0
+}
+
+/// Texture coordinates in `[0, 1]` of a direction in an equirectangular image
+pub fn equirectangular_uv(pos: &Vec3<f64>) -> (f64, f64) {
+    let lon = pos.x.atan2(pos.z);
+    let lat = pos.y.clamp(-1.0, 1.0).asin();
+    ((0.5 - lon / (2.0 * PI)).rem_euclid(1.0), 0.5 - lat / PI)
+}
+
+/// Face of a cube and texture coordinates in `[0, 1]` on it of a direction, following
+/// the selection of the faces of the cube textures
+pub fn cube_face_uv(pos: &Vec3<f64>) -> (usize, f64, f64) {
+    // The cube textures have a left-handed frame, the faces not being mirrored when seen
+    // from the inside with x flipped
+    let (x, y, z) = (-pos.x, pos.y, pos.z);
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+    // Face, coordinates along the s and t axes and along the major axis
+    let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+        if x > 0.0 {
+            (0, -z, -y, ax)
+        } else {
+            (1, z, -y, ax)
+        }
+    } else if ay >= az {
+        if y > 0.0 {
+            (2, x, z, ay)
+        } else {
+            (3, x, -z, ay)
+        }
+    } else if z > 0.0 {
+        (4, x, -y, az)
+    } else {
+        (5, -x, -y, az)
+    };
+    (face, 0.5 * (sc / ma + 1.0), 0.5 * (tc / ma + 1.0))
+}
+
+mod tests {
+    #[test]
+    fn sky_source() {
+        use super::*;
+        use cgmath::InnerSpace;
+
+        // The origin of the longitudes is at the center, the north pole at the top,
+        // the RA 90° to the left
+        assert_eq!(equirectangular_uv(&Vec3::new(0.0, 0.0, 1.0)), (0.5, 0.5));
+        assert_eq!(equirectangular_uv(&Vec3::new(0.0, 1.0, 0.0)).1, 0.0);
+        assert_eq!(equirectangular_uv(&Vec3::new(1.0, 0.0, 0.0)), (0.25, 0.5));
+        assert_eq!(equirectangular_uv(&Vec3::new(-1.0, 0.0, 0.0)), (0.75, 0.5));
+
+        assert_eq!(cube_face_uv(&Vec3::new(0.0, 0.0, 1.0)), (4, 0.5, 0.5));
+        assert_eq!(cube_face_uv(&Vec3::new(0.0, 1.0, 0.0)), (2, 0.5, 0.5));
+        // The faces around the equator go left as the longitude increases, as the equirectangular image
+        let pos = Vec3::new(0.5_f64, 0.0, 1.0).normalize();
+        let (face, u, v) = cube_face_uv(&pos);
+        assert_eq!((face, v), (4, 0.5));
+        assert!(u < 0.5);
+        assert!(equirectangular_uv(&pos).0 < 0.5);
+
+        // Faces of a cross, each filled with its index
+        let size = 4;
+        let mut cross = RgbaImage::new(4 * size, 3 * size);
+        let faces = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)];
+        for (k, &(col, row)) in faces.iter().enumerate() {
+            for (x, y) in (0..size).flat_map(|x| (0..size).map(move |y| (x, y))) {
+                cross.put_pixel(col * size + x, row * size + y, image::Rgba([k as u8, 0, 0, 255]));
+            }
+        }
+        let source = SkySource::from_image(cross).unwrap();
+        assert_eq!(source.index(), 2);
+        for (k, pos) in [
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        ]
+        .iter()
+        .enumerate()
+        {
+            assert_eq!(source.pixel(pos), Some([k as u8, 0, 0, 255]));
+        }
+
+        // Order of the tiles covering the sphere
+        assert_eq!(tiles_order(&vec![RgbaImage::new(1, 1); 12]), 0);
+        assert_eq!(tiles_order(&vec![RgbaImage::new(1, 1); 192]), 2);
+
+        let source = SkySource::from_image(RgbaImage::new(8, 4)).unwrap();
+        assert_eq!(source.name(), "equirectangular image");
+        assert!(SkySource::from_image(RgbaImage::new(8, 5)).is_err());
+    }
+}
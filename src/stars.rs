@@ -0,0 +1,515 @@
+use crate::angle::{Angle, ArcDeg};
+use crate::catalog::Catalog;
+use crate::error::Error;
+use crate::frame::Epoch;
+use crate::glsl;
+use crate::markers::{MarkerShape, MarkerUniform, MarkerView};
+use crate::math::{self, Vec3};
+use crate::vertex::MarkerInstance;
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::path::Path;
+use wgpu::util::DeviceExt;
+
+// Limiting magnitude of the whole sky, brightened or dimmed with the field of view
+const ALLSKY_LIMITING_MAG: f64 = 6.5;
+const MAX_LIMITING_MAG: f64 = 12.0;
+// Diameter in pixels of a star at the reference magnitude, and its growth per magnitude
+const REFERENCE_MAG: f64 = 6.5;
+const MIN_STAR_SIZE: f64 = 3.0;
+const STAR_SIZE_PER_MAG: f64 = 2.5;
+const MAX_STAR_SIZE: f64 = 24.0;
+
+// Yale Bright Star Catalog binary format
+const BSC5_HEADER_LEN: usize = 28;
+// Columns identifying the stars of a table, e.g. for the constellation figures
+const ID_NAMES: &[&str] = &["hip", "hr", "id", "hd"];
+
+/// A star of a bright star catalog
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Star {
+    /// Catalog number (e.g. the HR number of the BSC5 or the HIP one of Hipparcos)
+    pub id: Option<u64>,
+    /// ICRS right ascension in degrees
+    pub ra: f64,
+    /// ICRS declination in degrees
+    pub dec: f64,
+    /// Visual magnitude
+    pub mag: f64,
+    /// B-V color index, NaN if unknown
+    pub b_v: f64,
+}
+
+/// Stars sorted from the brightest to the faintest
+pub struct StarCatalog {
+    stars: Vec<Star>,
+}
+
+impl StarCatalog {
+    /// Build the catalog, the stars without a magnitude are dropped
+    pub fn new(mut stars: Vec<Star>) -> Self {
+        stars.retain(|star| star.mag.is_finite() && star.ra.is_finite() && star.dec.is_finite());
+        stars.sort_by(|a, b| a.mag.total_cmp(&b.mag));
+        Self { stars }
+    }
+
+    /// Stars of a catalog having a magnitude column, its color column being taken as the B-V index
+    pub fn from_catalog(catalog: &Catalog) -> Result<Self, Error> {
+        let mag = catalog.mag.as_ref().ok_or(Error::MissingColumn("magnitude"))?;
+        let ids = catalog
+            .table
+            .column_index(ID_NAMES)
+            .map(|idx| catalog.table.column_f64(idx));
+        let stars = (0..catalog.len())
+            .map(|i| Star {
+                id: ids
+                    .as_ref()
+                    .map(|ids| ids[i])
+                    .filter(|id| id.is_finite() && *id >= 0.0)
+                    .map(|id| id as u64),
+                ra: catalog.ra[i],
+                dec: catalog.dec[i],
+                mag: mag[i],
+                b_v: catalog.color.as_ref().map_or(f64::NAN, |color| color[i]),
+            })
+            .collect();
+        Ok(Self::new(stars))
+    }
+
+    /// Read the binary Yale Bright Star Catalog (e.g. the BSC5 file)
+    ///
+    /// Its J2000 positions are moved to the given epoch with the proper motions.
+    /// The B-V index being absent from that format, it is estimated from the
+    /// spectral type.
+    pub fn from_bsc5(bytes: &[u8], epoch: &Epoch) -> Result<Self, Error> {
+        let err = |msg: &str| Error::Parse(format!("BSC5: {}", msg));
+        if bytes.len() < BSC5_HEADER_LEN {
+            return Err(err("truncated header"));
+        }
+
+        // The file is written with the byte order of the machine that produced it
+        let read_i32_le = |offset: usize| i32::from_le_bytes(bytes[offset..(offset + 4)].try_into().unwrap());
+        let little_endian = (16..=256).contains(&read_i32_le(24));
+        let reader = Reader { bytes, little_endian };
+
+        let num_stars = reader.i32(8);
+        let star_number = reader.i32(12);
+        let proper_motion = reader.i32(16);
+        let num_mags = reader.i32(20).max(0) as usize;
+        let entry_len = reader.i32(24) as usize;
+        if num_stars >= 0 {
+            return Err(err("only the J2000 positions are supported"));
+        }
+        if !(16..=256).contains(&entry_len) {
+            return Err(err("invalid entry length"));
+        }
+        let num_stars = num_stars.unsigned_abs() as usize;
+        if bytes.len() < BSC5_HEADER_LEN + num_stars * entry_len {
+            return Err(err("truncated file"));
+        }
+
+        let years = epoch.julian_year() - 2000.0;
+        let stars = (0..num_stars)
+            .filter_map(|i| {
+                let mut offset = BSC5_HEADER_LEN + i * entry_len;
+                let id = (star_number > 0).then(|| reader.f32(offset) as u64);
+                if star_number > 0 {
+                    offset += 4;
+                }
+                let mut ra = reader.f64(offset);
+                let mut dec = reader.f64(offset + 8);
+                let spectral_type = [bytes[offset + 16], bytes[offset + 17]];
+                offset += 18;
+                let mag = (num_mags > 0).then(|| reader.i16(offset) as f64 / 100.0)?;
+                offset += 2 * num_mags;
+                if proper_motion > 0 {
+                    // Radians per year
+                    ra += reader.f32(offset) as f64 * years;
+                    dec += reader.f32(offset + 4) as f64 * years;
+                }
+
+                // Deleted entries are left empty
+                (ra != 0.0 || dec != 0.0).then_some(Star {
+                    id,
+                    ra: ra.to_degrees(),
+                    dec: dec.to_degrees(),
+                    mag,
+                    b_v: spectral_type_to_b_v(spectral_type),
+                })
+            })
+            .collect();
+
+        Ok(Self::new(stars))
+    }
+
+    /// Read a star catalog, either a binary BSC5 file or a table with a magnitude column
+    pub fn from_file<P: AsRef<Path>>(path: P, epoch: &Epoch) -> Result<Self, Error> {
+        let bytes = std::fs::read(path)?;
+        match std::str::from_utf8(&bytes) {
+            Ok(text) => {
+                let table = if text.trim_start().starts_with('<') {
+                    crate::catalog::Table::from_votable(text)?
+                } else {
+                    crate::catalog::Table::from_csv(text)?
+                };
+                Self::from_catalog(&Catalog::from_table(table, epoch)?)
+            }
+            Err(_) => Self::from_bsc5(&bytes, epoch),
+        }
+    }
+
+    pub fn stars(&self) -> &[Star] {
+        &self.stars
+    }
+
+    /// Positions of the stars having a catalog number, in the ICRS frame
+    pub fn positions_by_id(&self) -> HashMap<u64, Vec3<f64>> {
+        self.stars
+            .iter()
+            .filter_map(|star| {
+                let pos = math::radec_to_xyz(Angle::new(ArcDeg(star.ra)), Angle::new(ArcDeg(star.dec)));
+                star.id.map(|id| (id, pos))
+            })
+            .collect()
+    }
+
+    /// Number of stars brighter than a magnitude, i.e. the first ones of the catalog
+    pub fn count_brighter(&self, mag: f64) -> usize {
+        self.stars.partition_point(|star| star.mag <= mag)
+    }
+
+    pub fn len(&self) -> usize {
+        self.stars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stars.is_empty()
+    }
+
+    fn instances(&self) -> Vec<MarkerInstance> {
+        self.stars
+            .iter()
+            .map(|star| {
+                let pos = math::radec_to_xyz(Angle::new(ArcDeg(star.ra)), Angle::new(ArcDeg(star.dec)));
+                // The faintest stars are dimmed rather than shrunk
+                let size = MIN_STAR_SIZE + STAR_SIZE_PER_MAG * (REFERENCE_MAG - star.mag);
+                let alpha = (size / MIN_STAR_SIZE).clamp(0.3, 1.0);
+                let [r, g, b] = b_v_to_rgb(star.b_v);
+
+                MarkerInstance {
+                    pos_icrs: [pos.x as f32, pos.y as f32, pos.z as f32],
+                    size: size.clamp(MIN_STAR_SIZE, MAX_STAR_SIZE) as f32,
+                    color: [r, g, b, (alpha * 255.0).round() as u8],
+                }
+            })
+            .collect()
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn array<const N: usize>(&self, offset: usize) -> [u8; N] {
+        let mut bytes: [u8; N] = self.bytes[offset..(offset + N)].try_into().unwrap();
+        if !self.little_endian {
+            bytes.reverse();
+        }
+        bytes
+    }
+
+    fn i16(&self, offset: usize) -> i16 {
+        i16::from_le_bytes(self.array(offset))
+    }
+
+    fn i32(&self, offset: usize) -> i32 {
+        i32::from_le_bytes(self.array(offset))
+    }
+
+    fn f32(&self, offset: usize) -> f32 {
+        f32::from_le_bytes(self.array(offset))
+    }
+
+    fn f64(&self, offset: usize) -> f64 {
+        f64::from_le_bytes(self.array(offset))
+    }
+}
+
+// Typical B-V index of the main sequence stars of a spectral type, e.g. "G2"
+fn spectral_type_to_b_v(spectral_type: [u8; 2]) -> f64 {
+    const CLASSES: [(u8, f64); 8] = [
+        (b'O', -0.32),
+        (b'B', -0.30),
+        (b'A', 0.0),
+        (b'F', 0.30),
+        (b'G', 0.58),
+        (b'K', 0.81),
+        (b'M', 1.40),
+        // Ends the last class
+        (0, 2.0),
+    ];
+
+    let class = spectral_type[0].to_ascii_uppercase();
+    let subclass = match spectral_type[1] {
+        c @ b'0'..=b'9' => (c - b'0') as f64 / 10.0,
+        _ => 0.5,
+    };
+    match CLASSES.iter().position(|&(c, _)| c == class && c != 0) {
+        Some(i) => CLASSES[i].1 + (CLASSES[i + 1].1 - CLASSES[i].1) * subclass,
+        None => f64::NAN,
+    }
+}
+
+/// Color of a star of a given B-V index, white if unknown
+///
+/// The index gives the effective temperature (Ballesteros, 2012), whose
+/// black body color is approximated with the fit of Tanner Helland.
+pub fn b_v_to_rgb(b_v: f64) -> [u8; 3] {
+    if !b_v.is_finite() {
+        return [255, 255, 255];
+    }
+    let b_v = b_v.clamp(-0.4, 2.0);
+    let temperature = 4600.0 * (1.0 / (0.92 * b_v + 1.7) + 1.0 / (0.92 * b_v + 0.62));
+
+    let t = temperature / 100.0;
+    let (r, g, b) = if t <= 66.0 {
+        let g = 99.470_802_586_1 * t.ln() - 161.119_568_166_1;
+        let b = if t <= 19.0 {
+            0.0
+        } else {
+            138.517_731_223_1 * (t - 10.0).ln() - 305.044_792_730_7
+        };
+        (255.0, g, b)
+    } else {
+        let r = 329.698_727_446 * (t - 60.0).powf(-0.133_204_759_2);
+        let g = 288.122_169_528_3 * (t - 60.0).powf(-0.075_514_849_2);
+        (r, g, 255.0)
+    };
+
+    [r, g, b].map(|c: f64| c.clamp(0.0, 255.0).round() as u8)
+}
+
+/// Faintest magnitude of the stars drawn in a field of view
+pub fn limiting_magnitude(fov: Angle<f64>) -> f64 {
+    let fov = ArcDeg::from(fov).0.max(1e-3);
+    (ALLSKY_LIMITING_MAG + 2.5 * (360.0 / fov).log10()).min(MAX_LIMITING_MAG)
+}
+
+/// Draws a star catalog as point sprites, projected on the GPU like the catalog markers
+pub struct StarRenderer {
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    instance_buffer: Option<wgpu::Buffer>,
+    num_drawn: u32,
+}
+
+impl StarRenderer {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(
+                        std::mem::size_of::<MarkerUniform>() as wgpu::BufferAddress,
+                    ),
+                },
+                count: None,
+            }],
+            label: Some("stars_bind_group_layout"),
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("stars uniform"),
+            size: std::mem::size_of::<MarkerUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("stars_bind_group"),
+        });
+
+        // The stars are positioned by the vertex shader of the markers
+        let vs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("markers vert shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: glsl::with_shared(include_str!("shaders/markers.vert"), &[glsl::COMMON]).into(),
+                stage: naga::ShaderStage::Vertex,
+                defines: Default::default(),
+            },
+        });
+        let fs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("stars frag shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: include_str!("shaders/stars.frag").into(),
+                stage: naga::ShaderStage::Fragment,
+                defines: Default::default(),
+            },
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Stars Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Stars Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vs_shader,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                buffers: &[MarkerInstance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_shader,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    // The light of overlapping stars adds up
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::SrcAlpha,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            uniform_buffer,
+            bind_group,
+            instance_buffer: None,
+            num_drawn: 0,
+        }
+    }
+
+    /// Upload the stars of a catalog, replacing the previous ones
+    pub fn upload(&mut self, device: &wgpu::Device, catalog: &StarCatalog) {
+        self.instance_buffer = (!catalog.is_empty()).then(|| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("star instance buffer"),
+                contents: bytemuck::cast_slice(&catalog.instances()),
+                usage: wgpu::BufferUsages::VERTEX,
+            })
+        });
+    }
+
+    /// Update the view for the next frame, drawing the stars brighter than the limiting magnitude
+    ///
+    /// # Arguments
+    ///
+    /// * `catalog` - The uploaded catalog
+    /// * `view` - The current view
+    /// * `limiting_mag` - Faintest magnitude drawn, see [`limiting_magnitude`]
+    pub fn prepare(&mut self, queue: &wgpu::Queue, catalog: &StarCatalog, view: &MarkerView, limiting_mag: f64) {
+        let uniform = MarkerUniform::new(view, MarkerShape::Circle, 1.0);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        self.num_drawn = catalog.count_brighter(limiting_mag) as u32;
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if let Some(instance_buffer) = &self.instance_buffer {
+            if self.num_drawn > 0 {
+                render_pass.set_pipeline(&self.pipeline);
+                render_pass.set_bind_group(0, &self.bind_group, &[]);
+                render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+                render_pass.draw(0..6, 0..self.num_drawn);
+            }
+        }
+    }
+}
+
+mod tests {
+    #[test]
+    fn bsc5() {
+        use super::*;
+
+        // Header and two entries in the BSC5 layout, plus a deleted one
+        let header = [-3_i32, 1, -3, 1, 1, 1, 32];
+        // Catalog number, position, spectral type, magnitude and proper motion
+        type Entry = (f32, f64, f64, &'static [u8; 2], i16, f32, f32);
+        let entries: [Entry; 3] = [
+            (1.0, 1.5, 0.1, b"K0", 450, 0.0, 0.0),
+            (2.0, 0.0, 0.0, b"  ", 0, 0.0, 0.0),
+            (3.0, 0.5, -0.2, b"B9", -146, 1e-6, -2e-6),
+        ];
+        let encode = |big_endian: bool| {
+            let mut bytes = vec![];
+            let mut push = |mut b: Vec<u8>| {
+                if big_endian {
+                    b.reverse();
+                }
+                bytes.extend(b);
+            };
+            for v in header {
+                push(v.to_le_bytes().to_vec());
+            }
+            for (xno, ra, dec, sp, mag, pmra, pmdec) in entries {
+                push(xno.to_le_bytes().to_vec());
+                push(ra.to_le_bytes().to_vec());
+                push(dec.to_le_bytes().to_vec());
+                // The spectral type is a string, not swapped
+                push(if big_endian { vec![sp[1], sp[0]] } else { sp.to_vec() });
+                push(mag.to_le_bytes().to_vec());
+                push(pmra.to_le_bytes().to_vec());
+                push(pmdec.to_le_bytes().to_vec());
+            }
+            bytes
+        };
+
+        for big_endian in [false, true] {
+            let catalog = StarCatalog::from_bsc5(&encode(big_endian), &Epoch::from_julian_year(2100.0)).unwrap();
+            assert_eq!(catalog.len(), 2);
+            // Sorted by magnitude
+            let star = catalog.stars()[0];
+            assert_eq!(star.mag, -1.46);
+            assert_eq!(star.id, Some(3));
+            assert!((star.ra - (0.5 + 1e-4_f64).to_degrees()).abs() < 1e-9);
+            assert!((star.dec - (-0.2 - 2e-4_f64).to_degrees()).abs() < 1e-9);
+            assert!(star.b_v < 0.0 && catalog.stars()[1].b_v > 0.8);
+            assert_eq!(catalog.count_brighter(4.5), 2);
+            assert_eq!(catalog.count_brighter(4.0), 1);
+        }
+
+        // Hot stars are bluish, cool ones reddish
+        let [r, _, b] = b_v_to_rgb(-0.3);
+        assert!(b > r);
+        let [r, _, b] = b_v_to_rgb(1.6);
+        assert!(r > b);
+        assert!((limiting_magnitude(Angle(2.0 * std::f64::consts::PI)) - ALLSKY_LIMITING_MAG).abs() < 1e-9);
+        assert!((limiting_magnitude(Angle::new(ArcDeg(36.0))) - (ALLSKY_LIMITING_MAG + 2.5)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,404 @@
+use crate::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH, NUM_GLYPHS};
+use crate::math::{Vec2, Vec3};
+use crate::overlay::View;
+use crate::projection::Projection;
+use crate::texture::Texture;
+use crate::vertex::TextVertex;
+
+use wgpu::util::DeviceExt;
+
+// The glyphs are stored in the atlas on a grid of cells, one texel apart
+const CELL_WIDTH: u32 = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: u32 = GLYPH_HEIGHT + 1;
+const ATLAS_COLUMNS: u32 = 16;
+const ATLAS_ROWS: u32 = NUM_GLYPHS.div_ceil(ATLAS_COLUMNS);
+
+// Minimal gap between two labels, in pixels
+const LABEL_MARGIN: f64 = 2.0;
+// Distance between a label and its anchor, in pixels
+const LABEL_OFFSET: f64 = 4.0;
+const SHADOW_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.8];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rect {
+    min: Vec2<f64>,
+    max: Vec2<f64>,
+}
+
+impl Rect {
+    fn overlaps(&self, other: &Rect) -> bool {
+        self.min.x < other.max.x + LABEL_MARGIN
+            && other.min.x < self.max.x + LABEL_MARGIN
+            && self.min.y < other.max.y + LABEL_MARGIN
+            && other.min.y < self.max.y + LABEL_MARGIN
+    }
+}
+
+/// Texts to draw during a frame
+///
+/// Positions are given in pixels from the top left corner of the window.
+/// The HUD texts are always drawn whereas a label is dropped when it would
+/// overlap a text placed before it, so the most important labels must be
+/// added first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextLayout {
+    width: f64,
+    height: f64,
+    scale: u32,
+    placed: Vec<Rect>,
+    // Top left corner, glyph index and color of each glyph
+    glyphs: Vec<(Vec2<f64>, u32, [f32; 4])>,
+}
+
+impl TextLayout {
+    /// # Arguments
+    ///
+    /// * `width` - Width of the window in pixels
+    /// * `height` - Height of the window in pixels
+    /// * `scale` - Number of pixels per font texel
+    pub fn new(width: f64, height: f64, scale: u32) -> Self {
+        Self {
+            width,
+            height,
+            scale: scale.max(1),
+            placed: vec![],
+            glyphs: vec![],
+        }
+    }
+
+    /// Size in pixels of a single line of text
+    pub fn text_size(&self, text: &str) -> Vec2<f64> {
+        let n = text.chars().count() as u32;
+        let scale = self.scale as f64;
+        Vec2::new(
+            (n * CELL_WIDTH).saturating_sub(1) as f64 * scale,
+            GLYPH_HEIGHT as f64 * scale,
+        )
+    }
+
+    fn push(&mut self, text: &str, top_left: Vec2<f64>, color: [f32; 4]) {
+        let size = self.text_size(text);
+        self.placed.push(Rect {
+            min: top_left,
+            max: top_left + size,
+        });
+
+        let advance = (CELL_WIDTH * self.scale) as f64;
+        for (i, c) in text.chars().enumerate() {
+            let pos = Vec2::new(top_left.x + i as f64 * advance, top_left.y);
+            self.glyphs.push((pos, font::glyph_index(c), color));
+        }
+    }
+
+    /// Add a text fixed on the screen
+    pub fn add_hud(&mut self, text: &str, top_left: Vec2<f64>, color: [f32; 4]) {
+        self.push(text, top_left, color);
+    }
+
+    /// Add a label next to an anchor point
+    ///
+    /// The label is kept inside the window. Returns `false` if it has been
+    /// dropped because of an overlap with an already placed text.
+    pub fn add_label(&mut self, text: &str, anchor: Vec2<f64>, color: [f32; 4]) -> bool {
+        let size = self.text_size(text);
+        let min = Vec2::new(
+            (anchor.x + LABEL_OFFSET).min(self.width - size.x).max(0.0),
+            (anchor.y - 0.5 * size.y).min(self.height - size.y).max(0.0),
+        );
+        let rect = Rect {
+            min,
+            max: min + size,
+        };
+
+        if self.placed.iter().any(|placed| placed.overlaps(&rect)) {
+            false
+        } else {
+            self.push(text, min, color);
+            true
+        }
+    }
+
+    /// Add a label anchored at a clip space position
+    pub fn add_clip_label(
+        &mut self,
+        text: &str,
+        pos_clip_space: &Vec2<f64>,
+        ndc_to_clip: &Vec2<f64>,
+        color: [f32; 4],
+    ) -> bool {
+        let anchor = Vec2::new(
+            (pos_clip_space.x * ndc_to_clip.x + 1.0) * 0.5 * self.width,
+            (1.0 - pos_clip_space.y * ndc_to_clip.y) * 0.5 * self.height,
+        );
+        self.add_label(text, anchor, color)
+    }
+
+    /// Add a label anchored at a position of the sky
+    ///
+    /// The label stays upright on the screen and is culled when its anchor is
+    /// behind the camera or outside the window.
+    pub fn add_world_label<P: Projection<f64>>(
+        &mut self,
+        text: &str,
+        pos_icrs: &Vec3<f64>,
+        view: &View,
+        color: [f32; 4],
+    ) -> bool {
+        match view.project::<P>(pos_icrs) {
+            Some(pos_clip_space) => self.add_clip_label(text, &pos_clip_space, &view.ndc_to_clip, color),
+            None => false,
+        }
+    }
+
+    /// Number of glyphs placed
+    pub fn len(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+
+    // Two triangles per glyph, its shadow being drawn first
+    fn vertices(&self) -> Vec<TextVertex> {
+        let scale = self.scale as f64;
+        let glyph_size = Vec2::new(GLYPH_WIDTH as f64 * scale, GLYPH_HEIGHT as f64 * scale);
+        let to_ndc = |p: Vec2<f64>| {
+            [
+                (2.0 * p.x / self.width - 1.0) as f32,
+                (1.0 - 2.0 * p.y / self.height) as f32,
+            ]
+        };
+
+        let mut vertices = Vec::with_capacity(self.glyphs.len() * 12);
+        let shadows = self
+            .glyphs
+            .iter()
+            .map(|&(pos, idx, _)| (pos + Vec2::new(scale, scale) * 0.5, idx, SHADOW_COLOR));
+        for (pos, idx, color) in shadows.chain(self.glyphs.iter().copied()) {
+            let u = ((idx % ATLAS_COLUMNS) * CELL_WIDTH) as f32;
+            let v = ((idx / ATLAS_COLUMNS) * CELL_HEIGHT) as f32;
+            let (du, dv) = (GLYPH_WIDTH as f32, GLYPH_HEIGHT as f32);
+
+            let corners = [
+                (Vec2::new(0.0, 0.0), [u, v]),
+                (Vec2::new(0.0, glyph_size.y), [u, v + dv]),
+                (Vec2::new(glyph_size.x, glyph_size.y), [u + du, v + dv]),
+                (Vec2::new(glyph_size.x, 0.0), [u + du, v]),
+            ];
+            for i in [0, 1, 2, 0, 2, 3] {
+                let (offset, uv) = corners[i];
+                vertices.push(TextVertex {
+                    ndc: to_ndc(pos + offset),
+                    uv,
+                    color,
+                });
+            }
+        }
+
+        vertices
+    }
+}
+
+fn atlas_texels() -> Vec<u8> {
+    let (w, h) = (ATLAS_COLUMNS * CELL_WIDTH, ATLAS_ROWS * CELL_HEIGHT);
+    let mut texels = vec![0; (w * h * 4) as usize];
+    for idx in 0..NUM_GLYPHS {
+        let (x0, y0) = ((idx % ATLAS_COLUMNS) * CELL_WIDTH, (idx / ATLAS_COLUMNS) * CELL_HEIGHT);
+        for (y, row) in font::glyph(idx).iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                if row & (1 << (GLYPH_WIDTH - 1 - x)) != 0 {
+                    let i = (((y0 + y as u32) * w + x0 + x) * 4) as usize;
+                    texels[i..(i + 4)].copy_from_slice(&[255, 255, 255, 255]);
+                }
+            }
+        }
+    }
+
+    texels
+}
+
+/// Draws the texts of a layout over the sky
+pub struct TextRenderer {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    num_vertices: u32,
+    // Layout of the uploaded vertices
+    layout: Option<TextLayout>,
+}
+
+impl TextRenderer {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat) -> Self {
+        let texels = atlas_texels();
+        let atlas = Texture::from_raw_bytes::<u8>(
+            device,
+            queue,
+            Some(&texels),
+            (ATLAS_COLUMNS * CELL_WIDTH, ATLAS_ROWS * CELL_HEIGHT, 1),
+            4,
+            "glyph atlas",
+        );
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("text_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&atlas.sampler),
+                },
+            ],
+            label: Some("text_bind_group"),
+        });
+
+        let vs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("text vert shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: include_str!("shaders/text.vert").into(),
+                stage: naga::ShaderStage::Vertex,
+                defines: Default::default(),
+            },
+        });
+        let fs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("text frag shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: include_str!("shaders/text.frag").into(),
+                stage: naga::ShaderStage::Fragment,
+                defines: Default::default(),
+            },
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Text Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Text Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vs_shader,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                buffers: &[TextVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_shader,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("text vertex buffer"),
+            contents: &[0; 4096 * std::mem::size_of::<TextVertex>()],
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+            vertex_buffer,
+            num_vertices: 0,
+            layout: None,
+        }
+    }
+
+    /// Upload the texts to draw in the next frame
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, layout: &TextLayout) {
+        // The texts rarely change from a frame to the next
+        if self.layout.as_ref() == Some(layout) {
+            return;
+        }
+        self.layout = Some(layout.clone());
+        let vertices = layout.vertices();
+        let contents: &[u8] = bytemuck::cast_slice(&vertices);
+        if contents.len() as wgpu::BufferAddress > self.vertex_buffer.size() {
+            self.vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("text vertex buffer"),
+                contents,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            });
+        } else if !contents.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, contents);
+        }
+        self.num_vertices = vertices.len() as u32;
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.num_vertices > 0 {
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.draw(0..self.num_vertices, 0..1);
+        }
+    }
+}
+
+mod tests {
+    #[test]
+    fn label_collisions() {
+        use super::*;
+        let mut layout = TextLayout::new(800.0, 600.0, 2);
+        layout.add_hud("HUD", Vec2::new(0.0, 0.0), [1.0; 4]);
+
+        assert!(layout.add_label("+30°", Vec2::new(100.0, 100.0), [1.0; 4]));
+        // Too close to the first label
+        assert!(!layout.add_label("+40°", Vec2::new(110.0, 105.0), [1.0; 4]));
+        // Over the HUD
+        assert!(!layout.add_label("2h", Vec2::new(0.0, 5.0), [1.0; 4]));
+        // Kept inside the window
+        assert!(layout.add_label("12h", Vec2::new(799.0, 300.0), [1.0; 4]));
+        assert_eq!(layout.len(), 3 + 4 + 3);
+
+        // 5x7 glyphs with one pixel of spacing, scaled twice
+        assert_eq!(layout.text_size("12h"), Vec2::new(34.0, 14.0));
+        assert_eq!(layout.vertices().len(), 2 * 6 * layout.len());
+    }
+}
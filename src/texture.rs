@@ -118,7 +118,7 @@ impl Texture {
         num_bytes_per_pixel: usize,
         label: &str,
     ) -> Self {
-        let bytes = bytes.and_then(|bytes| Some(T::to_byte_slice(bytes)));
+        let bytes = bytes.map(|bytes| T::to_byte_slice(bytes));
         Self::from_bytes_rgba(
             device,
             queue,
@@ -130,6 +130,71 @@ impl Texture {
         )
     }
 
+    // single channel float values, read without filtering
+    pub fn from_scalar_values(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        values: Option<&[f32]>,
+        dimensions: (u32, u32, u32),
+        label: &str,
+    ) -> Self {
+        Self::from_bytes_rgba(
+            device,
+            queue,
+            wgpu::TextureFormat::R32Float,
+            values.map(|values| values.as_byte_slice()),
+            dimensions,
+            4,
+            label,
+        )
+    }
+
+    // the 6 square faces of a cube, in the +X, -X, +Y, -Y, +Z, -Z order
+    pub fn from_cube_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::RgbaImage],
+        label: &str,
+    ) -> Self {
+        let size = faces.first().map_or(1, |face| face.width().max(1));
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let texture = Self {
+            texture,
+            view,
+            sampler,
+            num_bytes_per_pixel: 4,
+        };
+        for (idx, face) in faces.iter().enumerate().filter(|(_, face)| face.dimensions() == (size, size)) {
+            texture.write_data(queue, (0, 0, idx as u32), face, (size, size, 1));
+        }
+
+        texture
+    }
+
     // rgba images
     pub fn from_image(
         device: &wgpu::Device,
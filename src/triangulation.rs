@@ -235,6 +235,7 @@ where
     }
 }
 
+#[allow(clippy::only_used_in_recursion)]
 fn recursive_triangulation<T: Float, P: Projection<T>>(
     face: &Face<T>,
     vertices: &mut Vec<Vector2<T>>,
@@ -0,0 +1,514 @@
+use crate::angle::{Angle, ArcDeg};
+use crate::error::Error;
+use crate::fits::{self, Header};
+use crate::frame;
+use crate::math::{self, Mat3, Vec2, Vec3};
+use crate::projection::{AzimuthalEquidistant, Gnomonic, Mollweide, Ortho, Projection};
+
+use cgmath::{InnerSpace, Matrix, Matrix2, SquareMatrix};
+
+// Highest order of the SIP polynomials
+pub const MAX_SIP_ORDER: usize = 9;
+// Iterations inverting the SIP distortion, which is small compared to the pixels
+const SIP_ITERATIONS: usize = 8;
+
+/// Sky projections of the FITS world coordinate systems that have an
+/// implementation in projection.rs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WcsProjection {
+    /// Gnomonic
+    Tan,
+    /// Orthographic
+    Sin,
+    /// Zenithal equidistant
+    Arc,
+    /// Mollweide
+    Mol,
+}
+
+impl WcsProjection {
+    /// Projection of a three letter code of the CTYPE keywords
+    pub fn parse(code: &str) -> Option<Self> {
+        match code {
+            "TAN" => Some(WcsProjection::Tan),
+            "SIN" => Some(WcsProjection::Sin),
+            "ARC" => Some(WcsProjection::Arc),
+            "MOL" => Some(WcsProjection::Mol),
+            _ => None,
+        }
+    }
+
+    /// Projection drawn with an index of the shaders, if it has a FITS counterpart
+    pub fn from_index(idx: usize) -> Option<Self> {
+        [WcsProjection::Tan, WcsProjection::Sin, WcsProjection::Arc, WcsProjection::Mol]
+            .iter()
+            .copied()
+            .find(|projection| projection.index() == idx)
+    }
+
+    /// Three letter code of the projection in the CTYPE keywords
+    pub fn code(self) -> &'static str {
+        match self {
+            WcsProjection::Tan => "TAN",
+            WcsProjection::Sin => "SIN",
+            WcsProjection::Arc => "ARC",
+            WcsProjection::Mol => "MOL",
+        }
+    }
+
+    /// Index of the projection, as dispatched by the shaders
+    pub fn index(self) -> usize {
+        match self {
+            WcsProjection::Tan => 5,
+            WcsProjection::Sin => 1,
+            WcsProjection::Arc => 4,
+            WcsProjection::Mol => 2,
+        }
+    }
+
+    /// Degrees of the FITS projection plane per clip space unit of projection.rs
+    pub fn clip_to_plane(self) -> Vec2<f64> {
+        let deg = 180.0 / std::f64::consts::PI;
+        match self {
+            WcsProjection::Tan => Vec2::new(180.0, 180.0),
+            WcsProjection::Sin => Vec2::new(deg, deg),
+            WcsProjection::Arc => Vec2::new(180.0, 180.0),
+            WcsProjection::Mol => Vec2::new(2.0 * 2.0_f64.sqrt() * deg, 2.0 * 2.0_f64.sqrt() * deg),
+        }
+    }
+
+    // Native frame position to projection plane coordinates in degrees
+    fn project(self, pos: &Vec3<f64>) -> Option<Vec2<f64>> {
+        fn project<P: Projection<f64>>(pos: &Vec3<f64>) -> Option<Vec2<f64>> {
+            let pos = pos.normalize().extend(1.0);
+            if !P::is_front_of_camera(&pos) {
+                return None;
+            }
+            P::world_to_clip_space(&pos)
+        }
+        let clip = match self {
+            WcsProjection::Tan => project::<Gnomonic>(pos),
+            WcsProjection::Sin => project::<Ortho>(pos),
+            WcsProjection::Arc => project::<AzimuthalEquidistant>(pos),
+            WcsProjection::Mol => project::<Mollweide>(pos),
+        }?;
+        let scale = self.clip_to_plane();
+        Some(Vec2::new(clip.x * scale.x, clip.y * scale.y))
+    }
+
+    fn deproject(self, plane: &Vec2<f64>) -> Option<Vec3<f64>> {
+        fn deproject<P: Projection<f64>>(clip: &Vec2<f64>) -> Option<Vec3<f64>> {
+            P::clip_to_world_space(clip).map(|p| p.truncate().normalize())
+        }
+        let scale = self.clip_to_plane();
+        let clip = Vec2::new(plane.x / scale.x, plane.y / scale.y);
+        match self {
+            WcsProjection::Tan => deproject::<Gnomonic>(&clip),
+            WcsProjection::Sin => deproject::<Ortho>(&clip),
+            WcsProjection::Arc => deproject::<AzimuthalEquidistant>(&clip),
+            WcsProjection::Mol => deproject::<Mollweide>(&clip),
+        }
+    }
+}
+
+/// Simple Imaging Polynomial distortion, added to the pixel offsets from the reference pixel
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sip {
+    pub order: usize,
+    /// Coefficients `A_p_q` of `u^p v^q` for the abscissa, at index `p * 10 + q`
+    pub a: [f64; 100],
+    pub b: [f64; 100],
+}
+
+impl Sip {
+    fn from_header(header: &Header) -> Option<Self> {
+        let order = header.get_i64("A_ORDER")?.max(header.get_i64("B_ORDER")?);
+        let order = order.clamp(0, MAX_SIP_ORDER as i64) as usize;
+        let mut sip = Self {
+            order,
+            a: [0.0; 100],
+            b: [0.0; 100],
+        };
+        for p in 0..=order {
+            for q in 0..=(order - p) {
+                sip.a[p * 10 + q] = header.get_f64(&format!("A_{}_{}", p, q)).unwrap_or(0.0);
+                sip.b[p * 10 + q] = header.get_f64(&format!("B_{}_{}", p, q)).unwrap_or(0.0);
+            }
+        }
+        Some(sip)
+    }
+
+    fn distortion(&self, uv: Vec2<f64>) -> Vec2<f64> {
+        let mut d = Vec2::new(0.0, 0.0);
+        let mut up = 1.0;
+        for p in 0..=self.order {
+            let mut vq = 1.0;
+            for q in 0..=(self.order - p) {
+                d.x += self.a[p * 10 + q] * up * vq;
+                d.y += self.b[p * 10 + q] * up * vq;
+                vq *= uv.y;
+            }
+            up *= uv.x;
+        }
+        d
+    }
+}
+
+/// Celestial world coordinate system of a FITS image
+#[derive(Clone, Debug, PartialEq)]
+pub struct Wcs {
+    pub projection: WcsProjection,
+    /// Reference pixel, the center of the first pixel being (1, 1)
+    pub crpix: Vec2<f64>,
+    /// Linear transformation from the pixel offsets to the projection plane, in degrees
+    pub cd: Matrix2<f64>,
+    /// Rotation from the ICRS to the native frame of the projection, whose
+    /// (0, 0, 1) axis is the reference point
+    pub icrs_to_native: Mat3<f64>,
+    pub sip: Option<Sip>,
+}
+
+impl Wcs {
+    /// Parse the celestial WCS keywords of a header
+    ///
+    /// Both the CD and the PC + CDELT (or CROTA2) conventions are read, in the
+    /// equatorial (ICRS, FK5) and galactic frames.
+    pub fn from_header(header: &Header) -> Result<Self, Error> {
+        let err = |msg: String| Error::Parse(format!("WCS: {}", msg));
+        let ctype1 = header.get("CTYPE1").ok_or_else(|| err("missing CTYPE1".to_string()))?;
+        let ctype2 = header.get("CTYPE2").ok_or_else(|| err("missing CTYPE2".to_string()))?;
+        let galactic = match (ctype1.get(..4), ctype2.get(..4)) {
+            (Some("RA--"), Some("DEC-")) => false,
+            (Some("GLON"), Some("GLAT")) => true,
+            _ => return Err(err(format!("unsupported axes {} {}", ctype1, ctype2))),
+        };
+        if header.get("RADESYS").is_some_and(|sys| sys.starts_with("FK4")) {
+            return Err(err("the FK4 frame is not supported".to_string()));
+        }
+        let code = ctype1.get(5..8).unwrap_or("");
+        let projection = WcsProjection::parse(code).ok_or_else(|| err(format!("unsupported projection {}", code)))?;
+
+        let key = |name: &str| header.get_f64(name);
+        let crpix = Vec2::new(key("CRPIX1").unwrap_or(0.0), key("CRPIX2").unwrap_or(0.0));
+        let crval = (key("CRVAL1").unwrap_or(0.0), key("CRVAL2").unwrap_or(0.0));
+
+        let cd = if let Some(cd1_1) = key("CD1_1") {
+            let (cd1_2, cd2_1, cd2_2) = (key("CD1_2"), key("CD2_1"), key("CD2_2"));
+            Matrix2::new(cd1_1, cd2_1.unwrap_or(0.0), cd1_2.unwrap_or(0.0), cd2_2.unwrap_or(0.0))
+        } else {
+            let cdelt1 = key("CDELT1").ok_or_else(|| err("missing CD1_1 or CDELT1".to_string()))?;
+            let cdelt2 = key("CDELT2").ok_or_else(|| err("missing CD2_2 or CDELT2".to_string()))?;
+            let pc = |i, j, default| key(&format!("PC{}_{}", i, j)).unwrap_or(default);
+            let (pc1_1, pc1_2, pc2_1, pc2_2) = match key("CROTA2") {
+                Some(rot) if header.get("PC1_1").is_none() => {
+                    let (s, c) = rot.to_radians().sin_cos();
+                    (c, -s * cdelt2 / cdelt1, s * cdelt1 / cdelt2, c)
+                }
+                _ => (pc(1, 1, 1.0), pc(1, 2, 0.0), pc(2, 1, 0.0), pc(2, 2, 1.0)),
+            };
+            Matrix2::new(cdelt1 * pc1_1, cdelt2 * pc2_1, cdelt1 * pc1_2, cdelt2 * pc2_2)
+        };
+        if cd.determinant().abs() < 1e-300 {
+            return Err(err("singular CD matrix".to_string()));
+        }
+
+        // Rotation of the native frame around the reference point, from the native longitude
+        // of the celestial pole given by LONPOLE
+        let (s, c) = match projection {
+            WcsProjection::Mol => {
+                let lonpole = key("LONPOLE").unwrap_or(if crval.1 >= 0.0 { 0.0 } else { 180.0 });
+                let latpole = key("LATPOLE").unwrap_or(90.0);
+                equatorial_reference_rotation(lonpole.to_radians(), latpole, crval.1.to_radians())
+                    .ok_or_else(|| err(format!("LONPOLE {} inconsistent with CRVAL2 {}", lonpole, crval.1)))?
+            }
+            _ => {
+                let default_lonpole = if crval.1 >= 90.0 { 0.0 } else { 180.0 };
+                (key("LONPOLE").unwrap_or(default_lonpole) - default_lonpole).to_radians().sin_cos()
+            }
+        };
+
+        let reference = math::radec_to_xyz(Angle::new(ArcDeg(crval.0)), Angle::new(ArcDeg(crval.1)));
+        let (east, north) = math::local_basis(&reference);
+        let native_to_celestial = Mat3::from_cols(east * c - north * s, east * s + north * c, reference);
+        let mut icrs_to_native = native_to_celestial.transpose();
+        if galactic {
+            icrs_to_native = icrs_to_native * frame::icrs_to_galactic();
+        }
+
+        let sip = if ctype1.ends_with("-SIP") {
+            Sip::from_header(header)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            projection,
+            crpix,
+            cd,
+            icrs_to_native,
+            sip,
+        })
+    }
+
+    /// WCS of a view rendered by the shaders, in the ICRS
+    ///
+    /// # Arguments
+    ///
+    /// * `projection` - Projection of the view
+    /// * `size` - Width and height of the view in pixels
+    /// * `ndc_to_clip` - Normalized device to clip space factor of the projection
+    /// * `x_sign` - Factor of the x axis of the world space, see `SphereMode::x_sign`
+    /// * `world_to_icrs` - Rotation from the world space of the projection to the ICRS
+    pub fn from_view(
+        projection: WcsProjection,
+        size: (u32, u32),
+        ndc_to_clip: Vec2<f64>,
+        x_sign: f64,
+        world_to_icrs: Mat3<f64>,
+    ) -> Self {
+        let (w, h) = (size.0 as f64, size.1 as f64);
+        let scale = projection.clip_to_plane();
+        Self {
+            projection,
+            // The center of the view, the first row being the bottom one
+            crpix: Vec2::new(0.5 * (w + 1.0), 0.5 * (h + 1.0)),
+            cd: Matrix2::new(x_sign * 2.0 / w / ndc_to_clip.x * scale.x, 0.0, 0.0, 2.0 / h / ndc_to_clip.y * scale.y),
+            icrs_to_native: world_to_icrs.transpose(),
+            sip: None,
+        }
+    }
+
+    /// WCS of a cutout centered on a position, the north being up and the east left
+    /// before the rotation
+    ///
+    /// # Arguments
+    ///
+    /// * `projection` - Projection of the cutout
+    /// * `center` - Right ascension and declination of the center in degrees
+    /// * `fov` - Width of the cutout in degrees of the projection plane
+    /// * `rotation` - Angle in degrees of the rotation of the cutout, as CROTA2
+    /// * `size` - Width and height of the cutout in pixels
+    pub fn cutout(
+        projection: WcsProjection,
+        center: (f64, f64),
+        fov: f64,
+        rotation: f64,
+        size: (u32, u32),
+    ) -> Result<Self, Error> {
+        let (w, h) = (size.0 as f64, size.1 as f64);
+        let cdelt = fov / w;
+        let float = |value: f64| format!("{:E}", value);
+        let code = projection.code();
+        let header = Header::from_cards(&[
+            fits::card("CTYPE1", &format!("'RA---{}'", code)),
+            fits::card("CTYPE2", &format!("'DEC--{}'", code)),
+            fits::card("CRPIX1", &float(0.5 * (w + 1.0))),
+            fits::card("CRPIX2", &float(0.5 * (h + 1.0))),
+            fits::card("CRVAL1", &float(center.0)),
+            fits::card("CRVAL2", &float(center.1)),
+            fits::card("CDELT1", &float(-cdelt)),
+            fits::card("CDELT2", &float(cdelt)),
+            fits::card("CROTA2", &float(rotation)),
+        ]);
+        Self::from_header(&header)
+    }
+
+    /// Header records of the WCS in the ICRS, without its SIP distortion
+    pub fn cards(&self) -> Vec<String> {
+        let native_to_celestial = self.icrs_to_native.transpose();
+        let reference = native_to_celestial.z;
+        let (ra, dec) = math::xyz_to_radec(&reference);
+        let (ra, dec) = (ArcDeg::from(ra).0.rem_euclid(360.0), ArcDeg::from(dec).0);
+
+        // Native longitude of the celestial pole, and its latitude for the Mollweide projection
+        let (lonpole, latpole) = match self.projection {
+            WcsProjection::Mol => {
+                let pole = self.icrs_to_native * Vec3::unit_y();
+                (pole.x.atan2(pole.z), Some(pole.y.clamp(-1.0, 1.0).asin()))
+            }
+            _ => {
+                let (east, north) = math::local_basis(&reference);
+                let delta = (-native_to_celestial.x.dot(north)).atan2(native_to_celestial.x.dot(east));
+                let default_lonpole = if dec >= 90.0 { 0.0 } else { std::f64::consts::PI };
+                (default_lonpole + delta, None)
+            }
+        };
+
+        let code = self.projection.code();
+        let float = |value: f64| format!("{:E}", value);
+        let mut cards = vec![
+            fits::card("CTYPE1", &format!("'RA---{}'", code)),
+            fits::card("CTYPE2", &format!("'DEC--{}'", code)),
+            fits::card("RADESYS", "'ICRS'"),
+            fits::card("CRPIX1", &float(self.crpix.x)),
+            fits::card("CRPIX2", &float(self.crpix.y)),
+            fits::card("CRVAL1", &float(ra)),
+            fits::card("CRVAL2", &float(dec)),
+            fits::card("CD1_1", &float(self.cd.x.x)),
+            fits::card("CD1_2", &float(self.cd.y.x)),
+            fits::card("CD2_1", &float(self.cd.x.y)),
+            fits::card("CD2_2", &float(self.cd.y.y)),
+            fits::card("LONPOLE", &float(lonpole.to_degrees().rem_euclid(360.0))),
+        ];
+        cards.extend(latpole.map(|latpole| fits::card("LATPOLE", &float(latpole.to_degrees()))));
+        cards
+    }
+
+    /// ICRS position of a pixel, the center of the first pixel being (1, 1)
+    pub fn pixel_to_icrs(&self, pixel: &Vec2<f64>) -> Option<Vec3<f64>> {
+        let mut uv = pixel - self.crpix;
+        if let Some(sip) = &self.sip {
+            uv += sip.distortion(uv);
+        }
+        let native = self.projection.deproject(&(self.cd * uv))?;
+        Some(self.icrs_to_native.transpose() * native)
+    }
+
+    /// Pixel of an ICRS position, if it can be projected
+    pub fn icrs_to_pixel(&self, pos: &Vec3<f64>) -> Option<Vec2<f64>> {
+        let plane = self.projection.project(&(self.icrs_to_native * pos))?;
+        let distorted = self.cd.invert()? * plane;
+        let mut uv = distorted;
+        if let Some(sip) = &self.sip {
+            for _ in 0..SIP_ITERATIONS {
+                uv = distorted - sip.distortion(uv);
+            }
+        }
+        Some(self.crpix + uv)
+    }
+}
+
+// Sine and cosine of the rotation of the native frame of a projection whose reference point is
+// on its equator, from the native longitude of the celestial pole and the sign of its latitude
+fn equatorial_reference_rotation(lonpole: f64, latpole: f64, dec: f64) -> Option<(f64, f64)> {
+    let (sin_dec, cos_dec) = dec.sin_cos();
+    if cos_dec < 1e-12 {
+        return Some((0.0, 1.0));
+    }
+    // The celestial pole is 90° - dec away from the reference point
+    let lat = if lonpole.cos().abs() > 1e-12 {
+        let cos_lat = sin_dec / lonpole.cos();
+        if !(0.0..=1.0 + 1e-12).contains(&cos_lat) {
+            return None;
+        }
+        cos_lat.min(1.0).acos().copysign(latpole)
+    } else {
+        latpole.to_radians()
+    };
+    Some((-lat.cos() * lonpole.sin() / cos_dec, lat.sin() / cos_dec))
+}
+
+mod tests {
+    #[test]
+    fn wcs() {
+        use super::*;
+        use crate::fits::parse;
+
+        let header = |cards: &[&str]| {
+            let mut text = String::from("SIMPLE  =                    T");
+            text = format!("{:<80}", text);
+            for card in cards.iter().chain(&["END"]) {
+                text.push_str(&format!("{:<80}", card));
+            }
+            let bytes = format!("{:<2880}", text).into_bytes();
+            parse(&bytes).unwrap().remove(0).header
+        };
+        let radec = |p: &Vec3<f64>| {
+            let (ra, dec) = math::xyz_to_radec(p);
+            ((ArcDeg::from(ra).0 + 360.0) % 360.0, ArcDeg::from(dec).0)
+        };
+
+        // One arcminute pixels, the east being on the left
+        let wcs = Wcs::from_header(&header(&[
+            "CTYPE1  = 'RA---TAN'",
+            "CTYPE2  = 'DEC--TAN'",
+            "CRPIX1  =                 50.5",
+            "CRPIX2  =                 50.5",
+            "CRVAL1  =                 83.6",
+            "CRVAL2  =                 22.0",
+            "CDELT1  =         -0.016666667",
+            "CDELT2  =          0.016666667",
+        ]))
+        .unwrap();
+        let (ra, dec) = radec(&wcs.pixel_to_icrs(&Vec2::new(50.5, 50.5)).unwrap());
+        assert!((ra - 83.6).abs() < 1e-9 && (dec - 22.0).abs() < 1e-9);
+        // One pixel towards the north and one towards the west
+        let (_, dec) = radec(&wcs.pixel_to_icrs(&Vec2::new(50.5, 51.5)).unwrap());
+        assert!((dec - 22.0 - 1.0 / 60.0).abs() < 1e-6);
+        let (ra, _) = radec(&wcs.pixel_to_icrs(&Vec2::new(51.5, 50.5)).unwrap());
+        assert!(ra < 83.6);
+
+        // Round trips through the projections and the SIP distortion
+        for (projection, sip) in [("TAN", true), ("SIN", false), ("ARC", false), ("MOL", false)] {
+            let ctype1 = format!("CTYPE1  = 'GLON-{}{}'", projection, if sip { "-SIP" } else { "" });
+            let ctype2 = format!("CTYPE2  = 'GLAT-{}{}'", projection, if sip { "-SIP" } else { "" });
+            let wcs = Wcs::from_header(&header(&[
+                &ctype1,
+                &ctype2,
+                "CRPIX1  =                100.0",
+                "CRPIX2  =                 80.0",
+                "CRVAL1  =                 30.0",
+                "CRVAL2  =                -40.0",
+                "CD1_1   =             -0.01   ",
+                "CD1_2   =              0.002  ",
+                "CD2_1   =              0.001  ",
+                "CD2_2   =              0.01   ",
+                "A_ORDER =                    2",
+                "B_ORDER =                    2",
+                "A_2_0   =               1.0E-5",
+                "B_1_1   =              -2.0D-5",
+            ]))
+            .unwrap();
+            assert_eq!(wcs.sip.is_some(), sip);
+            for pixel in [Vec2::new(1.0, 1.0), Vec2::new(250.0, 30.0), Vec2::new(120.0, 190.0)] {
+                let pos = wcs.pixel_to_icrs(&pixel).unwrap();
+                assert!((wcs.icrs_to_pixel(&pos).unwrap() - pixel).magnitude() < 1e-6);
+            }
+        }
+        assert!(Wcs::from_header(&header(&["CTYPE1  = 'RA---ZEA'", "CTYPE2  = 'DEC--ZEA'"])).is_err());
+
+        // The WCS of rendered views, rolled and centered in the southern hemisphere, read back
+        let world_to_icrs = Mat3::from_angle_y(cgmath::Deg(-70.0))
+            * Mat3::from_angle_x(cgmath::Deg(35.0))
+            * Mat3::from_angle_z(cgmath::Deg(20.0));
+        let (w, h) = (400, 300);
+        for (idx, x_sign) in [(1, -1.0), (2, -1.0), (4, 1.0), (5, -1.0)] {
+            let projection = WcsProjection::from_index(idx).unwrap();
+            let wcs = Wcs::from_view(projection, (w, h), Vec2::new(0.75, 1.0), x_sign, world_to_icrs);
+            let cards = wcs.cards();
+            let cards: Vec<&str> = cards.iter().map(String::as_str).collect();
+            let read = Wcs::from_header(&header(&cards)).unwrap();
+            for pixel in [Vec2::new(200.5, 150.5), Vec2::new(240.0, 160.0), Vec2::new(180.0, 120.0)] {
+                let pos = wcs.pixel_to_icrs(&pixel).unwrap();
+                assert!((read.pixel_to_icrs(&pixel).unwrap() - pos).magnitude() < 1e-9, "{:?}", projection);
+            }
+        }
+        // Same as the deprojection of the screen, whose rows go down, the sky being mirrored
+        let ndc_to_clip = Gnomonic::compute_ndc_to_clip_factor(w as f64, h as f64);
+        let wcs = Wcs::from_view(WcsProjection::Tan, (w, h), ndc_to_clip, -1.0, world_to_icrs);
+        let screen = Gnomonic::screen_to_world_space(&Vec2::new(249.5, 99.5), w as f64, h as f64).unwrap();
+        let screen = Vec3::new(-screen.x, screen.y, screen.z);
+        let pos = wcs.pixel_to_icrs(&Vec2::new(250.0, 201.0)).unwrap();
+        assert!((world_to_icrs * screen.normalize() - pos).magnitude() < 1e-9);
+        // The east is on the left of the sky
+        let (ra, _) = math::xyz_to_radec(&wcs.pixel_to_icrs(&Vec2::new(200.5, 150.5)).unwrap());
+        let (ra_right, _) = math::xyz_to_radec(&wcs.pixel_to_icrs(&Vec2::new(210.5, 150.5)).unwrap());
+        assert!((ra_right - ra).to_radians().sin() < 0.0);
+        assert_eq!(WcsProjection::from_index(0), None);
+
+        // Cutouts with the north up and the east left, then rotated to have the west up
+        let radec = |wcs: &Wcs, x: f64, y: f64| {
+            let (ra, dec) = math::xyz_to_radec(&wcs.pixel_to_icrs(&Vec2::new(x, y)).unwrap());
+            (ArcDeg::from(ra).0.rem_euclid(360.0), ArcDeg::from(dec).0)
+        };
+        for projection in [WcsProjection::Tan, WcsProjection::Sin, WcsProjection::Arc, WcsProjection::Mol] {
+            let wcs = Wcs::cutout(projection, (83.6, -5.4), 2.0, 0.0, (200, 100)).unwrap();
+            let (ra, dec) = radec(&wcs, 100.5, 50.5);
+            assert!((ra - 83.6).abs() < 1e-9 && (dec + 5.4).abs() < 1e-9, "{:?}", projection);
+            assert!(radec(&wcs, 100.5, 60.5).1 > dec && radec(&wcs, 110.5, 50.5).0 < ra);
+        }
+        let wcs = Wcs::cutout(WcsProjection::Tan, (83.6, -5.4), 2.0, 90.0, (200, 100)).unwrap();
+        let (ra, dec) = radec(&wcs, 100.5, 60.5);
+        assert!(ra < 83.6 && (dec + 5.4).abs() < 1e-3);
+    }
+}